@@ -0,0 +1,192 @@
+use crate::{
+    manifest::{Manifest, ManifestFile},
+    summary,
+};
+use parquet::{
+    errors::ParquetError,
+    file::{
+        footer,
+        metadata::{ParquetMetaData, RowGroupMetaData},
+    },
+    format::{ColumnOrder, FileMetaData, KeyValue, TypeDefinedOrder},
+    schema::types,
+    thrift::{TCompactOutputProtocol, TSerializable},
+};
+use std::{
+    convert::TryInto,
+    error::Error,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use tracing::{event, Level};
+
+/// Magic number at the start and the end of a Parquet file.
+pub(crate) const MAGIC: &[u8; 4] = b"PAR1";
+
+/// Changes to the key-value metadata in the footer of Parquet files.
+#[derive(Clone, Debug, Default)]
+pub struct Amendment {
+    /// Keys to add or replace, with their values
+    pub set: Vec<(String, String)>,
+    /// Keys to remove
+    pub remove: Vec<String>,
+}
+
+impl Amendment {
+    /// Applies the changes to `key_value_metadata`, keeping the order of existing keys.
+    fn apply(&self, key_value_metadata: &mut Vec<KeyValue>) {
+        key_value_metadata.retain(|kv| !self.remove.contains(&kv.key));
+        for (key, value) in &self.set {
+            match key_value_metadata.iter_mut().find(|kv| &kv.key == key) {
+                Some(kv) => kv.value = Some(value.clone()),
+                None => key_value_metadata.push(KeyValue {
+                    key: key.clone(),
+                    value: Some(value.clone()),
+                }),
+            }
+        }
+    }
+}
+
+/// Returns the length of the data before the footer of the Parquet `file` at `path`, and the
+/// length of the footer.
+pub(crate) fn footer_range(file: &mut File, path: &Path) -> Result<(u64, u64), Box<dyn Error>> {
+    let len = file.metadata()?.len();
+    // The footer is followed by its length and the magic number.
+    let mut trailer = [0; 8];
+    file.seek(SeekFrom::End(-8))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[4..] != MAGIC {
+        return Err(format!("`{}` is not a Parquet file", path.display()).into());
+    }
+    let footer_len = u32::from_le_bytes(trailer[..4].try_into().unwrap()) as u64;
+    let data_len = len
+        .checked_sub(footer_len + 8)
+        .ok_or_else(|| format!("`{}` has an invalid footer", path.display()))?;
+    Ok((data_len, footer_len))
+}
+
+/// Reads the footer of the Parquet `file` at `path`.
+///
+/// Returns the metadata of the file and the length of the data before the footer.
+pub(crate) fn read_footer(
+    file: &mut File,
+    path: &Path,
+) -> Result<(FileMetaData, u64), Box<dyn Error>> {
+    let (data_len, footer_len) = footer_range(file, path)?;
+    let mut footer = Vec::new();
+    file.seek(SeekFrom::Start(data_len))?;
+    file.take(footer_len).read_to_end(&mut footer)?;
+    Ok((decode_footer(&footer)?, data_len))
+}
+
+/// Decodes the thrift compact encoding of the footer `bytes` with the decoder of parquet, and
+/// returns it as the thrift struct that parquet writes.
+pub(crate) fn decode_footer(bytes: &[u8]) -> Result<FileMetaData, ParquetError> {
+    to_thrift(&footer::decode_metadata(bytes)?)
+}
+
+/// Returns the thrift struct of the footer of a file with the `metadata`.
+fn to_thrift(metadata: &ParquetMetaData) -> Result<FileMetaData, ParquetError> {
+    let file = metadata.file_metadata();
+    // Parquet writes the type defined order for all columns.
+    let column_orders = file.column_orders().map(|orders| {
+        orders
+            .iter()
+            .map(|_| ColumnOrder::TYPEORDER(TypeDefinedOrder::new()))
+            .collect()
+    });
+    Ok(FileMetaData {
+        version: file.version(),
+        schema: types::to_thrift(file.schema())?,
+        num_rows: file.num_rows(),
+        row_groups: metadata
+            .row_groups()
+            .iter()
+            .map(RowGroupMetaData::to_thrift)
+            .collect(),
+        key_value_metadata: file.key_value_metadata().cloned(),
+        created_by: file.created_by().map(str::to_string),
+        column_orders,
+        encryption_algorithm: None,
+        footer_signing_key_metadata: None,
+    })
+}
+
+/// Encodes the thrift struct `value` with the thrift compact protocol of parquet.
+pub(crate) fn encode(value: &impl TSerializable) -> Result<Vec<u8>, ParquetError> {
+    let mut bytes = Vec::new();
+    value.write_to_out_protocol(&mut TCompactOutputProtocol::new(&mut bytes))?;
+    Ok(bytes)
+}
+
+/// Writes `metadata` as the footer of a Parquet file to `output`, followed by its length and
+/// the magic number.
+pub(crate) fn write_footer(
+    output: &mut impl Write,
+    metadata: &FileMetaData,
+) -> Result<(), Box<dyn Error>> {
+    let footer = encode(metadata)?;
+    output.write_all(&footer)?;
+    output.write_all(&(footer.len() as u32).to_le_bytes())?;
+    output.write_all(MAGIC)?;
+    Ok(())
+}
+
+/// Rewrites the footer of the Parquet file at `path` with the `amendment` to its key-value
+/// metadata. The column data is copied as-is, so its offsets in the footer remain valid.
+pub fn amend_footer(path: &Path, amendment: &Amendment) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let (mut metadata, data_len) = read_footer(&mut file, path)?;
+    let mut key_value_metadata = metadata.key_value_metadata.take().unwrap_or_default();
+    amendment.apply(&mut key_value_metadata);
+    metadata.key_value_metadata = (!key_value_metadata.is_empty()).then(|| key_value_metadata);
+
+    // Write to a temporary file, so that an interrupted rewrite does not leave a broken file.
+    let tmp_path = path.with_extension("parquet.tmp");
+    let mut output = io::BufWriter::new(File::create(&tmp_path)?);
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut file).take(data_len), &mut output)?;
+    write_footer(&mut output, &metadata)?;
+    output.flush()?;
+    drop(output);
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Amends the footers of the Parquet files in `dir` and updates their checksums in the
+/// manifest and the summary metadata of the dir.
+///
+/// Returns the number of amended files.
+pub fn amend_metadata(dir: &Path, amendment: &Amendment) -> Result<usize, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let mut manifest = Manifest::load(dir)?;
+    let mut amended = 0;
+    let result = paths
+        .iter()
+        .filter(|path| {
+            path.extension().map_or(false, |ext| ext == "parquet")
+                && !path.file_name().unwrap().to_string_lossy().starts_with('_')
+        })
+        .try_for_each(|path| -> Result<(), Box<dyn Error>> {
+            let name = path.file_name().unwrap().to_string_lossy();
+            event!(Level::INFO, "Amending `{}`", name);
+            amend_footer(path, amendment)?;
+            amended += 1;
+            let upstream = manifest
+                .get(&name)
+                .map(|file| file.upstream.clone())
+                .unwrap_or_default();
+            manifest.insert(ManifestFile::new(dir, &name, upstream)?);
+            Ok(())
+        });
+    // Save the checksums of the files that were amended, also when another file failed.
+    manifest.save(dir)?;
+    result?;
+    summary::write_summary_metadata(dir)?;
+    Ok(amended)
+}
@@ -0,0 +1,63 @@
+//! Geodesic area of the cells of a latitude-longitude raster on the WGS 84 ellipsoid.
+
+/// Semi-major axis of the WGS 84 ellipsoid in meters.
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.;
+
+/// Flattening of the WGS 84 ellipsoid.
+const FLATTENING: f64 = 1. / 298.257_223_563;
+
+/// Returns the area in square meters of the cell between the latitudes `lat1` and `lat2` that
+/// is `width` degrees of longitude wide, on the WGS 84 ellipsoid.
+///
+/// The area of a cell shrinks towards the poles, a pixel of one arc second is about 950 m² at
+/// the equator and 480 m² at 60°.
+pub fn cell_area(lat1: f64, lat2: f64, width: f64) -> f64 {
+    let e2 = FLATTENING * (2. - FLATTENING);
+    let e = e2.sqrt();
+    // The area between the equator and a latitude is proportional to the authalic function q.
+    let q = |lat: f64| {
+        let sin = lat.clamp(-90., 90.).to_radians().sin();
+        sin / (1. - e2 * sin * sin) + ((1. + e * sin) / (1. - e * sin)).ln() / (2. * e)
+    };
+    let b2 = SEMI_MAJOR_AXIS * SEMI_MAJOR_AXIS * (1. - e2);
+    (b2 * width.abs().to_radians() / 2. * (q(lat2) - q(lat1))).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            ((actual - expected) / expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn area_of_the_ellipsoid() {
+        assert_close(cell_area(-90., 90., 360.), 510_065_621_724_088.6);
+    }
+
+    #[test]
+    fn arc_second_cells() {
+        let arc_second = 1. / 3600.;
+        assert_close(cell_area(0., arc_second, arc_second), 949.774_080_192_647_5);
+        assert_close(
+            cell_area(60., 60. + arc_second, arc_second),
+            479.689_806_307_645_54,
+        );
+        // The cells are symmetric about the equator, and the order of the edges does not matter.
+        assert_close(
+            cell_area(-arc_second, 0., arc_second),
+            cell_area(0., arc_second, arc_second),
+        );
+        assert_close(
+            cell_area(arc_second, 0., -arc_second),
+            cell_area(0., arc_second, arc_second),
+        );
+        assert_eq!(cell_area(10., 10., 1.), 0.);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::usage::parse_bytes;
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Download bandwidth.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rate {
+    /// No limit
+    Full,
+    /// Limit in bytes per second
+    Limit(u64),
+}
+
+impl FromStr for Rate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str = "expected `full` or a rate like `10MB/s`";
+        if s == "full" {
+            return Ok(Self::Full);
+        }
+        let s = s.strip_suffix("/s").ok_or(ERR)?;
+        parse_bytes(s).map(Self::Limit).ok_or(ERR)
+    }
+}
+
+/// Bandwidth per time window of the day, in local time.
+///
+/// Parsed from comma-separated `HH:MM-HH:MM=rate` windows, e.g.
+/// `22:00-06:00=full,06:00-22:00=10MB/s`. Windows that end before they start wrap around
+/// midnight. The bandwidth is not limited outside the windows.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule(Vec<(u32, u32, Rate)>);
+
+impl Schedule {
+    /// Returns the rate at `minute` of the day, of the first window that contains it.
+    pub fn rate(&self, minute: u32) -> Rate {
+        self.0
+            .iter()
+            .find(|&&(start, end, _)| {
+                if start < end {
+                    (start..end).contains(&minute)
+                } else {
+                    minute >= start || minute < end
+                }
+            })
+            .map_or(Rate::Full, |&(_, _, rate)| rate)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str = "expected `HH:MM-HH:MM=rate` windows, e.g. `22:00-06:00=full`";
+        let minute = |time: &str| match time.split_once(':') {
+            Some((hours, minutes)) => match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+                (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+                _ => Err(ERR),
+            },
+            None => Err(ERR),
+        };
+        s.split(',')
+            .map(|window| {
+                let (times, rate) = window.trim().split_once('=').ok_or(ERR)?;
+                let (start, end) = times.split_once('-').ok_or(ERR)?;
+                Ok((minute(start)?, minute(end)?, rate.parse()?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Returns the current minute of the day in local time.
+fn local_minute() -> u32 {
+    // Safety: localtime_r only writes to the provided struct.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&libc::time(std::ptr::null_mut()), &mut tm) };
+    (tm.tm_hour * 60 + tm.tm_min) as u32
+}
+
+/// Limits the combined bandwidth of all downloads according to a [`Schedule`].
+#[derive(Debug, Default)]
+pub struct Throttle {
+    schedule: Schedule,
+    /// When the bytes received so far are paid off at the current rate.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(schedule: Schedule) -> Self {
+        Throttle {
+            schedule,
+            next: Mutex::default(),
+        }
+    }
+
+    /// Waits until `bytes` that were received fit in the current rate.
+    pub async fn consume(&self, bytes: u64) {
+        let rate = match self.schedule.rate(local_minute()) {
+            Rate::Full => return,
+            Rate::Limit(rate) => rate,
+        };
+        let deadline = {
+            let mut next = self.next.lock().unwrap();
+            // Idle time does not build up a burst allowance.
+            let now = Instant::now();
+            let deadline = next.map_or(now, |next| next.max(now))
+                + Duration::from_secs_f64(bytes as f64 / rate as f64);
+            *next = Some(deadline);
+            deadline
+        };
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
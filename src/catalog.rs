@@ -0,0 +1,134 @@
+use parquet::{
+    basic::{self, ConvertedType},
+    file::reader::{FileReader, SerializedFileReader},
+    schema::types::Type,
+};
+use rusoto_core::{Region, RusotoError};
+use rusoto_glue::{
+    Column, CreateTableError, CreateTableRequest, Glue, GlueClient, SerDeInfo, StorageDescriptor,
+    TableInput, UpdateTableRequest,
+};
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::Path,
+};
+
+/// Returns the Hive type of a Parquet schema type.
+fn hive_type(field: &Type) -> Result<String, Box<dyn Error>> {
+    if field.is_group() {
+        let fields = field
+            .get_fields()
+            .iter()
+            .map(|field| Ok(format!("{}:{}", field.name(), hive_type(field)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        return Ok(format!("struct<{}>", fields.join(",")));
+    }
+    Ok(match field.get_physical_type() {
+        basic::Type::BOOLEAN => "boolean",
+        basic::Type::INT32 => "int",
+        basic::Type::INT64 => "bigint",
+        basic::Type::FLOAT => "float",
+        basic::Type::DOUBLE => "double",
+        basic::Type::BYTE_ARRAY
+            if field.get_basic_info().converted_type() == ConvertedType::UTF8 =>
+        {
+            "string"
+        }
+        basic::Type::BYTE_ARRAY => "binary",
+        other => return Err(format!("unsupported column type {}", other).into()),
+    }
+    .to_string())
+}
+
+/// Returns the columns (name and Hive type) of the Parquet files in `dir`, read from the schema
+/// of the first output file.
+pub fn hive_columns(dir: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let path = paths
+        .into_iter()
+        .find(|path| {
+            path.extension().map_or(false, |ext| ext == "parquet")
+                && !path.file_name().unwrap().to_string_lossy().starts_with('_')
+        })
+        .ok_or_else(|| format!("no Parquet files in `{}`", dir.display()))?;
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    reader
+        .metadata()
+        .file_metadata()
+        .schema()
+        .get_fields()
+        .iter()
+        .map(|field| Ok((field.name().to_string(), hive_type(field)?)))
+        .collect()
+}
+
+/// Creates or updates the external Parquet `table` in the Glue `database`, with the `columns`
+/// and the files at `location` (e.g. `s3://bucket/prefix/`).
+pub async fn register_glue(
+    database: String,
+    table: String,
+    location: String,
+    columns: Vec<(String, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let client = GlueClient::new(Region::default());
+    let table_input = TableInput {
+        name: table,
+        table_type: Some("EXTERNAL_TABLE".to_string()),
+        parameters: Some(
+            std::iter::once(("classification".to_string(), "parquet".to_string())).collect(),
+        ),
+        storage_descriptor: Some(StorageDescriptor {
+            columns: Some(
+                columns
+                    .into_iter()
+                    .map(|(name, type_)| Column {
+                        name,
+                        type_: Some(type_),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            location: Some(location),
+            input_format: Some(
+                "org.apache.hadoop.hive.ql.io.parquet.MapredParquetInputFormat".to_string(),
+            ),
+            output_format: Some(
+                "org.apache.hadoop.hive.ql.io.parquet.MapredParquetOutputFormat".to_string(),
+            ),
+            serde_info: Some(SerDeInfo {
+                serialization_library: Some(
+                    "org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe".to_string(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    match client
+        .create_table(CreateTableRequest {
+            database_name: database.clone(),
+            table_input: table_input.clone(),
+            ..Default::default()
+        })
+        .await
+    {
+        Ok(_) => Ok(()),
+        // Update the table when it exists, e.g. after the bands changed.
+        Err(RusotoError::Service(CreateTableError::AlreadyExists(_))) => {
+            client
+                .update_table(UpdateTableRequest {
+                    database_name: database,
+                    table_input,
+                    ..Default::default()
+                })
+                .await?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
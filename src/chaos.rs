@@ -0,0 +1,65 @@
+//! Failure injection, to check that the retry, resume and cleanup paths work before a long run.
+//!
+//! Only built with the `chaos` feature. The failure rates are set once for the process with
+//! [`configure`], and every injection point fails with the rate of its [`Stage`].
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::{event, Level};
+
+/// Failure rate of the downloads, as the bits of an `f64`.
+static DOWNLOAD_RATE: AtomicU64 = AtomicU64::new(0);
+/// Failure rate of the conversions, as the bits of an `f64`.
+static CONVERT_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Stage of a tile at which failures are injected.
+#[derive(Copy, Clone, Debug)]
+pub enum Stage {
+    /// A chunk of an S3 download, which fails with a transient error after the previous chunks
+    /// were written to the partial file
+    Download,
+    /// The conversion to Parquet, which fails before the footer of the file is written
+    Convert,
+}
+
+impl Stage {
+    fn rate(self) -> &'static AtomicU64 {
+        match self {
+            Stage::Download => &DOWNLOAD_RATE,
+            Stage::Convert => &CONVERT_RATE,
+        }
+    }
+}
+
+/// Sets the failure rates (between 0 and 1) of the downloads and the conversions.
+pub fn configure(download_rate: f64, convert_rate: f64) -> Result<(), &'static str> {
+    if !(0. ..=1.).contains(&download_rate) || !(0. ..=1.).contains(&convert_rate) {
+        return Err("failure rates must be between 0 and 1");
+    }
+    DOWNLOAD_RATE.store(download_rate.to_bits(), Ordering::Relaxed);
+    CONVERT_RATE.store(convert_rate.to_bits(), Ordering::Relaxed);
+    if download_rate > 0. || convert_rate > 0. {
+        event!(
+            Level::WARN,
+            "Injecting failures in {:.1}% of the downloads and {:.1}% of the conversions",
+            download_rate * 100.,
+            convert_rate * 100.
+        );
+    }
+    Ok(())
+}
+
+/// Returns true when the `stage` should fail, at random with the rate of the stage.
+pub fn fail(stage: Stage) -> bool {
+    let rate = f64::from_bits(stage.rate().load(Ordering::Relaxed));
+    // The hasher keys of a new `RandomState` are random.
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    let fail = random < rate;
+    if fail {
+        event!(Level::WARN, "Injecting a failure of the {:?} stage", stage);
+    }
+    fail
+}
@@ -0,0 +1,1290 @@
+//! Command line of the binary: the options and subcommands, and the options that are merged
+//! from the environment, the config file and the manifest of the dataset to append to.
+
+use crate::{
+    bandwidth::Schedule,
+    convert::{
+        parse_column_compression, parse_compression, parse_encoding, parse_writer_version,
+        BandType, Nodata, OutOfRange, Sentinel, ValidRange,
+    },
+    curve::SortOrder,
+    dataset::Dataset,
+    grid::{Aggregation, Resampling},
+    join::parse_join_raster,
+    manifest::Manifest,
+    mercator::TileFormat,
+    mirror::Server,
+    relief::ReliefFormat,
+    sink::Sink,
+    source::EndpointStrategy,
+    terrain::Derived,
+    upload::OutputUrl,
+    usage::parse_bytes,
+    Coords, IfExists, Set,
+};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use parquet::{
+    basic::{Compression, Encoding},
+    file::properties::WriterVersion,
+};
+use reqwest::Url;
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    ffi::OsString,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const TIF_DIR: &str = "tif";
+const PARQUET_DIR: &str = "parquet";
+/// Options that shape the Parquet files of a dataset (their schema, content and names), which
+/// are recorded in the manifest and reused with `--append`.
+const DATASET_OPTIONS: &[&str] = &[
+    "bands",
+    "band-names",
+    "band-units",
+    "band-type",
+    "band-scale",
+    "band-offset",
+    "dataset",
+    "dataset-version",
+    "nodata-value",
+    "nodata",
+    "valid-range",
+    "out-of-range",
+    "nodata-sentinel",
+    "allow-clamped-nodata",
+    "target-grid",
+    "grid-origin",
+    "resampling",
+    "downsample",
+    "downsample-method",
+    "coords",
+    "target-crs",
+    "compression",
+    "compression-per-column",
+    "row-group-size",
+    "page-size",
+    "encoding",
+    "parquet-version",
+    "emit-indices",
+    "name-include-bbox",
+    "clip",
+    "thin",
+    "sort",
+    "split-degrees",
+    "mask",
+    "derive",
+    "hillshade",
+    "hillshade-azimuth",
+    "hillshade-altitude",
+    "cell-area",
+    "country-boundaries",
+    "country-field",
+    "join-raster",
+    "h3-resolution",
+    "s2-level",
+    "map-tile-zoom",
+    "map-tile-format",
+    "geoparquet",
+];
+
+/// Download ALOS World 3D 30 meter DEM GeoTIFFs and convert them to Parquet
+#[derive(Parser)]
+#[command(version)]
+pub struct Opt {
+    /// Output dir for GeoTIFF files
+    #[arg(
+        id = "tif",
+        short = 't',
+        long = "tif",
+        default_value = TIF_DIR,
+        global = true,
+        env = "AW3D30_TIF"
+    )]
+    pub tif_dir: PathBuf,
+
+    /// Output dir for Parquet files
+    #[arg(
+        id = "parquet",
+        short = 'p',
+        long = "parquet",
+        default_value = PARQUET_DIR,
+        global = true,
+        env = "AW3D30_PARQUET"
+    )]
+    pub parquet_dir: PathBuf,
+
+    /// Dir to write the Parquet files to before they are verified and moved to the Parquet dir,
+    /// so that consumers of the Parquet dir never observe incomplete files
+    #[arg(long, global = true, env = "AW3D30_STAGING_DIR")]
+    pub staging_dir: Option<PathBuf>,
+
+    /// Upload the Parquet files, the manifest, the spatial index and the summary metadata to S3
+    /// (`s3://bucket/prefix/`), Google Cloud Storage (`gs://bucket/prefix/`) or Azure Blob
+    /// Storage (`az://container/prefix/`), with the credentials of the environment. The files
+    /// are still written to the Parquet dir first
+    #[arg(
+        long,
+        conflicts_with_all = ["merge", "mosaic"],
+        global = true,
+        env = "AW3D30_OUTPUT_URL"
+    )]
+    pub output_url: Option<OutputUrl>,
+
+    /// Commit the Parquet files to the Delta Lake table in this dir, of which the Parquet dir is
+    /// a subdir. Workers with their own Parquet dir in the table (e.g. with `--shard-index`)
+    /// commit concurrently, a commit is retried on the next version of the table when another
+    /// worker committed first
+    #[arg(
+        long,
+        conflicts_with_all = ["merge", "mosaic", "output_url"],
+        global = true,
+        env = "AW3D30_DELTA_TABLE"
+    )]
+    pub delta_table: Option<PathBuf>,
+
+    /// Append a JSON line per stage of every tile (download, conversion and upload) with its
+    /// start and end, the size of its file and its outcome to `_events.jsonl` in the Parquet
+    /// dir, as a record of how every file was produced
+    #[arg(long, conflicts_with_all = ["merge", "mosaic"], global = true)]
+    pub event_log: bool,
+
+    /// Output dir for hillshaded color relief previews of the converted tiles (of the first
+    /// band), for visual QA
+    #[arg(long, global = true, env = "AW3D30_COLOR_RELIEF_DIR")]
+    pub color_relief_dir: Option<PathBuf>,
+
+    /// Image format of the color relief previews: `png` or `tif` (GeoTIFF)
+    #[arg(
+        long,
+        default_value = "png",
+        global = true,
+        env = "AW3D30_COLOR_RELIEF_FORMAT"
+    )]
+    pub color_relief_format: ReliefFormat,
+
+    /// Output dir for hillshade GeoTIFFs of the converted tiles (of the first band), lit with
+    /// the hillshade azimuth and altitude, for visualization pipelines
+    #[arg(long, global = true, env = "AW3D30_HILLSHADE_DIR")]
+    pub hillshade_dir: Option<PathBuf>,
+
+    /// Raster bands to convert, written as one column per band
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1",
+        global = true,
+        env = "AW3D30_BANDS"
+    )]
+    pub bands: Vec<isize>,
+
+    /// Column names for the converted raster bands
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "elevation",
+        global = true,
+        env = "AW3D30_BAND_NAMES"
+    )]
+    pub band_names: Vec<String>,
+
+    /// Units of the converted raster bands, stored in the column metadata
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "m",
+        global = true,
+        env = "AW3D30_BAND_UNITS"
+    )]
+    pub band_units: Vec<String>,
+
+    /// Type of the band columns: `int32` (the values of the GeoTIFFs), `auto` (the narrowest
+    /// integer columns that hold the values of the first GeoTIFF), `float` or `double`
+    #[arg(long, default_value = "int32", global = true, env = "AW3D30_BAND_TYPE")]
+    pub band_type: BandType,
+
+    /// Factor the band values are multiplied by, e.g. `3.28084` for feet (requires float or
+    /// double bands)
+    #[arg(long, default_value = "1", global = true, env = "AW3D30_BAND_SCALE")]
+    pub band_scale: f64,
+
+    /// Offset added to the band values after scaling, e.g. to shift the vertical datum (requires
+    /// float or double bands)
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        default_value = "0",
+        global = true,
+        env = "AW3D30_BAND_OFFSET"
+    )]
+    pub band_offset: f64,
+
+    /// Nodata value of the raster bands, overrides the value from the GeoTIFF metadata
+    #[arg(long, global = true, env = "AW3D30_NODATA_VALUE")]
+    pub nodata_value: Option<f64>,
+
+    /// What to do with nodata values (e.g. voids and oceans): `keep` them, `drop` the points
+    /// with a nodata value in any band, or write the bands as nullable columns with `null` values
+    #[arg(long, default_value = "keep", global = true, env = "AW3D30_NODATA")]
+    pub nodata: Nodata,
+
+    /// Range of valid values of the bands, `min..max` (inclusive), e.g. `-500..9000`, to catch
+    /// values that were decoded wrongly
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        global = true,
+        env = "AW3D30_VALID_RANGE"
+    )]
+    pub valid_range: Option<ValidRange>,
+
+    /// What to do with values outside the valid range (other than nodata): `flag` them in the
+    /// log and the summary, or replace them with the `nodata` value of the band
+    #[arg(
+        long,
+        default_value = "nodata",
+        global = true,
+        env = "AW3D30_OUT_OF_RANGE"
+    )]
+    pub out_of_range: OutOfRange,
+
+    /// Replace the kept nodata values with this value (e.g. `-32768`), or `clamp` them to the
+    /// closest bound of the valid range
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        global = true,
+        env = "AW3D30_NODATA_SENTINEL"
+    )]
+    pub nodata_sentinel: Option<Sentinel>,
+
+    /// Resample the output onto a regular grid with this resolution (in degrees)
+    #[arg(long, global = true, env = "AW3D30_TARGET_GRID")]
+    pub target_grid: Option<f64>,
+
+    /// Origin (`lon,lat`) of the target grid
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_hyphen_values = true,
+        default_value = "0,0",
+        global = true,
+        env = "AW3D30_GRID_ORIGIN"
+    )]
+    pub grid_origin: Vec<f64>,
+
+    /// Resampling method for the target grid: `nearest` or `bilinear`
+    #[arg(
+        long,
+        default_value = "nearest",
+        global = true,
+        env = "AW3D30_RESAMPLING"
+    )]
+    pub resampling: Resampling,
+
+    /// Downsample the rasters by this factor before the conversion, e.g. `3` for ~90 m or `30`
+    /// for ~1 km pixels
+    #[arg(
+        long,
+        conflicts_with = "target_grid",
+        global = true,
+        env = "AW3D30_DOWNSAMPLE"
+    )]
+    pub downsample: Option<usize>,
+
+    /// Aggregation of the pixels of a downsampled pixel: `nearest`, `mean` or `max` (nodata
+    /// pixels are ignored)
+    #[arg(
+        long,
+        default_value = "mean",
+        global = true,
+        env = "AW3D30_DOWNSAMPLE_METHOD"
+    )]
+    pub downsample_method: Aggregation,
+
+    /// Layout of the coordinates: `flat` `lat` and `lon` columns, a `struct` `location`
+    /// column with `lat` and `lon` fields, or `none` (requires `--emit-indices`)
+    #[arg(long, default_value = "flat", global = true, env = "AW3D30_COORDS")]
+    pub coords: Coords,
+
+    /// Coordinate reference system to transform the coordinates to, e.g. `EPSG:3857` or a PROJ
+    /// string, written as `x` and `y` columns instead of `lat` and `lon`
+    #[arg(long, global = true, env = "AW3D30_TARGET_CRS")]
+    pub target_crs: Option<String>,
+
+    /// Compression codec of the Parquet files: none, snappy, gzip, zstd, lz4 or brotli, with an
+    /// optional level, e.g. `zstd:7`
+    #[arg(
+        long,
+        default_value = "snappy",
+        value_parser = parse_compression,
+        global = true,
+        env = "AW3D30_COMPRESSION"
+    )]
+    pub compression: Compression,
+
+    /// Compression codecs of columns instead of `--compression`, `column=codec`, e.g.
+    /// `lat=zstd:7,lon=zstd:7`
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_column_compression,
+        global = true,
+        env = "AW3D30_COMPRESSION_PER_COLUMN"
+    )]
+    pub compression_per_column: Vec<(String, Compression)>,
+
+    /// Maximum number of rows of the row groups of the Parquet files, a row group per tile by
+    /// default
+    #[arg(long, global = true, env = "AW3D30_ROW_GROUP_SIZE")]
+    pub row_group_size: Option<usize>,
+
+    /// Target size of the data pages of the Parquet files in bytes
+    #[arg(long, global = true, env = "AW3D30_PAGE_SIZE")]
+    pub page_size: Option<usize>,
+
+    /// Memory a conversion of a tile may use, e.g. `256MiB`, by converting the tile in chunks of
+    /// rows with a row group per chunk (can not be combined with a target grid, sort order or
+    /// GeoParquet)
+    #[arg(long, value_parser = parse_memory, global = true, env = "AW3D30_MAX_MEMORY")]
+    pub max_memory: Option<u64>,
+
+    /// Encodings of columns instead of dictionary encoding, `column=encoding` with `plain`,
+    /// `delta_binary_packed` (for integer columns) or `byte_stream_split` (for float columns),
+    /// e.g. `elevation=delta_binary_packed,lat=byte_stream_split`
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_encoding,
+        global = true,
+        env = "AW3D30_ENCODING"
+    )]
+    pub encoding: Vec<(String, Encoding)>,
+
+    /// Version of the Parquet format, 2 for data pages v2 and the newer encodings
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = parse_writer_version,
+        global = true,
+        env = "AW3D30_PARQUET_VERSION"
+    )]
+    pub parquet_version: WriterVersion,
+
+    /// Write the `row` and `col` indices of the pixels in the raster as UINT16 columns
+    #[arg(long, global = true)]
+    pub emit_indices: bool,
+
+    /// Allow Parquet files with another schema or settings in the Parquet dir, which are
+    /// refused by default so that a dataset does not mix incompatible files
+    #[arg(long, global = true)]
+    pub allow_mixed_schema: bool,
+
+    /// Append the tiles to the dataset in the Parquet dir, with the options it was created with
+    /// (as recorded in its manifest) for the options that are not given, which must match
+    #[arg(
+        long,
+        conflicts_with_all = ["allow_mixed_schema", "merge", "mosaic"],
+        global = true
+    )]
+    pub append: bool,
+
+    /// Embed the bounding box of the tile in the Parquet file names (e.g.
+    /// `ALPSMLC30_N052E004_DSM_52.0_53.0_4.0_5.0.parquet`, as `min_lat_max_lat_min_lon_max_lon`)
+    #[arg(long, global = true)]
+    pub name_include_bbox: bool,
+
+    /// Remove the downloaded GeoTIFF files (and mask files) once their Parquet files are written
+    #[arg(long, global = true)]
+    pub remove_tif: bool,
+
+    /// Accept `--nodata-sentinel clamp`, which writes the bounds of the valid range for the
+    /// nodata pixels: values that can not be told apart from real elevations without the column
+    /// metadata
+    #[arg(long, global = true)]
+    pub allow_clamped_nodata: bool,
+
+    /// Stream the converted tiles to an Arrow Flight endpoint (`flight://host:port`) with
+    /// `DoPut` requests, or to a Kafka topic (`kafka://host:port/topic`), instead of writing
+    /// Parquet files
+    // The conflicts are declared here, as the subcommands have no `--output` sink.
+    #[arg(
+        long,
+        conflicts_with_all = ["output_url", "delta_table", "event_log", "append", "merge", "mosaic"],
+        env = "AW3D30_OUTPUT"
+    )]
+    pub output: Option<Sink>,
+
+    /// Output format: `parquet` files, or a `gpkg` GeoPackage with a point layer in the Parquet
+    /// dir
+    #[arg(long, default_value = "parquet", global = true, env = "AW3D30_FORMAT")]
+    pub format: Format,
+
+    /// Write all tiles to this single Parquet file, with a row group per tile, instead of a
+    /// Parquet file per tile
+    #[arg(long, global = true, env = "AW3D30_MERGE")]
+    pub merge: Option<PathBuf>,
+
+    /// Convert a VRT mosaic of the tiles, cropped to the bounding box of a custom set and the
+    /// boundary polygons, to this single Parquet file in one pass, instead of a Parquet file
+    /// per tile
+    #[arg(long, conflicts_with = "merge", global = true, env = "AW3D30_MOSAIC")]
+    pub mosaic: Option<PathBuf>,
+
+    /// Vector file (e.g. GeoJSON or Shapefile) with polygons in longitude and latitude, only the
+    /// tiles of the set that intersect the polygons are selected
+    #[arg(long, global = true, env = "AW3D30_BOUNDARY")]
+    pub boundary: Option<PathBuf>,
+
+    /// Only write the points inside the boundary polygons
+    #[arg(long, requires = "boundary", global = true)]
+    pub clip: bool,
+
+    /// Only write every nth point in both axes (after clipping), for sparse point sets instead
+    /// of resampled grids
+    #[arg(long, global = true, env = "AW3D30_THIN")]
+    pub thin: Option<u32>,
+
+    /// Order of the points of a tile: along a `hilbert` or `zorder` curve, or by `latlon`,
+    /// instead of row by row
+    #[arg(long, global = true, env = "AW3D30_SORT")]
+    pub sort: Option<SortOrder>,
+
+    /// Split the tiles into sub-tiles of this size in degrees (e.g. `0.25`), which are written
+    /// in row groups of their own, for queries of small areas
+    #[arg(long, global = true, env = "AW3D30_SPLIT_DEGREES")]
+    pub split_degrees: Option<f64>,
+
+    /// Download the `_MSK` mask file of every tile and write its quality flags (e.g. void
+    /// filling, clouds and sea) in a `mask` column
+    #[arg(long, conflicts_with = "mosaic", global = true)]
+    pub mask: bool,
+
+    /// Terrain attributes to derive from the first band and write as columns: `slope` and
+    /// `aspect` in degrees
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with = "target_grid",
+        global = true,
+        env = "AW3D30_DERIVE"
+    )]
+    pub derive: Vec<Derived>,
+
+    /// Write the hillshade of the first band in a `hillshade` column, from 0 (in shadow) to 255
+    /// (facing the light)
+    #[arg(long, conflicts_with = "target_grid", global = true)]
+    pub hillshade: bool,
+
+    /// Compass direction of the light of the hillshade in degrees, clockwise from north
+    #[arg(
+        long,
+        default_value = "315",
+        global = true,
+        env = "AW3D30_HILLSHADE_AZIMUTH"
+    )]
+    pub hillshade_azimuth: f64,
+
+    /// Angle of the light of the hillshade above the horizon in degrees
+    #[arg(
+        long,
+        default_value = "45",
+        global = true,
+        env = "AW3D30_HILLSHADE_ALTITUDE"
+    )]
+    pub hillshade_altitude: f64,
+
+    /// Write the geodesic area of the pixels (or grid cells) in square meters in a `cell_area`
+    /// column, for area-weighted statistics
+    #[arg(long, global = true)]
+    pub cell_area: bool,
+
+    /// Vector file with country boundaries in longitude and latitude (e.g. Natural Earth admin 0
+    /// countries), to write the country code of the points in a `country` column
+    #[arg(long, global = true, env = "AW3D30_COUNTRY_BOUNDARIES")]
+    pub country_boundaries: Option<PathBuf>,
+
+    /// Attribute of the country boundaries with the country code
+    #[arg(
+        long,
+        default_value = "ISO_A2_EH",
+        global = true,
+        env = "AW3D30_COUNTRY_FIELD"
+    )]
+    pub country_field: String,
+
+    /// Rasters to join to the points, `name=path` with a raster dataset (e.g. a VRT) or a dir of
+    /// GeoTIFF files co-registered with the tiles (e.g. ESA WorldCover), of which the first band
+    /// is written in a nullable `name` column
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_join_raster,
+        global = true,
+        env = "AW3D30_JOIN_RASTER"
+    )]
+    pub join_raster: Vec<(String, PathBuf)>,
+
+    /// Write the index of the H3 cell of the points at this resolution (0 to 15) in an `h3`
+    /// column
+    #[arg(long, global = true, env = "AW3D30_H3_RESOLUTION")]
+    pub h3_resolution: Option<u8>,
+
+    /// Write the id of the S2 cell of the points at this level (0 to 30) in an `s2_cell` column
+    #[arg(long, global = true, env = "AW3D30_S2_LEVEL")]
+    pub s2_level: Option<u8>,
+
+    /// Write the id of the web mercator map tile of the points at this zoom level (0 to 30) in a
+    /// `map_tile` column
+    #[arg(long, global = true, env = "AW3D30_MAP_TILE_ZOOM")]
+    pub map_tile_zoom: Option<u8>,
+
+    /// Format of the map tile ids: `xyz` (`z/x/y`) or `quadkey`
+    #[arg(
+        long,
+        default_value = "xyz",
+        global = true,
+        env = "AW3D30_MAP_TILE_FORMAT"
+    )]
+    pub map_tile_format: TileFormat,
+
+    /// Write GeoParquet: a WKB `geometry` point column and the `geo` file metadata
+    #[arg(long, global = true)]
+    pub geoparquet: bool,
+
+    /// What to do with existing GeoTIFF and Parquet files: `skip` them, `verify` them (size of
+    /// GeoTIFF files, footer of Parquet files) and replace them when invalid, `overwrite` them,
+    /// or `error`
+    #[arg(
+        long,
+        default_value = "verify",
+        global = true,
+        env = "AW3D30_IF_EXISTS"
+    )]
+    pub if_exists: IfExists,
+
+    /// DEM to download and convert: `aw3d30`, `cop30` for the Copernicus GLO-30 DEM, whose
+    /// float elevations are rounded to whole meters, or `srtmgl1` for SRTM GL1 (between 60
+    /// degrees south and north)
+    #[arg(long, default_value = "aw3d30", global = true, env = "AW3D30_DATASET")]
+    pub dataset: Dataset,
+
+    /// Release of the dataset to download from the OpenTopography S3 bucket, the name of its dir
+    /// (e.g. `AW3D30_global`, the default for AW3D30), or `latest` for the release that was
+    /// modified last
+    #[arg(
+        id = "dataset_version",
+        long = "dataset-version",
+        conflicts_with_all = ["index_url", "source"],
+        global = true,
+        env = "AW3D30_DATASET_VERSION"
+    )]
+    pub release: Option<String>,
+
+    /// URL of an HTTP directory index to download the GeoTIFF files from, instead of the
+    /// OpenTopography S3 bucket
+    #[arg(long, global = true, env = "AW3D30_INDEX_URL")]
+    pub index_url: Option<Url>,
+
+    /// Server to download the GeoTIFF files from: the `opentopography` S3 bucket, an HTTPS
+    /// mirror with the GeoTIFF files in one dir (`https://host/path/`), or a server with the
+    /// layout of the JAXA distribution, with zip archives per tile in dirs per 5 degrees
+    /// (`jaxa:https://host/path/`)
+    #[arg(
+        id = "source",
+        long = "source",
+        default_value = "opentopography",
+        conflicts_with = "index_url",
+        global = true,
+        env = "AW3D30_SOURCE"
+    )]
+    pub server: Server,
+
+    /// S3 endpoints with mirrors of the OpenTopography bucket, e.g. `mirror.example.com:9000`,
+    /// instead of the OpenTopography storage server (include it to use it as well)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = ["index_url", "source"],
+        global = true,
+        env = "AW3D30_ENDPOINT"
+    )]
+    pub endpoint: Vec<String>,
+
+    /// Order in which the endpoints are tried for every object: `failover` to the next
+    /// endpoint when one fails or is down, or `round-robin` to also spread the load
+    #[arg(
+        long,
+        default_value = "failover",
+        global = true,
+        env = "AW3D30_ENDPOINT_STRATEGY"
+    )]
+    pub endpoint_strategy: EndpointStrategy,
+
+    /// Download bandwidth per time window of the day in local time, e.g.
+    /// `22:00-06:00=full,06:00-22:00=10MB/s`, unlimited outside the windows
+    #[arg(long, global = true, env = "AW3D30_SCHEDULE_BANDWIDTH")]
+    pub schedule_bandwidth: Option<Schedule>,
+
+    /// Maximum number of attempts of S3 operations that fail with transient errors, after which
+    /// the tile (or the listing) fails
+    #[arg(long, default_value = "5", global = true, env = "AW3D30_MAX_ATTEMPTS")]
+    pub max_attempts: u32,
+
+    /// Delay before the first retry of a failed S3 operation in seconds, doubled for every
+    /// next retry (with random jitter)
+    #[arg(long, default_value = "0.5", global = true, env = "AW3D30_RETRY_DELAY")]
+    pub retry_delay: f64,
+
+    /// Number of consecutive transient failures on an endpoint after which all downloads pause,
+    /// 0 disables the circuit breaker
+    #[arg(
+        long,
+        default_value = "10",
+        global = true,
+        env = "AW3D30_BREAKER_THRESHOLD"
+    )]
+    pub breaker_threshold: u32,
+
+    /// Pause of the downloads in seconds when an endpoint is down, doubled while it stays down
+    #[arg(
+        long,
+        default_value = "30",
+        global = true,
+        env = "AW3D30_BREAKER_COOLDOWN"
+    )]
+    pub breaker_cooldown: f64,
+
+    /// Maximum number of runs that attempt a tile that keeps failing, after which it is no
+    /// longer retried. The attempts are recorded in the manifest
+    #[arg(
+        long,
+        default_value = "10",
+        global = true,
+        env = "AW3D30_TILE_MAX_ATTEMPTS"
+    )]
+    pub tile_max_attempts: u32,
+
+    /// Delay before a failed tile is attempted again by a later run in seconds, doubled for
+    /// every next failed attempt (up to a day)
+    #[arg(
+        long,
+        default_value = "60",
+        global = true,
+        env = "AW3D30_TILE_RETRY_DELAY"
+    )]
+    pub tile_retry_delay: f64,
+
+    /// Forget the failed attempts of the tiles recorded in the manifest, so that all failed
+    /// tiles are attempted again
+    #[arg(long, global = true)]
+    pub reset_retries: bool,
+
+    /// Maximum number of keys per page of the S3 listing (at most 1000)
+    #[arg(long, global = true, env = "AW3D30_LIST_PAGE_SIZE")]
+    pub list_page_size: Option<usize>,
+
+    /// Stop listing the S3 bucket after this number of keys, e.g. to try a run on a few tiles
+    #[arg(long, global = true, env = "AW3D30_MAX_KEYS")]
+    pub max_keys: Option<usize>,
+
+    /// Fraction of the chunks of S3 downloads that fail with a transient error, to check the
+    /// retries and the resumption of partial downloads
+    #[cfg(feature = "chaos")]
+    #[arg(
+        long,
+        default_value = "0",
+        global = true,
+        env = "AW3D30_CHAOS_DOWNLOAD_RATE"
+    )]
+    pub chaos_download_rate: f64,
+
+    /// Fraction of the conversions that fail before the Parquet footer is written, to check
+    /// that the next run replaces the invalid files
+    #[cfg(feature = "chaos")]
+    #[arg(
+        long,
+        default_value = "0",
+        global = true,
+        env = "AW3D30_CHAOS_CONVERT_RATE"
+    )]
+    pub chaos_convert_rate: f64,
+
+    /// Index of the shard of the tiles to process (starting at 0)
+    #[arg(long, default_value = "0", global = true, env = "AW3D30_SHARD_INDEX")]
+    pub shard_index: usize,
+
+    /// Number of shards to partition the tiles in, for parallel workers
+    #[arg(long, default_value = "1", global = true, env = "AW3D30_SHARD_COUNT")]
+    pub shard_count: usize,
+
+    /// Show progress bars of the tiles, downloads and conversions instead of logs, which are
+    /// limited to warnings and errors
+    #[arg(long, conflicts_with = "quiet", global = true)]
+    pub progress: bool,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// List and filter the tiles, then report the downloads and conversions of a run without
+    /// running them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Only warn when the disk space check finds that the downloads and Parquet files don't fit
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Append the metrics of the run (durations, throughput and settings, without paths) as a
+    /// JSON line to this local file
+    #[arg(long, global = true, env = "AW3D30_METRICS_FILE")]
+    pub metrics_file: Option<PathBuf>,
+
+    /// JSON file with options, e.g. `{"compression": "zstd", "geoparquet": true}`, for the
+    /// options that are not given on the command line or in `AW3D30_*` environment variables
+    #[arg(long, env = "AW3D30_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Set(Set),
+    /// Rewrite the key-value metadata in the footers of the Parquet files, without re-encoding
+    /// the column data, and update their checksums in the manifest
+    AmendMetadata {
+        /// Key-value metadata to add or replace, as `key=value`
+        #[arg(long = "set", value_parser = parse_key_value)]
+        set: Vec<(String, String)>,
+
+        /// Keys of the key-value metadata to remove
+        #[arg(long)]
+        remove: Vec<String>,
+    },
+    /// Verify the Parquet files against the manifest (re-hashes all files), exits with an error
+    /// when they do not match
+    #[command(alias = "verify")]
+    VerifyManifest {
+        /// Expected manifest to verify against, instead of the manifest in the Parquet dir,
+        /// e.g. of a reference build
+        #[arg(long)]
+        expect: Option<PathBuf>,
+    },
+    /// Compare the manifest with the current listing of the source files of a set (keys, sizes
+    /// and entity tags), and print the tiles that were added, removed or changed, without
+    /// downloading them
+    RemoteDiff {
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+
+        #[command(subcommand)]
+        set: Set,
+    },
+    /// Sample the bands at the points of a Parquet file with `lat` and `lon` columns, only
+    /// downloading the tiles that contain the points
+    SamplePoints {
+        /// Parquet file with the points
+        #[arg(long)]
+        points: PathBuf,
+
+        /// Output Parquet file with the sampled points
+        #[arg(long, default_value = "samples.parquet")]
+        output: PathBuf,
+    },
+    /// Look up the bands at points read from stdin, a `lat,lon` line per point, and print a
+    /// `lat,lon,value,...` line per point, downloading the tiles of the points on their first
+    /// lookup
+    Lookup {
+        /// Maximum memory of the decoded tiles that are kept for later lookups, e.g. `1GiB`
+        #[arg(long, default_value = "1GiB", value_parser = parse_memory)]
+        tile_cache: u64,
+    },
+    /// Register the Parquet output dir as a table in a data catalog
+    #[command(subcommand)]
+    Register(Catalog),
+    /// Convert the GeoTIFF files in a local dir instead of downloading them
+    Convert {
+        /// Dir with the GeoTIFF files
+        #[arg(long)]
+        input: PathBuf,
+
+        /// Include the GeoTIFF files in subdirs of the input dir
+        #[arg(long)]
+        recursive: bool,
+    },
+    /// Print the files of a set with their size and the total download size, without
+    /// downloading them
+    List {
+        /// Print the files as JSON
+        #[arg(long)]
+        json: bool,
+
+        #[command(subcommand)]
+        set: Set,
+    },
+    /// Download the GeoTIFF files of a set without converting them
+    Download {
+        #[command(subcommand)]
+        set: Set,
+    },
+    /// Write the footprints of the tiles of a set with their status in the Parquet output dir
+    /// (selected, completed or failed) as GeoJSON
+    Coverage {
+        /// Output GeoJSON file
+        #[arg(long, default_value = "coverage.geojson")]
+        output: PathBuf,
+
+        #[command(subcommand)]
+        set: Set,
+    },
+    /// Compute statistics of the first band per polygon of a vector file (e.g. GeoJSON), only
+    /// downloading the tiles that overlap the polygons
+    ZonalStats {
+        /// Vector file with the polygons, in longitude and latitude
+        #[arg(long)]
+        zones: PathBuf,
+
+        /// Percentiles to compute
+        #[arg(long, value_delimiter = ',', default_value = "5,25,50,75,95")]
+        percentiles: Vec<u8>,
+
+        /// Output Parquet file with the statistics per zone
+        #[arg(long, default_value = "zonal_stats.parquet")]
+        output: PathBuf,
+    },
+    /// Compute the per-pixel elevation difference against a reference DEM over the same region
+    Diff {
+        /// Reference DEM in longitude and latitude: a raster (e.g. a VRT) or a dir of GeoTIFF
+        /// files
+        #[arg(long)]
+        reference: PathBuf,
+
+        /// Output dir with a Parquet file with the differences per tile
+        #[arg(long, default_value = "diff")]
+        output: PathBuf,
+    },
+    /// Compute the hypsometric curve (the area per elevation interval) of the first band in a
+    /// region of the Parquet output dir, weighted by the geodesic area of the pixels
+    Hypsometry {
+        /// Region as `min_lon,min_lat,max_lon,max_lat`, or a vector file with polygons in
+        /// longitude and latitude
+        #[arg(long)]
+        region: String,
+
+        /// Width of the elevation intervals, in the unit of the band
+        #[arg(long, default_value = "10")]
+        interval: f64,
+
+        /// Output CSV file with the curve, or Parquet file if it ends with `.parquet`
+        #[arg(long, default_value = "hypsometry.csv")]
+        output: PathBuf,
+
+        /// Output SVG file with a plot of the curve
+        #[arg(long)]
+        plot: Option<PathBuf>,
+    },
+    /// Print the schema, row groups, encodings, compression, statistics and metadata keys of
+    /// Parquet files, as written in their footer
+    Inspect {
+        /// Parquet files to inspect, all Parquet files in the Parquet dir by default
+        files: Vec<PathBuf>,
+
+        /// Print the reports as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the shell completion script for `shell`: bash, fish, zsh, powershell or elvish
+    Completions {
+        #[arg(value_enum, ignore_case = true)]
+        shell: Shell,
+    },
+    /// Print the man page, in roff
+    Manpage,
+}
+
+#[derive(Subcommand)]
+pub enum Catalog {
+    /// Create or update an AWS Glue table, with the schema of the Parquet output dir
+    Glue {
+        /// Glue database of the table
+        #[arg(long)]
+        database: String,
+
+        /// Name of the table
+        #[arg(long)]
+        table: String,
+
+        /// Location of the uploaded Parquet output dir, e.g. `s3://bucket/prefix/`
+        #[arg(long)]
+        location: String,
+    },
+}
+
+impl Command {
+    /// Returns the name of the subcommand, `set` for the sets of tiles.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set(_) => "set",
+            Command::AmendMetadata { .. } => "amend-metadata",
+            Command::VerifyManifest { .. } => "verify-manifest",
+            Command::RemoteDiff { .. } => "remote-diff",
+            Command::SamplePoints { .. } => "sample-points",
+            Command::Lookup { .. } => "lookup",
+            Command::Register(_) => "register",
+            Command::Convert { .. } => "convert",
+            Command::List { .. } => "list",
+            Command::Download { .. } => "download",
+            Command::Coverage { .. } => "coverage",
+            Command::ZonalStats { .. } => "zonal-stats",
+            Command::Diff { .. } => "diff",
+            Command::Hypsometry { .. } => "hypsometry",
+            Command::Inspect { .. } => "inspect",
+            Command::Completions { .. } => "completions",
+            Command::Manpage => "manpage",
+        }
+    }
+}
+
+/// Parses a `key=value` pair.
+fn parse_key_value(s: &str) -> Result<(String, String), &'static str> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or("expected `key=value`")
+}
+
+/// Parses a memory limit, a positive number of bytes with a unit: `B`, `KB`, `MB`, `GB`, `KiB`,
+/// `MiB` or `GiB`, e.g. `256MiB` or `1.5GB`.
+fn parse_memory(s: &str) -> Result<u64, &'static str> {
+    parse_bytes(s).ok_or("expected a size like `256MiB`")
+}
+
+/// Format of the converted tiles.
+#[derive(Copy, Clone)]
+pub enum Format {
+    Parquet,
+    GeoPackage,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(Self::Parquet),
+            "gpkg" => Ok(Self::GeoPackage),
+            _ => Err("expected one of: parquet, gpkg"),
+        }
+    }
+}
+
+/// Prints the man page, with the help of the options and subcommands as its description.
+pub fn print_manpage() -> Result<(), Box<dyn Error>> {
+    let mut help = Vec::new();
+    Opt::command().write_long_help(&mut help)?;
+    let help = String::from_utf8(help)?;
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n.SH NAME\n{} \\- {}\n.SH DESCRIPTION\n.nf\n",
+        env!("CARGO_PKG_NAME").to_uppercase(),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_DESCRIPTION"),
+    );
+    // Backslashes and lines that start like a request are escaped.
+    for line in help.lines() {
+        let line = line.replace('\\', "\\e");
+        if line.starts_with('.') || line.starts_with('\'') {
+            page.push_str("\\&");
+        }
+        page.push_str(&line);
+        page.push('\n');
+    }
+    page.push_str(".fi\n");
+    io::stdout().write_all(page.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the id of the argument of the option `name`, e.g. `row_group_size` for
+/// `row-group-size`.
+fn arg_id(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Returns whether the option `name` is given on the command line or in its environment
+/// variable. Unknown options are not given.
+fn given(matches: &ArgMatches, name: &str) -> bool {
+    let id = arg_id(name);
+    matches.try_contains_id(&id).unwrap_or(false)
+        && matches!(
+            matches.value_source(&id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+}
+
+/// Returns the dataset options that are given on the command line or in the environment, with
+/// their values. Flags have no values.
+fn dataset_options(matches: &ArgMatches) -> BTreeMap<String, Vec<String>> {
+    DATASET_OPTIONS
+        .iter()
+        .filter(|&&name| given(matches, name))
+        .map(|&name| {
+            let values = match FLAG_OPTIONS.contains(&name) {
+                true => Vec::new(),
+                false => matches
+                    .get_raw(&arg_id(name))
+                    .into_iter()
+                    .flatten()
+                    .map(|value| value.to_string_lossy().into_owned())
+                    .collect(),
+            };
+            (name.to_string(), values)
+        })
+        .collect()
+}
+
+/// Prefix of the environment variables with options, e.g. `AW3D30_ROW_GROUP_SIZE` for
+/// `--row-group-size`. The options with values read them with the `env` of their argument.
+const ENV_PREFIX: &str = "AW3D30_";
+
+/// The flags (options without a value), which are read from the environment by [`env_flags`]
+/// instead of clap, so that only `true`, `false`, `1` and `0` are accepted.
+const FLAG_OPTIONS: &[&str] = &[
+    "event-log",
+    "emit-indices",
+    "allow-mixed-schema",
+    "append",
+    "name-include-bbox",
+    "remove-tif",
+    "allow-clamped-nodata",
+    "clip",
+    "mask",
+    "hillshade",
+    "cell-area",
+    "geoparquet",
+    "reset-retries",
+    "progress",
+    "quiet",
+    "dry-run",
+    "force",
+];
+
+/// Returns the name of the environment variable of the option `name`.
+fn env_name(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.to_uppercase().replace('-', "_"))
+}
+
+/// Returns the flags that are set (`true` or `1`) or unset (`false` or `0`) in their `AW3D30_*`
+/// environment variable, as returned by `var`. Other environment variables are ignored.
+fn env_flags(
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Result<BTreeMap<String, bool>, Box<dyn Error>> {
+    let mut flags = BTreeMap::new();
+    for &name in FLAG_OPTIONS {
+        let key = env_name(name);
+        let set = match var(&key) {
+            None => continue,
+            Some(value) => match value.to_str() {
+                Some("true" | "1") => true,
+                Some("false" | "0") => false,
+                _ => {
+                    return Err(format!(
+                        "expected `true`, `false`, `1` or `0` for `{}`, got `{}`",
+                        key,
+                        value.to_string_lossy()
+                    )
+                    .into())
+                }
+            },
+        };
+        flags.insert(name.to_string(), set);
+    }
+    Ok(flags)
+}
+
+/// Returns the options of the JSON config file at `path`, an object with the names of the
+/// options as keys. Flags are `true` or `false`, and options with multiple values are arrays.
+fn config_options(path: &Path) -> Result<BTreeMap<String, Vec<String>>, Box<dyn Error>> {
+    let config = serde_json::from_slice::<BTreeMap<String, serde_json::Value>>(
+        &std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+    )
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let value = |name: &str, value: &serde_json::Value| match value {
+        serde_json::Value::String(value) => Ok(value.clone()),
+        serde_json::Value::Number(value) => Ok(value.to_string()),
+        _ => Err(format!(
+            "{}: expected a string or number for `{}`",
+            path.display(),
+            name
+        )),
+    };
+    let mut options = BTreeMap::new();
+    for (name, values) in &config {
+        match values {
+            serde_json::Value::Bool(false) => {}
+            serde_json::Value::Bool(true) => {
+                options.insert(name.clone(), Vec::new());
+            }
+            serde_json::Value::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|v| value(name, v))
+                    .collect::<Result<_, _>>()?;
+                options.insert(name.clone(), values);
+            }
+            values => {
+                options.insert(name.clone(), vec![value(name, values)?]);
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Adds the `options` that are not given in `matches` to the command line `args`, before the
+/// subcommand, and parses the command line again.
+fn merge_options(
+    args: Vec<OsString>,
+    matches: &ArgMatches,
+    options: &BTreeMap<String, Vec<String>>,
+) -> (Vec<OsString>, ArgMatches) {
+    let mut merged = vec![args[0].clone()];
+    for (name, values) in options {
+        if !given(matches, name) {
+            merged.push(match values.is_empty() {
+                true => format!("--{}", name).into(),
+                false => format!("--{}={}", name, values.join(",")).into(),
+            });
+        }
+    }
+    merged.extend(args.into_iter().skip(1));
+    let matches = Opt::command().get_matches_from(&merged);
+    (merged, matches)
+}
+
+/// Parses the command line, and returns the options with the dataset options that were given.
+///
+/// Options that are not given on the command line are taken from the `AW3D30_*` environment
+/// variables, and else from the `--config` file.
+///
+/// With `--append`, the dataset options recorded in the manifest of the Parquet dir are added
+/// to the command line when they are not given, so that the appended files get the schema and
+/// names of the dataset. Returns an error when the dataset options differ from the recorded
+/// options.
+pub fn parse_args() -> Result<(Opt, BTreeMap<String, Vec<String>>), Box<dyn Error>> {
+    let args = env::args_os().collect::<Vec<_>>();
+    let matches = Opt::command().get_matches_from(&args);
+    // The options with values that are given in the environment are in the matches.
+    let flags = env_flags(|key| env::var_os(key))?;
+    let mut options = flags
+        .iter()
+        .filter(|(_, &set)| set)
+        .map(|(name, _)| (name.clone(), Vec::new()))
+        .collect::<BTreeMap<_, _>>();
+    if let Some(config) = Opt::from_arg_matches(&matches)?.config {
+        for (name, values) in config_options(&config)? {
+            if !flags.contains_key(&name) {
+                options.entry(name).or_insert(values);
+            }
+        }
+    }
+    let (args, matches) = merge_options(args, &matches, &options);
+    let opt = Opt::from_arg_matches(&matches)?;
+    if !opt.append {
+        return Ok((opt, dataset_options(&matches)));
+    }
+    let manifest = Manifest::load(&opt.parquet_dir)?;
+    if manifest.files.is_empty() {
+        return Err(format!(
+            "`{}` has no dataset to append to",
+            opt.parquet_dir.display()
+        )
+        .into());
+    }
+    let mut recorded = manifest.options.ok_or_else(|| {
+        format!(
+            "the manifest in `{}` does not record the options of the dataset",
+            opt.parquet_dir.display()
+        )
+    })?;
+    // Earlier manifests record the release of the dataset as `version`.
+    if let Some(values) = recorded.remove("version") {
+        recorded.insert("dataset-version".to_string(), values);
+    }
+    let (_, matches) = merge_options(args, &matches, &recorded);
+    let options = dataset_options(&matches);
+    if let Some(name) = DATASET_OPTIONS
+        .iter()
+        .find(|&&name| options.get(name) != recorded.get(name))
+    {
+        return Err(format!("`--{}` differs from the options of the dataset", name).into());
+    }
+    Ok((Opt::from_arg_matches(&matches)?, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_value() {
+        assert_eq!(
+            parse_key_value("attribution=JAXA AW3D30"),
+            Ok(("attribution".to_string(), "JAXA AW3D30".to_string()))
+        );
+        // Only the first `=` separates the key from the value.
+        assert_eq!(
+            parse_key_value("a=b=c"),
+            Ok(("a".to_string(), "b=c".to_string()))
+        );
+        assert_eq!(parse_key_value("a="), Ok(("a".to_string(), String::new())));
+        assert!(parse_key_value("attribution").is_err());
+    }
+
+    #[test]
+    fn flag_options_are_flags() {
+        for &name in FLAG_OPTIONS {
+            let flag = format!("--{}", name);
+            let mut args = vec!["aw3d30", flag.as_str()];
+            if name == "clip" {
+                args.extend(["--boundary", "boundary.geojson"]);
+            }
+            args.push("netherlands");
+            let matches = Opt::command()
+                .try_get_matches_from(args)
+                .unwrap_or_else(|e| panic!("{}: {}", flag, e));
+            assert!(given(&matches, name), "{}", flag);
+            // A flag does not take the set as its value.
+            assert_eq!(matches.subcommand_name(), Some("netherlands"), "{}", flag);
+        }
+    }
+
+    #[test]
+    fn env_flag_values() {
+        assert_eq!(
+            env_name("allow-clamped-nodata"),
+            "AW3D30_ALLOW_CLAMPED_NODATA"
+        );
+        let vars = BTreeMap::from([
+            ("AW3D30_CELL_AREA", "true"),
+            ("AW3D30_HILLSHADE", "0"),
+            ("AW3D30_COMPRESSION", "zstd"),
+            ("AW3D30_UNKNOWN", "yes"),
+        ]);
+        let var = |key: &str| vars.get(key).map(OsString::from);
+        let flags = env_flags(var).unwrap();
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags.get("cell-area"), Some(&true));
+        assert_eq!(flags.get("hillshade"), Some(&false));
+        assert_eq!(flags.get("geoparquet"), None);
+        assert!(env_flags(|key| (key == "AW3D30_GEOPARQUET").then(|| "yes".into())).is_err());
+    }
+}
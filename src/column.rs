@@ -0,0 +1,204 @@
+//! Mapping of raster bands to Parquet and Arrow columns.
+
+use arrow::{
+    array::{
+        ArrayRef, Float32Array, Float64Array, Int16Array, Int32Array, UInt16Array, UInt8Array,
+    },
+    datatypes::DataType,
+};
+use gdal::raster::{GdalType, RasterBand};
+use parquet::{
+    basic::{self, ConvertedType, Repetition},
+    errors::ParquetError,
+    schema::types::Type,
+};
+use std::{error::Error, ops::Range, sync::Arc};
+
+/// Type of a column with the values of a raster band (or pixel indices).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColumnType {
+    /// Unsigned 8-bit integers, an INT32 column annotated as UINT_8
+    UInt8,
+    /// Signed 16-bit integers, an INT32 column annotated as INT_16
+    Int16,
+    /// Unsigned 16-bit integers, an INT32 column annotated as UINT_16
+    UInt16,
+    /// 32-bit integers
+    Int32,
+    /// 32-bit floats
+    Float,
+    /// 64-bit floats
+    Double,
+}
+
+impl ColumnType {
+    /// Returns the narrowest column type that holds the values of the raster `band`.
+    ///
+    /// The values are read as 32-bit integers, so bands of other GDAL data types (e.g. Float32
+    /// or UInt32) are refused instead of truncating their values, unless the floats are
+    /// `rounded` to the nearest integer.
+    pub fn of_band(band: &RasterBand, rounded: bool) -> Result<Self, String> {
+        let data_type = band.band_type();
+        let float = data_type == f32::gdal_type() || data_type == f64::gdal_type();
+        if float && rounded {
+            Ok(ColumnType::Int32)
+        } else if data_type == u8::gdal_type() {
+            Ok(ColumnType::UInt8)
+        } else if data_type == i16::gdal_type() {
+            Ok(ColumnType::Int16)
+        } else if data_type == u16::gdal_type() {
+            Ok(ColumnType::UInt16)
+        } else if data_type == i32::gdal_type() {
+            Ok(ColumnType::Int32)
+        } else {
+            let name = if data_type == f32::gdal_type() {
+                "Float32"
+            } else if data_type == f64::gdal_type() {
+                "Float64"
+            } else if data_type == u32::gdal_type() {
+                "UInt32"
+            } else {
+                "complex"
+            };
+            Err(format!(
+                "{} raster bands can not be read as 32-bit integers without truncating the values",
+                name
+            ))
+        }
+    }
+
+    /// Returns true when the column holds all values of an `other` column.
+    pub fn holds(self, other: ColumnType) -> bool {
+        use ColumnType::*;
+        matches!(
+            (self, other),
+            (UInt8, UInt8)
+                | (Int16, UInt8 | Int16)
+                | (UInt16, UInt8 | UInt16)
+                | (Int32, UInt8 | Int16 | UInt16 | Int32)
+                // Floats hold integers up to 2^24 exactly.
+                | (Float, UInt8 | Int16 | UInt16 | Float)
+                | (Double, _)
+        )
+    }
+
+    /// Returns the range of the values of an integer column.
+    fn range(self) -> Option<(i32, i32)> {
+        match self {
+            ColumnType::UInt8 => Some((u8::MIN.into(), u8::MAX.into())),
+            ColumnType::Int16 => Some((i16::MIN.into(), i16::MAX.into())),
+            ColumnType::UInt16 => Some((u16::MIN.into(), u16::MAX.into())),
+            ColumnType::Int32 | ColumnType::Float | ColumnType::Double => None,
+        }
+    }
+
+    /// Returns an error when a value of the column `name` is outside the range of the column,
+    /// e.g. a nodata override that does not fit an unsigned column.
+    pub(crate) fn check(self, name: &str, values: &[i32]) -> Result<(), String> {
+        match self.range() {
+            Some((min, max)) => match values.iter().find(|&&value| value < min || value > max) {
+                Some(value) => Err(format!(
+                    "value {} of `{}` does not fit its {:?} column",
+                    value, name, self
+                )),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the size of a value in bytes, before compression.
+    pub fn size(self) -> u64 {
+        match self {
+            ColumnType::Double => 8,
+            _ => 4,
+        }
+    }
+
+    /// Returns the Parquet type of the column `name`.
+    pub fn parquet_type(self, name: &str, repetition: Repetition) -> Result<Type, ParquetError> {
+        let (physical_type, converted_type) = match self {
+            ColumnType::UInt8 => (basic::Type::INT32, ConvertedType::UINT_8),
+            ColumnType::Int16 => (basic::Type::INT32, ConvertedType::INT_16),
+            ColumnType::UInt16 => (basic::Type::INT32, ConvertedType::UINT_16),
+            ColumnType::Int32 => (basic::Type::INT32, ConvertedType::NONE),
+            ColumnType::Float => (basic::Type::FLOAT, ConvertedType::NONE),
+            ColumnType::Double => (basic::Type::DOUBLE, ConvertedType::NONE),
+        };
+        Type::primitive_type_builder(name, physical_type)
+            .with_converted_type(converted_type)
+            .with_repetition(repetition)
+            .build()
+    }
+
+    /// Returns the Arrow data type of the column.
+    pub fn arrow_type(self) -> DataType {
+        match self {
+            ColumnType::UInt8 => DataType::UInt8,
+            ColumnType::Int16 => DataType::Int16,
+            ColumnType::UInt16 => DataType::UInt16,
+            ColumnType::Int32 => DataType::Int32,
+            ColumnType::Float => DataType::Float32,
+            ColumnType::Double => DataType::Float64,
+        }
+    }
+
+    /// Returns an array of the column with the `values`, nulls for `None`. Float columns get
+    /// the values after `scaled`.
+    pub(crate) fn array(self, values: &[Option<i32>], scaled: impl Fn(f64) -> f64) -> ArrayRef {
+        let scaled = || {
+            values
+                .iter()
+                .map(|value| value.map(|value| scaled(f64::from(value))))
+        };
+        // The values are checked to fit integer columns when they are read.
+        let integers = || values.iter().copied();
+        match self {
+            ColumnType::UInt8 => Arc::new(UInt8Array::from(
+                integers()
+                    .map(|value| value.map(|value| value as u8))
+                    .collect::<Vec<_>>(),
+            )),
+            ColumnType::Int16 => Arc::new(Int16Array::from(
+                integers()
+                    .map(|value| value.map(|value| value as i16))
+                    .collect::<Vec<_>>(),
+            )),
+            ColumnType::UInt16 => Arc::new(UInt16Array::from(
+                integers()
+                    .map(|value| value.map(|value| value as u16))
+                    .collect::<Vec<_>>(),
+            )),
+            ColumnType::Int32 => Arc::new(Int32Array::from(values.to_vec())),
+            ColumnType::Float => Arc::new(Float32Array::from(
+                scaled()
+                    .map(|value| value.map(|value| value as f32))
+                    .collect::<Vec<_>>(),
+            )),
+            ColumnType::Double => Arc::new(Float64Array::from(scaled().collect::<Vec<_>>())),
+        }
+    }
+}
+
+/// Reads the values of the raster `band` as 32-bit integers, with the narrowest column type
+/// that holds them. Returns an error for bands with values that do not fit, instead of
+/// truncating them, unless float values are `rounded` (GDAL rounds them to the nearest integer).
+pub fn read_band(
+    band: &RasterBand,
+    rounded: bool,
+) -> Result<(ColumnType, Vec<i32>), Box<dyn Error + Send + Sync>> {
+    let column_type = ColumnType::of_band(band, rounded)?;
+    Ok((column_type, band.read_band_as::<i32>()?.data))
+}
+
+/// Reads the values of the `rows` of the raster `band`, like [`read_band`].
+pub fn read_band_rows(
+    band: &RasterBand,
+    rows: Range<usize>,
+    rounded: bool,
+) -> Result<(ColumnType, Vec<i32>), Box<dyn Error + Send + Sync>> {
+    let column_type = ColumnType::of_band(band, rounded)?;
+    let size = (band.size().0, rows.len());
+    let window = band.read_as::<i32>((0, rows.start as isize), size, size)?;
+    Ok((column_type, window.data))
+}
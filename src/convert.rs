@@ -0,0 +1,2447 @@
+use crate::{
+    area,
+    column::{read_band_rows, ColumnType},
+    country::CountryIndex,
+    crs::TargetCrs,
+    curve::{self, SortOrder},
+    dataset::Dataset as Dem,
+    geoparquet::{self, GEO, GEOMETRY},
+    grid::{Aggregation, Downsample, Resampling, TargetGrid},
+    join::Join,
+    manifest::Upstream,
+    mask,
+    mercator::{self, TileFormat},
+    polygon::Polygon,
+    terrain::{self, Derived, Light},
+    IfExists, Usage,
+};
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, Float32Array, Float64Array, StringArray, StructArray, UInt64Array,
+        UInt8Array,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    ipc::writer::{IpcDataGenerator, IpcWriteOptions},
+    record_batch::RecordBatch,
+};
+use gdal::Dataset;
+use h3o::{error::InvalidLatLng, LatLng, Resolution};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{
+        self, BrotliLevel, Compression, ConvertedType, Encoding, GzipLevel, Repetition, ZstdLevel,
+    },
+    file::{
+        metadata::KeyValue,
+        properties::{EnabledStatistics, WriterProperties, WriterVersion},
+        reader::SerializedFileReader,
+    },
+    schema::types::{ColumnPath, SchemaDescriptor, Type},
+};
+use s2::{
+    cellid::{CellID, MAX_LEVEL},
+    latlng::LatLng as S2LatLng,
+};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error,
+    fs::{self, File},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::Ordering, Arc},
+    thread,
+};
+use tracing::{event, instrument, Level};
+
+/// A raster band that is converted to a column.
+#[derive(Clone, Debug)]
+pub struct Band {
+    /// Index of the band in the raster (starting at 1)
+    pub index: isize,
+    /// Column name
+    pub name: String,
+    /// Unit of the values
+    pub unit: String,
+}
+
+/// Layout of the coordinate columns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Coords {
+    /// Separate `lat` and `lon` columns
+    Flat,
+    /// A `location` struct column with `lat` and `lon` fields
+    Struct,
+    /// No coordinate columns, the points are located by their pixel indices
+    None,
+}
+
+impl FromStr for Coords {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "struct" => Ok(Self::Struct),
+            "none" => Ok(Self::None),
+            _ => Err("expected one of: flat, struct, none"),
+        }
+    }
+}
+
+/// Type of the band columns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BandType {
+    /// The narrowest integer type of the values of the GeoTIFF per band, e.g. 16-bit integers
+    /// for the AW3D30 elevation
+    Auto,
+    /// 32-bit integers, the values of the GeoTIFF
+    Int32,
+    /// 32-bit floats
+    Float,
+    /// 64-bit floats
+    Double,
+}
+
+impl FromStr for BandType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "int32" => Ok(Self::Int32),
+            "float" => Ok(Self::Float),
+            "double" => Ok(Self::Double),
+            _ => Err("expected one of: auto, int32, float, double"),
+        }
+    }
+}
+
+/// What to do with the nodata values of the bands.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Nodata {
+    /// Write the nodata values like other values
+    Keep,
+    /// Drop the points with a nodata value in any band
+    Drop,
+    /// Write the bands as nullable columns, with nulls for the nodata values
+    Null,
+}
+
+impl FromStr for Nodata {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "drop" => Ok(Self::Drop),
+            "null" => Ok(Self::Null),
+            _ => Err("expected one of: keep, drop, null"),
+        }
+    }
+}
+
+/// Replacement of the nodata values of the bands, when they are kept.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sentinel {
+    /// Replace the nodata values with this value, e.g. `-32768`
+    Value(i32),
+    /// Replace the nodata values with the closest bound of the valid range, so that the bands
+    /// have no nodata values
+    Clamp,
+}
+
+impl Sentinel {
+    /// Returns the value that replaces the `nodata` value of a band with values in `range`.
+    fn replacement(self, nodata: f64, range: Option<ValidRange>) -> Option<i32> {
+        match (self, range) {
+            (Sentinel::Value(value), _) => Some(value),
+            (Sentinel::Clamp, Some(range)) => {
+                let (min, max) = (f64::from(range.min), f64::from(range.max));
+                Some(match (nodata - min).abs() <= (nodata - max).abs() {
+                    true => range.min,
+                    false => range.max,
+                })
+            }
+            (Sentinel::Clamp, None) => None,
+        }
+    }
+}
+
+impl FromStr for Sentinel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            s => s
+                .parse()
+                .map(Self::Value)
+                .map_err(|_| "expected `clamp` or an integer value"),
+        }
+    }
+}
+
+/// Range of valid values of the bands, `min..max` (inclusive), e.g. `-500..9000` for
+/// elevations in meters.
+#[derive(Copy, Clone, Debug)]
+pub struct ValidRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+impl FromStr for ValidRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str = "expected `min..max`, e.g. `-500..9000`";
+        let (min, max) = s.split_once("..").ok_or(ERR)?;
+        let (min, max) = (min.parse().map_err(|_| ERR)?, max.parse().map_err(|_| ERR)?);
+        if min > max {
+            return Err("the minimum of the range exceeds the maximum");
+        }
+        Ok(ValidRange { min, max })
+    }
+}
+
+impl ValidRange {
+    fn contains(&self, value: i32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// What to do with values outside the [`ValidRange`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutOfRange {
+    /// Keep the values, only count them
+    Flag,
+    /// Replace the values with the nodata value of the band
+    Nodata,
+}
+
+impl FromStr for OutOfRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(Self::Flag),
+            "nodata" => Ok(Self::Nodata),
+            _ => Err("expected one of: flag, nodata"),
+        }
+    }
+}
+
+/// Parses a Parquet compression codec: `none`, `snappy`, `gzip`, `zstd`, `lz4` or `brotli`,
+/// optionally with a level, e.g. `zstd:7` (`gzip` 0-9, `zstd` 1-22 and `brotli` 0-11).
+pub fn parse_compression(s: &str) -> Result<Compression, &'static str> {
+    const ERR: &str = "expected one of: none, snappy, gzip, zstd, lz4, brotli";
+    const LEVEL: &str =
+        "invalid compression level, expected e.g. `zstd:7` (gzip 0-9, zstd 1-22, brotli 0-11)";
+    let (codec, level) = match s.split_once(':') {
+        Some((codec, level)) => (codec, Some(level.parse::<u32>().map_err(|_| LEVEL)?)),
+        None => (s, None),
+    };
+    match (codec, level) {
+        ("none", None) => Ok(Compression::UNCOMPRESSED),
+        ("snappy", None) => Ok(Compression::SNAPPY),
+        ("lz4", None) => Ok(Compression::LZ4),
+        ("gzip", level) => GzipLevel::try_new(level.unwrap_or(6))
+            .map(Compression::GZIP)
+            .map_err(|_| LEVEL),
+        ("zstd", level) => ZstdLevel::try_new(level.unwrap_or(1) as i32)
+            .map(Compression::ZSTD)
+            .map_err(|_| LEVEL),
+        ("brotli", level) => BrotliLevel::try_new(level.unwrap_or(1))
+            .map(Compression::BROTLI)
+            .map_err(|_| LEVEL),
+        ("none" | "snappy" | "lz4", Some(_)) => {
+            Err("only gzip, zstd and brotli have compression levels")
+        }
+        _ => Err(ERR),
+    }
+}
+
+/// Returns the name of a Parquet compression codec, as accepted by [`parse_compression`].
+pub fn codec_name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::UNCOMPRESSED => "none",
+        Compression::SNAPPY => "snappy",
+        Compression::GZIP(_) => "gzip",
+        Compression::LZO => "lzo",
+        Compression::BROTLI(_) => "brotli",
+        Compression::LZ4 => "lz4",
+        Compression::ZSTD(_) => "zstd",
+        Compression::LZ4_RAW => "lz4_raw",
+    }
+}
+
+/// Formats a Parquet compression codec like [`parse_compression`] parses it, with the level
+/// only when it is not the default of the codec.
+pub fn format_compression(compression: Compression) -> String {
+    let level = match compression {
+        Compression::GZIP(level) if level != GzipLevel::default() => {
+            Some(level.compression_level().to_string())
+        }
+        Compression::ZSTD(level) if level != ZstdLevel::default() => {
+            Some(level.compression_level().to_string())
+        }
+        Compression::BROTLI(level) if level != BrotliLevel::default() => {
+            Some(level.compression_level().to_string())
+        }
+        _ => None,
+    };
+    match level {
+        Some(level) => format!("{}:{}", codec_name(compression), level),
+        None => codec_name(compression).to_string(),
+    }
+}
+
+/// Parses the compression codec of a column, `column=codec` with the codecs of
+/// [`parse_compression`], e.g. `lat=zstd`.
+pub fn parse_column_compression(s: &str) -> Result<(String, Compression), &'static str> {
+    let (column, codec) = s
+        .split_once('=')
+        .ok_or("expected `column=codec`, e.g. `lat=zstd`")?;
+    match column {
+        "" => Err("expected `column=codec`, e.g. `lat=zstd`"),
+        column => Ok((column.to_string(), parse_compression(codec)?)),
+    }
+}
+
+/// Parses the version of the Parquet format: `1`, or `2` for data pages v2 and the encodings of
+/// version 2 (e.g. delta encoding of the integer columns when dictionaries overflow).
+pub fn parse_writer_version(s: &str) -> Result<WriterVersion, &'static str> {
+    match s {
+        "1" => Ok(WriterVersion::PARQUET_1_0),
+        "2" => Ok(WriterVersion::PARQUET_2_0),
+        _ => Err("expected one of: 1, 2"),
+    }
+}
+
+/// Parses the encoding of a column, `column=encoding` with `plain`, `delta_binary_packed` (for
+/// integer columns) or `byte_stream_split` (for float columns). Columns are dictionary encoded
+/// by default.
+pub fn parse_encoding(s: &str) -> Result<(String, Encoding), &'static str> {
+    const ERR: &str =
+        "expected `column=encoding` with one of: plain, delta_binary_packed, byte_stream_split";
+    let (column, encoding) = s.split_once('=').ok_or(ERR)?;
+    let encoding = match encoding {
+        "plain" => Encoding::PLAIN,
+        "delta_binary_packed" => Encoding::DELTA_BINARY_PACKED,
+        "byte_stream_split" => Encoding::BYTE_STREAM_SPLIT,
+        _ => return Err(ERR),
+    };
+    match column {
+        "" => Err(ERR),
+        column => Ok((column.to_string(), encoding)),
+    }
+}
+
+/// Settings shared by all Parquet conversions of a run.
+pub struct Conversion {
+    pub bands: Vec<Band>,
+    /// Type of the band columns, with the scale and offset applied to the values
+    band_type: BandType,
+    /// Type of the column of every band
+    band_columns: Vec<ColumnType>,
+    scale: f64,
+    offset: f64,
+    nodata_value: Option<f64>,
+    nodata: Nodata,
+    /// Replacement of the nodata values that are kept
+    sentinel: Option<Sentinel>,
+    /// Float bands are rounded to read them as integers
+    rounded: bool,
+    /// Policy for existing Parquet files
+    pub if_exists: IfExists,
+    target_grid: Option<TargetGrid>,
+    /// Downsampling of the rasters, instead of a target grid
+    downsample: Option<Downsample>,
+    coords: Coords,
+    /// Coordinate reference system of the `x` and `y` coordinates, instead of `lat` and `lon`
+    target_crs: Option<TargetCrs>,
+    /// Write the `row` and `col` pixel indices of the points
+    pixel_indices: bool,
+    /// Polygons to clip the points to
+    clip: Option<Arc<Vec<Polygon>>>,
+    /// Keep every nth point in both axes
+    thin: Option<u32>,
+    /// Order of the points of a tile, row by row from the upper-left pixel by default
+    sort: Option<SortOrder>,
+    /// Size of the sub-tiles in degrees, of which the points are written in row groups of their
+    /// own
+    split: Option<f64>,
+    /// Range of valid values of the bands, and what to do with the other values
+    valid_range: Option<(ValidRange, OutOfRange)>,
+    /// Terrain attributes derived from the first band
+    derived: Vec<Derived>,
+    /// Light of the hillshade of the first band, if it is written
+    hillshade: Option<Light>,
+    /// Write the geodesic area of the cells of the points
+    cell_area: bool,
+    /// Write the flags of the mask files of the tiles
+    mask: bool,
+    /// Country boundaries to write the country code of the points
+    countries: Option<Arc<CountryIndex>>,
+    /// Sources of the joined columns, after the country code
+    joins: Vec<Arc<dyn Join>>,
+    /// Resolution of the H3 cells of the points, if they are written
+    h3: Option<Resolution>,
+    /// Level of the S2 cells of the points, if they are written
+    s2_level: Option<u64>,
+    /// Zoom level and id format of the web mercator map tiles of the points, if they are
+    /// written
+    map_tiles: Option<(u8, TileFormat)>,
+    /// Coordinate reference system of the GeoParquet geometry column, if it is written
+    geoparquet: Option<serde_json::Value>,
+    compression: Compression,
+    /// Compression codecs of columns that override `compression`
+    column_compression: Vec<(ColumnPath, Compression)>,
+    /// Maximum number of rows of a row group, the whole tile by default
+    row_group_size: Option<usize>,
+    /// Target size of the data pages in bytes
+    page_size: Option<usize>,
+    /// Memory a conversion may use in bytes, the tiles are converted in chunks of rows
+    max_memory: Option<u64>,
+    /// Encodings of columns that are not dictionary encoded
+    encodings: Vec<(ColumnPath, Encoding)>,
+    writer_version: WriterVersion,
+    schema: Arc<Type>,
+    /// Key-value metadata added to every file
+    key_value_metadata: Vec<KeyValue>,
+}
+
+impl Conversion {
+    /// Returns the settings to convert `bands`, with the schema of the output.
+    ///
+    /// The `row` and `col` pixel indices are written when `pixel_indices`, which requires the
+    /// pixels of the rasters as points (no target grid).
+    pub fn new(
+        bands: Vec<Band>,
+        nodata_value: Option<f64>,
+        if_exists: IfExists,
+        target_grid: Option<TargetGrid>,
+        coords: Coords,
+        pixel_indices: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        if pixel_indices && target_grid.is_some() {
+            return Err("pixel indices require the pixels as points, not a target grid".into());
+        }
+        if coords == Coords::None && !pixel_indices {
+            return Err("points without coordinates require pixel indices".into());
+        }
+        let coordinate_type = |name: &str| {
+            Arc::new(
+                Type::primitive_type_builder(name, basic::Type::DOUBLE)
+                    .with_repetition(Repetition::REQUIRED)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let mut fields = vec![coordinate_type("lat"), coordinate_type("lon")];
+        match coords {
+            Coords::Flat => {}
+            // The leaves of the required struct are written like the flat columns.
+            Coords::Struct => {
+                fields = vec![Arc::new(
+                    Type::group_type_builder("location")
+                        .with_repetition(Repetition::REQUIRED)
+                        .with_fields(fields)
+                        .build()?,
+                )]
+            }
+            Coords::None => fields.clear(),
+        }
+        if pixel_indices {
+            for name in ["row", "col"] {
+                fields.push(Arc::new(
+                    Type::primitive_type_builder(name, basic::Type::INT32)
+                        .with_converted_type(ConvertedType::UINT_16)
+                        .with_repetition(Repetition::REQUIRED)
+                        .build()?,
+                ));
+            }
+        }
+        for band in &bands {
+            fields.push(Arc::new(
+                ColumnType::Int32.parquet_type(&band.name, Repetition::REQUIRED)?,
+            ));
+        }
+        let schema = Arc::new(
+            Type::group_type_builder("schema")
+                .with_fields(fields)
+                .build()?,
+        );
+        let mut key_value_metadata = vec![KeyValue::new(
+            "ARROW:schema".to_string(),
+            encode_arrow_schema(&arrow_schema(
+                &bands,
+                target_grid.is_some(),
+                coords,
+                pixel_indices,
+            )),
+        )];
+        if let Some(grid) = &target_grid {
+            key_value_metadata.push(KeyValue::new(
+                "target_grid".to_string(),
+                format!(
+                    "resolution={},origin={},{},resampling={:?}",
+                    grid.resolution, grid.origin.0, grid.origin.1, grid.resampling
+                ),
+            ));
+        }
+        Ok(Conversion {
+            band_columns: vec![ColumnType::Int32; bands.len()],
+            bands,
+            band_type: BandType::Int32,
+            scale: 1.,
+            offset: 0.,
+            nodata_value,
+            nodata: Nodata::Keep,
+            sentinel: None,
+            rounded: false,
+            if_exists,
+            target_grid,
+            downsample: None,
+            coords,
+            target_crs: None,
+            pixel_indices,
+            clip: None,
+            thin: None,
+            sort: None,
+            split: None,
+            valid_range: None,
+            derived: Vec::new(),
+            hillshade: None,
+            cell_area: false,
+            mask: false,
+            countries: None,
+            joins: Vec::new(),
+            h3: None,
+            s2_level: None,
+            map_tiles: None,
+            geoparquet: None,
+            compression: Compression::SNAPPY,
+            column_compression: Vec::new(),
+            row_group_size: None,
+            page_size: None,
+            max_memory: None,
+            encodings: Vec::new(),
+            writer_version: WriterVersion::PARQUET_1_0,
+            schema,
+            key_value_metadata,
+        })
+    }
+
+    /// Downsamples the rasters before the conversion, which is recorded in the `downsample`
+    /// key-value metadata. The pixel indices are those of the downsampled rasters.
+    pub fn with_downsample(mut self, downsample: Downsample) -> Result<Self, Box<dyn Error>> {
+        if self.target_grid.is_some() {
+            return Err("the rasters are resampled onto the target grid, not downsampled".into());
+        }
+        if downsample.factor < 2 {
+            return Err("the downsampling factor must be at least 2".into());
+        }
+        self.key_value_metadata.push(KeyValue::new(
+            "downsample".to_string(),
+            format!(
+                "factor={},aggregation={:?}",
+                downsample.factor, downsample.aggregation
+            ),
+        ));
+        self.downsample = Some(downsample);
+        Ok(self)
+    }
+
+    /// Writes the coordinates of the points in the `crs`, as `x` and `y` columns (in the order
+    /// of the axes of the CRS) instead of `lat` and `lon`. The CRS is recorded in the `crs`
+    /// key-value metadata as PROJJSON.
+    pub fn with_target_crs(mut self, crs: TargetCrs) -> Result<Self, Box<dyn Error>> {
+        let coordinate_type = |name: &str| {
+            Type::primitive_type_builder(name, basic::Type::DOUBLE)
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .map(Arc::new)
+        };
+        let projected = vec![coordinate_type("x")?, coordinate_type("y")?];
+        let mut fields = self.schema.get_fields().to_vec();
+        match self.coords {
+            Coords::Flat => {
+                fields.splice(0..2, projected);
+            }
+            Coords::Struct => {
+                fields[0] = Arc::new(
+                    Type::group_type_builder("location")
+                        .with_repetition(Repetition::REQUIRED)
+                        .with_fields(projected)
+                        .build()?,
+                )
+            }
+            Coords::None => return Err("a target CRS requires coordinate columns".into()),
+        }
+        self.key_value_metadata
+            .push(KeyValue::new("crs".to_string(), crs.projjson().to_string()));
+        self.target_crs = Some(crs);
+        self.set_fields(fields)?;
+        Ok(self)
+    }
+
+    /// Only writes the points inside the `polygons`.
+    pub fn with_clip(mut self, polygons: Arc<Vec<Polygon>>) -> Self {
+        self.clip = Some(polygons);
+        self
+    }
+
+    /// Only writes every `thin`th point in both axes, counted from the origin of the pixels (or
+    /// of the target grid), so that the points of neighbouring tiles line up.
+    pub fn with_thin(mut self, thin: u32) -> Self {
+        self.thin = Some(thin);
+        self
+    }
+
+    /// Writes the points of a tile in `order`, which is recorded in the `sort` key-value
+    /// metadata.
+    pub fn with_sort(mut self, order: SortOrder) -> Self {
+        self.sort = Some(order);
+        self.key_value_metadata
+            .push(KeyValue::new("sort".to_string(), order.to_string()));
+        self
+    }
+
+    /// Splits the tiles into sub-tiles of `degrees` by `degrees` (e.g. 0.25), of which the
+    /// points are consecutive and written in row groups of their own, so that the statistics of
+    /// the row groups bound small areas. The sub-tiles are ordered like the pixels, and the
+    /// points of a sub-tile like those of a tile. The size is recorded in the `split_degrees`
+    /// key-value metadata.
+    pub fn with_split(mut self, degrees: f64) -> Result<Self, Box<dyn Error>> {
+        // Sub-tiles of the same size cover a tile.
+        let count = (1. / degrees).round();
+        if !(degrees > 0. && degrees < 1.) || (count * degrees - 1.).abs() > 1e-9 {
+            return Err("the sub-tiles must divide a degree, e.g. 0.5 or 0.25".into());
+        }
+        self.split = Some(degrees);
+        self.key_value_metadata.push(KeyValue::new(
+            "split_degrees".to_string(),
+            degrees.to_string(),
+        ));
+        Ok(self)
+    }
+
+    /// Drops the points with nodata values, or writes the bands as nullable columns with nulls
+    /// for the nodata values, according to `nodata`. The nodata value of a band is the override
+    /// or the value in the GeoTIFF metadata, bands without one have no nodata values.
+    pub fn with_nodata(mut self, nodata: Nodata) -> Result<Self, Box<dyn Error>> {
+        self.nodata = nodata;
+        self.set_band_fields()?;
+        Ok(self)
+    }
+
+    /// Writes the bands as `band_type` columns with the values multiplied by `scale` and then
+    /// increased by `offset` (e.g. to convert meters to feet, or to shift the vertical datum).
+    /// The nodata values in the metadata are scaled alike, the valid range is not.
+    pub fn with_band_type(
+        mut self,
+        band_type: BandType,
+        scale: f64,
+        offset: f64,
+    ) -> Result<Self, Box<dyn Error>> {
+        if !scale.is_finite() || !offset.is_finite() {
+            return Err("the scale and offset of the bands must be finite".into());
+        }
+        if matches!(band_type, BandType::Auto | BandType::Int32) && (scale != 1. || offset != 0.) {
+            return Err("a scale or offset requires float or double bands".into());
+        }
+        let column_type = match band_type {
+            // The columns are picked from a GeoTIFF, see `with_band_columns_of`.
+            BandType::Auto | BandType::Int32 => ColumnType::Int32,
+            BandType::Float => ColumnType::Float,
+            BandType::Double => ColumnType::Double,
+        };
+        self.band_type = band_type;
+        self.band_columns = vec![column_type; self.bands.len()];
+        self.scale = scale;
+        self.offset = offset;
+        self.set_band_fields()?;
+        Ok(self)
+    }
+
+    /// Writes every band in the narrowest column that holds the values of the band in the
+    /// GeoTIFF file at `path`, for the `auto` band type. Tiles with values that do not fit
+    /// these columns fail to convert, instead of truncating the values.
+    pub fn with_band_columns_of(mut self, path: &Path) -> Result<Self, Box<dyn Error>> {
+        if self.band_type != BandType::Auto {
+            return Err("the band columns are only picked for the auto band type".into());
+        }
+        let dataset = Dataset::open(path)?;
+        self.band_columns = self
+            .bands
+            .iter()
+            .map(|band| -> Result<_, Box<dyn Error>> {
+                Ok(ColumnType::of_band(
+                    &dataset.rasterband(band.index)?,
+                    self.rounded,
+                )?)
+            })
+            .collect::<Result<_, _>>()?;
+        self.set_band_fields()?;
+        Ok(self)
+    }
+
+    /// Returns the column types of the bands.
+    pub fn band_columns(&self) -> &[ColumnType] {
+        &self.band_columns
+    }
+
+    /// Returns the band `value` after scaling.
+    fn scaled(&self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+
+    /// Replaces the band columns of the schema by columns of the types of the bands, which are
+    /// nullable when the nodata values are written as nulls.
+    fn set_band_fields(&mut self) -> Result<(), Box<dyn Error>> {
+        let repetition = match self.nodata {
+            Nodata::Null => Repetition::OPTIONAL,
+            Nodata::Keep | Nodata::Drop => Repetition::REQUIRED,
+        };
+        let mut fields = self.schema.get_fields().to_vec();
+        for field in &mut fields {
+            if let Some(index) = self.bands.iter().position(|band| band.name == field.name()) {
+                *field = Arc::new(self.band_columns[index].parquet_type(field.name(), repetition)?);
+            }
+        }
+        self.set_fields(fields)
+    }
+
+    /// Counts the values of the bands (other than nodata) outside the `range`, which are
+    /// replaced by the nodata value of the band when `out_of_range` is to replace them.
+    pub fn with_valid_range(mut self, range: ValidRange, out_of_range: OutOfRange) -> Self {
+        self.valid_range = Some((range, out_of_range));
+        self
+    }
+
+    /// Replaces the nodata values of the bands with `sentinel`, so that the output follows the
+    /// convention of its consumers instead of that of the source (-9999 for AW3D30). The nodata
+    /// values are only replaced when they are kept, and clamping requires a valid range. The
+    /// sentinel (or the clamping, with the bounds it writes) is recorded in the metadata of the
+    /// band columns.
+    pub fn with_sentinel(mut self, sentinel: Sentinel) -> Result<Self, Box<dyn Error>> {
+        if self.nodata != Nodata::Keep {
+            return Err("the nodata values are only replaced when they are kept".into());
+        }
+        if sentinel == Sentinel::Clamp && self.valid_range.is_none() {
+            return Err("clamping the nodata values requires a valid range".into());
+        }
+        self.sentinel = Some(sentinel);
+        Ok(self)
+    }
+
+    /// Converts the GeoTIFF files of `release` of `dem`: its float elevations are rounded to
+    /// whole meters, and its name, release and vertical datum are recorded in the file metadata.
+    pub fn with_dataset(mut self, dem: Dem, release: &str) -> Self {
+        self.rounded = dem.rounded();
+        self.key_value_metadata
+            .push(KeyValue::new("dataset".to_string(), dem.name().to_string()));
+        self.key_value_metadata.push(KeyValue::new(
+            "dataset_version".to_string(),
+            release.to_string(),
+        ));
+        self.key_value_metadata.push(KeyValue::new(
+            "vertical_datum".to_string(),
+            dem.vertical_datum().to_string(),
+        ));
+        self
+    }
+
+    /// Writes the `derived` terrain attributes of the first band (the elevation) in nullable
+    /// float columns named after them, after the bands. They are computed from the pixels, so
+    /// they are not available with a target grid.
+    pub fn with_derived(mut self, derived: Vec<Derived>) -> Result<Self, Box<dyn Error>> {
+        if self.target_grid.is_some() {
+            return Err("derived columns are computed from the pixels, not a target grid".into());
+        }
+        for attribute in derived {
+            if self.derived.contains(&attribute) {
+                return Err(format!("`{}` is derived more than once", attribute.name()).into());
+            }
+            self.derived.push(attribute);
+            self.push_column(
+                Type::primitive_type_builder(attribute.name(), basic::Type::FLOAT)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .build()?,
+            )?;
+        }
+        Ok(self)
+    }
+
+    /// Writes the hillshade of the first band (the elevation) lit by `light` in a nullable
+    /// `hillshade` unsigned 8-bit column, from 0 (in shadow) to 255 (facing the light), after
+    /// the derived columns. Like those, it is not available with a target grid.
+    pub fn with_hillshade(mut self, light: Light) -> Result<Self, Box<dyn Error>> {
+        if self.target_grid.is_some() {
+            return Err("the hillshade is computed from the pixels, not a target grid".into());
+        }
+        self.hillshade = Some(light);
+        self.push_column(
+            Type::primitive_type_builder("hillshade", basic::Type::INT32)
+                .with_converted_type(ConvertedType::UINT_8)
+                .with_repetition(Repetition::OPTIONAL)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the geodesic area in square meters of the pixels (or the cells of the target grid
+    /// around the grid points) on the WGS 84 ellipsoid in a `cell_area` column, after the
+    /// hillshade. The area shrinks with the cosine of the latitude, so area-weighted statistics
+    /// of the points need no trigonometry.
+    pub fn with_cell_area(mut self) -> Result<Self, Box<dyn Error>> {
+        self.cell_area = true;
+        self.push_column(
+            Type::primitive_type_builder("cell_area", basic::Type::DOUBLE)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the quality flags of the points in a `mask` unsigned 8-bit column, after the
+    /// cell area. The flags are read from the mask file next to the GeoTIFF file of a
+    /// tile, with `_MSK` instead of `_DSM` in its name.
+    pub fn with_mask(mut self) -> Result<Self, Box<dyn Error>> {
+        self.mask = true;
+        self.push_column(
+            Type::primitive_type_builder("mask", basic::Type::INT32)
+                .with_converted_type(ConvertedType::UINT_8)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the code of the country of the points in a nullable `country` column, after the
+    /// mask.
+    pub fn with_country_codes(
+        mut self,
+        countries: Arc<CountryIndex>,
+    ) -> Result<Self, Box<dyn Error>> {
+        self.countries = Some(countries);
+        self.push_column(
+            Type::primitive_type_builder("country", basic::Type::BYTE_ARRAY)
+                .with_converted_type(ConvertedType::UTF8)
+                .with_repetition(Repetition::OPTIONAL)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the values of the `join` at the points in a nullable double column named after the
+    /// join, after the country code and the columns of previous joins.
+    pub fn with_join(mut self, join: Arc<dyn Join>) -> Result<Self, Box<dyn Error>> {
+        let name = join.name();
+        if self.arrow_schema().field_with_name(name).is_ok() {
+            return Err(format!("the schema already has a column `{}`", name).into());
+        }
+        let column = Type::primitive_type_builder(name, basic::Type::DOUBLE)
+            .with_repetition(Repetition::OPTIONAL)
+            .build()?;
+        self.joins.push(join);
+        self.push_column(column)?;
+        Ok(self)
+    }
+
+    /// Writes the index of the H3 cell at `resolution` (0 to 15) that contains the points in an
+    /// `h3` unsigned 64-bit column, after the joined columns.
+    pub fn with_h3(mut self, resolution: u8) -> Result<Self, Box<dyn Error>> {
+        self.h3 = Some(Resolution::try_from(resolution)?);
+        self.push_column(
+            Type::primitive_type_builder("h3", basic::Type::INT64)
+                .with_converted_type(ConvertedType::UINT_64)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the id of the S2 cell at `level` (0 to 30) that contains the points in an
+    /// `s2_cell` unsigned 64-bit column, after the H3 cell.
+    pub fn with_s2(mut self, level: u8) -> Result<Self, Box<dyn Error>> {
+        let level = u64::from(level);
+        if level > MAX_LEVEL {
+            return Err(format!("S2 levels are 0 to {}", MAX_LEVEL).into());
+        }
+        self.s2_level = Some(level);
+        self.push_column(
+            Type::primitive_type_builder("s2_cell", basic::Type::INT64)
+                .with_converted_type(ConvertedType::UINT_64)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the id of the web mercator map tile at `zoom` that contains the points in a
+    /// `map_tile` string column in `format`, after the S2 cell.
+    pub fn with_map_tiles(mut self, zoom: u8, format: TileFormat) -> Result<Self, Box<dyn Error>> {
+        if zoom > mercator::MAX_ZOOM {
+            return Err(format!("map tile zoom levels are 0 to {}", mercator::MAX_ZOOM).into());
+        }
+        self.map_tiles = Some((zoom, format));
+        self.push_column(
+            Type::primitive_type_builder("map_tile", basic::Type::BYTE_ARRAY)
+                .with_converted_type(ConvertedType::UTF8)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Writes the points as GeoParquet, with a WKB `geometry` column as the last column and the
+    /// `geo` file metadata.
+    pub fn with_geoparquet(mut self) -> Result<Self, Box<dyn Error>> {
+        let crs = geoparquet::crs()?;
+        // Outputs without a bounding box per file (e.g. a merged file) have this metadata.
+        self.key_value_metadata.push(KeyValue::new(
+            GEO.to_string(),
+            geoparquet::metadata(&crs, None),
+        ));
+        self.geoparquet = Some(crs);
+        self.push_column(
+            Type::primitive_type_builder(GEOMETRY, basic::Type::BYTE_ARRAY)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        )?;
+        Ok(self)
+    }
+
+    /// Adds the `column` after the columns of the schema. The columns after the bands are in
+    /// the order of the Arrow schema: derived columns, hillshade, cell area, mask, country code,
+    /// joins, H3 cell, S2 cell, map tile and geometry, so they must be added in that order.
+    fn push_column(&mut self, column: Type) -> Result<(), Box<dyn Error>> {
+        let mut fields = self.schema.get_fields().to_vec();
+        fields.push(Arc::new(column));
+        self.set_fields(fields)
+    }
+
+    /// Replaces the columns of the schema by `fields`, which must match the Arrow schema of the
+    /// output.
+    fn set_fields(&mut self, fields: Vec<Arc<Type>>) -> Result<(), Box<dyn Error>> {
+        self.schema = Arc::new(
+            Type::group_type_builder("schema")
+                .with_fields(fields)
+                .build()?,
+        );
+        let arrow_schema = self.arrow_schema();
+        let names = arrow_schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str());
+        if !names.eq(self.schema.get_fields().iter().map(|field| field.name())) {
+            return Err(
+                "columns after the bands must be added in the order: slope and aspect, hillshade, \
+                 cell_area, mask, country, joins, h3, s2_cell, map_tile, geometry"
+                    .into(),
+            );
+        }
+        let encoded = encode_arrow_schema(&arrow_schema);
+        for kv in &mut self.key_value_metadata {
+            if kv.key == "ARROW:schema" {
+                kv.value = Some(encoded.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Compresses the Parquet files with `compression` instead of Snappy.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Compresses the columns with the codecs of `column_compression` (by column name, with a
+    /// `.` between the names of nested fields) instead of the codec of the files.
+    pub fn with_column_compression(
+        mut self,
+        column_compression: Vec<(String, Compression)>,
+    ) -> Result<Self, String> {
+        let descriptor = SchemaDescriptor::new(self.schema.clone());
+        for (name, compression) in column_compression {
+            let path = ColumnPath::from(name.split('.').map(str::to_string).collect::<Vec<_>>());
+            if !descriptor
+                .columns()
+                .iter()
+                .any(|column| column.path() == &path)
+            {
+                return Err(format!("no column `{}` in the schema", name));
+            }
+            self.column_compression.push((path, compression));
+        }
+        Ok(self)
+    }
+
+    /// Splits the points of a tile into row groups of at most `row_group_size` rows, so that
+    /// readers can skip row groups by the statistics of the coordinates.
+    pub fn with_row_group_size(mut self, row_group_size: usize) -> Self {
+        self.row_group_size = Some(row_group_size);
+        self
+    }
+
+    /// Limits the data pages of the columns to about `page_size` bytes.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Converts the tiles in chunks of rows of points, with a row group (or more) per chunk, so
+    /// that a conversion uses about `max_memory` bytes instead of holding the whole tile.
+    ///
+    /// A target grid, sort order or GeoParquet bbox depend on all points of a tile, which can
+    /// not be combined with chunks (see [`Conversion::check_options`]).
+    pub fn with_max_memory(mut self, max_memory: u64) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Writes the Parquet files in format `writer_version` instead of version 1.
+    pub fn with_writer_version(mut self, writer_version: WriterVersion) -> Self {
+        self.writer_version = writer_version;
+        self
+    }
+
+    /// Writes the columns with the `encodings` (by column name, with a `.` between the names of
+    /// nested fields) instead of dictionary encoding them.
+    pub fn with_encodings(mut self, encodings: Vec<(String, Encoding)>) -> Result<Self, String> {
+        let descriptor = SchemaDescriptor::new(self.schema.clone());
+        for (name, encoding) in encodings {
+            let path = ColumnPath::from(name.split('.').map(str::to_string).collect::<Vec<_>>());
+            let column = descriptor
+                .columns()
+                .iter()
+                .find(|column| column.path() == &path)
+                .ok_or_else(|| format!("no column `{}` in the schema", name))?;
+            if encoding == Encoding::DELTA_BINARY_PACKED
+                && column.physical_type() != basic::Type::INT32
+            {
+                return Err(format!(
+                    "delta_binary_packed requires an integer column, `{}` is not",
+                    name
+                ));
+            }
+            if encoding == Encoding::BYTE_STREAM_SPLIT
+                && !matches!(
+                    column.physical_type(),
+                    basic::Type::FLOAT | basic::Type::DOUBLE
+                )
+            {
+                return Err(format!(
+                    "byte_stream_split requires a float column, `{}` is not",
+                    name
+                ));
+            }
+            self.encodings.push((path, encoding));
+        }
+        Ok(self)
+    }
+
+    /// Returns the size of the values of a tile of `pixels` by `pixels` before encoding and
+    /// compression, an upper bound of the size of its Parquet file.
+    pub fn uncompressed_tile_size(&self, pixels: u64) -> u64 {
+        let points = match (&self.target_grid, &self.downsample) {
+            (Some(grid), _) => (1. / grid.resolution).ceil() as u64,
+            (None, Some(downsample)) => pixels / downsample.factor as u64,
+            (None, None) => pixels,
+        };
+        // Points per axis, of which every nth point is kept.
+        let thin = self.thin.unwrap_or(1) as u64;
+        let points = (points + thin - 1) / thin;
+        points * points * self.point_size()
+    }
+
+    /// Returns the size of the values of a point before encoding and compression.
+    fn point_size(&self) -> u64 {
+        let coordinates = match self.coords {
+            Coords::None => 0,
+            Coords::Flat | Coords::Struct => 2 * 8,
+        };
+        let indices = if self.pixel_indices { 2 * 4 } else { 0 };
+        let derived = self.derived.len() as u64 * 4;
+        let hillshade = if self.hillshade.is_some() { 1 } else { 0 };
+        let cell_area = if self.cell_area { 8 } else { 0 };
+        let mask = if self.mask { 1 } else { 0 };
+        // Country codes are dictionary encoded.
+        let countries = if self.countries.is_some() { 2 } else { 0 };
+        // Points in WKB with their length.
+        let geometry = if self.geoparquet.is_some() { 21 + 4 } else { 0 };
+        let joins = self.joins.len() as u64 * 8;
+        let cells = (self.h3.is_some() as u64 + self.s2_level.is_some() as u64) * 8;
+        // Map tile ids are dictionary encoded.
+        let map_tiles = if self.map_tiles.is_some() { 2 } else { 0 };
+        let extra = indices
+            + derived
+            + hillshade
+            + cell_area
+            + mask
+            + countries
+            + joins
+            + cells
+            + map_tiles
+            + geometry;
+        let bands = self
+            .band_columns
+            .iter()
+            .map(|column_type| column_type.size())
+            .sum::<u64>();
+        coordinates + extra + bands
+    }
+
+    /// Returns the rows of points of the chunks of the tile at `input_path`, all rows in one
+    /// chunk without a memory limit.
+    fn chunks(&self, input_path: &Path) -> Result<Vec<Range<usize>>, Box<dyn Error>> {
+        let dataset = Dataset::open(input_path)?;
+        let raster_size = dataset.raster_size();
+        let (x_size, y_size) = match &self.downsample {
+            Some(downsample) => {
+                downsample
+                    .transform(&dataset.geo_transform()?, raster_size)
+                    .1
+            }
+            None => raster_size,
+        };
+        let chunk_rows = match self.max_memory {
+            // A point is held as read (at full resolution), as point, in an array and in the
+            // pages of its row group.
+            Some(max_memory) => {
+                let factor = self
+                    .downsample
+                    .as_ref()
+                    .map_or(1, |downsample| downsample.factor as u64);
+                let read = self.bands.len() as u64 * 4 * factor * factor;
+                let point = read + 3 * self.point_size();
+                (max_memory / (point * x_size.max(1) as u64)) as usize
+            }
+            None => y_size,
+        }
+        .max(1);
+        Ok((0..y_size.max(1))
+            .step_by(chunk_rows)
+            .map(|start| start..(start + chunk_rows).min(y_size))
+            .collect())
+    }
+
+    /// Returns the Arrow schema of the output.
+    pub fn arrow_schema(&self) -> Schema {
+        let schema = arrow_schema(
+            &self.bands,
+            self.target_grid.is_some(),
+            self.coords,
+            self.pixel_indices,
+        );
+        let mut fields = schema
+            .fields()
+            .iter()
+            .map(|field| field.as_ref().clone())
+            .collect::<Vec<_>>();
+        if let Some(crs) = &self.target_crs {
+            let convention = match self.target_grid {
+                Some(_) => "grid-point",
+                None => "pixel-center",
+            };
+            let coordinate = |name: &str| {
+                Field::new(name, DataType::Float64, false).with_metadata(
+                    [("crs", crs.definition()), ("convention", convention)]
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                )
+            };
+            let projected = vec![coordinate("x"), coordinate("y")];
+            match self.coords {
+                Coords::Flat => {
+                    fields.splice(0..2, projected);
+                }
+                Coords::Struct => {
+                    fields[0] = Field::new("location", DataType::Struct(projected.into()), false)
+                }
+                Coords::None => {}
+            }
+        }
+        for field in &mut fields {
+            if let Some(index) = self
+                .bands
+                .iter()
+                .position(|band| &band.name == field.name())
+            {
+                let mut metadata = field.metadata().clone();
+                // The scale and offset are recorded so that the source values can be restored.
+                if self.scale != 1. || self.offset != 0. {
+                    metadata.insert("scale".to_string(), self.scale.to_string());
+                    metadata.insert("offset".to_string(), self.offset.to_string());
+                }
+                // The sentinel of the kept nodata values, when it does not depend on the GeoTIFF
+                // files.
+                let sentinel = match self.sentinel {
+                    Some(Sentinel::Value(value)) => Some(self.scaled(f64::from(value)).to_string()),
+                    // The clamped nodata values look like real values, so the substitution is
+                    // recorded instead of a nodata value.
+                    Some(Sentinel::Clamp) => {
+                        if let Some((range, _)) = self.valid_range {
+                            metadata.insert("nodata_replacement".to_string(), "clamp".to_string());
+                            metadata.insert(
+                                "nodata_replacement_values".to_string(),
+                                format!(
+                                    "{},{}",
+                                    self.scaled(f64::from(range.min)),
+                                    self.scaled(f64::from(range.max))
+                                ),
+                            );
+                        }
+                        None
+                    }
+                    None if self.nodata == Nodata::Keep => self
+                        .nodata_value
+                        .map(|value| self.scaled(value).to_string()),
+                    None => None,
+                };
+                if let Some(sentinel) = sentinel {
+                    metadata.insert("nodata".to_string(), sentinel);
+                }
+                *field = Field::new(
+                    field.name(),
+                    self.band_columns[index].arrow_type(),
+                    self.nodata == Nodata::Null,
+                )
+                .with_metadata(metadata);
+            }
+        }
+        fields.extend(self.derived.iter().map(|attribute| {
+            let mut metadata = HashMap::new();
+            metadata.insert("unit".to_string(), "degree".to_string());
+            if *attribute == Derived::Aspect {
+                metadata.insert("convention".to_string(), "clockwise-from-north".to_string());
+            }
+            Field::new(attribute.name(), DataType::Float32, true).with_metadata(metadata)
+        }));
+        if let Some(light) = &self.hillshade {
+            let mut metadata = HashMap::new();
+            metadata.insert("azimuth".to_string(), light.azimuth.to_string());
+            metadata.insert("altitude".to_string(), light.altitude.to_string());
+            fields.push(Field::new("hillshade", DataType::UInt8, true).with_metadata(metadata));
+        }
+        if self.cell_area {
+            let mut metadata = HashMap::new();
+            metadata.insert("unit".to_string(), "m^2".to_string());
+            metadata.insert("crs".to_string(), "EPSG:4326".to_string());
+            fields.push(Field::new("cell_area", DataType::Float64, false).with_metadata(metadata));
+        }
+        if self.mask {
+            fields.push(Field::new("mask", DataType::UInt8, false));
+        }
+        if self.countries.is_some() {
+            fields.push(Field::new("country", DataType::Utf8, true));
+        }
+        fields.extend(
+            self.joins
+                .iter()
+                .map(|join| Field::new(join.name(), DataType::Float64, true)),
+        );
+        if self.h3.is_some() {
+            fields.push(Field::new("h3", DataType::UInt64, false));
+        }
+        if self.s2_level.is_some() {
+            fields.push(Field::new("s2_cell", DataType::UInt64, false));
+        }
+        if self.map_tiles.is_some() {
+            fields.push(Field::new("map_tile", DataType::Utf8, false));
+        }
+        if self.geoparquet.is_some() {
+            fields.push(Field::new(GEOMETRY, DataType::Binary, false));
+        }
+        Schema::new(fields)
+    }
+
+    /// Returns the Parquet schema of the output.
+    pub fn schema(&self) -> Arc<Type> {
+        self.schema.clone()
+    }
+
+    /// Returns the compression codec of the Parquet files.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the maximum number of rows of a row group, if the tiles are split.
+    pub fn row_group_size(&self) -> Option<usize> {
+        self.row_group_size
+    }
+
+    /// Returns true when the quality flags of the mask files of the tiles are written.
+    pub fn mask(&self) -> bool {
+        self.mask
+    }
+
+    /// Checks that the options of the conversion can be combined, once all of them are set.
+    pub fn check_options(&self) -> Result<(), Box<dyn Error>> {
+        if self.max_memory.is_some()
+            && (self.target_grid.is_some() || self.sort.is_some() || self.geoparquet.is_some())
+        {
+            return Err(
+                "a memory limit can not be combined with a target grid, sort order or GeoParquet"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns an error when a Parquet file in `dir` has another schema or was written with
+    /// other settings (like the target grid), so that a dataset does not mix incompatible
+    /// files. Files without a valid footer are ignored, they are replaced by the conversion.
+    pub fn check_compatible(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.extension().map_or(true, |ext| ext != "parquet") || name.starts_with('_') {
+                continue;
+            }
+            let reader = match File::open(&path).map(SerializedFileReader::new) {
+                Ok(Ok(reader)) => reader,
+                _ => continue,
+            };
+            let metadata = reader.metadata().file_metadata();
+            if metadata.schema() != self.schema.as_ref() {
+                return Err(format!("`{}` has a different schema", name).into());
+            }
+            // The settings are recorded in the key-value metadata of every file.
+            let value = |key_value_metadata: Option<&Vec<KeyValue>>, key: &str| {
+                key_value_metadata
+                    .and_then(|key_values| key_values.iter().find(|kv| kv.key == key))
+                    .and_then(|kv| kv.value.clone())
+            };
+            for key in ["ARROW:schema", "target_grid", "downsample", "crs"] {
+                if value(metadata.key_value_metadata(), key)
+                    != value(Some(&self.key_value_metadata), key)
+                {
+                    return Err(format!("`{}` has a different `{}`", name, key).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the writer properties for a Parquet file with the given additional key-value
+    /// metadata.
+    pub fn writer_properties(&self, key_value_metadata: Vec<KeyValue>) -> WriterProperties {
+        // Readers prune row groups and files by the min and max statistics of the columns.
+        let mut builder = WriterProperties::builder()
+            .set_writer_version(self.writer_version)
+            .set_compression(self.compression)
+            .set_statistics_enabled(EnabledStatistics::Page);
+        if let Some(page_size) = self.page_size {
+            builder = builder.set_data_pagesize_limit(page_size);
+        }
+        if let Some(row_group_size) = self.row_group_size {
+            builder = builder.set_max_row_group_size(row_group_size);
+        }
+        for (path, compression) in &self.column_compression {
+            builder = builder.set_column_compression(path.clone(), *compression);
+        }
+        for (path, encoding) in &self.encodings {
+            builder = builder
+                .set_column_dictionary_enabled(path.clone(), false)
+                .set_column_encoding(path.clone(), *encoding);
+        }
+        // The metadata of a file replaces that of the conversion with the same key.
+        builder
+            .set_key_value_metadata(Some(
+                self.key_value_metadata
+                    .iter()
+                    .filter(|kv| key_value_metadata.iter().all(|file| file.key != kv.key))
+                    .cloned()
+                    .chain(key_value_metadata)
+                    .collect(),
+            ))
+            .build()
+    }
+}
+
+/// Returns the Arrow schema of the output, with the semantics of the columns in the field
+/// metadata.
+pub fn arrow_schema(
+    bands: &[Band],
+    target_grid: bool,
+    coords: Coords,
+    pixel_indices: bool,
+) -> Schema {
+    let field = |name: &str, data_type, metadata: &[(&str, &str)]| {
+        Field::new(name, data_type, false).with_metadata(
+            metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        )
+    };
+    // The coordinates are computed for the center of the pixels, or are the points of the target
+    // grid.
+    let coordinate = [
+        ("unit", "degree"),
+        ("crs", "EPSG:4326"),
+        (
+            "convention",
+            if target_grid {
+                "grid-point"
+            } else {
+                "pixel-center"
+            },
+        ),
+    ];
+    let mut fields = vec![
+        field("lat", DataType::Float64, &coordinate),
+        field("lon", DataType::Float64, &coordinate),
+    ];
+    match coords {
+        Coords::Flat => {}
+        Coords::Struct => {
+            fields = vec![Field::new(
+                "location",
+                DataType::Struct(fields.into()),
+                false,
+            )]
+        }
+        Coords::None => fields.clear(),
+    }
+    // The indices of the pixels in the raster, from the upper-left pixel.
+    if pixel_indices {
+        let index = [("convention", "pixel-index"), ("origin", "upper-left")];
+        fields.push(field("row", DataType::UInt16, &index));
+        fields.push(field("col", DataType::UInt16, &index));
+    }
+    fields.extend(bands.iter().map(|band| {
+        let index = band.index.to_string();
+        field(
+            &band.name,
+            DataType::Int32,
+            &[("unit", band.unit.as_str()), ("band", index.as_str())],
+        )
+    }));
+
+    Schema::new(fields)
+}
+
+/// Encodes an Arrow schema like the Arrow Parquet writer, so that Arrow readers restore it
+/// (including the field metadata) from the `ARROW:schema` key.
+pub fn encode_arrow_schema(schema: &Schema) -> String {
+    let encoded = IpcDataGenerator::default().schema_to_bytes(schema, &IpcWriteOptions::default());
+    // Prefix the message with the continuation marker and length of the legacy IPC format.
+    let mut bytes = Vec::with_capacity(encoded.ipc_message.len() + 8);
+    bytes.extend_from_slice(&[255; 4]);
+    bytes.extend_from_slice(&(encoded.ipc_message.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&encoded.ipc_message);
+    base64::encode(&bytes)
+}
+
+/// Returns true when the Parquet file at `path` has a valid footer.
+pub fn valid_parquet(path: &Path) -> bool {
+    File::open(path).map_or(false, |file| SerializedFileReader::new(file).is_ok())
+}
+
+/// Points of a tile, with the values of the bands.
+struct Points {
+    lat: Vec<f64>,
+    lon: Vec<f64>,
+    /// Coordinates of the points in the target CRS
+    projected: Option<(Vec<f64>, Vec<f64>)>,
+    /// The `row` and `col` pixel indices
+    pixel_indices: Option<(Vec<i32>, Vec<i32>)>,
+    bands: Vec<Vec<i32>>,
+    /// Values of the bands that are written as nulls, if the bands are nullable
+    nulls: Option<Vec<Option<i32>>>,
+    /// Values of the derived terrain attributes
+    derived: Vec<Vec<Option<f32>>>,
+    /// Hillshade of the first band
+    hillshade: Option<Vec<Option<u8>>>,
+    /// Geodesic area of the cells of the points
+    cell_area: Option<Vec<f64>>,
+    /// Flags of the mask file
+    mask: Option<Vec<i32>>,
+    /// Indices of the country codes of the points
+    countries: Option<Vec<Option<u16>>>,
+    /// Values of the joins at the points
+    joins: Vec<Vec<Option<f64>>>,
+    /// Indices of the H3 cells of the points
+    h3: Option<Vec<u64>>,
+    /// Ids of the S2 cells of the points
+    s2: Option<Vec<u64>>,
+    /// Ids of the map tiles of the points
+    map_tiles: Option<Vec<String>>,
+    /// Indices of the first points of the sub-tiles, when the tiles are split
+    groups: Option<Vec<usize>>,
+    /// Number of pixels of the bands outside the valid range
+    out_of_range: u64,
+    /// Nodata values of the bands and the version of the source file
+    key_value_metadata: Vec<KeyValue>,
+}
+
+/// Reads the points of the `rows` of the GeoTIFF file at `input_path`, which is the `upstream`
+/// version of the source file. The rows are those of the (downsampled) raster, a target grid
+/// requires all rows.
+fn read_points(
+    input_path: &Path,
+    upstream: &Upstream,
+    conversion: &Conversion,
+    rows: Range<usize>,
+) -> Result<Points, Box<dyn Error>> {
+    let dataset = Dataset::open(input_path)?;
+    let raster_size = dataset.raster_size();
+    // The points are the pixels of the downsampled raster.
+    let (gt, (x_size, y_size)) = match &conversion.downsample {
+        Some(downsample) => downsample.transform(&dataset.geo_transform()?, raster_size),
+        None => (dataset.geo_transform()?, raster_size),
+    };
+    let rows = rows.start.min(y_size)..rows.end.min(y_size);
+    // The terrain attributes of the rows depend on the rows around them.
+    let halo = (!conversion.derived.is_empty() || conversion.hillshade.is_some()) as usize;
+    let window = rows.start.saturating_sub(halo)..(rows.end + halo).min(y_size);
+    // The rows of pixels of the window in the raster, before downsampling.
+    let factor = conversion
+        .downsample
+        .as_ref()
+        .map_or(1, |downsample| downsample.factor);
+    let raster_rows = |rows: &Range<usize>| rows.start * factor..rows.end * factor;
+    let window_size = (raster_size.0, raster_rows(&window).len());
+    let window_gt = [
+        gt[0] + window.start as f64 * gt[2],
+        gt[1],
+        gt[2],
+        gt[3] + window.start as f64 * gt[5],
+        gt[4],
+        gt[5],
+    ];
+    // https://gdal.org/user/raster_data_model.html#affine-geotransform, at the pixel centers.
+    let coordinates = |f: &(dyn Fn(f64, f64) -> f64 + Sync)| {
+        rows.clone()
+            .flat_map(|y| (0..x_size).map(move |x| f(x as f64 + 0.5, y as f64 + 0.5)))
+            .collect::<Vec<_>>()
+    };
+    // Points of the target grid in the raster, the bands are resampled at these points.
+    let grid = conversion.target_grid.as_ref();
+    let grid_points = grid.map(|grid| grid.points(&gt, (x_size, y_size)));
+    // Compute the coordinates and read the bands on separate threads, so that the
+    // conversion of a large raster is not bound to a single core.
+    let (pixel_coordinates, bands) = thread::scope(|scope| {
+        let pixel_coordinates = grid.is_none().then(|| {
+            (
+                scope.spawn(|| coordinates(&|x, y| gt[3] + x * gt[4] + y * gt[5])),
+                scope.spawn(|| coordinates(&|x, y| gt[0] + x * gt[1] + y * gt[2])),
+            )
+        });
+        // One column of values per selected band.
+        let bands = conversion
+            .bands
+            .iter()
+            .zip(&conversion.band_columns)
+            .map(|(band, &column_type)| {
+                let grid_points = grid_points.as_ref();
+                let nodata_value = conversion.nodata_value;
+                let valid_range = conversion.valid_range;
+                let sentinel = conversion.sentinel;
+                let rounded = conversion.rounded;
+                let downsample = conversion.downsample.as_ref();
+                let window = raster_rows(&window);
+                scope.spawn(move || -> Result<_, Box<dyn Error + Send + Sync>> {
+                    let dataset = Dataset::open(input_path)?;
+                    let rasterband = dataset.rasterband(band.index)?;
+                    // The override takes precedence over the nodata value of the band.
+                    let nodata = nodata_value.or_else(|| rasterband.no_data_value());
+                    let (source, mut data) = read_band_rows(&rasterband, window, rounded)?;
+                    if !column_type.holds(source) {
+                        return Err(format!(
+                            "band `{}` has {:?} values, which do not fit its {:?} column",
+                            band.name, source, column_type
+                        )
+                        .into());
+                    }
+                    // Values are checked before resampling and downsampling, so that replaced
+                    // values are resampled as nodata.
+                    let out_of_range = match valid_range {
+                        Some((range, out_of_range)) => {
+                            check_range(band, &mut data, nodata, range, out_of_range)?
+                        }
+                        None => 0,
+                    };
+                    if let Some(downsample) = downsample {
+                        data = downsample.apply(&data, window_size, nodata);
+                    }
+                    if let (Some(grid), Some((lat, lon))) = (grid, grid_points) {
+                        data = grid.resample(&gt, (x_size, y_size), &data, nodata, lat, lon);
+                    }
+                    // Replaced last, so that they are downsampled and resampled as nodata.
+                    let replacement = sentinel.zip(nodata).and_then(|(sentinel, nodata)| {
+                        sentinel.replacement(nodata, valid_range.map(|(range, _)| range))
+                    });
+                    let nodata = match (nodata, replacement) {
+                        (Some(nodata), Some(replacement)) => {
+                            for value in data.iter_mut().filter(|value| **value as f64 == nodata) {
+                                *value = replacement;
+                            }
+                            match sentinel {
+                                Some(Sentinel::Clamp) => None,
+                                _ => Some(f64::from(replacement)),
+                            }
+                        }
+                        (nodata, _) => nodata,
+                    };
+                    // Replacements and nodata overrides are not values of the raster.
+                    column_type.check(&band.name, &data)?;
+                    Ok((nodata, data, out_of_range))
+                })
+            })
+            .collect::<Vec<_>>();
+        (
+            pixel_coordinates.map(|(lat, lon)| (lat.join().unwrap(), lon.join().unwrap())),
+            bands
+                .into_iter()
+                .map(|band| band.join().unwrap())
+                .collect::<Result<Vec<_>, _>>(),
+        )
+    });
+    let (mut lat, mut lon) = pixel_coordinates.or(grid_points).unwrap();
+    let bands = bands.map_err(|e| e as Box<dyn Error>)?;
+    // Record the nodata values, as they are written.
+    let mut key_value_metadata = conversion
+        .bands
+        .iter()
+        .zip(&bands)
+        .filter_map(|(band, (nodata, ..))| {
+            nodata.map(|nodata| {
+                KeyValue::new(
+                    format!("{}.nodata", band.name),
+                    conversion.scaled(nodata).to_string(),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let nodata = bands.iter().map(|(nodata, ..)| *nodata).collect::<Vec<_>>();
+    let out_of_range = bands.iter().map(|(.., out_of_range)| out_of_range).sum();
+    let mut bands = bands
+        .into_iter()
+        .map(|(_, data, _)| data)
+        .collect::<Vec<_>>();
+    // The terrain attributes of the (downsampled) pixels of the first band.
+    let size = (x_size, window.len());
+    let mut derived = if conversion.derived.is_empty() {
+        Vec::new()
+    } else {
+        let (slope, aspect) = terrain::slope_aspect(&bands[0], &window_gt, size, nodata[0]);
+        conversion
+            .derived
+            .iter()
+            .map(|attribute| match attribute {
+                Derived::Slope => slope.clone(),
+                Derived::Aspect => aspect.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut hillshade = conversion
+        .hillshade
+        .as_ref()
+        .map(|light| terrain::hillshade(&bands[0], &window_gt, size, nodata[0], light));
+    // Only the points of the rows are kept, without the window around them. A target grid has
+    // all rows.
+    if window != rows {
+        let points = (rows.start - window.start) * x_size..(rows.end - window.start) * x_size;
+        fn trim<T>(values: &mut Vec<T>, points: &Range<usize>) {
+            values.truncate(points.end);
+            values.drain(..points.start);
+        }
+        bands.iter_mut().for_each(|values| trim(values, &points));
+        derived.iter_mut().for_each(|values| trim(values, &points));
+        if let Some(hillshade) = &mut hillshade {
+            trim(hillshade, &points);
+        }
+    }
+    // The flags are not aggregated or interpolated, the nearest pixel of the mask is taken.
+    let mut mask = conversion
+        .mask
+        .then(|| -> Result<_, Box<dyn Error>> {
+            let dataset = Dataset::open(&mask::mask_path(input_path))?;
+            if dataset.raster_size() != raster_size {
+                return Err("the mask file has another size than the tile".into());
+            }
+            let (_, mut data) = read_band_rows(&dataset.rasterband(1)?, raster_rows(&rows), false)
+                .map_err(|e| e as Box<dyn Error>)?;
+            if let Some(downsample) = &conversion.downsample {
+                let nearest = Downsample {
+                    aggregation: Aggregation::Nearest,
+                    ..downsample.clone()
+                };
+                data = nearest.apply(&data, (raster_size.0, raster_rows(&rows).len()), None);
+            }
+            // The points are those of the target grid.
+            if let Some(grid) = grid {
+                let nearest = TargetGrid {
+                    resampling: Resampling::Nearest,
+                    ..grid.clone()
+                };
+                data = nearest.resample(&gt, (x_size, y_size), &data, None, &lat, &lon);
+            }
+            Ok(data)
+        })
+        .transpose()?;
+    // Indices of the pixels, in the same order as the coordinates.
+    let mut pixel_indices = conversion.pixel_indices.then(|| {
+        let cols = rows
+            .clone()
+            .flat_map(|_| 0..x_size as i32)
+            .collect::<Vec<_>>();
+        let rows = rows
+            .clone()
+            .flat_map(|y| iter::repeat(y as i32).take(x_size))
+            .collect::<Vec<_>>();
+        (rows, cols)
+    });
+    let inside = conversion.clip.as_ref().map(|polygons| {
+        lat.iter()
+            .zip(&lon)
+            .map(|(&lat, &lon)| polygons.iter().any(|polygon| polygon.contains(lon, lat)))
+            .collect::<Vec<_>>()
+    });
+    // The points on a lattice of every nth pixel (or grid point) from the origin, the pixel
+    // centers are half a pixel from it.
+    let thinned = conversion.thin.map(|thin| {
+        let ((lon_origin, lat_origin), (lon_step, lat_step)) = match grid {
+            Some(grid) => (grid.origin, (grid.resolution, grid.resolution)),
+            None => ((gt[1] / 2., gt[5] / 2.), (gt[1], gt[5])),
+        };
+        let on_lattice = |index: f64| (index.round() as i64).rem_euclid(thin as i64) == 0;
+        lat.iter()
+            .zip(&lon)
+            .map(|(&lat, &lon)| {
+                on_lattice((lat - lat_origin) / lat_step)
+                    && on_lattice((lon - lon_origin) / lon_step)
+            })
+            .collect::<Vec<_>>()
+    });
+    // The points without a nodata value in any band.
+    let valid = (conversion.nodata == Nodata::Drop).then(|| {
+        (0..lat.len())
+            .map(|point| {
+                bands.iter().zip(&nodata).all(|(values, nodata)| {
+                    nodata.map_or(true, |nodata| values[point] as f64 != nodata)
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    let keep = vec![inside, thinned, valid]
+        .into_iter()
+        .flatten()
+        .reduce(|keep, mask| keep.iter().zip(&mask).map(|(&a, &b)| a && b).collect());
+    if let Some(keep) = keep {
+        fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+            let mut keep = keep.iter();
+            values.retain(|_| *keep.next().unwrap());
+        }
+        retain(&mut lat, &keep);
+        retain(&mut lon, &keep);
+        if let Some((rows, cols)) = &mut pixel_indices {
+            retain(rows, &keep);
+            retain(cols, &keep);
+        }
+        bands.iter_mut().for_each(|values| retain(values, &keep));
+        derived.iter_mut().for_each(|values| retain(values, &keep));
+        if let Some(hillshade) = &mut hillshade {
+            retain(hillshade, &keep);
+        }
+        if let Some(mask) = &mut mask {
+            retain(mask, &keep);
+        }
+    }
+    // The sub-tile of a point, north to south and then west to east like the pixels. A pixel
+    // belongs to the sub-tile that contains its center.
+    let sub_tile = conversion.split.map(|degrees| {
+        move |lat: f64, lon: f64| {
+            (
+                Reverse((lat / degrees).floor() as i64),
+                (lon / degrees).floor() as i64,
+            )
+        }
+    });
+    let mut order = conversion
+        .sort
+        .map(|order| curve::sorted_indices(order, &lat, &lon));
+    if let Some(sub_tile) = sub_tile {
+        // The sort is stable, so the points of a sub-tile keep their order.
+        let mut indices = order.unwrap_or_else(|| (0..lat.len()).collect());
+        indices.sort_by_key(|&index| sub_tile(lat[index], lon[index]));
+        order = Some(indices);
+    }
+    if let Some(indices) = order {
+        fn permute<T: Copy>(values: &mut Vec<T>, indices: &[usize]) {
+            *values = indices.iter().map(|&index| values[index]).collect();
+        }
+        permute(&mut lat, &indices);
+        permute(&mut lon, &indices);
+        if let Some((rows, cols)) = &mut pixel_indices {
+            permute(rows, &indices);
+            permute(cols, &indices);
+        }
+        bands
+            .iter_mut()
+            .for_each(|values| permute(values, &indices));
+        derived
+            .iter_mut()
+            .for_each(|values| permute(values, &indices));
+        if let Some(hillshade) = &mut hillshade {
+            permute(hillshade, &indices);
+        }
+        if let Some(mask) = &mut mask {
+            permute(mask, &indices);
+        }
+    }
+    let groups = sub_tile.map(|sub_tile| {
+        (0..lat.len())
+            .filter(|&point| {
+                point == 0
+                    || sub_tile(lat[point], lon[point]) != sub_tile(lat[point - 1], lon[point - 1])
+            })
+            .collect()
+    });
+    let projected = conversion
+        .target_crs
+        .as_ref()
+        .map(|crs| crs.transform(&lat, &lon))
+        .transpose()?;
+    // Pixel centers and grid points are at the center of their cell.
+    let cell_area = conversion.cell_area.then(|| {
+        let (dlat, dlon) = match grid {
+            Some(grid) => (grid.resolution, grid.resolution),
+            None => (gt[5].abs(), gt[1].abs()),
+        };
+        lat.iter()
+            .map(|&lat| area::cell_area(lat - dlat / 2., lat + dlat / 2., dlon))
+            .collect::<Vec<_>>()
+    });
+    let countries = conversion
+        .countries
+        .as_ref()
+        .map(|countries| countries.lookup(&lat, &lon));
+    let joins = if conversion.joins.is_empty() {
+        Vec::new()
+    } else {
+        // The joins are sampled at the points, the center of the pixels or the grid points.
+        let points = lon
+            .iter()
+            .zip(&lat)
+            .map(|(&lon, &lat)| (lon, lat))
+            .collect::<Vec<_>>();
+        let min = |f: fn(&(f64, f64)) -> f64| points.iter().map(f).fold(f64::INFINITY, f64::min);
+        let max =
+            |f: fn(&(f64, f64)) -> f64| points.iter().map(f).fold(f64::NEG_INFINITY, f64::max);
+        // The bounds are widened so that the window includes the pixels of points on its edge.
+        const MARGIN: f64 = 1e-9;
+        let bounds = (
+            min(|point| point.0) - MARGIN,
+            min(|point| point.1) - MARGIN,
+            max(|point| point.0) + MARGIN,
+            max(|point| point.1) + MARGIN,
+        );
+        conversion
+            .joins
+            .iter()
+            .map(|join| join.values(bounds, &points))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let h3 = conversion
+        .h3
+        .map(|resolution| {
+            lat.iter()
+                .zip(&lon)
+                .map(|(&lat, &lon)| Ok(u64::from(LatLng::new(lat, lon)?.to_cell(resolution))))
+                .collect::<Result<Vec<_>, InvalidLatLng>>()
+        })
+        .transpose()?;
+    let s2 = conversion.s2_level.map(|level| {
+        lat.iter()
+            .zip(&lon)
+            .map(|(&lat, &lon)| {
+                CellID::from(S2LatLng::from_degrees(lat, lon))
+                    .parent(level)
+                    .0
+            })
+            .collect::<Vec<_>>()
+    });
+    let map_tiles = conversion.map_tiles.map(|(zoom, format)| {
+        lat.iter()
+            .zip(&lon)
+            .map(|(&lat, &lon)| {
+                let (x, y) = mercator::tile(lat, lon, zoom);
+                mercator::tile_id(format, zoom, x, y)
+            })
+            .collect::<Vec<_>>()
+    });
+    // Nodata values that are not integers do not occur in the integer bands.
+    let nulls = (conversion.nodata == Nodata::Null).then(|| {
+        nodata
+            .iter()
+            .map(|nodata| {
+                nodata
+                    .filter(|nodata| nodata.fract() == 0.)
+                    .map(|nodata| nodata as i32)
+            })
+            .collect()
+    });
+    // Record the version of the source file, so that consumers can cite the data currency.
+    let source = [
+        ("source.key", &upstream.key),
+        ("source.etag", &upstream.etag),
+        ("source.last_modified", &upstream.last_modified),
+    ];
+    key_value_metadata.extend(source.iter().filter_map(|(key, value)| {
+        value
+            .as_ref()
+            .map(|value| KeyValue::new(key.to_string(), value.clone()))
+    }));
+    if let Some(crs) = &conversion.geoparquet {
+        let bbox = (!lat.is_empty()).then(|| {
+            let min = |values: &[f64]| values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = |values: &[f64]| values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (min(&lon), min(&lat), max(&lon), max(&lat))
+        });
+        key_value_metadata.push(KeyValue::new(
+            GEO.to_string(),
+            geoparquet::metadata(crs, bbox),
+        ));
+    }
+    Ok(Points {
+        lat,
+        lon,
+        projected,
+        pixel_indices,
+        bands,
+        nulls,
+        derived,
+        hillshade,
+        cell_area,
+        mask,
+        countries,
+        joins,
+        h3,
+        s2,
+        map_tiles,
+        groups,
+        out_of_range,
+        key_value_metadata,
+    })
+}
+
+/// Returns the number of `data` values of `band` (other than `nodata`) outside the `range`,
+/// replacing them by `nodata` when `out_of_range` is to replace them.
+fn check_range(
+    band: &Band,
+    data: &mut [i32],
+    nodata: Option<f64>,
+    range: ValidRange,
+    out_of_range: OutOfRange,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let replacement = match (out_of_range, nodata) {
+        (OutOfRange::Nodata, Some(nodata)) => Some(nodata as i32),
+        (OutOfRange::Nodata, None) => {
+            return Err(format!(
+                "band `{}` has no nodata value to replace values out of range",
+                band.name
+            )
+            .into())
+        }
+        (OutOfRange::Flag, _) => None,
+    };
+    let mut count = 0;
+    for value in data
+        .iter_mut()
+        .filter(|value| nodata.map_or(true, |nodata| **value as f64 != nodata))
+        .filter(|value| !range.contains(**value))
+    {
+        count += 1;
+        if let Some(replacement) = replacement {
+            *value = replacement;
+        }
+    }
+    if count > 0 {
+        event!(
+            Level::WARN,
+            "{} values of band `{}` outside {}..{}",
+            count,
+            band.name,
+            range.min,
+            range.max
+        );
+    }
+    Ok(count)
+}
+
+/// Converts the GeoTIFF file at `input_path` to a Parquet file at `output_path`, which is
+/// converted from the `upstream` version of the source file.
+///
+/// Returns false when an existing file was kept.
+#[instrument(fields(key = %input_path.file_stem().unwrap().to_str().unwrap()), skip(input_path, output_path, upstream, conversion, usage), err)]
+pub fn write_parquet(
+    input_path: PathBuf,
+    output_path: PathBuf,
+    upstream: &Upstream,
+    conversion: Arc<Conversion>,
+    usage: Arc<Usage>,
+) -> Result<bool, Box<dyn Error>> {
+    // Verify existing files by reading their footer.
+    if !conversion
+        .if_exists
+        .keep(&output_path, || valid_parquet(&output_path))?
+    {
+        let schema = Arc::new(conversion.arrow_schema());
+        let mut writer = None;
+        let mut out_of_range = 0;
+        for rows in conversion.chunks(&input_path)? {
+            let points = read_points(&input_path, upstream, &conversion, rows)?;
+            out_of_range += points.out_of_range;
+            // The whole chunk (by default the tile) is one row group. A tile without points has
+            // no row groups.
+            let row_group_size = conversion
+                .row_group_size
+                .unwrap_or_else(|| points.lat.len())
+                .max(1);
+            let batches = batches(&points, &conversion, schema.clone(), row_group_size)?;
+            // The key-value metadata of the chunks is the same. The writer adds the
+            // `ARROW:schema` key, encoded like that of the conversion.
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => writer.insert(ArrowWriter::try_new(
+                    File::create(&output_path)?,
+                    schema.clone(),
+                    Some(conversion.writer_properties(points.key_value_metadata.clone())),
+                )?),
+            };
+            for batch in &batches {
+                writer.write(batch)?;
+            }
+        }
+        let writer = writer.ok_or("a tile has at least one chunk")?;
+        // The file is left without a footer, as by a crash.
+        #[cfg(feature = "chaos")]
+        if crate::chaos::fail(crate::chaos::Stage::Convert) {
+            return Err("injected failure".into());
+        }
+        writer.close()?;
+
+        usage
+            .converted_read
+            .fetch_add(input_path.metadata()?.len(), Ordering::Relaxed);
+        usage
+            .converted_written
+            .fetch_add(output_path.metadata()?.len(), Ordering::Relaxed);
+        usage
+            .out_of_range
+            .fetch_add(out_of_range, Ordering::Relaxed);
+        Ok(true)
+    } else {
+        event!(Level::WARN, "Skipping Parquet. File already exists.",);
+        Ok(false)
+    }
+}
+
+/// Converts the GeoTIFF file at `input_path` to Arrow record batches of at most `batch_rows`
+/// rows, for outputs other than Parquet files. The batches have the Arrow schema of the Parquet
+/// files, with their key-value metadata as schema metadata.
+pub fn record_batches(
+    input_path: &Path,
+    upstream: &Upstream,
+    conversion: &Conversion,
+    batch_rows: usize,
+) -> Result<Vec<RecordBatch>, Box<dyn Error>> {
+    let mut record_batches = Vec::new();
+    for rows in conversion.chunks(input_path)? {
+        let points = read_points(input_path, upstream, conversion, rows)?;
+        // The Arrow schema is not repeated in the metadata.
+        let metadata = conversion
+            .key_value_metadata
+            .iter()
+            .chain(&points.key_value_metadata)
+            .filter(|kv| kv.key != "ARROW:schema")
+            .filter_map(|kv| kv.value.clone().map(|value| (kv.key.clone(), value)))
+            .collect::<HashMap<_, _>>();
+        let schema = Arc::new(Schema::new_with_metadata(
+            conversion.arrow_schema().fields().clone(),
+            metadata,
+        ));
+        record_batches.extend(batches(&points, conversion, schema, batch_rows)?);
+    }
+    Ok(record_batches)
+}
+
+/// Returns the points in record batches of at most `batch_rows` rows with the `schema`, the
+/// Arrow schema of the conversion (with or without schema metadata).
+fn batches(
+    points: &Points,
+    conversion: &Conversion,
+    schema: SchemaRef,
+    batch_rows: usize,
+) -> Result<Vec<RecordBatch>, Box<dyn Error>> {
+    let rows = points.lat.len();
+    // The batches do not span sub-tiles.
+    let starts = points.groups.clone().unwrap_or_else(|| vec![0]);
+    let ends = starts
+        .iter()
+        .skip(1)
+        .copied()
+        .chain([rows])
+        .collect::<Vec<_>>();
+    starts
+        .into_iter()
+        .zip(ends)
+        .flat_map(|(start, end)| {
+            (start..end)
+                .step_by(batch_rows)
+                .map(move |start| start..(start + batch_rows).min(end))
+        })
+        .map(|range| {
+            let coordinates = match &points.projected {
+                Some((x, y)) => [x, y],
+                None => [&points.lat, &points.lon],
+            };
+            let coordinates = coordinates
+                .iter()
+                .map(|values| {
+                    Arc::new(Float64Array::from(values[range.clone()].to_vec())) as ArrayRef
+                })
+                .collect::<Vec<_>>();
+            let mut columns = match (conversion.coords, schema.field(0).data_type()) {
+                (Coords::Flat, _) => coordinates,
+                (Coords::Struct, DataType::Struct(fields)) => vec![Arc::new(StructArray::from(
+                    fields.iter().cloned().zip(coordinates).collect::<Vec<_>>(),
+                )) as ArrayRef],
+                (Coords::Struct, _) => unreachable!(),
+                (Coords::None, _) => Vec::new(),
+            };
+            if let Some((rows, cols)) = &points.pixel_indices {
+                columns.extend([rows, cols].iter().map(|values| {
+                    let values = values[range.clone()].iter().copied().map(Some);
+                    ColumnType::UInt16.array(&values.collect::<Vec<_>>(), |value| value)
+                }));
+            }
+            columns.extend(points.bands.iter().enumerate().map(|(index, values)| {
+                let null = points.nulls.as_ref().and_then(|nulls| nulls[index]);
+                let values = values[range.clone()]
+                    .iter()
+                    .map(|&value| (Some(value) != null).then(|| value))
+                    .collect::<Vec<_>>();
+                conversion.band_columns[index].array(&values, |value| conversion.scaled(value))
+            }));
+            columns.extend(points.derived.iter().map(|values| {
+                Arc::new(Float32Array::from(values[range.clone()].to_vec())) as ArrayRef
+            }));
+            if let Some(hillshade) = &points.hillshade {
+                let values = hillshade[range.clone()].to_vec();
+                columns.push(Arc::new(UInt8Array::from(values)) as ArrayRef);
+            }
+            if let Some(cell_area) = &points.cell_area {
+                let values = cell_area[range.clone()].to_vec();
+                columns.push(Arc::new(Float64Array::from(values)) as ArrayRef);
+            }
+            if let Some(mask) = &points.mask {
+                let values = mask[range.clone()].iter().map(|&flags| flags as u8);
+                columns.push(Arc::new(UInt8Array::from(values.collect::<Vec<_>>())) as ArrayRef);
+            }
+            if let (Some(countries), Some(index)) = (&points.countries, &conversion.countries) {
+                let values = countries[range.clone()]
+                    .iter()
+                    .map(|code| code.map(|code| index.codes[code as usize].as_str()));
+                columns.push(Arc::new(StringArray::from(values.collect::<Vec<_>>())) as ArrayRef);
+            }
+            columns.extend(points.joins.iter().map(|values| {
+                Arc::new(Float64Array::from(values[range.clone()].to_vec())) as ArrayRef
+            }));
+            columns.extend(points.h3.iter().chain(&points.s2).map(|cells| {
+                Arc::new(UInt64Array::from(cells[range.clone()].to_vec())) as ArrayRef
+            }));
+            if let Some(map_tiles) = &points.map_tiles {
+                let values = map_tiles[range.clone()].iter().map(String::as_str);
+                columns.push(Arc::new(StringArray::from(values.collect::<Vec<_>>())) as ArrayRef);
+            }
+            if conversion.geoparquet.is_some() {
+                let values = points.lat[range.clone()]
+                    .iter()
+                    .zip(&points.lon[range.clone()])
+                    .map(|(&lat, &lon)| geoparquet::wkb_point(lon, lat))
+                    .collect::<Vec<_>>();
+                let values = values.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                columns.push(Arc::new(BinaryArray::from(values)) as ArrayRef);
+            }
+            Ok(RecordBatch::try_new(schema.clone(), columns)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_range_from_str() {
+        let range = "-500..9000".parse::<ValidRange>().unwrap();
+        assert_eq!((range.min, range.max), (-500, 9000));
+        assert!(range.contains(-500));
+        assert!(range.contains(9000));
+        assert!(!range.contains(-501));
+        assert!(!range.contains(9001));
+        let range = "7..7".parse::<ValidRange>().unwrap();
+        assert!(range.contains(7));
+        assert!("9000..-500".parse::<ValidRange>().is_err());
+        assert!("-500-9000".parse::<ValidRange>().is_err());
+        assert!("..9000".parse::<ValidRange>().is_err());
+        assert!("-500..".parse::<ValidRange>().is_err());
+        assert!("-500.5..9000".parse::<ValidRange>().is_err());
+    }
+
+    #[test]
+    fn check_range_replaces_values() {
+        let band = Band {
+            index: 1,
+            name: "elevation".to_string(),
+            unit: "m".to_string(),
+        };
+        let range = ValidRange {
+            min: -500,
+            max: 9000,
+        };
+        let mut data = vec![-9999, -501, 0, 9000, 9001];
+        let count = check_range(&band, &mut data, Some(-9999.), range, OutOfRange::Flag).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(data, [-9999, -501, 0, 9000, 9001]);
+        let count = check_range(&band, &mut data, Some(-9999.), range, OutOfRange::Nodata).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(data, [-9999, -9999, 0, 9000, -9999]);
+        assert!(check_range(&band, &mut data, None, range, OutOfRange::Nodata).is_err());
+    }
+
+    #[test]
+    fn clamp_replacement() {
+        let range = Some(ValidRange {
+            min: -500,
+            max: 9000,
+        });
+        assert_eq!(Sentinel::Clamp.replacement(-9999., range), Some(-500));
+        assert_eq!(Sentinel::Clamp.replacement(32767., range), Some(9000));
+        assert_eq!(Sentinel::Clamp.replacement(-9999., None), None);
+        assert_eq!(
+            Sentinel::Value(-32768).replacement(-9999., None),
+            Some(-32768)
+        );
+    }
+
+    #[test]
+    fn parse_compression_levels() {
+        assert_eq!(parse_compression("none"), Ok(Compression::UNCOMPRESSED));
+        assert_eq!(parse_compression("snappy"), Ok(Compression::SNAPPY));
+        assert_eq!(parse_compression("lz4"), Ok(Compression::LZ4));
+        assert_eq!(
+            parse_compression("gzip"),
+            Ok(Compression::GZIP(GzipLevel::default()))
+        );
+        assert_eq!(
+            parse_compression("zstd"),
+            Ok(Compression::ZSTD(ZstdLevel::default()))
+        );
+        assert_eq!(
+            parse_compression("brotli"),
+            Ok(Compression::BROTLI(BrotliLevel::default()))
+        );
+        assert_eq!(
+            parse_compression("zstd:7"),
+            Ok(Compression::ZSTD(ZstdLevel::try_new(7).unwrap()))
+        );
+        assert_eq!(
+            parse_compression("gzip:9"),
+            Ok(Compression::GZIP(GzipLevel::try_new(9).unwrap()))
+        );
+        assert_eq!(
+            parse_compression("brotli:0"),
+            Ok(Compression::BROTLI(BrotliLevel::try_new(0).unwrap()))
+        );
+        assert!(parse_compression("zstd:23").is_err());
+        assert!(parse_compression("gzip:10").is_err());
+        assert!(parse_compression("brotli:12").is_err());
+        assert!(parse_compression("zstd:-1").is_err());
+        assert!(parse_compression("zstd:").is_err());
+        assert!(parse_compression("snappy:1").is_err());
+        assert!(parse_compression("lzo").is_err());
+        assert!(parse_compression("ZSTD").is_err());
+    }
+
+    #[test]
+    fn format_compression_round_trips() {
+        for s in [
+            "none",
+            "snappy",
+            "lz4",
+            "gzip",
+            "gzip:9",
+            "zstd",
+            "zstd:7",
+            "brotli",
+            "brotli:11",
+        ] {
+            assert_eq!(format_compression(parse_compression(s).unwrap()), s);
+        }
+        // Default levels are not repeated.
+        assert_eq!(
+            format_compression(parse_compression("zstd:1").unwrap()),
+            "zstd"
+        );
+        assert_eq!(
+            format_compression(parse_compression("gzip:6").unwrap()),
+            "gzip"
+        );
+        assert_eq!(codec_name(Compression::LZ4_RAW), "lz4_raw");
+    }
+
+    #[test]
+    fn parse_encoding_columns() {
+        assert_eq!(
+            parse_encoding("elevation=delta_binary_packed"),
+            Ok(("elevation".to_string(), Encoding::DELTA_BINARY_PACKED))
+        );
+        assert_eq!(
+            parse_encoding("lat=plain"),
+            Ok(("lat".to_string(), Encoding::PLAIN))
+        );
+        assert_eq!(
+            parse_encoding("location.lon=byte_stream_split"),
+            Ok(("location.lon".to_string(), Encoding::BYTE_STREAM_SPLIT))
+        );
+        assert!(parse_encoding("=plain").is_err());
+        assert!(parse_encoding("lat").is_err());
+        assert!(parse_encoding("lat=rle").is_err());
+        assert!(parse_encoding("lat=PLAIN").is_err());
+    }
+
+    #[test]
+    fn check_options_after_max_memory() {
+        let band = Band {
+            index: 1,
+            name: "elevation".to_string(),
+            unit: "m".to_string(),
+        };
+        let conversion = || {
+            Conversion::new(
+                vec![band.clone()],
+                None,
+                IfExists::Verify,
+                None,
+                Coords::Flat,
+                false,
+            )
+            .unwrap()
+        };
+        assert!(conversion()
+            .with_max_memory(1 << 20)
+            .check_options()
+            .is_ok());
+        // The sort order is set after the memory limit.
+        assert!(conversion()
+            .with_max_memory(1 << 20)
+            .with_sort(SortOrder::Hilbert)
+            .check_options()
+            .is_err());
+        assert!(conversion()
+            .with_sort(SortOrder::Hilbert)
+            .check_options()
+            .is_ok());
+    }
+
+    #[test]
+    fn write_parquet_bands() {
+        use arrow::array::{Array, Int32Array};
+        use gdal::{raster::Buffer, Driver};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let dir = std::env::temp_dir().join(format!("aw3d30-convert-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("ALPSMLC30_N052E004_DSM.tif");
+        // A raster of 3 by 2 pixels with two bands.
+        let mut dataset = Driver::get("GTiff")
+            .unwrap()
+            .create_with_band_type::<i16, _>(&*input_path.to_string_lossy(), 3, 2, 2)
+            .unwrap();
+        dataset
+            .set_geo_transform(&[4., 0.5, 0., 53., 0., -1.])
+            .unwrap();
+        for (index, values) in [(1, [1, 2, 3, 4, 5, 6]), (2, [-1, -2, -3, -4, -5, -6])] {
+            dataset
+                .rasterband(index)
+                .unwrap()
+                .write((0, 0), (3, 2), &Buffer::<i16>::new((3, 2), values.to_vec()))
+                .unwrap();
+        }
+        drop(dataset);
+
+        let band = |index: isize, name: &str| Band {
+            index,
+            name: name.to_string(),
+            unit: "m".to_string(),
+        };
+        let conversion = Conversion::new(
+            vec![band(1, "elevation"), band(2, "difference")],
+            None,
+            IfExists::Overwrite,
+            None,
+            Coords::Flat,
+            false,
+        )
+        .unwrap();
+        let output_path = dir.join("ALPSMLC30_N052E004_DSM.parquet");
+        assert!(write_parquet(
+            input_path,
+            output_path.clone(),
+            &Upstream::default(),
+            Arc::new(conversion),
+            Arc::new(Usage::default()),
+        )
+        .unwrap());
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output_path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["lat", "lon", "elevation", "difference"]);
+        let values = |index: usize| {
+            let column = batch.column(index).as_any();
+            let column = column.downcast_ref::<Int32Array>().unwrap();
+            assert_eq!(column.null_count(), 0);
+            column.values().to_vec()
+        };
+        // Each band is a column of its own, row by row from the upper-left pixel.
+        assert_eq!(values(2), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(values(3), [-1, -2, -3, -4, -5, -6]);
+        let lat = batch.column(0).as_any().downcast_ref::<Float64Array>();
+        assert_eq!(
+            lat.unwrap().values()[..],
+            [52.5, 52.5, 52.5, 51.5, 51.5, 51.5]
+        );
+        let lon = batch.column(1).as_any().downcast_ref::<Float64Array>();
+        assert_eq!(
+            lon.unwrap().values()[..],
+            [4.25, 4.75, 5.25, 4.25, 4.75, 5.25]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
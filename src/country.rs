@@ -0,0 +1,94 @@
+use crate::polygon::{read_labeled_polygons, Polygon};
+use std::{collections::BTreeSet, error::Error, path::Path};
+use tracing::{event, Level};
+
+/// Cells per degree of the lookup grid. Points get the country of the center of their cell,
+/// which is about 1 km wide at the equator.
+const CELLS_PER_DEGREE: f64 = 120.;
+
+/// Country boundaries, to look up the country code of points.
+///
+/// The countries are looked up for the cells of a low-resolution grid instead of for every
+/// point, so points within about 500 m of a border can get the code of the neighbouring
+/// country.
+pub struct CountryIndex {
+    /// Country codes, sorted
+    pub codes: Vec<String>,
+    /// Boundaries with the index of their code
+    polygons: Vec<(u16, Polygon)>,
+}
+
+impl CountryIndex {
+    /// Reads the country boundaries of the vector file at `path` (e.g. Natural Earth admin 0
+    /// countries), with the country code in the `field` attribute.
+    pub fn open(path: &Path, field: &str) -> Result<Self, Box<dyn Error>> {
+        let polygons = read_labeled_polygons(path, field)?;
+        let codes = polygons
+            .iter()
+            .map(|(code, _)| code.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if codes.is_empty() {
+            return Err(format!("no countries with a `{}` in `{}`", field, path.display()).into());
+        }
+        if codes.len() > u16::MAX as usize {
+            return Err("too many country codes".into());
+        }
+        event!(
+            Level::INFO,
+            "Read {} boundaries of {} countries",
+            polygons.len(),
+            codes.len()
+        );
+        let polygons = polygons
+            .into_iter()
+            .map(|(code, polygon)| (codes.binary_search(&code).unwrap() as u16, polygon))
+            .collect();
+        Ok(CountryIndex { codes, polygons })
+    }
+
+    /// Returns the index in [`CountryIndex::codes`] of the country of every point, or `None` for
+    /// points outside the countries.
+    pub fn lookup(&self, lat: &[f64], lon: &[f64]) -> Vec<Option<u16>> {
+        if lat.is_empty() {
+            return Vec::new();
+        }
+        let cell = |value: f64| (value * CELLS_PER_DEGREE).floor() as i64;
+        let min = |values: &[f64]| values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = |values: &[f64]| values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let (min_row, max_row) = (cell(min(lat)), cell(max(lat)));
+        let (min_col, max_col) = (cell(min(lon)), cell(max(lon)));
+        // Only the boundaries that overlap the points are tested.
+        let extent = (
+            min_col as f64 / CELLS_PER_DEGREE,
+            min_row as f64 / CELLS_PER_DEGREE,
+            (max_col + 1) as f64 / CELLS_PER_DEGREE,
+            (max_row + 1) as f64 / CELLS_PER_DEGREE,
+        );
+        let candidates = self
+            .polygons
+            .iter()
+            .filter(|(_, polygon)| polygon.intersects(extent))
+            .collect::<Vec<_>>();
+
+        // The country of a cell is looked up once, for the first point in it.
+        let width = (max_col - min_col + 1) as usize;
+        let mut cells = vec![None; width * (max_row - min_row + 1) as usize];
+        lat.iter()
+            .zip(lon)
+            .map(|(&lat, &lon)| {
+                let (row, col) = (cell(lat), cell(lon));
+                *cells[(row - min_row) as usize * width + (col - min_col) as usize]
+                    .get_or_insert_with(|| {
+                        let lat = (row as f64 + 0.5) / CELLS_PER_DEGREE;
+                        let lon = (col as f64 + 0.5) / CELLS_PER_DEGREE;
+                        candidates
+                            .iter()
+                            .find(|(_, polygon)| polygon.contains(lon, lat))
+                            .map(|&&(code, _)| code)
+                    })
+            })
+            .collect()
+    }
+}
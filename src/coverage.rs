@@ -0,0 +1,76 @@
+use crate::{job::parquet_name, manifest::Manifest, Coordinate};
+use serde_json::json;
+use std::{fs::File, io, path::Path};
+
+/// Status of a tile in an output dir.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Selected, but not converted yet
+    Selected,
+    /// Converted to a Parquet file
+    Completed,
+    /// Failed to download or convert in the last run
+    Failed,
+}
+
+impl Status {
+    /// Returns the status of the tile with GeoTIFF file `name` at `coordinate`, according to
+    /// the `manifest` of the output dir. The Parquet file may have the bounding box in its name.
+    pub fn of(manifest: &Manifest, name: &str, coordinate: Coordinate) -> Self {
+        if manifest.failed.iter().any(|tile| tile == name) {
+            Status::Failed
+        } else if [None, Some(coordinate)]
+            .iter()
+            .any(|&coordinate| manifest.get(&parquet_name(name, coordinate)).is_some())
+        {
+            Status::Completed
+        } else {
+            Status::Selected
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Selected => "selected",
+            Status::Completed => "completed",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// Writes the footprints of the `tiles` (name, coordinate and status) as a GeoJSON feature
+/// collection to `path`.
+pub fn write_coverage(path: &Path, tiles: &[(String, Coordinate, Status)]) -> io::Result<()> {
+    let features = tiles
+        .iter()
+        .map(|(name, coordinate, status)| {
+            let (lat, lon) = coordinate.degrees();
+            let (lat, lon) = (f64::from(lat), f64::from(lon));
+            json!({
+                "type": "Feature",
+                "properties": {
+                    "tile": name,
+                    "lat": lat,
+                    "lon": lon,
+                    "status": status.as_str(),
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [lon, lat],
+                        [lon + 1., lat],
+                        [lon + 1., lat + 1.],
+                        [lon, lat + 1.],
+                        [lon, lat],
+                    ]],
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    serde_json::to_writer(io::BufWriter::new(File::create(path)?), &collection)?;
+    Ok(())
+}
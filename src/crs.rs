@@ -0,0 +1,56 @@
+//! Transformation of the coordinates of the points to another coordinate reference system.
+
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use std::error::Error;
+
+/// Coordinate reference system the coordinates of the points are transformed to.
+#[derive(Clone, Debug)]
+pub struct TargetCrs {
+    /// Definition of the CRS, e.g. `EPSG:3857` or a PROJ string
+    definition: String,
+    /// PROJJSON of the CRS
+    projjson: String,
+}
+
+impl TargetCrs {
+    /// Returns the CRS of `definition`, anything GDAL accepts as a user input (e.g. `EPSG:3857`,
+    /// a PROJ string or WKT).
+    pub fn new(definition: &str) -> Result<Self, Box<dyn Error>> {
+        let projjson = SpatialRef::from_definition(definition)?.to_projjson()?;
+        Ok(TargetCrs {
+            definition: definition.to_string(),
+            projjson,
+        })
+    }
+
+    /// Returns the definition of the CRS.
+    pub fn definition(&self) -> &str {
+        &self.definition
+    }
+
+    /// Returns the PROJJSON of the CRS.
+    pub fn projjson(&self) -> &str {
+        &self.projjson
+    }
+
+    /// Returns the coordinates of the points at `lat` and `lon` in the CRS, in the order of
+    /// the axes of the CRS (e.g. easting and northing).
+    pub fn transform(
+        &self,
+        lat: &[f64],
+        lon: &[f64],
+    ) -> Result<(Vec<f64>, Vec<f64>), Box<dyn Error>> {
+        if lat.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        // The axes of CRS84 are longitude and latitude, unlike those of EPSG:4326.
+        let source = SpatialRef::from_definition("OGC:CRS84")?;
+        let target = SpatialRef::from_definition(&self.definition)?;
+        let transform = CoordTransform::new(&source, &target)?;
+        let mut x = lon.to_vec();
+        let mut y = lat.to_vec();
+        let mut z = vec![0.; lat.len()];
+        transform.transform_coords(&mut x, &mut y, &mut z)?;
+        Ok((x, y))
+    }
+}
@@ -0,0 +1,168 @@
+//! Orders of the points along space-filling curves, so that the points of a row group are close
+//! together and readers can prune row groups of spatial range queries by their statistics.
+
+use std::{fmt, str::FromStr};
+
+/// Order of the points of a tile.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SortOrder {
+    /// Along a Hilbert curve
+    Hilbert,
+    /// Along a Z-order (Morton) curve
+    ZOrder,
+    /// By latitude, then longitude
+    LatLon,
+}
+
+impl FromStr for SortOrder {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hilbert" => Ok(Self::Hilbert),
+            "zorder" => Ok(Self::ZOrder),
+            "latlon" => Ok(Self::LatLon),
+            _ => Err("expected one of: hilbert, zorder, latlon"),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hilbert => "hilbert",
+            Self::ZOrder => "zorder",
+            Self::LatLon => "latlon",
+        })
+    }
+}
+
+/// Returns the cell of a global grid of 2^32 by 2^32 cells (about 1 cm at the equator) that
+/// contains the point.
+fn cell(lat: f64, lon: f64) -> (u32, u32) {
+    let scale =
+        |value: f64, range: f64| (value / range * 2_f64.powi(32)).clamp(0., u32::MAX as f64) as u32;
+    (scale(lon + 180., 360.), scale(lat + 90., 180.))
+}
+
+/// Returns the distance of the cell (`x`, `y`) along the Hilbert curve that fills the grid.
+fn hilbert(mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = 1_u32 << 31;
+    while s > 0 {
+        let rx = x & s != 0;
+        let ry = y & s != 0;
+        d += u64::from(s) * u64::from(s) * ((3 * rx as u64) ^ ry as u64);
+        // Rotate the quadrant, so that the curve is continuous.
+        if !ry {
+            if rx {
+                x = !x;
+                y = !y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s >>= 1;
+    }
+    d
+}
+
+/// Returns the position of the cell (`x`, `y`) along the Z-order curve, its interleaved bits.
+fn zorder(x: u32, y: u32) -> u64 {
+    let spread = |value: u32| {
+        let mut value = u64::from(value);
+        value = (value | value << 16) & 0x0000_ffff_0000_ffff;
+        value = (value | value << 8) & 0x00ff_00ff_00ff_00ff;
+        value = (value | value << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+        value = (value | value << 2) & 0x3333_3333_3333_3333;
+        (value | value << 1) & 0x5555_5555_5555_5555
+    };
+    spread(x) | spread(y) << 1
+}
+
+/// Returns the indices of the points (`lat`, `lon`) in `order`.
+pub fn sorted_indices(order: SortOrder, lat: &[f64], lon: &[f64]) -> Vec<usize> {
+    let mut indices = (0..lat.len()).collect::<Vec<_>>();
+    match order {
+        SortOrder::Hilbert | SortOrder::ZOrder => {
+            let curve = if order == SortOrder::Hilbert {
+                hilbert
+            } else {
+                zorder
+            };
+            let keys = lat
+                .iter()
+                .zip(lon)
+                .map(|(&lat, &lon)| {
+                    let (x, y) = cell(lat, lon);
+                    curve(x, y)
+                })
+                .collect::<Vec<_>>();
+            indices.sort_by_key(|&index| keys[index]);
+        }
+        SortOrder::LatLon => indices.sort_by(|&a, &b| {
+            lat[a]
+                .total_cmp(&lat[b])
+                .then_with(|| lon[a].total_cmp(&lon[b]))
+        }),
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_cover_the_globe() {
+        assert_eq!(cell(-90., -180.), (0, 0));
+        assert_eq!(cell(90., 180.), (u32::MAX, u32::MAX));
+        assert_eq!(cell(0., 0.), (1 << 31, 1 << 31));
+    }
+
+    #[test]
+    fn hilbert_curve_is_continuous() {
+        let s = 1_u32 << 31;
+        let quadrant = |x, y| hilbert(x, y) / (u64::from(s) * u64::from(s));
+        assert_eq!(
+            [(0, 0), (0, s), (s, s), (s, 0)].map(|(x, y)| quadrant(x, y)),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(hilbert(u32::MAX, 0), u64::MAX);
+        // The cells of the corner block are visited one by one, from neighbour to neighbour.
+        let mut cells = (0..8)
+            .flat_map(|x| (0..8).map(move |y| (hilbert(x, y), x, y)))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        assert!(cells.iter().map(|&(d, ..)| d).eq(0..64));
+        for pair in cells.windows(2) {
+            let (_, x1, y1) = pair[0];
+            let (_, x2, y2) = pair[1];
+            assert_eq!(
+                (x1 as i64 - x2 as i64).abs() + (y1 as i64 - y2 as i64).abs(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn zorder_interleaves_bits() {
+        assert_eq!(zorder(0, 0), 0);
+        assert_eq!(zorder(1, 0), 1);
+        assert_eq!(zorder(0, 1), 2);
+        assert_eq!(zorder(3, 3), 15);
+        assert_eq!(zorder(0b101, 0b010), 0b011001);
+        assert_eq!(zorder(u32::MAX, u32::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn sorted_points() {
+        let lat = [1., 0., 1., 0.];
+        let lon = [1., 1., 0., 0.];
+        assert_eq!(sorted_indices(SortOrder::LatLon, &lat, &lon), [3, 1, 2, 0]);
+        assert_eq!(sorted_indices(SortOrder::ZOrder, &lat, &lon), [3, 1, 2, 0]);
+        assert_eq!(sorted_indices(SortOrder::Hilbert, &lat, &lon), [3, 1, 2, 0]);
+        assert!(sorted_indices(SortOrder::Hilbert, &[], &[]).is_empty());
+        assert_eq!("zorder".parse(), Ok(SortOrder::ZOrder));
+        assert_eq!(SortOrder::LatLon.to_string(), "latlon");
+    }
+}
@@ -0,0 +1,136 @@
+//! Global 30 meter DEMs on the OpenTopography storage server, which share the layout of a
+//! raster file per tile of 1 by 1 degree.
+
+use std::{ops::Range, str::FromStr};
+
+/// DEM to download and convert.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dataset {
+    /// ALOS World 3D 30 meter DEM of JAXA, `ALPSMLC30_N052E004_DSM.tif`
+    Aw3d30,
+    /// Copernicus GLO-30 DEM of ESA, `Copernicus_DSM_10_N52_00_E004_00_DEM.tif`
+    Cop30,
+    /// SRTM GL1 of NASA, `N52E004.hgt`, between 60 degrees south and north
+    SrtmGl1,
+}
+
+impl FromStr for Dataset {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aw3d30" => Ok(Self::Aw3d30),
+            "cop30" => Ok(Self::Cop30),
+            "srtmgl1" => Ok(Self::SrtmGl1),
+            _ => Err("expected one of: aw3d30, cop30, srtmgl1"),
+        }
+    }
+}
+
+impl Dataset {
+    /// Returns the name of the dataset, as accepted by [`FromStr`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Dataset::Aw3d30 => "aw3d30",
+            Dataset::Cop30 => "cop30",
+            Dataset::SrtmGl1 => "srtmgl1",
+        }
+    }
+
+    /// Returns the prefix of the raster files in the [`BUCKET`](crate::BUCKET).
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Dataset::Aw3d30 => crate::PREFIX,
+            Dataset::Cop30 => "COP30/COP30_hh/",
+            Dataset::SrtmGl1 => "SRTM_GL1/SRTM_GL1_srtm/",
+        }
+    }
+
+    /// Returns the prefix of the dirs of the releases of the dataset, e.g. `AW3D30/`, of which
+    /// the [`prefix`](Self::prefix) is the default release.
+    pub fn releases(self) -> &'static str {
+        let prefix = self.prefix();
+        let end = prefix[..prefix.len() - 1]
+            .rfind('/')
+            .map_or(0, |idx| idx + 1);
+        &prefix[..end]
+    }
+
+    /// Returns the name of the default release, e.g. `AW3D30_global`.
+    pub fn release(self) -> &'static str {
+        self.prefix()[self.releases().len()..].trim_end_matches('/')
+    }
+
+    /// Returns the name of the raster file of the tile with its south-west corner at `lat` and
+    /// `lon`.
+    pub fn tile_name(self, lat: i16, lon: i16) -> String {
+        match self {
+            Dataset::Aw3d30 => format!("ALPSMLC30_{}_DSM.tif", corner_name(lat, lon)),
+            Dataset::Cop30 => format!(
+                "Copernicus_DSM_10_{}{:02}_00_{}{:03}_00_DEM.tif",
+                if lat < 0 { 'S' } else { 'N' },
+                lat.abs(),
+                if lon < 0 { 'W' } else { 'E' },
+                lon.abs()
+            ),
+            Dataset::SrtmGl1 => format!(
+                "{}{:02}{}{:03}.hgt",
+                if lat < 0 { 'S' } else { 'N' },
+                lat.abs(),
+                if lon < 0 { 'W' } else { 'E' },
+                lon.abs()
+            ),
+        }
+    }
+
+    /// Returns the latitudes of the south-west corners of the tiles that the dataset covers.
+    pub fn latitudes(self) -> Range<i16> {
+        match self {
+            Dataset::Aw3d30 | Dataset::Cop30 => -90..90,
+            Dataset::SrtmGl1 => -60..60,
+        }
+    }
+
+    /// Returns the names of the raster files of all tiles that may exist, within the coverage of
+    /// the dataset.
+    pub fn tile_names(self) -> impl Iterator<Item = String> {
+        self.latitudes()
+            .flat_map(move |lat| (-180..180).map(move |lon| self.tile_name(lat, lon)))
+    }
+
+    /// Returns the nodata value of the elevations, when the raster files do not set it.
+    ///
+    /// The COP30 GeoTIFFs have no nodata value, while its product specification reserves
+    /// -32767 for pixels without elevation. GDAL reads -32768 as the nodata value of SRTM.
+    pub fn nodata_value(self) -> Option<f64> {
+        match self {
+            Dataset::Aw3d30 | Dataset::SrtmGl1 => None,
+            Dataset::Cop30 => Some(-32767.),
+        }
+    }
+
+    /// Returns the geoid that the elevations are relative to.
+    pub fn vertical_datum(self) -> &'static str {
+        match self {
+            Dataset::Aw3d30 | Dataset::SrtmGl1 => "EGM96",
+            Dataset::Cop30 => "EGM2008",
+        }
+    }
+
+    /// Returns true when the elevations are floats, which are rounded to whole meters (the
+    /// precision of AW3D30) to read them as integers.
+    pub fn rounded(self) -> bool {
+        self == Dataset::Cop30
+    }
+}
+
+/// Returns the name of the south-west corner at `lat` and `lon`, e.g. `N052E004`.
+pub(crate) fn corner_name(lat: i16, lon: i16) -> String {
+    format!(
+        "{}{:03}{}{:03}",
+        if lat < 0 { 'S' } else { 'N' },
+        lat.abs(),
+        if lon < 0 { 'W' } else { 'E' },
+        lon.abs()
+    )
+}
@@ -0,0 +1,361 @@
+use crate::manifest::Manifest;
+use arrow::datatypes::{DataType, Field, Schema};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{event, Level};
+
+/// Dir of the transaction log in a Delta table.
+const DELTA_LOG: &str = "_delta_log";
+
+/// Number of commits attempted by the process, which names their temporary files apart when
+/// threads commit concurrently.
+static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Delta Lake table of which the Parquet files of one or more Parquet dirs (e.g. one per worker)
+/// in the table dir are the data files.
+///
+/// Every commit is a JSON file with the next version in the transaction log. The file is
+/// created with a hard link, which fails when it exists, so when workers commit concurrently
+/// only one of them commits a version and the others retry on the next version. Appends never
+/// conflict, the files of the other commits are only used to skip the files that are already
+/// in the table.
+pub struct DeltaTable {
+    root: PathBuf,
+}
+
+/// State of a Delta table, replayed from its transaction log.
+#[derive(Default)]
+struct Snapshot {
+    /// Version of the next commit
+    version: u64,
+    /// Sizes of the data files, by path relative to the table dir
+    files: HashMap<String, u64>,
+    /// Schema of the table, as a Spark schema in JSON
+    schema: Option<String>,
+}
+
+impl DeltaTable {
+    /// Returns the Delta table in the dir at `root`, which is created on the first commit.
+    pub fn new(root: &Path) -> Self {
+        DeltaTable {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Commits the files in the manifest of `parquet_dir`, a dir in the table dir, that are not
+    /// in the table or have another size. The table is created with `schema` by the first
+    /// commit, which must match the schema of later commits.
+    ///
+    /// Returns the number of files that were added.
+    pub fn commit(&self, parquet_dir: &Path, schema: &Schema) -> Result<usize, Box<dyn Error>> {
+        let prefix = fs::canonicalize(parquet_dir)?
+            .strip_prefix(fs::canonicalize(&self.root)?)
+            .map_err(|_| {
+                format!(
+                    "`{}` is not in the dir of the Delta table `{}`",
+                    parquet_dir.display(),
+                    self.root.display()
+                )
+            })?
+            .to_path_buf();
+        let manifest = Manifest::load(parquet_dir)?;
+        let schema = spark_schema(schema)?;
+        let log_dir = self.root.join(DELTA_LOG);
+        fs::create_dir_all(&log_dir)?;
+        loop {
+            let snapshot = self.snapshot()?;
+            if snapshot
+                .schema
+                .as_ref()
+                .map_or(false, |table| table != &schema)
+            {
+                return Err(format!(
+                    "the schema of `{}` differs from that of the Delta table",
+                    parquet_dir.display()
+                )
+                .into());
+            }
+            let adds = manifest
+                .files
+                .iter()
+                .map(|file| {
+                    let path = prefix.join(&file.path).to_string_lossy().replace('\\', "/");
+                    (path, file)
+                })
+                .filter(|(path, file)| snapshot.files.get(path) != Some(&file.size))
+                .map(|(path, file)| {
+                    let modified = parquet_dir.join(&file.path).metadata()?.modified()?;
+                    Ok(json!({
+                        "add": {
+                            "path": path,
+                            "partitionValues": {},
+                            "size": file.size,
+                            "modificationTime": millis(modified),
+                            "dataChange": true,
+                            "stats": json!({ "numRecords": file.num_rows }).to_string(),
+                        }
+                    }))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            if adds.is_empty() {
+                event!(Level::INFO, "All files are in the Delta table");
+                return Ok(0);
+            }
+
+            let now = millis(SystemTime::now());
+            let mut actions = vec![json!({
+                "commitInfo": {
+                    "timestamp": now,
+                    "operation": "WRITE",
+                    "operationParameters": { "mode": "Append" },
+                    "isBlindAppend": true,
+                    "engineInfo": concat!("aw3d30-parquet/", env!("CARGO_PKG_VERSION")),
+                }
+            })];
+            if snapshot.version == 0 {
+                actions.push(json!({
+                    "protocol": { "minReaderVersion": 1, "minWriterVersion": 2 }
+                }));
+                actions.push(json!({
+                    "metaData": {
+                        "id": table_id(&self.root, now),
+                        "format": { "provider": "parquet", "options": {} },
+                        "schemaString": schema,
+                        "partitionColumns": [],
+                        "configuration": {},
+                        "createdTime": now,
+                    }
+                }));
+            }
+            let files = adds.len();
+            actions.extend(adds);
+
+            // The commit is written to a temporary file that is linked to its version, so that
+            // it is never read partially.
+            let path = log_dir.join(format!("{:020}.json", snapshot.version));
+            let tmp_path = log_dir.join(format!(
+                ".{:020}.json.{}-{}.tmp",
+                snapshot.version,
+                process::id(),
+                ATTEMPTS.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut tmp = File::create(&tmp_path)?;
+            for action in &actions {
+                writeln!(tmp, "{}", action)?;
+            }
+            tmp.sync_all()?;
+            drop(tmp);
+            let linked = fs::hard_link(&tmp_path, &path);
+            fs::remove_file(&tmp_path)?;
+            match linked {
+                Ok(()) => {
+                    event!(
+                        Level::INFO,
+                        "Committed {} files as version {} of the Delta table",
+                        files,
+                        snapshot.version
+                    );
+                    return Ok(files);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    event!(
+                        Level::INFO,
+                        "Version {} of the Delta table was committed by another writer, retrying",
+                        snapshot.version
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Replays the transaction log of the table.
+    fn snapshot(&self) -> Result<Snapshot, Box<dyn Error>> {
+        let log_dir = self.root.join(DELTA_LOG);
+        let mut versions = fs::read_dir(&log_dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?
+            .into_iter()
+            .filter_map(|name| name.strip_suffix(".json")?.parse::<u64>().ok())
+            .collect::<Vec<_>>();
+        versions.sort_unstable();
+        let mut snapshot = Snapshot::default();
+        for version in versions {
+            // The log is cleaned up after a checkpoint, which is not read.
+            if version != snapshot.version {
+                return Err(format!(
+                    "version {} of the Delta table is missing, checkpoints are not supported",
+                    snapshot.version
+                )
+                .into());
+            }
+            let file = File::open(log_dir.join(format!("{:020}.json", version)))?;
+            for line in BufReader::new(file).lines() {
+                let action = serde_json::from_str::<Value>(&line?)?;
+                if let Some(add) = action.get("add") {
+                    let path = add["path"].as_str().ok_or("add action without a path")?;
+                    let size = add["size"].as_u64().unwrap_or_default();
+                    snapshot.files.insert(path.to_string(), size);
+                } else if let Some(remove) = action.get("remove") {
+                    if let Some(path) = remove["path"].as_str() {
+                        snapshot.files.remove(path);
+                    }
+                } else if let Some(schema) = action["metaData"]["schemaString"].as_str() {
+                    snapshot.schema = Some(schema.to_string());
+                }
+            }
+            snapshot.version = version + 1;
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Returns `schema` as a Spark schema in JSON, the schema of a Delta table.
+fn spark_schema(schema: &Schema) -> Result<String, Box<dyn Error>> {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| spark_field(field))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(json!({ "type": "struct", "fields": fields }).to_string())
+}
+
+/// Returns `field` as a field of a Spark schema.
+fn spark_field(field: &Field) -> Result<Value, Box<dyn Error>> {
+    Ok(json!({
+        "name": field.name(),
+        "type": spark_type(field.data_type())?,
+        "nullable": field.is_nullable(),
+        "metadata": {},
+    }))
+}
+
+/// Returns the Spark type of an Arrow type, as Spark reads it from a Parquet file. Spark has no
+/// unsigned types, they are read as the next larger signed type.
+fn spark_type(data_type: &DataType) -> Result<Value, Box<dyn Error>> {
+    Ok(match data_type {
+        DataType::Boolean => json!("boolean"),
+        DataType::Int8 => json!("byte"),
+        DataType::Int16 | DataType::UInt8 => json!("short"),
+        DataType::Int32 | DataType::UInt16 => json!("integer"),
+        DataType::Int64 | DataType::UInt32 => json!("long"),
+        DataType::UInt64 => json!("decimal(20,0)"),
+        DataType::Float32 => json!("float"),
+        DataType::Float64 => json!("double"),
+        DataType::Utf8 => json!("string"),
+        DataType::Binary => json!("binary"),
+        DataType::Struct(fields) => json!({
+            "type": "struct",
+            "fields": fields
+                .iter()
+                .map(|field| spark_field(field))
+                .collect::<Result<Vec<_>, _>>()?,
+        }),
+        other => return Err(format!("unsupported column type {}", other).into()),
+    })
+}
+
+/// Returns `time` in milliseconds since the Unix epoch.
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Returns a UUID-formatted id for the table at `root`, created at `now`.
+fn table_id(root: &Path, now: u64) -> String {
+    let hash = Sha256::new()
+        .chain(root.to_string_lossy().as_bytes())
+        .chain(now.to_le_bytes())
+        .chain(process::id().to_le_bytes())
+        .finalize();
+    let hex = format!("{:x}", hash);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{ManifestFile, Upstream};
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    #[test]
+    fn concurrent_commits() {
+        let root = std::env::temp_dir().join(format!("aw3d30-delta-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let schema = Schema::new(vec![Field::new("elevation", DataType::Int16, false)]);
+        let workers = ["worker-0", "worker-1"];
+        for (worker, name) in workers.iter().zip(["N052E004.parquet", "N052E005.parquet"]) {
+            let dir = root.join(worker);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(name), name).unwrap();
+            let mut manifest = Manifest::default();
+            manifest.insert(ManifestFile {
+                path: name.to_string(),
+                size: name.len() as u64,
+                sha256: String::new(),
+                num_rows: Some(1),
+                coordinate: None,
+                bbox: None,
+                tool_version: None,
+                upstream: Upstream::default(),
+            });
+            manifest.save(&dir).unwrap();
+        }
+
+        // Both workers read the log before either of them commits.
+        let barrier = Arc::new(Barrier::new(workers.len()));
+        let commits = workers
+            .iter()
+            .map(|worker| {
+                let (root, dir) = (root.clone(), root.join(worker));
+                let (barrier, schema) = (barrier.clone(), schema.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    DeltaTable::new(&root)
+                        .commit(&dir, &schema)
+                        .map_err(|e| e.to_string())
+                })
+            })
+            .collect::<Vec<_>>();
+        for commit in commits {
+            assert_eq!(commit.join().unwrap(), Ok(1));
+        }
+
+        let snapshot = DeltaTable::new(&root).snapshot().unwrap();
+        assert_eq!(snapshot.version, 2);
+        let mut paths = snapshot
+            .files
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            ["worker-0/N052E004.parquet", "worker-1/N052E005.parquet"]
+        );
+        assert!(snapshot.schema.is_some());
+        // The temporary files of the commits are removed.
+        assert_eq!(fs::read_dir(root.join(DELTA_LOG)).unwrap().count(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    column::read_band, convert::valid_parquet, reference::Reference, tile_coordinate, Band,
+    IfExists, Source, Usage, TILE_PATTERN,
+};
+use futures::{stream, StreamExt};
+use gdal::Dataset;
+use parquet::{
+    basic::{self, Repetition},
+    column::writer::ColumnWriter,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use regex::Regex;
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::Path,
+    sync::Arc,
+};
+use tokio::task;
+use tracing::{event, Level};
+
+/// Points of a tile with an elevation in both DEMs.
+#[derive(Default)]
+struct Differences {
+    lat: Vec<f64>,
+    lon: Vec<f64>,
+    elevation: Vec<i32>,
+    reference: Vec<f64>,
+    difference: Vec<f64>,
+}
+
+/// Computes the difference between `band` of the tile at `path` and the `reference` at the
+/// center of every pixel, skipping nodata pixels (the band's nodata value when `nodata` is
+/// `None`). The coordinates are of the center of the pixels, like the conversion.
+/// Float bands are `rounded` or refused.
+fn diff_tile(
+    path: &Path,
+    band: &Band,
+    nodata: Option<f64>,
+    rounded: bool,
+    reference: &Reference,
+) -> Result<Differences, Box<dyn Error>> {
+    let dataset = Dataset::open(path)?;
+    let gt = dataset.geo_transform()?;
+    let (x_size, y_size) = dataset.raster_size();
+    let rasterband = dataset.rasterband(band.index)?;
+    let nodata = nodata.or_else(|| rasterband.no_data_value());
+    let (_, data) = read_band(&rasterband, rounded).map_err(|e| e as Box<dyn Error>)?;
+
+    let centers = (0..y_size)
+        .flat_map(|y| {
+            (0..x_size).map(move |x| {
+                (
+                    gt[0] + (x as f64 + 0.5) * gt[1],
+                    gt[3] + (y as f64 + 0.5) * gt[5],
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let bounds = (
+        gt[0],
+        gt[3] + y_size as f64 * gt[5],
+        gt[0] + x_size as f64 * gt[1],
+        gt[3],
+    );
+    let samples = reference.sample(bounds, &centers)?;
+
+    let mut differences = Differences::default();
+    for ((&value, sample), &(lon, lat)) in data.iter().zip(samples).zip(&centers) {
+        let sample = match sample {
+            Some(sample) if nodata.map_or(true, |nodata| value as f64 != nodata) => sample,
+            _ => continue,
+        };
+        differences.lat.push(lat);
+        differences.lon.push(lon);
+        differences.elevation.push(value);
+        differences.reference.push(sample);
+        differences.difference.push(value as f64 - sample);
+    }
+    Ok(differences)
+}
+
+/// Writes the `differences` to a Parquet file at `path`, with the elevation in a column named
+/// after the `band`.
+fn write_differences(
+    path: &Path,
+    band: &Band,
+    differences: &Differences,
+) -> Result<(), Box<dyn Error>> {
+    let field = |name: &str, physical_type| {
+        Arc::new(
+            Type::primitive_type_builder(name, physical_type)
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .unwrap(),
+        )
+    };
+    let fields = vec![
+        field("lat", basic::Type::DOUBLE),
+        field("lon", basic::Type::DOUBLE),
+        field(&band.name, basic::Type::INT32),
+        field("reference", basic::Type::DOUBLE),
+        field("difference", basic::Type::DOUBLE),
+    ];
+    let schema = Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?,
+    );
+
+    let mut writer = SerializedFileWriter::new(
+        File::create(path)?,
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_writer = writer.next_row_group()?;
+    for values in [&differences.lat, &differences.lon] {
+        if let Some(mut col_writer) = row_writer.next_column()? {
+            match col_writer.untyped() {
+                ColumnWriter::DoubleColumnWriter(c) => c.write_batch(values, None, None)?,
+                _ => unreachable!(),
+            };
+            col_writer.close()?;
+        }
+    }
+    if let Some(mut col_writer) = row_writer.next_column()? {
+        match col_writer.untyped() {
+            ColumnWriter::Int32ColumnWriter(c) => {
+                c.write_batch(&differences.elevation, None, None)?
+            }
+            _ => unreachable!(),
+        };
+        col_writer.close()?;
+    }
+    for values in [&differences.reference, &differences.difference] {
+        if let Some(mut col_writer) = row_writer.next_column()? {
+            match col_writer.untyped() {
+                ColumnWriter::DoubleColumnWriter(c) => c.write_batch(values, None, None)?,
+                _ => unreachable!(),
+            };
+            col_writer.close()?;
+        }
+    }
+    row_writer.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes the per-pixel difference between `band` of the tiles that overlap the `reference`
+/// DEM and the reference to a Parquet file per tile in `output`, downloading only those tiles.
+///
+/// Logs the mean difference and the root mean square error per tile.
+#[allow(clippy::too_many_arguments)]
+pub async fn diff(
+    source: &Source,
+    reference: &Path,
+    output: &Path,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    band: &Band,
+    nodata_value: Option<f64>,
+    usage: &Arc<Usage>,
+) -> Result<(), Box<dyn Error>> {
+    let reference = Arc::new(Reference::open(reference)?);
+    let tiles = reference.tiles();
+    let re = Regex::new(TILE_PATTERN)?;
+    let objects = source
+        .list(|key| {
+            tile_coordinate(&re, key)
+                .map_or(false, |coordinate| tiles.contains(&coordinate.degrees()))
+        })
+        .await?;
+    event!(Level::INFO, "Downloading {} files", objects.len());
+    fs::create_dir_all(output)?;
+
+    let rounded = source.dataset().rounded();
+    let mut paths = stream::iter(objects)
+        .map(|object| source.download(object, tif_dir.to_path_buf(), if_exists, usage.clone()))
+        .buffer_unordered(1);
+    while let Some(path) = paths.next().await {
+        let (path, _) = path?.map_err(|e| e as Box<dyn Error>)?;
+        let output_path = output
+            .join(path.file_stem().unwrap())
+            .with_extension("parquet");
+        if if_exists.keep(&output_path, || valid_parquet(&output_path))? {
+            event!(
+                Level::WARN,
+                "Skipping `{}`. File already exists.",
+                output_path.display()
+            );
+            continue;
+        }
+        let (tile, band, reference) = (path.clone(), band.clone(), reference.clone());
+        let differences = task::spawn_blocking(move || {
+            diff_tile(&tile, &band, nodata_value, rounded, &reference).map_err(|e| e.to_string())
+        })
+        .await??;
+        let count = differences.difference.len();
+        if count > 0 {
+            let mean = differences.difference.iter().sum::<f64>() / count as f64;
+            let rmse =
+                (differences.difference.iter().map(|d| d * d).sum::<f64>() / count as f64).sqrt();
+            event!(
+                Level::INFO,
+                "{}: {} points, mean difference {:.2}, RMSE {:.2}",
+                path.file_stem().unwrap().to_string_lossy(),
+                count,
+                mean,
+                rmse
+            );
+        }
+        let band = band.clone();
+        task::spawn_blocking(move || {
+            write_differences(&output_path, &band, &differences).map_err(|e| e.to_string())
+        })
+        .await??;
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+/// Returns the space in bytes that is available to unprivileged users on the file system of
+/// `path`.
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // Safety: statvfs only writes to the provided struct.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Returns true when `a` and `b` are on the same file system.
+pub fn same_file_system(a: &Path, b: &Path) -> io::Result<bool> {
+    Ok(a.metadata()?.dev() == b.metadata()?.dev())
+}
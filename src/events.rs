@@ -0,0 +1,117 @@
+//! Append-only log of the stages of the tiles of a job, as an auditable record of how every file
+//! was produced that does not depend on the configuration of the tracing subscriber.
+
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{event, Level};
+
+/// File name of the event log in the Parquet output dir.
+pub const EVENT_LOG: &str = "_events.jsonl";
+
+/// Stage of a tile.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Download of the GeoTIFF file
+    Download,
+    /// Conversion to a Parquet file
+    Convert,
+    /// Upload of the Parquet file to object storage
+    Upload,
+}
+
+/// Outcome of a stage.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    /// An existing file was kept instead
+    Skipped,
+    Failed,
+}
+
+/// A line of the event log.
+#[derive(Debug, Serialize)]
+pub struct TileEvent<'a> {
+    /// Name of the GeoTIFF file of the tile
+    pub tile: &'a str,
+    pub stage: Stage,
+    /// Start of the stage in seconds since the Unix epoch
+    pub start: f64,
+    /// End of the stage in seconds since the Unix epoch
+    pub end: f64,
+    /// Size of the file that the stage produced or kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> TileEvent<'a> {
+    /// Returns the event of `stage` of `tile`, which started at `start` and ended now with
+    /// `outcome`.
+    pub fn new(tile: &'a str, stage: Stage, start: SystemTime, outcome: Outcome) -> Self {
+        let seconds = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0., |elapsed| elapsed.as_secs_f64())
+        };
+        TileEvent {
+            tile,
+            stage,
+            start: seconds(start),
+            end: seconds(SystemTime::now()),
+            bytes: None,
+            outcome,
+            error: None,
+        }
+    }
+
+    /// Records the size of the file at `path`, if it exists.
+    pub fn with_file(mut self, path: &Path) -> Self {
+        self.bytes = path.metadata().ok().map(|metadata| metadata.len());
+        self
+    }
+
+    /// Records that the stage failed with `error`.
+    pub fn with_error(mut self, error: &dyn Display) -> Self {
+        self.outcome = Outcome::Failed;
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// Event log file, shared by the tasks of a job.
+pub struct EventLog {
+    file: Mutex<File>,
+}
+
+impl EventLog {
+    /// Opens the event log at `path` to append to it, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(EventLog {
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Appends `entry` as a JSON line. Failures to write are logged, the stage itself is not
+    /// affected.
+    pub fn record(&self, entry: &TileEvent) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => return event!(Level::ERROR, "Failed to serialize tile event: {}", e),
+        };
+        line.push(b'\n');
+        // A single write, so that the lines of concurrent tasks do not interleave.
+        if let Err(e) = self.file.lock().unwrap().write_all(&line) {
+            event!(Level::ERROR, "Failed to write the event log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use arrow::record_batch::RecordBatch;
+use arrow_flight::{encode::FlightDataEncoderBuilder, FlightDescriptor};
+use futures::{stream, TryStreamExt};
+use std::{error::Error, str::FromStr};
+use tonic::transport::Channel;
+use tracing::{event, Level};
+
+/// Maximum number of rows of a record batch, which keeps the messages below the default 4 MiB
+/// limit of gRPC servers.
+pub const BATCH_ROWS: usize = 65_536;
+
+/// Arrow Flight endpoint that ingests the converted tiles, parsed from `flight://host:port`.
+#[derive(Clone, Debug)]
+pub struct FlightTarget {
+    /// URL of the gRPC endpoint
+    endpoint: String,
+}
+
+impl FromStr for FlightTarget {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("flight://") {
+            Some(address) if !address.is_empty() => Ok(FlightTarget {
+                endpoint: format!("http://{}", address),
+            }),
+            _ => Err("expected `flight://host:port`"),
+        }
+    }
+}
+
+impl FlightTarget {
+    /// Connects to the endpoint.
+    pub async fn connect(&self) -> Result<FlightClient, Box<dyn Error>> {
+        event!(Level::INFO, "Connecting to `{}`", self.endpoint);
+        let channel = Channel::from_shared(self.endpoint.clone())?
+            .connect()
+            .await?;
+        Ok(FlightClient(arrow_flight::FlightClient::new(channel)))
+    }
+}
+
+/// Connection to an Arrow Flight endpoint.
+pub struct FlightClient(arrow_flight::FlightClient);
+
+impl FlightClient {
+    /// Sends the `batches` of `tile` in a `DoPut` request, with the name of the tile as the path
+    /// of the flight descriptor.
+    pub async fn put(
+        &mut self,
+        tile: &str,
+        batches: Vec<RecordBatch>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Tiles without points (all points were clipped) are not sent.
+        if batches.is_empty() {
+            return Ok(());
+        }
+        // Encode the batches (after the schema) while they are sent.
+        let data = FlightDataEncoderBuilder::new()
+            .with_flight_descriptor(Some(FlightDescriptor::new_path(vec![tile.to_string()])))
+            .build(stream::iter(batches.into_iter().map(Ok)));
+        let mut results = self.0.do_put(data).await?;
+        // The endpoint acknowledges the data before the stream ends.
+        while results.try_next().await?.is_some() {}
+        Ok(())
+    }
+}
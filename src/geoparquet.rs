@@ -0,0 +1,46 @@
+use gdal::spatial_ref::SpatialRef;
+use serde_json::{json, Value};
+use std::error::Error;
+
+/// Version of the GeoParquet specification of the output.
+const VERSION: &str = "1.0.0";
+/// Name of the WKB geometry column.
+pub const GEOMETRY: &str = "geometry";
+/// Key of the GeoParquet file metadata.
+pub const GEO: &str = "geo";
+
+/// Returns the PROJJSON of the coordinate reference system of the points, longitude and
+/// latitude on WGS 84.
+pub fn crs() -> Result<Value, Box<dyn Error>> {
+    let projjson = SpatialRef::from_definition("OGC:CRS84")?.to_projjson()?;
+    Ok(serde_json::from_str(&projjson)?)
+}
+
+/// Returns the `geo` file metadata of a file with points in `crs`, with the bounding box
+/// (`min_lon`, `min_lat`, `max_lon`, `max_lat`) of the points when it is known.
+pub fn metadata(crs: &Value, bbox: Option<(f64, f64, f64, f64)>) -> String {
+    let mut column = json!({
+        "encoding": "WKB",
+        "geometry_types": ["Point"],
+        "crs": crs,
+    });
+    if let Some((min_lon, min_lat, max_lon, max_lat)) = bbox {
+        column["bbox"] = json!([min_lon, min_lat, max_lon, max_lat]);
+    }
+    json!({
+        "version": VERSION,
+        "primary_column": GEOMETRY,
+        "columns": { GEOMETRY: column },
+    })
+    .to_string()
+}
+
+/// Returns the little-endian WKB encoding of a point.
+pub fn wkb_point(lon: f64, lat: f64) -> Vec<u8> {
+    let mut wkb = Vec::with_capacity(21);
+    wkb.push(1);
+    wkb.extend_from_slice(&1_u32.to_le_bytes());
+    wkb.extend_from_slice(&lon.to_le_bytes());
+    wkb.extend_from_slice(&lat.to_le_bytes());
+    wkb
+}
@@ -0,0 +1,161 @@
+use crate::{geoparquet::GEOMETRY, Conversion, IfExists};
+use arrow::{
+    array::{as_primitive_array, as_string_array, as_struct_array, Array, ArrayRef, Float64Array},
+    datatypes::{
+        DataType, Float32Type, Float64Type, Int16Type, Int32Type, UInt16Type, UInt64Type, UInt8Type,
+    },
+    record_batch::RecordBatch,
+};
+use gdal::{
+    spatial_ref::SpatialRef,
+    vector::{FieldDefn, FieldValue, Geometry, LayerOptions, OGRFieldType, OGRwkbGeometryType},
+    Dataset, Driver,
+};
+use std::{error::Error, fs, path::Path};
+use tracing::{event, Level};
+
+/// File name of the GeoPackage in the output dir.
+pub const GEOPACKAGE: &str = "aw3d30.gpkg";
+/// Name of the point layer.
+const LAYER: &str = "points";
+/// Number of rows of the record batches that are written.
+pub const BATCH_ROWS: usize = 65_536;
+
+/// GeoPackage with the converted tiles as a point layer, for GIS tools without Parquet support.
+///
+/// The columns other than the coordinates (the bands and pixel indices) are integer fields of
+/// the features, the country code is a string field.
+pub struct GeoPackage {
+    dataset: Dataset,
+    /// Names of the fields, in column order
+    fields: Vec<String>,
+}
+
+impl GeoPackage {
+    /// Creates the GeoPackage at `path` for the output of `conversion`, replacing an existing
+    /// file only when `if_exists` is to overwrite.
+    pub fn create(
+        path: &Path,
+        conversion: &Conversion,
+        if_exists: IfExists,
+    ) -> Result<Self, Box<dyn Error>> {
+        // An existing GeoPackage is not appended to, so only overwriting replaces it.
+        if if_exists.keep(path, || true)? {
+            return Err(format!("`{}` already exists", path.display()).into());
+        }
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let schema = conversion.arrow_schema();
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .filter(|name| !["lat", "lon", "location", GEOMETRY].contains(&name.as_str()))
+            .collect::<Vec<_>>();
+        if fields.len() == schema.fields().len() {
+            return Err("a GeoPackage requires the coordinates of the points".into());
+        }
+
+        event!(Level::INFO, "Writing GeoPackage `{}`", path.display());
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut dataset = Driver::get("GPKG")?.create_vector_only(&path.to_string_lossy())?;
+        let srs = SpatialRef::from_epsg(4326)?;
+        let layer = dataset.create_layer(LayerOptions {
+            name: LAYER,
+            srs: Some(&srs),
+            ty: OGRwkbGeometryType::wkbPoint,
+            ..Default::default()
+        })?;
+        for name in &fields {
+            let field_type = match schema.field_with_name(name)?.data_type() {
+                DataType::Utf8 => OGRFieldType::OFTString,
+                DataType::Float32 | DataType::Float64 => OGRFieldType::OFTReal,
+                DataType::UInt64 => OGRFieldType::OFTInteger64,
+                _ => OGRFieldType::OFTInteger,
+            };
+            FieldDefn::new(name, field_type)?.add_to_layer(&layer)?;
+        }
+        Ok(GeoPackage { dataset, fields })
+    }
+
+    /// Adds the points of the `batches` to the layer, in one transaction.
+    pub fn write(&mut self, batches: &[RecordBatch]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let names = self.fields.iter().map(String::as_str).collect::<Vec<_>>();
+        let mut transaction = self.dataset.start_transaction()?;
+        {
+            let mut layer = transaction.layer_by_name(LAYER)?;
+            for batch in batches {
+                let (lat, lon) = coordinates(batch);
+                let columns = names
+                    .iter()
+                    .map(|name| batch.column(batch.schema().index_of(name).unwrap()).clone())
+                    .collect::<Vec<_>>();
+                for row in 0..batch.num_rows() {
+                    let mut point = Geometry::empty(OGRwkbGeometryType::wkbPoint)?;
+                    point.set_point_2d(0, (lon.value(row), lat.value(row)));
+                    // Null values are left unset.
+                    let (names, values): (Vec<_>, Vec<_>) = names
+                        .iter()
+                        .zip(&columns)
+                        .filter(|(_, column)| column.is_valid(row))
+                        .map(|(&name, column)| (name, field_value(column, row)))
+                        .unzip();
+                    layer.create_feature_fields(point, &names, &values)?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Returns the latitudes and longitudes of the points of `batch`, from the flat columns or the
+/// `location` struct.
+fn coordinates(batch: &RecordBatch) -> (&Float64Array, &Float64Array) {
+    let schema = batch.schema();
+    let column = |name: &str| batch.column(schema.index_of(name).unwrap());
+    match schema.index_of("location") {
+        Ok(index) => {
+            let location = as_struct_array(batch.column(index));
+            (
+                as_primitive_array::<Float64Type>(location.column_by_name("lat").unwrap()),
+                as_primitive_array::<Float64Type>(location.column_by_name("lon").unwrap()),
+            )
+        }
+        Err(_) => (
+            as_primitive_array::<Float64Type>(column("lat")),
+            as_primitive_array::<Float64Type>(column("lon")),
+        ),
+    }
+}
+
+/// Returns the value of `column` (a band, pixel index, derived attribute, mask, country code,
+/// joined value or cell id) at `row`.
+fn field_value(column: &ArrayRef, row: usize) -> FieldValue {
+    match column.data_type() {
+        DataType::Utf8 => FieldValue::StringValue(as_string_array(column).value(row).to_string()),
+        DataType::Float64 => {
+            FieldValue::RealValue(as_primitive_array::<Float64Type>(column).value(row))
+        }
+        DataType::Float32 => FieldValue::RealValue(f64::from(
+            as_primitive_array::<Float32Type>(column).value(row),
+        )),
+        // GeoPackage integers are signed, S2 cell ids of the faces 4 and 5 are negative.
+        DataType::UInt64 => {
+            FieldValue::Integer64Value(as_primitive_array::<UInt64Type>(column).value(row) as i64)
+        }
+        DataType::UInt16 => FieldValue::IntegerValue(i32::from(
+            as_primitive_array::<UInt16Type>(column).value(row),
+        )),
+        DataType::UInt8 => FieldValue::IntegerValue(i32::from(
+            as_primitive_array::<UInt8Type>(column).value(row),
+        )),
+        DataType::Int16 => FieldValue::IntegerValue(i32::from(
+            as_primitive_array::<Int16Type>(column).value(row),
+        )),
+        _ => FieldValue::IntegerValue(as_primitive_array::<Int32Type>(column).value(row)),
+    }
+}
@@ -0,0 +1,207 @@
+use std::str::FromStr;
+
+/// Tolerance for grid points that fall on the edge of a raster.
+const EPSILON: f64 = 1e-9;
+
+/// Method to resample raster values at the points of a target grid.
+#[derive(Copy, Clone, Debug)]
+pub enum Resampling {
+    /// Value of the pixel that contains the point
+    Nearest,
+    /// Bilinear interpolation between the four nearest pixel centers
+    Bilinear,
+}
+
+impl FromStr for Resampling {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            _ => Err("expected one of: nearest, bilinear"),
+        }
+    }
+}
+
+/// A regular grid the output points are resampled onto.
+#[derive(Clone, Debug)]
+pub struct TargetGrid {
+    /// Distance between the grid points in degrees
+    pub resolution: f64,
+    /// Longitude and latitude of a point of the grid
+    pub origin: (f64, f64),
+    pub resampling: Resampling,
+}
+
+impl TargetGrid {
+    /// Returns the latitudes and longitudes of the grid points in the extent of a raster with
+    /// geotransform `gt` and `size`, ordered like the pixels (north to south, west to east).
+    ///
+    /// The extent includes its west and north edges, so that adjacent rasters do not share
+    /// grid points.
+    pub fn points(&self, gt: &[f64; 6], size: (usize, usize)) -> (Vec<f64>, Vec<f64>) {
+        let (origin_lon, origin_lat) = self.origin;
+        let west = gt[0];
+        let east = gt[0] + size.0 as f64 * gt[1];
+        let north = gt[3];
+        let south = gt[3] + size.1 as f64 * gt[5];
+
+        let columns = ((west - origin_lon) / self.resolution - EPSILON).ceil() as i64
+            ..((east - origin_lon) / self.resolution - EPSILON).ceil() as i64;
+        let rows = ((south - origin_lat) / self.resolution + EPSILON).floor() as i64 + 1
+            ..((north - origin_lat) / self.resolution + EPSILON).floor() as i64 + 1;
+
+        let capacity =
+            (columns.end - columns.start).max(0) as usize * (rows.end - rows.start).max(0) as usize;
+        let mut lat = Vec::with_capacity(capacity);
+        let mut lon = Vec::with_capacity(capacity);
+        for row in rows.rev() {
+            for column in columns.clone() {
+                lat.push(origin_lat + row as f64 * self.resolution);
+                lon.push(origin_lon + column as f64 * self.resolution);
+            }
+        }
+        (lat, lon)
+    }
+
+    /// Resamples the `data` of a raster with geotransform `gt` and `size` at the points.
+    ///
+    /// Interpolation that involves a `nodata` pixel results in `nodata`.
+    pub fn resample(
+        &self,
+        gt: &[f64; 6],
+        size: (usize, usize),
+        data: &[i32],
+        nodata: Option<f64>,
+        lat: &[f64],
+        lon: &[f64],
+    ) -> Vec<i32> {
+        let (x_size, y_size) = size;
+        let clamp = |value: f64, size: usize| value.max(0.).min((size - 1) as f64);
+        let value = |x: usize, y: usize| data[y * x_size + x];
+        lat.iter()
+            .zip(lon)
+            .map(|(lat, lon)| {
+                // Continuous pixel coordinates, the tiles are north-up.
+                let px = (lon - gt[0]) / gt[1];
+                let py = (lat - gt[3]) / gt[5];
+                match self.resampling {
+                    Resampling::Nearest => value(
+                        clamp(px.floor(), x_size) as usize,
+                        clamp(py.floor(), y_size) as usize,
+                    ),
+                    Resampling::Bilinear => {
+                        // Interpolate between the pixel centers.
+                        let u = clamp(px - 0.5, x_size);
+                        let v = clamp(py - 0.5, y_size);
+                        let (x0, y0) = (u.floor() as usize, v.floor() as usize);
+                        let (x1, y1) = ((x0 + 1).min(x_size - 1), (y0 + 1).min(y_size - 1));
+                        let (fx, fy) = (u - x0 as f64, v - y0 as f64);
+                        let corners = [value(x0, y0), value(x1, y0), value(x0, y1), value(x1, y1)];
+                        match nodata {
+                            Some(nodata) if corners.iter().any(|&c| c as f64 == nodata) => {
+                                nodata as i32
+                            }
+                            _ => {
+                                let [a, b, c, d] = corners.map(f64::from);
+                                let top = a + (b - a) * fx;
+                                let bottom = c + (d - c) * fx;
+                                (top + (bottom - top) * fy).round() as i32
+                            }
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Method to aggregate the pixels of a block into a downsampled pixel.
+#[derive(Copy, Clone, Debug)]
+pub enum Aggregation {
+    /// Value of the pixel at the center of the block
+    Nearest,
+    /// Mean of the pixels other than nodata, rounded
+    Mean,
+    /// Maximum of the pixels other than nodata
+    Max,
+}
+
+impl FromStr for Aggregation {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "mean" => Ok(Self::Mean),
+            "max" => Ok(Self::Max),
+            _ => Err("expected one of: nearest, mean, max"),
+        }
+    }
+}
+
+/// Downsampling of the rasters by aggregating blocks of `factor` by `factor` pixels.
+#[derive(Clone, Debug)]
+pub struct Downsample {
+    pub factor: usize,
+    pub aggregation: Aggregation,
+}
+
+impl Downsample {
+    /// Returns the geotransform and size of a raster with geotransform `gt` and `size` after
+    /// downsampling. The pixels at the east and south edges that do not fill a block are
+    /// dropped.
+    pub fn transform(&self, gt: &[f64; 6], size: (usize, usize)) -> ([f64; 6], (usize, usize)) {
+        let factor = self.factor as f64;
+        let gt = [
+            gt[0],
+            gt[1] * factor,
+            gt[2] * factor,
+            gt[3],
+            gt[4] * factor,
+            gt[5] * factor,
+        ];
+        (gt, (size.0 / self.factor, size.1 / self.factor))
+    }
+
+    /// Downsamples the `data` of a raster with `size`. A block of `nodata` pixels results in
+    /// `nodata`.
+    pub fn apply(&self, data: &[i32], size: (usize, usize), nodata: Option<f64>) -> Vec<i32> {
+        let factor = self.factor;
+        let (x_size, y_size) = (size.0 / factor, size.1 / factor);
+        let valid = |value: &i32| nodata.map_or(true, |nodata| *value as f64 != nodata);
+        let mut output = Vec::with_capacity(x_size * y_size);
+        for y in 0..y_size {
+            for x in 0..x_size {
+                let block = (y * factor..(y + 1) * factor).flat_map(|row| {
+                    let start = row * size.0 + x * factor;
+                    data[start..start + factor].iter()
+                });
+                let value = match self.aggregation {
+                    Aggregation::Nearest => {
+                        data[(y * factor + factor / 2) * size.0 + x * factor + factor / 2]
+                    }
+                    Aggregation::Mean => {
+                        let (sum, count) = block
+                            .filter(|value| valid(value))
+                            .fold((0i64, 0i64), |(sum, count), &value| {
+                                (sum + i64::from(value), count + 1)
+                            });
+                        match count {
+                            0 => nodata.map_or(0, |nodata| nodata as i32),
+                            count => (sum as f64 / count as f64).round() as i32,
+                        }
+                    }
+                    Aggregation::Max => block
+                        .filter(|value| valid(value))
+                        .max()
+                        .copied()
+                        .unwrap_or_else(|| nodata.map_or(0, |nodata| nodata as i32)),
+                };
+                output.push(value);
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{bandwidth::Throttle, manifest::Upstream, IfExists, Usage};
+use regex::Regex;
+use reqwest::{
+    header::{HeaderName, ETAG, LAST_MODIFIED},
+    Client, Url,
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::{event, instrument, Level};
+
+/// Returns the links of the HTTP directory index page at `url`.
+pub async fn list_index(client: &Client, url: &Url) -> Result<Vec<Url>, Box<dyn Error>> {
+    let page = client
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let href = Regex::new(r#"(?i)href\s*=\s*"([^"]+)""#)?;
+    Ok(href
+        .captures_iter(&page)
+        .filter_map(|cap| url.join(&cap[1]).ok())
+        .collect())
+}
+
+#[instrument(err, skip(client, tif_dir, if_exists, usage, throttle), fields(url = %url))]
+pub async fn download(
+    client: Client,
+    url: Url,
+    tif_dir: PathBuf,
+    if_exists: IfExists,
+    usage: Arc<Usage>,
+    throttle: Arc<Throttle>,
+) -> Result<(PathBuf, Upstream), Box<dyn Error + Send + Sync>> {
+    let name = Path::new(url.path())
+        .file_name()
+        .ok_or("url without file name")?;
+    let path = tif_dir.join(name);
+    let key = url.to_string();
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    let header = |name: HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    // Verify existing files by their size.
+    let size = response.content_length();
+    let upstream = Upstream {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+        size,
+        key: Some(key),
+    };
+    if if_exists.keep(&path, || path.metadata().ok().map(|m| m.len()) == size)? {
+        event!(Level::WARN, "Skipping download. File already exists.");
+    } else {
+        let mut file = File::create(&path).await?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            usage
+                .downloaded
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            throttle.consume(chunk.len() as u64).await;
+        }
+        file.flush().await?;
+    }
+    Ok((path, upstream))
+}
+
+/// Returns true for errors of HTTP downloads that may succeed when retried: connection errors,
+/// timeouts, interrupted transfers, throttling and server errors.
+pub fn transient(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    error.downcast_ref::<reqwest::Error>().map_or(false, |e| {
+        e.is_connect()
+            || e.is_timeout()
+            || e.is_body()
+            || e.status().map_or(false, |status| {
+                status.is_server_error() || status.as_u16() == 429
+            })
+    })
+}
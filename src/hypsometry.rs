@@ -0,0 +1,263 @@
+//! Hypsometric curve (the distribution of the area over the elevation) of a region of the
+//! converted points.
+
+use crate::{area, mosaic::escape, polygon::Polygon, reader::Elevation, Bbox};
+use arrow::{
+    array::{ArrayRef, Float64Array},
+    datatypes::{DataType, Field, Schema},
+    record_batch::RecordBatch,
+};
+use parquet::arrow::ArrowWriter;
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+/// Pixel size of AW3D30 in degrees, for regions with a single point.
+const ARC_SECOND: f64 = 1. / 3600.;
+
+/// A region to compute the hypsometric curve of.
+pub enum Region {
+    /// Points in the bounding box, including its edges
+    Bbox(Bbox),
+    /// Points in any of the polygons
+    Polygons(Vec<Polygon>),
+}
+
+impl Region {
+    /// Returns the bounding box of the region.
+    pub fn bbox(&self) -> Bbox {
+        match self {
+            Region::Bbox(bbox) => *bbox,
+            Region::Polygons(polygons) => polygons.iter().map(|polygon| polygon.bbox).fold(
+                Bbox {
+                    min_lon: f64::INFINITY,
+                    min_lat: f64::INFINITY,
+                    max_lon: f64::NEG_INFINITY,
+                    max_lat: f64::NEG_INFINITY,
+                },
+                |bbox, (min_lon, min_lat, max_lon, max_lat)| Bbox {
+                    min_lon: bbox.min_lon.min(min_lon),
+                    min_lat: bbox.min_lat.min(min_lat),
+                    max_lon: bbox.max_lon.max(max_lon),
+                    max_lat: bbox.max_lat.max(max_lat),
+                },
+            ),
+        }
+    }
+
+    /// Returns true when the point is in the region.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        match self {
+            Region::Bbox(bbox) => {
+                (bbox.min_lat..=bbox.max_lat).contains(&lat)
+                    && (bbox.min_lon..=bbox.max_lon).contains(&lon)
+            }
+            Region::Polygons(polygons) => polygons.iter().any(|polygon| polygon.contains(lon, lat)),
+        }
+    }
+}
+
+/// An elevation interval of the hypsometric curve.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bin {
+    /// Lower bound of the elevation interval
+    pub elevation: f64,
+    /// Area of the points in the interval in square meters
+    pub area: f64,
+    /// Area of the points at or above the lower bound in square meters
+    pub area_above: f64,
+    /// Fraction of the area of the region at or above the lower bound
+    pub fraction_above: f64,
+    /// Lower bound relative to the elevation range of the region, from 0 (lowest) to 1
+    pub relative_elevation: f64,
+}
+
+/// Returns the hypsometric curve of the `elevations` in intervals of `interval`, from the
+/// lowest to the highest elevation. Points without a value are skipped.
+///
+/// The points are weighted by the geodesic area of the pixel around them, so that the curve of a region
+/// that spans many degrees of latitude is not biased to the poles. The pixel size is the
+/// spacing of the points, which is that of the downsampled raster or target grid alike.
+pub fn hypsometry(elevations: &[Elevation], interval: f64) -> Vec<Bin> {
+    let points = elevations
+        .iter()
+        .filter_map(|elevation| elevation.value.map(|value| (elevation.lat, value)))
+        .collect::<Vec<_>>();
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let (min, max) = points.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(min, max), &(_, value)| (min.min(value), max.max(value)),
+    );
+    let pixel = pixel_size(elevations);
+    let start = (min / interval).floor() * interval;
+    let mut areas = vec![0.; ((max - start) / interval).floor() as usize + 1];
+    for (lat, value) in points {
+        let index = ((value - start) / interval).floor() as usize;
+        areas[index.min(areas.len() - 1)] +=
+            area::cell_area(lat - pixel / 2., lat + pixel / 2., pixel);
+    }
+    let total = areas.iter().sum::<f64>();
+    let mut area_above = total;
+    areas
+        .iter()
+        .enumerate()
+        .map(|(index, &area)| {
+            let elevation = start + index as f64 * interval;
+            let bin = Bin {
+                elevation,
+                area,
+                area_above,
+                fraction_above: area_above / total,
+                relative_elevation: match max > min {
+                    true => ((elevation - min) / (max - min)).max(0.),
+                    false => 0.,
+                },
+            };
+            area_above -= area;
+            bin
+        })
+        .collect()
+}
+
+/// Returns the spacing in degrees of the rows of the points.
+fn pixel_size(elevations: &[Elevation]) -> f64 {
+    let mut lats = elevations
+        .iter()
+        .map(|elevation| elevation.lat)
+        .collect::<Vec<_>>();
+    lats.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    lats.dedup();
+    // Rounding errors of the coordinates are well below a pixel.
+    let step = lats
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .filter(|&step| step > 1e-9)
+        .fold(f64::INFINITY, f64::min);
+    if step.is_finite() {
+        step
+    } else {
+        ARC_SECOND
+    }
+}
+
+/// Writes the `bins` to a Parquet file when `path` ends with `.parquet`, or a CSV file.
+pub fn write_bins(path: &Path, bins: &[Bin]) -> Result<(), Box<dyn Error>> {
+    let columns: [(&str, fn(&Bin) -> f64); 5] = [
+        ("elevation", |bin| bin.elevation),
+        ("area", |bin| bin.area),
+        ("area_above", |bin| bin.area_above),
+        ("fraction_above", |bin| bin.fraction_above),
+        ("relative_elevation", |bin| bin.relative_elevation),
+    ];
+    if path.extension().map_or(false, |ext| ext == "parquet") {
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(name, _)| Field::new(name, DataType::Float64, false))
+                .collect(),
+        ));
+        let arrays = columns
+            .iter()
+            .map(|(_, value)| {
+                Arc::new(Float64Array::from(
+                    bins.iter().map(value).collect::<Vec<_>>(),
+                )) as ArrayRef
+            })
+            .collect();
+        let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+        let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+    } else {
+        let mut output = BufWriter::new(File::create(path)?);
+        let names = columns.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        writeln!(output, "{}", names.join(","))?;
+        for bin in bins {
+            let values = columns
+                .iter()
+                .map(|(_, value)| value(bin).to_string())
+                .collect::<Vec<_>>();
+            writeln!(output, "{}", values.join(","))?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes a plot of the hypsometric curve of the `bins` as an SVG file: the elevation against
+/// the fraction of the area above it.
+pub fn write_svg(path: &Path, bins: &[Bin], unit: &str) -> Result<(), Box<dyn Error>> {
+    const WIDTH: f64 = 640.;
+    const HEIGHT: f64 = 480.;
+    const MARGIN: f64 = 60.;
+    let (min, max) = match (bins.first(), bins.last()) {
+        (Some(first), Some(last)) => (first.elevation, last.elevation.max(first.elevation + 1.)),
+        _ => (0., 1.),
+    };
+    let x = |fraction: f64| MARGIN + fraction * (WIDTH - 2. * MARGIN);
+    let y =
+        |elevation: f64| HEIGHT - MARGIN - (elevation - min) / (max - min) * (HEIGHT - 2. * MARGIN);
+    let points = bins
+        .iter()
+        .map(|bin| format!("{:.1},{:.1}", x(bin.fraction_above), y(bin.elevation)))
+        .collect::<Vec<_>>();
+
+    let mut svg = BufWriter::new(File::create(path)?);
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-size="12">"#,
+        WIDTH, HEIGHT
+    )?;
+    writeln!(
+        svg,
+        r#"  <rect x="{0}" y="{0}" width="{1}" height="{2}" fill="none" stroke="black"/>"#,
+        MARGIN,
+        WIDTH - 2. * MARGIN,
+        HEIGHT - 2. * MARGIN
+    )?;
+    writeln!(
+        svg,
+        r#"  <polyline points="{}" fill="none" stroke="steelblue" stroke-width="2"/>"#,
+        points.join(" ")
+    )?;
+    // Ticks at the fractions of the area and at the ends of the elevation range.
+    for fraction in [0., 0.25, 0.5, 0.75, 1.] {
+        writeln!(
+            svg,
+            r#"  <text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+            x(fraction),
+            HEIGHT - MARGIN + 18.,
+            fraction
+        )?;
+    }
+    for elevation in [min, max] {
+        writeln!(
+            svg,
+            r#"  <text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#,
+            MARGIN - 6.,
+            y(elevation) + 4.,
+            elevation
+        )?;
+    }
+    writeln!(
+        svg,
+        r#"  <text x="{:.1}" y="{:.1}" text-anchor="middle">Fraction of area above</text>"#,
+        WIDTH / 2.,
+        HEIGHT - 16.
+    )?;
+    writeln!(
+        svg,
+        r#"  <text transform="translate(16 {:.1}) rotate(-90)" text-anchor="middle">{}</text>"#,
+        HEIGHT / 2.,
+        escape(&format!("Elevation ({})", unit))
+    )?;
+    writeln!(svg, "</svg>")?;
+    svg.flush()?;
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use parquet::{
+    basic::{self, ConvertedType, Repetition},
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        statistics::Statistics,
+        writer::SerializedFileWriter,
+    },
+    schema::types::Type,
+};
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::Path,
+    sync::Arc,
+};
+
+/// File name of the spatial index in the Parquet output dir.
+///
+/// The leading underscore makes query engines skip the file when reading the output dir as a
+/// dataset.
+pub const SPATIAL_INDEX: &str = "_spatial_index.parquet";
+
+/// Bounding box of a row group in an output file.
+struct RowGroupBounds {
+    file: String,
+    row_group: i32,
+    num_rows: i64,
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+}
+
+/// Returns the min and max statistics of the double column `name` of a row group, which is
+/// either a top-level column or a field of the `location` struct column.
+pub(crate) fn min_max(
+    row_group: &parquet::file::metadata::RowGroupMetaData,
+    name: &str,
+) -> Option<(f64, f64)> {
+    let location = format!("location.{}", name);
+    row_group
+        .columns()
+        .iter()
+        .find(|column| {
+            let path = column.column_path().string();
+            path == name || path == location
+        })
+        .and_then(|column| column.statistics())
+        .and_then(|statistics| match statistics {
+            Statistics::Double(stats) if stats.has_min_max_set() => {
+                Some((*stats.min(), *stats.max()))
+            }
+            _ => None,
+        })
+}
+
+/// Writes the spatial index of all row groups of the Parquet files in `dir`, sorted by their
+/// bounding box, so that readers can locate the row groups for a bounding box in one lookup.
+///
+/// Returns the number of indexed row groups.
+pub fn write_spatial_index(dir: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut bounds = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if path.extension().map_or(true, |ext| ext != "parquet") || name.starts_with('_') {
+            continue;
+        }
+        let reader = SerializedFileReader::new(File::open(&path)?)?;
+        for (idx, row_group) in reader.metadata().row_groups().iter().enumerate() {
+            if let (Some((min_lat, max_lat)), Some((min_lon, max_lon))) =
+                (min_max(row_group, "lat"), min_max(row_group, "lon"))
+            {
+                bounds.push(RowGroupBounds {
+                    file: name.clone(),
+                    row_group: idx as i32,
+                    num_rows: row_group.num_rows(),
+                    min_lat,
+                    max_lat,
+                    min_lon,
+                    max_lon,
+                });
+            }
+        }
+    }
+    bounds.sort_by(|a, b| {
+        a.min_lat
+            .total_cmp(&b.min_lat)
+            .then(a.min_lon.total_cmp(&b.min_lon))
+    });
+
+    let field = |name: &str, physical_type| {
+        Arc::new(
+            Type::primitive_type_builder(name, physical_type)
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .unwrap(),
+        )
+    };
+    let schema = Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(vec![
+                Arc::new(
+                    Type::primitive_type_builder("file", basic::Type::BYTE_ARRAY)
+                        .with_converted_type(ConvertedType::UTF8)
+                        .with_repetition(Repetition::REQUIRED)
+                        .build()?,
+                ),
+                field("row_group", basic::Type::INT32),
+                field("num_rows", basic::Type::INT64),
+                field("min_lat", basic::Type::DOUBLE),
+                field("max_lat", basic::Type::DOUBLE),
+                field("min_lon", basic::Type::DOUBLE),
+                field("max_lon", basic::Type::DOUBLE),
+            ])
+            .build()?,
+    );
+
+    // Write to a temporary file first, so that readers never observe a partial index.
+    let tmp_path = dir.join(SPATIAL_INDEX).with_extension("parquet.tmp");
+    let mut writer = SerializedFileWriter::new(
+        File::create(&tmp_path)?,
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_writer = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut col_writer) = row_writer.next_column()? {
+        match col_writer.untyped() {
+            ColumnWriter::ByteArrayColumnWriter(c) => {
+                let files = bounds
+                    .iter()
+                    .map(|bounds| ByteArray::from(bounds.file.as_str()))
+                    .collect::<Vec<_>>();
+                c.write_batch(&files, None, None)?
+            }
+            ColumnWriter::Int32ColumnWriter(c) => {
+                let row_groups = bounds.iter().map(|b| b.row_group).collect::<Vec<_>>();
+                c.write_batch(&row_groups, None, None)?
+            }
+            ColumnWriter::Int64ColumnWriter(c) => {
+                let num_rows = bounds.iter().map(|b| b.num_rows).collect::<Vec<_>>();
+                c.write_batch(&num_rows, None, None)?
+            }
+            ColumnWriter::DoubleColumnWriter(c) => {
+                // The bounds follow the first three columns, in schema order.
+                let values = bounds
+                    .iter()
+                    .map(|b| [b.min_lat, b.max_lat, b.min_lon, b.max_lon][column - 3])
+                    .collect::<Vec<_>>();
+                c.write_batch(&values, None, None)?
+            }
+            _ => unreachable!(),
+        };
+        col_writer.close()?;
+        column += 1;
+    }
+
+    row_writer.close()?;
+    writer.close()?;
+    fs::rename(tmp_path, dir.join(SPATIAL_INDEX))?;
+
+    Ok(bounds.len())
+}
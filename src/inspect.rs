@@ -0,0 +1,168 @@
+use crate::{convert::codec_name, usage::format_bytes};
+use parquet::{
+    basic::Encoding,
+    file::reader::{FileReader, SerializedFileReader},
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// Layout of a column chunk over all row groups of a file.
+#[derive(Debug, Serialize)]
+pub struct ColumnReport {
+    /// Column path, with a `.` between the names of nested fields
+    pub path: String,
+    pub physical_type: String,
+    pub converted_type: String,
+    /// Whether the column is nullable
+    pub optional: bool,
+    /// Codec of the column chunks
+    pub compression: BTreeSet<String>,
+    /// Encodings of the column chunks
+    pub encodings: BTreeSet<String>,
+    /// Number of row groups with min and max statistics of the column
+    pub statistics: usize,
+    pub compressed_bytes: i64,
+    pub uncompressed_bytes: i64,
+}
+
+/// Layout and metadata of a Parquet file, as written in its footer.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub version: i32,
+    pub created_by: Option<String>,
+    pub num_rows: i64,
+    /// Number of rows per row group
+    pub row_groups: Vec<i64>,
+    pub columns: Vec<ColumnReport>,
+    /// Keys of the key-value metadata
+    pub metadata_keys: Vec<String>,
+}
+
+impl FileReport {
+    /// Reads the footer of the Parquet file at `path`.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let reader = SerializedFileReader::new(File::open(path)?)?;
+        let metadata = reader.metadata();
+        let file_metadata = metadata.file_metadata();
+        let row_groups = metadata.row_groups();
+        let columns = file_metadata
+            .schema_descr()
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let chunks = row_groups
+                    .iter()
+                    .map(move |row_group| row_group.column(index));
+                ColumnReport {
+                    path: column.path().string(),
+                    physical_type: format!("{}", column.physical_type()),
+                    converted_type: format!("{}", column.converted_type()),
+                    optional: column.max_def_level() > 0,
+                    compression: chunks
+                        .clone()
+                        .map(|chunk| codec_name(chunk.compression()).to_string())
+                        .collect(),
+                    encodings: chunks
+                        .clone()
+                        .flat_map(|chunk| chunk.encodings().iter().map(Encoding::to_string))
+                        .collect(),
+                    statistics: chunks
+                        .clone()
+                        .filter(|chunk| {
+                            chunk
+                                .statistics()
+                                .map_or(false, |statistics| statistics.has_min_max_set())
+                        })
+                        .count(),
+                    compressed_bytes: chunks.clone().map(|chunk| chunk.compressed_size()).sum(),
+                    uncompressed_bytes: chunks.map(|chunk| chunk.uncompressed_size()).sum(),
+                }
+            })
+            .collect();
+        Ok(FileReport {
+            path: path.to_path_buf(),
+            version: file_metadata.version(),
+            created_by: file_metadata.created_by().map(str::to_string),
+            num_rows: file_metadata.num_rows(),
+            row_groups: row_groups
+                .iter()
+                .map(|row_group| row_group.num_rows())
+                .collect(),
+            columns,
+            metadata_keys: file_metadata
+                .key_value_metadata()
+                .into_iter()
+                .flatten()
+                .map(|kv| kv.key.clone())
+                .collect(),
+        })
+    }
+
+    /// Prints the report as a table.
+    pub fn print(&self) {
+        println!("{}", self.path.display());
+        println!(
+            "  version {}, created by {}",
+            self.version,
+            self.created_by.as_deref().unwrap_or("-")
+        );
+        let min = self.row_groups.iter().min().copied().unwrap_or_default();
+        let max = self.row_groups.iter().max().copied().unwrap_or_default();
+        println!(
+            "  {} rows in {} row groups ({} to {} rows)",
+            self.num_rows,
+            self.row_groups.len(),
+            min,
+            max
+        );
+        println!(
+            "  {:<16} {:<12} {:<10} {:<8} {:<14} {:<40} {:<10} {:>12} {:>12}",
+            "column",
+            "type",
+            "converted",
+            "nullable",
+            "compression",
+            "encodings",
+            "statistics",
+            "compressed",
+            "uncompressed"
+        );
+        let join = |values: &BTreeSet<String>| values.iter().cloned().collect::<Vec<_>>().join(",");
+        for column in &self.columns {
+            println!(
+                "  {:<16} {:<12} {:<10} {:<8} {:<14} {:<40} {:<10} {:>12} {:>12}",
+                column.path,
+                column.physical_type,
+                column.converted_type,
+                if column.optional { "yes" } else { "no" },
+                join(&column.compression),
+                join(&column.encodings),
+                format!("{}/{}", column.statistics, self.row_groups.len()),
+                format_bytes(column.compressed_bytes as u64),
+                format_bytes(column.uncompressed_bytes as u64)
+            );
+        }
+        println!("  metadata keys: {}", self.metadata_keys.join(", "));
+    }
+}
+
+/// Returns the paths of the Parquet files in `dir`, sorted, without the files that start with
+/// an underscore (like the spatial index).
+pub fn parquet_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| {
+        path.extension().map_or(false, |ext| ext == "parquet")
+            && !path.file_name().unwrap().to_string_lossy().starts_with('_')
+    });
+    paths.sort();
+    Ok(paths)
+}
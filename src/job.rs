@@ -0,0 +1,552 @@
+use crate::{
+    convert::valid_parquet,
+    events::{EventLog, Outcome, Stage, TileEvent, EVENT_LOG},
+    index::{self, SPATIAL_INDEX},
+    manifest::{Manifest, ManifestFile, Upstream, MANIFEST},
+    mask,
+    relief::{ColorRelief, HillshadeRaster},
+    retry::TileRetry,
+    summary::{self, SUMMARY_METADATA},
+    tile_coordinate,
+    upload::Uploader,
+    write_parquet, Conversion, Coordinate, RemoteFile, Source, Usage, TILE_PATTERN,
+};
+use futures::{future, stream, FutureExt, StreamExt};
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+use tokio::task;
+use tracing::{event, Level};
+
+/// Hooks to report the progress of a [`ConvertJob`] per tile.
+///
+/// All hooks do nothing by default.
+pub trait Progress: Send + Sync {
+    /// The download of `tile` started.
+    fn started(&self, _tile: &str) {}
+    /// The Parquet file of `tile` is at `path`, `written` is false when an existing file was
+    /// kept.
+    fn finished(&self, _tile: &str, _path: &Path, _written: bool) {}
+    /// Downloading or converting `tile` failed.
+    fn failed(&self, _tile: &str, _error: &(dyn Error + Send + Sync)) {}
+}
+
+/// Progress that logs failed tiles.
+pub struct LogProgress;
+
+impl Progress for LogProgress {
+    fn failed(&self, tile: &str, error: &(dyn Error + Send + Sync)) {
+        event!(Level::ERROR, "Converting {} failed: {}", tile, error);
+    }
+}
+
+/// Token to cancel a [`ConvertJob`].
+///
+/// Tiles that are in progress are finished, remaining tiles are not started.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Cancels the job.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true when the job is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the name of the Parquet file of the GeoTIFF file `name`, with the bounding box of
+/// the tile at `coordinate` appended to the stem when given.
+pub fn parquet_name(name: &str, coordinate: Option<Coordinate>) -> String {
+    let stem = Path::new(name).file_stem().unwrap().to_string_lossy();
+    match coordinate {
+        Some(coordinate) => format!("{}_{}.parquet", stem, coordinate.bbox_name()),
+        None => format!("{}.parquet", stem),
+    }
+}
+
+/// Converts the GeoTIFF file at `input_path` to a Parquet file in `staging_dir` and moves it to
+/// `output_path` once it is verified, so that the output dir never has incomplete files.
+///
+/// Returns false when an existing file was kept.
+fn write_staged(
+    input_path: PathBuf,
+    output_path: &Path,
+    staging_dir: &Path,
+    upstream: &Upstream,
+    conversion: Arc<Conversion>,
+    usage: Arc<Usage>,
+) -> Result<bool, Box<dyn Error>> {
+    if conversion
+        .if_exists
+        .keep(output_path, || valid_parquet(output_path))?
+    {
+        event!(Level::WARN, "Skipping Parquet. File already exists.");
+        return Ok(false);
+    }
+    let staged_path = staging_dir.join(output_path.file_name().unwrap());
+    // Staged files are leftovers of an interrupted run, they are always replaced.
+    if staged_path.exists() {
+        fs::remove_file(&staged_path)?;
+    }
+    write_parquet(input_path, staged_path.clone(), upstream, conversion, usage)?;
+    if !valid_parquet(&staged_path) {
+        return Err(format!("staged file `{}` is invalid", staged_path.display()).into());
+    }
+    // Renaming is atomic within a file system, otherwise copy to a temporary file in the
+    // output dir first.
+    if fs::rename(&staged_path, output_path).is_err() {
+        let tmp_path = output_path.with_extension("parquet.tmp");
+        fs::copy(&staged_path, &tmp_path)?;
+        fs::rename(tmp_path, output_path)?;
+        fs::remove_file(staged_path)?;
+    }
+    Ok(true)
+}
+
+/// Downloads a list of tiles and converts them to Parquet files in an output dir, updating the
+/// manifest, the spatial index and the summary metadata of the dir.
+pub struct ConvertJob {
+    objects: Vec<RemoteFile>,
+    tif_dir: PathBuf,
+    parquet_dir: PathBuf,
+    staging_dir: Option<PathBuf>,
+    relief: Option<Arc<ColorRelief>>,
+    hillshade: Option<Arc<HillshadeRaster>>,
+    uploader: Option<Arc<Uploader>>,
+    tile_retry: TileRetry,
+    reset_retries: bool,
+    event_log: bool,
+    mixed_schema: bool,
+    bbox_names: bool,
+    remove_tif: bool,
+    options: BTreeMap<String, Vec<String>>,
+    conversion: Arc<Conversion>,
+    usage: Arc<Usage>,
+    progress: Arc<dyn Progress>,
+    cancellation: CancellationToken,
+}
+
+impl ConvertJob {
+    pub fn new(
+        objects: Vec<RemoteFile>,
+        tif_dir: PathBuf,
+        parquet_dir: PathBuf,
+        conversion: Arc<Conversion>,
+        usage: Arc<Usage>,
+    ) -> Self {
+        ConvertJob {
+            objects,
+            tif_dir,
+            parquet_dir,
+            staging_dir: None,
+            relief: None,
+            hillshade: None,
+            uploader: None,
+            tile_retry: TileRetry::default(),
+            reset_retries: false,
+            event_log: false,
+            mixed_schema: false,
+            bbox_names: false,
+            remove_tif: false,
+            options: BTreeMap::new(),
+            conversion,
+            usage,
+            progress: Arc::new(LogProgress),
+            cancellation: CancellationToken::default(),
+        }
+    }
+
+    /// Reports the progress of the job to `progress`.
+    pub fn with_progress(mut self, progress: impl Progress + 'static) -> Self {
+        self.progress = Arc::new(progress);
+        self
+    }
+
+    /// Writes the Parquet files to `staging_dir` and moves them to the output dir once they are
+    /// verified.
+    pub fn with_staging_dir(mut self, staging_dir: PathBuf) -> Self {
+        self.staging_dir = Some(staging_dir);
+        self
+    }
+
+    /// Writes a color relief preview of every converted tile.
+    pub fn with_color_relief(mut self, relief: ColorRelief) -> Self {
+        self.relief = Some(Arc::new(relief));
+        self
+    }
+
+    /// Writes a hillshade GeoTIFF of every converted tile.
+    pub fn with_hillshade_raster(mut self, hillshade: HillshadeRaster) -> Self {
+        self.hillshade = Some(Arc::new(hillshade));
+        self
+    }
+
+    /// Uploads the Parquet files to object storage with `uploader` once they are written, and
+    /// the manifest, the spatial index and the summary metadata at the end of the job.
+    ///
+    /// The files are written to the output dir first, as the Parquet writer needs a seekable
+    /// file, so the output dir can be a scratch dir on workers without persistent disk. Kept
+    /// files are uploaded when their object is missing or has another size.
+    pub fn with_upload(mut self, uploader: Uploader) -> Self {
+        self.uploader = Some(Arc::new(uploader));
+        self
+    }
+
+    /// Retries the tiles that failed in earlier runs according to `tile_retry`: tiles are
+    /// skipped until their backoff has passed, and once their attempts are exhausted.
+    pub fn with_tile_retry(mut self, tile_retry: TileRetry) -> Self {
+        self.tile_retry = tile_retry;
+        self
+    }
+
+    /// Forgets the retry state of the failed tiles when `reset_retries`, so that they are all
+    /// attempted again.
+    pub fn with_reset_retries(mut self, reset_retries: bool) -> Self {
+        self.reset_retries = reset_retries;
+        self
+    }
+
+    /// Appends the stages of the tiles (download, conversion and upload) to the event log in the
+    /// output dir when `event_log`, with their start and end, the size of their file and their
+    /// outcome.
+    pub fn with_event_log(mut self, event_log: bool) -> Self {
+        self.event_log = event_log;
+        self
+    }
+
+    /// Allows files with another schema or settings in the output dir when `mixed_schema`,
+    /// which the job refuses to mix by default.
+    pub fn with_mixed_schema(mut self, mixed_schema: bool) -> Self {
+        self.mixed_schema = mixed_schema;
+        self
+    }
+
+    /// Embeds the bounding box of the tiles in the names of the Parquet files when
+    /// `bbox_names`, e.g. `ALPSMLC30_N052E004_DSM_52.0_53.0_4.0_5.0.parquet`, so that files
+    /// can be selected by name without reading their footers.
+    pub fn with_bbox_names(mut self, bbox_names: bool) -> Self {
+        self.bbox_names = bbox_names;
+        self
+    }
+
+    /// Removes the downloaded GeoTIFF file of a tile once its Parquet file is written when
+    /// `remove_tif`, so that the GeoTIFF files of a set don't need to fit on disk next to the
+    /// Parquet files. Local input files are never removed.
+    pub fn with_remove_tif(mut self, remove_tif: bool) -> Self {
+        self.remove_tif = remove_tif;
+        self
+    }
+
+    /// Records the `options` that shape the files (e.g. the command line options of the schema
+    /// and the file names) in the manifest when the job creates the dataset, so that later
+    /// runs can append to it with the same options. The options of an existing dataset are
+    /// kept.
+    pub fn with_options(mut self, options: BTreeMap<String, Vec<String>>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns a token to cancel the job.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Runs the job, fetching the tiles from `source`.
+    ///
+    /// Returns an error when the output dir has files with another schema (unless mixed
+    /// schemas are allowed), when the job is cancelled or when tiles failed (in this run, or
+    /// in earlier runs without being retried), after saving the manifest of the tiles that
+    /// were converted.
+    pub async fn run(self, source: &Source) -> Result<(), Box<dyn Error>> {
+        let ConvertJob {
+            objects,
+            tif_dir,
+            parquet_dir,
+            staging_dir,
+            relief,
+            hillshade,
+            uploader,
+            tile_retry,
+            reset_retries,
+            event_log,
+            mixed_schema,
+            bbox_names,
+            remove_tif,
+            options,
+            conversion,
+            usage,
+            progress,
+            cancellation,
+        } = self;
+        let re = Regex::new(TILE_PATTERN)?;
+        // Fail before downloading anything.
+        if !mixed_schema {
+            conversion.check_compatible(&parquet_dir)?;
+        }
+        if let Some(staging_dir) = &staging_dir {
+            fs::create_dir_all(staging_dir)?;
+        }
+        let mut manifest = Manifest::load(&parquet_dir)?;
+        if manifest.files.is_empty() && manifest.options.is_none() {
+            manifest.options = Some(options);
+        }
+        if reset_retries {
+            manifest.retries.clear();
+        }
+        // Skip the tiles that failed in earlier runs until their backoff has passed.
+        let now = SystemTime::now();
+        let mut skipped = 0;
+        let objects = objects
+            .into_iter()
+            .filter(|object| {
+                let tile = object.name();
+                let state = match manifest.retries.get(tile) {
+                    Some(state) => state,
+                    None => return true,
+                };
+                match tile_retry.wait(state, now) {
+                    Some(wait) if wait.is_zero() => return true,
+                    Some(wait) => event!(
+                        Level::WARN,
+                        "Deferring {} for {:.0?} after {} failed attempts: {}",
+                        tile,
+                        wait,
+                        state.attempts,
+                        state.error
+                    ),
+                    None => event!(
+                        Level::WARN,
+                        "Not retrying {} after {} failed attempts: {}",
+                        tile,
+                        state.attempts,
+                        state.error
+                    ),
+                }
+                skipped += 1;
+                false
+            })
+            .collect::<Vec<_>>();
+        let manifest = Arc::new(Mutex::new(manifest));
+        let failed = AtomicUsize::new(0);
+        let events = event_log
+            .then(|| EventLog::open(&parquet_dir.join(EVENT_LOG)))
+            .transpose()?
+            .map(Arc::new);
+
+        event!(Level::INFO, "Downloading {} files", objects.len());
+        stream::iter(objects)
+            .take_while(|_| future::ready(!cancellation.is_cancelled()))
+            .map(|object| {
+                let tile = object.name().to_string();
+                let local = matches!(object, RemoteFile::Local(_));
+                progress.started(&tile);
+                let start = SystemTime::now();
+                let events = events.clone();
+                source
+                    .download(object, tif_dir.clone(), conversion.if_exists, usage.clone())
+                    .map(move |result| {
+                        if let Some(events) = &events {
+                            let entry = TileEvent::new(&tile, Stage::Download, start, Outcome::Ok);
+                            events.record(&match &result {
+                                Ok(Ok((path, _))) => entry.with_file(path),
+                                Ok(Err(e)) => entry.with_error(e),
+                                Err(e) => entry.with_error(e),
+                            });
+                        }
+                        (tile, local, result)
+                    })
+            })
+            .buffer_unordered(1)
+            .for_each_concurrent(None, |(tile, local, download)| {
+                let conversion = conversion.clone();
+                let usage = usage.clone();
+                let parquet_dir = parquet_dir.clone();
+                let staging_dir = staging_dir.clone();
+                let relief = relief.clone();
+                let hillshade = hillshade.clone();
+                let uploader = uploader.clone();
+                let events = events.clone();
+                let progress = progress.clone();
+                let manifest = &manifest;
+                let failed = &failed;
+                let re = &re;
+                async move {
+                    let (input_path, upstream) = match download {
+                        Ok(Ok(download)) => download,
+                        Ok(Err(e)) => return Err((tile, e)),
+                        Err(e) => return Err((tile, e.into())),
+                    };
+                    // Tiles that are not named after their coordinate keep the plain name.
+                    let coordinate = bbox_names.then(|| tile_coordinate(re, &tile)).flatten();
+                    let output_path = parquet_dir.join(parquet_name(&tile, coordinate));
+                    let path = output_path.clone();
+                    let manifest = manifest.clone();
+                    let start = SystemTime::now();
+                    let mask = conversion.mask();
+                    let convert = task::spawn_blocking(move || -> Result<bool, String> {
+                        let written = match &staging_dir {
+                            Some(staging_dir) => write_staged(
+                                input_path.clone(),
+                                &path,
+                                staging_dir,
+                                &upstream,
+                                conversion,
+                                usage,
+                            ),
+                            None => write_parquet(
+                                input_path.clone(),
+                                path.clone(),
+                                &upstream,
+                                conversion,
+                                usage,
+                            ),
+                        }
+                        .map_err(|e| e.to_string())?;
+                        // Write previews (and hillshades) of converted tiles, and of kept tiles
+                        // without one.
+                        if let Some(relief) = &relief {
+                            if written || !relief.path(&input_path).exists() {
+                                relief.write(&input_path).map_err(|e| e.to_string())?;
+                            }
+                        }
+                        if let Some(hillshade) = &hillshade {
+                            if written || !hillshade.path(&input_path).exists() {
+                                hillshade.write(&input_path).map_err(|e| e.to_string())?;
+                            }
+                        }
+                        // Hash files that were written or are not yet listed in the manifest.
+                        let name = path.file_name().unwrap().to_string_lossy();
+                        if written || manifest.lock().unwrap().get(&name).is_none() {
+                            let file = ManifestFile::new(&parquet_dir, &name, upstream)
+                                .map_err(|e| e.to_string())?;
+                            // Saved after every file, so that an interrupted run keeps the
+                            // entries of the files it wrote (whose GeoTIFF files may be removed).
+                            let mut manifest = manifest.lock().unwrap();
+                            manifest.insert(file);
+                            manifest.save(&parquet_dir).map_err(|e| e.to_string())?;
+                        }
+                        if remove_tif && !local {
+                            mask::remove_tile(&input_path, mask).map_err(|e| e.to_string())?;
+                        }
+                        Ok(written)
+                    });
+                    let convert = convert.await;
+                    if let Some(events) = &events {
+                        let outcome = match &convert {
+                            Ok(Ok(false)) => Outcome::Skipped,
+                            _ => Outcome::Ok,
+                        };
+                        let entry = TileEvent::new(&tile, Stage::Convert, start, outcome);
+                        events.record(&match &convert {
+                            Ok(Ok(_)) => entry.with_file(&output_path),
+                            Ok(Err(e)) => entry.with_error(e),
+                            Err(e) => entry.with_error(e),
+                        });
+                    }
+                    let written = match convert {
+                        Ok(Ok(written)) => written,
+                        Ok(Err(e)) => return Err((tile, e.into())),
+                        Err(e) => return Err((tile, e.into())),
+                    };
+                    if let Some(uploader) = &uploader {
+                        let name = output_path.file_name().unwrap().to_string_lossy();
+                        let start = SystemTime::now();
+                        let uploaded = async {
+                            let size = tokio::fs::metadata(&output_path).await?.len();
+                            let upload = written || !uploader.exists(&name, size).await?;
+                            if upload {
+                                uploader.upload(&output_path, &name).await?;
+                            }
+                            Ok::<_, Box<dyn Error + Send + Sync>>(upload)
+                        }
+                        .await;
+                        if let Some(events) = &events {
+                            let outcome = match &uploaded {
+                                Ok(false) => Outcome::Skipped,
+                                _ => Outcome::Ok,
+                            };
+                            let entry = TileEvent::new(&tile, Stage::Upload, start, outcome);
+                            events.record(&match &uploaded {
+                                Ok(_) => entry.with_file(&output_path),
+                                Err(e) => entry.with_error(e),
+                            });
+                        }
+                        if let Err(e) = uploaded {
+                            return Err((tile, e));
+                        }
+                    }
+                    Ok((tile, output_path, written))
+                }
+                .map(move |result| match result {
+                    Ok((tile, path, written)) => {
+                        manifest.lock().unwrap().set_failed(&tile, false);
+                        progress.finished(&tile, &path, written)
+                    }
+                    Err((tile, e)) => {
+                        // Record the failure, so that it shows up in the coverage of the dir.
+                        manifest
+                            .lock()
+                            .unwrap()
+                            .record_failure(&tile, &e.to_string());
+                        failed.fetch_add(1, Ordering::Relaxed);
+                        progress.failed(&tile, &*e);
+                    }
+                })
+            })
+            .await;
+
+        manifest.lock().unwrap().save(&parquet_dir)?;
+        if cancellation.is_cancelled() {
+            return Err("conversion cancelled".into());
+        }
+        event!(Level::INFO, "Writing spatial index");
+        let row_groups = index::write_spatial_index(&parquet_dir)?;
+        event!(Level::INFO, "Indexed {} row groups", row_groups);
+        match summary::write_summary_metadata(&parquet_dir)? {
+            Some(row_groups) => event!(Level::INFO, "Summarized {} row groups", row_groups),
+            None => event!(
+                Level::WARN,
+                "Not writing the summary metadata, the files have different schemas"
+            ),
+        }
+        if let Some(uploader) = &uploader {
+            event!(
+                Level::INFO,
+                "Uploading the metadata to `{}`",
+                uploader.url()
+            );
+            for name in [MANIFEST, SPATIAL_INDEX, SUMMARY_METADATA, EVENT_LOG] {
+                let path = parquet_dir.join(name);
+                if path.exists() {
+                    uploader
+                        .upload(&path, name)
+                        .await
+                        .map_err(|e| e as Box<dyn Error>)?;
+                }
+            }
+        }
+
+        match (failed.into_inner(), skipped) {
+            (0, 0) => Ok(()),
+            (failed, 0) => Err(format!("{} tiles failed", failed).into()),
+            (failed, skipped) => Err(format!(
+                "{} tiles failed, {} failed tiles not retried",
+                failed, skipped
+            )
+            .into()),
+        }
+    }
+}
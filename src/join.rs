@@ -0,0 +1,62 @@
+use crate::reference::Reference;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Source of the values of an extra column of the output, joined to the points by their
+/// coordinates.
+pub trait Join: Send + Sync {
+    /// Returns the name of the column.
+    fn name(&self) -> &str;
+
+    /// Returns the value at the `points` (`lon`, `lat`) in `bounds` (`min_lon`, `min_lat`,
+    /// `max_lon`, `max_lat`), or `None` where there is no value.
+    fn values(
+        &self,
+        bounds: (f64, f64, f64, f64),
+        points: &[(f64, f64)],
+    ) -> Result<Vec<Option<f64>>, Box<dyn Error>>;
+}
+
+/// Values of the first band of a raster dataset that is co-registered with the tiles (e.g. ESA
+/// WorldCover land cover classes), at the pixel containing the center of the points.
+pub struct RasterJoin {
+    name: String,
+    reference: Reference,
+}
+
+impl RasterJoin {
+    /// Opens the raster dataset (a raster file, e.g. a VRT, or a dir of GeoTIFF files) at
+    /// `path`, of which the values are written in a column named `name`.
+    pub fn open(name: &str, path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(RasterJoin {
+            name: name.to_string(),
+            reference: Reference::open(path)?,
+        })
+    }
+}
+
+impl Join for RasterJoin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn values(
+        &self,
+        bounds: (f64, f64, f64, f64),
+        points: &[(f64, f64)],
+    ) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+        self.reference.sample(bounds, points)
+    }
+}
+
+/// Parses a raster to join, `name=path`.
+pub fn parse_join_raster(s: &str) -> Result<(String, PathBuf), &'static str> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err("expected `name=path`"),
+    }
+}
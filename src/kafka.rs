@@ -0,0 +1,91 @@
+use arrow::{ipc::writer::StreamWriter, record_batch::RecordBatch};
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+    ClientConfig,
+};
+use std::{error::Error, str::FromStr};
+use tracing::{event, Level};
+
+/// Maximum number of rows of a record batch, which keeps the messages below the default 1 MB
+/// limit of Kafka brokers for a few bands.
+pub const BATCH_ROWS: usize = 16_384;
+
+/// Kafka (or Redpanda) topic that receives the converted tiles, parsed from
+/// `kafka://host:port[,host:port...]/topic`.
+#[derive(Clone, Debug)]
+pub struct KafkaTarget {
+    /// Bootstrap servers
+    brokers: String,
+    topic: String,
+}
+
+impl FromStr for KafkaTarget {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+            .strip_prefix("kafka://")
+            .and_then(|target| target.split_once('/'))
+        {
+            Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => Ok(KafkaTarget {
+                brokers: brokers.to_string(),
+                topic: topic.to_string(),
+            }),
+            _ => Err("expected `kafka://host:port/topic`"),
+        }
+    }
+}
+
+impl KafkaTarget {
+    /// Returns a producer for the topic.
+    pub fn connect(&self) -> Result<KafkaProducer, Box<dyn Error>> {
+        event!(
+            Level::INFO,
+            "Publishing to `{}` at `{}`",
+            self.topic,
+            self.brokers
+        );
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &self.brokers)
+            .create()?;
+        Ok(KafkaProducer {
+            producer,
+            topic: self.topic.clone(),
+        })
+    }
+}
+
+/// Producer of messages to a Kafka topic.
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaProducer {
+    /// Publishes the `batches` of `tile`, one message per batch encoded as an Arrow IPC stream
+    /// with its schema, keyed by the name of the tile. The messages of a tile are in one
+    /// partition, so consumers receive them in order.
+    pub async fn send(
+        &self,
+        tile: &str,
+        batches: Vec<RecordBatch>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for batch in batches {
+            let mut payload = Vec::new();
+            let mut writer = StreamWriter::try_new(&mut payload, &batch.schema())?;
+            writer.write(&batch)?;
+            writer.finish()?;
+            drop(writer);
+            // Wait for room in the queue of the producer.
+            self.producer
+                .send(
+                    FutureRecord::to(&self.topic).key(tile).payload(&payload),
+                    Timeout::Never,
+                )
+                .await
+                .map_err(|(e, _)| e)?;
+        }
+        Ok(())
+    }
+}
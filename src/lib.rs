@@ -0,0 +1,128 @@
+//! Download ALOS World 3D 30 meter DEM GeoTIFFs and convert them to Parquet.
+//!
+//! The [`Source`] lists and downloads the GeoTIFF files of the tiles selected by a [`Set`], and
+//! [`write_parquet`] converts a GeoTIFF file to a Parquet file with the schema of a
+//! [`Conversion`]. A [`job::ConvertJob`] runs both stages for a list of tiles, or
+//! [`sink::put_tiles`] sends the points of the tiles to an [`OutputSink`] instead. The binary
+//! parses its command line with [`cli::parse_args`] and runs it with [`run::run`].
+
+use std::{io, path::Path, str::FromStr};
+
+pub mod amend;
+pub mod area;
+pub mod bandwidth;
+pub mod catalog;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod cli;
+pub mod column;
+pub mod convert;
+pub mod country;
+pub mod coverage;
+pub mod crs;
+pub mod curve;
+pub mod dataset;
+pub mod delta;
+pub mod diff;
+pub mod disk;
+pub mod events;
+pub mod flight;
+pub mod geoparquet;
+pub mod gpkg;
+pub mod grid;
+pub mod http;
+pub mod hypsometry;
+pub mod index;
+pub mod inspect;
+pub mod job;
+pub mod join;
+pub mod kafka;
+pub mod manifest;
+pub mod mask;
+pub mod mercator;
+pub mod merge;
+pub mod mirror;
+pub mod mosaic;
+pub mod plan;
+pub mod polygon;
+pub mod progress;
+pub mod reader;
+pub mod reference;
+pub mod relief;
+pub mod remote;
+pub mod report;
+pub mod retry;
+pub mod run;
+pub mod sample;
+pub mod sink;
+pub mod source;
+pub mod summary;
+pub mod terrain;
+pub mod tile;
+pub mod upload;
+pub mod usage;
+pub mod zonal;
+
+pub use convert::{write_parquet, Band, Conversion, Coords};
+pub use reader::{Aw3d30Reader, Elevation};
+pub use sink::OutputSink;
+pub use source::{download_object, RemoteFile, Source};
+pub use tile::{tile_coordinate, Bbox, Coordinate, Country, Set};
+pub use usage::Usage;
+
+/// Bucket of the GeoTIFF files of the datasets on the OpenTopography storage server.
+pub const BUCKET: &str = "raster";
+/// Prefix of the AW3D30 GeoTIFF files in the [`BUCKET`].
+pub const PREFIX: &str = "AW3D30/AW3D30_global/";
+/// Endpoint of the OpenTopography storage server.
+pub const ENDPOINT: &str = "opentopography.s3.sdsc.edu";
+/// Pattern of the tile coordinate in the name of a raster file, of AW3D30 (e.g.
+/// `ALPSMLC30_N052E004_DSM`), COP30 (e.g. `Copernicus_DSM_10_N52_00_E004_00_DEM`) or SRTM GL1
+/// (e.g. `N52E004.hgt`). The mask files of AW3D30 do not match.
+pub const TILE_PATTERN: &str = r"(?:ALPSMLC30_|Copernicus_DSM(?:_COG)?_10_|^|/)(?P<y>[NS])(?P<lat>\d{2,3})(?:_00_)?(?P<x>[EW])(?P<lon>\d{3})(?:(?:_00)?_D[SE]M|\.|_\d)";
+
+/// Policy for output files that already exist.
+#[derive(Copy, Clone, Debug)]
+pub enum IfExists {
+    /// Keep existing files
+    Skip,
+    /// Keep existing files that are valid, replace invalid files
+    Verify,
+    /// Replace existing files
+    Overwrite,
+    /// Fail on existing files
+    Error,
+}
+
+impl FromStr for IfExists {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "verify" => Ok(Self::Verify),
+            "overwrite" => Ok(Self::Overwrite),
+            "error" => Ok(Self::Error),
+            _ => Err("expected one of: skip, verify, overwrite, error"),
+        }
+    }
+}
+
+impl IfExists {
+    /// Returns true when the existing file at `path` should be kept, where `valid` checks the
+    /// file when the policy is to verify it.
+    pub fn keep(self, path: &Path, valid: impl FnOnce() -> bool) -> io::Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        match self {
+            Self::Skip => Ok(true),
+            Self::Verify => Ok(valid()),
+            Self::Overwrite => Ok(false),
+            Self::Error => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("`{}` already exists", path.display()),
+            )),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{index::min_max, tile_coordinate, TILE_PATTERN};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// File name of the manifest in the Parquet output dir.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Manifest of the Parquet files in an output dir.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Produced files, sorted by path
+    pub files: Vec<ManifestFile>,
+    /// Names of the tiles that failed to download or convert, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+    /// Retry state of the failed tiles by name, so that later runs keep backing off
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub retries: BTreeMap<String, RetryState>,
+    /// Options (e.g. of the command line) that shaped the files of the dataset, by name, as
+    /// recorded by the run that created it, so that later runs can append files with the same
+    /// schema and names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<BTreeMap<String, Vec<String>>>,
+}
+
+/// A file listed in the manifest.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the output dir
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Hex encoded SHA-256 checksum
+    pub sha256: String,
+    /// Number of rows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_rows: Option<i64>,
+    /// Latitude and longitude of the south-west corner of the tile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coordinate: Option<(i16, i16)>,
+    /// Bounding box of the points, `[min_lon, min_lat, max_lon, max_lat]`, from the statistics
+    /// of the coordinate columns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<[f64; 4]>,
+    /// Version of the application that wrote the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    /// Version of the source GeoTIFF file the file was converted from
+    #[serde(default)]
+    pub upstream: Upstream,
+}
+
+/// Version of a source file, as reported by the upstream server.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Upstream {
+    /// Entity tag of the file
+    pub etag: Option<String>,
+    /// Last modification time of the file
+    pub last_modified: Option<String>,
+    /// Size of the file in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Object key or URL of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// Retry state of a tile that failed to download or convert.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RetryState {
+    /// Number of consecutive runs in which the tile failed
+    pub attempts: u32,
+    /// Error of the last attempt
+    pub error: String,
+    /// End of the last attempt, in seconds since the Unix epoch
+    pub failed_at: u64,
+}
+
+impl ManifestFile {
+    /// Returns the manifest entry for the file at `path` in `dir`, converted from the `upstream`
+    /// version of its source file.
+    pub fn new(dir: &Path, path: &str, upstream: Upstream) -> io::Result<Self> {
+        let full_path = dir.join(path);
+        let re = Regex::new(TILE_PATTERN).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(ManifestFile {
+            path: path.to_string(),
+            size: full_path.metadata()?.len(),
+            sha256: sha256(&full_path)?,
+            num_rows: Some(num_rows(&full_path)?),
+            coordinate: tile_coordinate(&re, path).map(|coordinate| coordinate.degrees()),
+            bbox: bbox(&full_path)?,
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            upstream,
+        })
+    }
+}
+
+/// Result of verifying the files in an output dir against its manifest.
+#[derive(Default)]
+pub struct Verification {
+    /// Files listed in the manifest that do not exist
+    pub missing: Vec<String>,
+    /// Parquet files that are not listed in the manifest
+    pub extra: Vec<String>,
+    /// Files with a size, checksum or number of rows that does not match the manifest
+    pub corrupted: Vec<String>,
+}
+
+impl Verification {
+    /// Returns true when all files match the manifest.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+impl Manifest {
+    /// Loads the manifest from `dir`, returning an empty manifest when there is none.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match Manifest::read(&dir.join(MANIFEST)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            result => result,
+        }
+    }
+
+    /// Reads the manifest file at `path`.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    /// Writes the manifest to `dir`.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        // Write to a temporary file first, so that an interrupted write does not leave a
+        // truncated manifest behind.
+        let tmp_path = dir.join(MANIFEST).with_extension("json.tmp");
+        serde_json::to_writer_pretty(io::BufWriter::new(File::create(&tmp_path)?), self)?;
+        fs::rename(tmp_path, dir.join(MANIFEST))
+    }
+
+    /// Returns the entry for the file at `path`.
+    pub fn get(&self, path: &str) -> Option<&ManifestFile> {
+        self.files
+            .binary_search_by(|file| file.path.as_str().cmp(path))
+            .ok()
+            .map(|idx| &self.files[idx])
+    }
+
+    /// Adds or replaces the entry for a file.
+    pub fn insert(&mut self, file: ManifestFile) {
+        match self
+            .files
+            .binary_search_by(|entry| entry.path.cmp(&file.path))
+        {
+            Ok(idx) => self.files[idx] = file,
+            Err(idx) => self.files.insert(idx, file),
+        }
+    }
+
+    /// Marks the tile with `name` as failed with `error`, counting the attempt in its retry
+    /// state.
+    pub fn record_failure(&mut self, name: &str, error: &str) {
+        self.set_failed(name, true);
+        let failed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let state = self.retries.entry(name.to_string()).or_insert(RetryState {
+            attempts: 0,
+            error: String::new(),
+            failed_at,
+        });
+        state.attempts += 1;
+        state.error = error.to_string();
+        state.failed_at = failed_at;
+    }
+
+    /// Marks the tile with `name` as failed, or as no longer failed, which also clears its
+    /// retry state.
+    pub fn set_failed(&mut self, name: &str, failed: bool) {
+        if !failed {
+            self.retries.remove(name);
+        }
+        match (
+            self.failed.binary_search_by(|tile| tile.as_str().cmp(name)),
+            failed,
+        ) {
+            (Err(idx), true) => self.failed.insert(idx, name.to_string()),
+            (Ok(idx), false) => {
+                self.failed.remove(idx);
+            }
+            _ => {}
+        }
+    }
+
+    /// Re-hashes the files in `dir` and compares them against the manifest.
+    pub fn verify(&self, dir: &Path) -> io::Result<Verification> {
+        let mut verification = Verification::default();
+        for file in &self.files {
+            let path = dir.join(&file.path);
+            if !path.exists() {
+                verification.missing.push(file.path.clone());
+            } else if path.metadata()?.len() != file.size
+                || sha256(&path)? != file.sha256
+                || file
+                    .num_rows
+                    .map_or(Ok(false), |rows| num_rows(&path).map(|n| n != rows))?
+            {
+                verification.corrupted.push(file.path.clone());
+            }
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "parquet") {
+                let name = path.file_name().unwrap().to_string_lossy();
+                // Files with a leading underscore are derived metadata, like the spatial index.
+                if !name.starts_with('_') && self.get(&name).is_none() {
+                    verification.extra.push(name.into_owned());
+                }
+            }
+        }
+        verification.extra.sort();
+        Ok(verification)
+    }
+}
+
+/// Returns the number of rows of the Parquet file at `path`, from its footer.
+fn num_rows(path: &Path) -> io::Result<i64> {
+    let reader = SerializedFileReader::new(File::open(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(reader.metadata().file_metadata().num_rows())
+}
+
+/// Returns the bounding box of the points of the Parquet file at `path`, from the statistics of
+/// the `lat` and `lon` columns of its row groups. Files without points or statistics (e.g. with
+/// projected coordinates) have none.
+fn bbox(path: &Path) -> io::Result<Option<[f64; 4]>> {
+    let reader = SerializedFileReader::new(File::open(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let bounds = reader
+        .metadata()
+        .row_groups()
+        .iter()
+        .map(|row_group| Some((min_max(row_group, "lon")?, min_max(row_group, "lat")?)))
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+    Ok(bounds
+        .into_iter()
+        .fold(None, |bbox, ((min_lon, max_lon), (min_lat, max_lat))| {
+            Some(match bbox {
+                Some([a, b, c, d]) => [
+                    min_lon.min(a),
+                    min_lat.min(b),
+                    max_lon.max(c),
+                    max_lat.max(d),
+                ],
+                None => [min_lon, min_lat, max_lon, max_lat],
+            })
+        }))
+}
+
+/// Returns the hex encoded SHA-256 checksum of the file at `path`.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64, sha256: &str) -> ManifestFile {
+        ManifestFile {
+            path: path.to_string(),
+            size,
+            sha256: sha256.to_string(),
+            num_rows: None,
+            coordinate: None,
+            bbox: None,
+            tool_version: None,
+            upstream: Upstream::default(),
+        }
+    }
+
+    #[test]
+    fn insert_keeps_files_sorted() {
+        let mut manifest = Manifest::default();
+        for path in ["b.parquet", "c.parquet", "a.parquet"] {
+            manifest.insert(file(path, 1, "x"));
+        }
+        manifest.insert(file("b.parquet", 2, "y"));
+        let paths = manifest
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["a.parquet", "b.parquet", "c.parquet"]);
+        assert_eq!(manifest.get("b.parquet").map(|file| file.size), Some(2));
+        assert!(manifest.get("d.parquet").is_none());
+    }
+
+    #[test]
+    fn set_failed_and_retries() {
+        let mut manifest = Manifest::default();
+        manifest.record_failure("N052E004", "timeout");
+        manifest.record_failure("N052E004", "reset");
+        manifest.set_failed("N051E004", true);
+        manifest.set_failed("N051E004", true);
+        assert_eq!(manifest.failed, ["N051E004", "N052E004"]);
+        let state = &manifest.retries["N052E004"];
+        assert_eq!((state.attempts, state.error.as_str()), (2, "reset"));
+        assert!(!manifest.retries.contains_key("N051E004"));
+
+        manifest.set_failed("N052E004", false);
+        assert_eq!(manifest.failed, ["N051E004"]);
+        assert!(manifest.retries.is_empty());
+        manifest.set_failed("N050E004", false);
+        assert_eq!(manifest.failed, ["N051E004"]);
+    }
+
+    #[test]
+    fn verify_files() {
+        let dir = std::env::temp_dir().join(format!("aw3d30-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut manifest = Manifest::default();
+        for name in ["a.parquet", "b.parquet"] {
+            let path = dir.join(name);
+            fs::write(&path, name).unwrap();
+            manifest.insert(file(name, 9, &sha256(&path).unwrap()));
+        }
+        manifest.insert(file("missing.parquet", 1, "x"));
+        // Same size, other content.
+        fs::write(dir.join("b.parquet"), "B.parquet").unwrap();
+        fs::write(dir.join("extra.parquet"), "").unwrap();
+        fs::write(dir.join("_index.parquet"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let verification = manifest.verify(&dir).unwrap();
+        assert_eq!(verification.missing, ["missing.parquet"]);
+        assert_eq!(verification.corrupted, ["b.parquet"]);
+        assert_eq!(verification.extra, ["extra.parquet"]);
+        assert!(!verification.is_ok());
+
+        manifest.files.retain(|file| file.path == "a.parquet");
+        fs::remove_file(dir.join("b.parquet")).unwrap();
+        fs::remove_file(dir.join("extra.parquet")).unwrap();
+        assert!(manifest.verify(&dir).unwrap().is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
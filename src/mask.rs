@@ -0,0 +1,56 @@
+//! Mask files of the tiles (`_MSK`), with quality flags of the pixels such as void filling,
+//! clouds and sea.
+
+use crate::RemoteFile;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Returns the name of the tile of the mask file `name`, or `name` when it is not a mask file.
+pub fn tile_name(name: &str) -> String {
+    name.replace("_MSK", "_DSM")
+}
+
+/// Returns true when `name` is the name of a mask file.
+pub fn is_mask(name: &str) -> bool {
+    name.contains("_MSK")
+}
+
+/// Returns the path of the mask file of the tile at `path`, in the same dir.
+pub fn mask_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(name.replace("_DSM", "_MSK"))
+}
+
+/// Removes the GeoTIFF file of the tile at `path`, and its mask file when `mask` files were
+/// downloaded with it.
+pub fn remove_tile(path: &Path, mask: bool) -> io::Result<()> {
+    fs::remove_file(path)?;
+    if !mask {
+        return Ok(());
+    }
+    match fs::remove_file(mask_path(path)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Splits the mask files from the tiles in `objects`, returning the tiles and the masks of those
+/// tiles.
+pub fn split(objects: Vec<RemoteFile>) -> (Vec<RemoteFile>, Vec<RemoteFile>) {
+    objects
+        .into_iter()
+        .partition(|object| !is_mask(object.name()))
+}
+
+/// Returns the `masks` of the `tiles`, without the masks of other tiles.
+pub fn of_tiles(tiles: &[RemoteFile], masks: Vec<RemoteFile>) -> Vec<RemoteFile> {
+    masks
+        .into_iter()
+        .filter(|mask| {
+            let name = tile_name(mask.name());
+            tiles.iter().any(|tile| tile.name() == name)
+        })
+        .collect()
+}
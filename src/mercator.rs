@@ -0,0 +1,85 @@
+//! Web mercator map tiles ("slippy map" tiles) of the points.
+
+use std::str::FromStr;
+
+/// Maximum zoom level of the map tiles.
+pub const MAX_ZOOM: u8 = 30;
+
+/// Latitude limit of the web mercator projection, points beyond it are in the edge tiles.
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Format of the id of a map tile.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileFormat {
+    /// `z/x/y`
+    Xyz,
+    /// Bing Maps quadkey, a digit per zoom level
+    Quadkey,
+}
+
+impl FromStr for TileFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xyz" => Ok(Self::Xyz),
+            "quadkey" => Ok(Self::Quadkey),
+            _ => Err("expected one of: xyz, quadkey"),
+        }
+    }
+}
+
+/// Returns the `x` and `y` of the map tile at `zoom` that contains the point.
+pub fn tile(lat: f64, lon: f64, zoom: u8) -> (u32, u32) {
+    let n = (1_u64 << zoom) as f64;
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.) / 360. * n;
+    let y = (1. - (lat.tan() + 1. / lat.cos()).ln() / std::f64::consts::PI) / 2. * n;
+    // Points on the east and south edges are in the last tiles.
+    let index = |value: f64| value.floor().clamp(0., n - 1.) as u32;
+    (index(x), index(y))
+}
+
+/// Returns the id of the map tile `x`, `y` at `zoom` in `format`.
+pub fn tile_id(format: TileFormat, zoom: u8, x: u32, y: u32) -> String {
+    match format {
+        TileFormat::Xyz => format!("{}/{}/{}", zoom, x, y),
+        TileFormat::Quadkey => (1..=zoom)
+            .rev()
+            .map(|level| {
+                let mask = 1 << (level - 1);
+                let digit = (x & mask != 0) as u8 + 2 * (y & mask != 0) as u8;
+                char::from(b'0' + digit)
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_of_points() {
+        assert_eq!(tile(0., 0., 0), (0, 0));
+        assert_eq!(tile(0., 0., 1), (1, 1));
+        // Amsterdam.
+        assert_eq!(tile(52.37, 4.89, 10), (525, 336));
+        // The poles and the antimeridian are in the edge tiles.
+        assert_eq!(tile(90., 180., 2), (3, 0));
+        assert_eq!(tile(-90., -180., 2), (0, 3));
+        let n = 1 << MAX_ZOOM;
+        assert_eq!(tile(-90., 180., MAX_ZOOM), (n - 1, n - 1));
+    }
+
+    #[test]
+    fn tile_ids() {
+        assert_eq!(tile_id(TileFormat::Xyz, 3, 3, 5), "3/3/5");
+        // The example of the Bing Maps tile system.
+        assert_eq!(tile_id(TileFormat::Quadkey, 3, 3, 5), "213");
+        assert_eq!(tile_id(TileFormat::Quadkey, 1, 1, 0), "1");
+        assert_eq!(tile_id(TileFormat::Quadkey, 0, 0, 0), "");
+        assert_eq!("quadkey".parse(), Ok(TileFormat::Quadkey));
+        assert!("tms".parse::<TileFormat>().is_err());
+    }
+}
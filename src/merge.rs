@@ -0,0 +1,470 @@
+use crate::{
+    amend::{self, MAGIC},
+    Conversion, IfExists,
+};
+use arrow::{
+    datatypes::{Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    file::{
+        metadata::{OffsetIndexBuilder, RowGroupMetaData},
+        page_index::index_reader,
+        properties::WriterProperties,
+    },
+    format::FileMetaData,
+    schema::types::{self, SchemaDescriptor},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{event, Level};
+
+/// Single Parquet file with the points of all tiles, written tile by tile.
+///
+/// Every record batch is written as a row group, so a tile is one row group unless the
+/// conversion limits the size of the row groups. The key-value metadata of the file is that of
+/// the conversion, the nodata values and upstream versions of the tiles are not recorded.
+///
+/// The row groups of every tile are appended to the `.tmp` file as they are written, and the
+/// tile, the length of the file and the metadata of its row groups are recorded in a
+/// `.progress` file next to it. An interrupted build is resumed from the tiles that were
+/// completed, and the footer is written after the last tile.
+pub struct MergedParquet {
+    schema: SchemaRef,
+    properties: WriterProperties,
+    path: PathBuf,
+    /// Path of the file while it is written
+    tmp_path: PathBuf,
+    /// Path of the progress of the build
+    progress_path: PathBuf,
+    /// Length of the data of the completed tiles in the `.tmp` file
+    len: u64,
+    /// Metadata of the row groups of the completed tiles
+    metadata: Option<FileMetaData>,
+    /// Completed tiles
+    completed: HashSet<String>,
+    /// Number of rows of the record batches of a tile
+    pub(crate) batch_rows: usize,
+}
+
+/// First line of the progress file, with the schema the build was started with.
+#[derive(Deserialize, Serialize)]
+struct Header {
+    schema: String,
+}
+
+/// Line of the progress file for a completed tile.
+#[derive(Deserialize, Serialize)]
+struct Progress {
+    tile: String,
+    /// Length of the `.tmp` file after the row groups of the tile
+    len: u64,
+    /// Metadata of the row groups of the tile (thrift compact protocol, base64), none when all
+    /// points of the tile were clipped
+    metadata: Option<String>,
+}
+
+impl MergedParquet {
+    /// Creates the merged Parquet file at `path` for the output of `conversion`, replacing an
+    /// existing file only when `if_exists` is to overwrite. An interrupted build with the same
+    /// schema is resumed, unless `if_exists` is to overwrite.
+    pub fn create(
+        path: &Path,
+        conversion: &Conversion,
+        if_exists: IfExists,
+    ) -> Result<Self, Box<dyn Error>> {
+        // An existing file is not appended to, so only overwriting replaces it.
+        if if_exists.keep(path, || true)? {
+            return Err(format!("`{}` already exists", path.display()).into());
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let schema = Arc::new(conversion.arrow_schema());
+        let mut merged = MergedParquet {
+            schema: schema.clone(),
+            properties: conversion.writer_properties(Vec::new()),
+            path: path.to_path_buf(),
+            tmp_path: path.with_extension("parquet.tmp"),
+            progress_path: path.with_extension("parquet.progress"),
+            len: 0,
+            metadata: None,
+            completed: HashSet::new(),
+            // The whole tile unless the conversion limits the size of the row groups.
+            batch_rows: conversion.row_group_size().unwrap_or(usize::MAX),
+        };
+        let schema = fingerprint(&schema);
+        if !matches!(if_exists, IfExists::Overwrite) && merged.resume(&schema)? {
+            event!(
+                Level::INFO,
+                "Resuming merged Parquet file `{}` after {} tiles",
+                path.display(),
+                merged.completed.len()
+            );
+            return Ok(merged);
+        }
+        event!(
+            Level::INFO,
+            "Writing merged Parquet file `{}`",
+            path.display()
+        );
+        File::create(&merged.tmp_path)?;
+        let header = serde_json::to_string(&Header { schema })?;
+        fs::write(&merged.progress_path, header + "\n")?;
+        Ok(merged)
+    }
+
+    /// Loads the progress of an interrupted build with `schema`. Returns false when there is
+    /// none to resume.
+    fn resume(&mut self, schema: &str) -> Result<bool, Box<dyn Error>> {
+        let progress = match fs::read_to_string(&self.progress_path) {
+            Ok(progress) => progress,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = progress.split_inclusive('\n');
+        let mut valid = match lines.next() {
+            Some(line) if serde_json::from_str::<Header>(line)?.schema == schema => line.len(),
+            Some(_) => {
+                return Err(format!(
+                    "`{}` was started with other options, start over with `--if-exists overwrite`",
+                    self.tmp_path.display()
+                )
+                .into())
+            }
+            None => return Ok(false),
+        };
+        // A line that was cut off by the interruption ends the progress, and is dropped so that
+        // the progress of the next tiles starts on a line of its own.
+        for line in lines {
+            let progress = match line
+                .strip_suffix('\n')
+                .and_then(|line| serde_json::from_str::<Progress>(line).ok())
+            {
+                Some(progress) => progress,
+                None => break,
+            };
+            if let Some(metadata) = progress.metadata {
+                self.append(decode(&metadata)?);
+            }
+            self.len = progress.len;
+            self.completed.insert(progress.tile);
+            valid += line.len();
+        }
+        OpenOptions::new()
+            .write(true)
+            .open(&self.progress_path)?
+            .set_len(valid as u64)?;
+        let len = self.tmp_path.metadata().map_or(0, |m| m.len());
+        if len < self.len {
+            return Err(format!(
+                "`{}` is shorter than its progress, start over with `--if-exists overwrite`",
+                self.tmp_path.display()
+            )
+            .into());
+        }
+        Ok(true)
+    }
+
+    /// Returns true when the row groups of `tile` were written.
+    pub fn completed(&self, tile: &str) -> bool {
+        self.completed.contains(tile)
+    }
+
+    /// Writes the `batches` of `tile` as row groups, and records the tile as completed.
+    pub fn write(
+        &mut self,
+        tile: &str,
+        batches: &[RecordBatch],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.completed(tile) {
+            return Ok(());
+        }
+        let metadata = match batches.is_empty() {
+            true => None,
+            false => Some(self.write_row_groups(batches)?),
+        };
+        let progress = Progress {
+            tile: tile.to_string(),
+            len: self.len,
+            metadata: metadata.as_ref().map(encode).transpose()?,
+        };
+        let mut file = OpenOptions::new().append(true).open(&self.progress_path)?;
+        file.write_all((serde_json::to_string(&progress)? + "\n").as_bytes())?;
+        file.sync_data()?;
+        if let Some(metadata) = metadata {
+            self.append(metadata);
+        }
+        self.completed.insert(progress.tile);
+        Ok(())
+    }
+
+    /// Appends the row groups of the `batches` to the data of the completed tiles, and returns
+    /// their metadata with the offsets in the `.tmp` file.
+    fn write_row_groups(
+        &mut self,
+        batches: &[RecordBatch],
+    ) -> Result<FileMetaData, Box<dyn Error + Send + Sync>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.tmp_path)?;
+        // Drop the data of a tile that was interrupted.
+        file.set_len(self.len)?;
+        file.seek(SeekFrom::Start(self.len))?;
+        // The row groups are written as a Parquet file of their own, of which the magic number
+        // is dropped after the first tile, so its offsets are shifted by the data before it.
+        let skip = match self.len {
+            0 => 0,
+            _ => MAGIC.len(),
+        };
+        let shift = (self.len - skip as u64) as i64;
+        let mut writer = ArrowWriter::try_new(
+            Segment {
+                file: &mut file,
+                skip,
+            },
+            self.schema.clone(),
+            Some(self.properties.clone()),
+        )?;
+        for batch in batches {
+            // The metadata of the tiles differs, the batches get the schema of the file.
+            let batch = RecordBatch::try_new(self.schema.clone(), batch.columns().to_vec())?;
+            writer.write(&batch)?;
+        }
+        let mut metadata = writer.close()?;
+        let (data_len, _) =
+            amend::footer_range(&mut file, &self.tmp_path).map_err(|e| e.to_string())?;
+        file.set_len(data_len)?;
+
+        let schema = Arc::new(SchemaDescriptor::new(types::from_thrift(&metadata.schema)?));
+        let first = self.metadata.as_ref().map_or(0, |m| m.row_groups.len());
+        for (index, row_group) in metadata.row_groups.iter_mut().enumerate() {
+            row_group.file_offset = row_group.file_offset.map(|offset| offset + shift);
+            row_group.ordinal = i16::try_from(first + index).ok();
+            for column in &mut row_group.columns {
+                column.file_offset += shift;
+                if let Some(meta_data) = &mut column.meta_data {
+                    meta_data.data_page_offset += shift;
+                    meta_data.index_page_offset = meta_data.index_page_offset.map(|o| o + shift);
+                    meta_data.dictionary_page_offset =
+                        meta_data.dictionary_page_offset.map(|o| o + shift);
+                }
+                column.column_index_offset = column.column_index_offset.map(|o| o + shift);
+                column.offset_index_offset = column.offset_index_offset.map(|o| o + shift);
+            }
+            // The offset index has the offsets of the pages, it is appended with the shifted
+            // offsets.
+            let num_rows = row_group.num_rows;
+            let columns = RowGroupMetaData::from_thrift(schema.clone(), row_group.clone())?;
+            let locations = index_reader::read_pages_locations(&file, columns.columns())?;
+            for (column, locations) in row_group.columns.iter_mut().zip(locations) {
+                let mut offset_index = OffsetIndexBuilder::new();
+                for (i, page) in locations.iter().enumerate() {
+                    // The rows of a page run up to the first row of the next page.
+                    let end = locations
+                        .get(i + 1)
+                        .map_or(num_rows, |next| next.first_row_index);
+                    offset_index.append_row_count(end - page.first_row_index);
+                    offset_index
+                        .append_offset_and_size(page.offset + shift, page.compressed_page_size);
+                }
+                let bytes = amend::encode(&offset_index.build_to_thrift())?;
+                column.offset_index_offset = Some(file.seek(SeekFrom::End(0))? as i64);
+                column.offset_index_length = Some(bytes.len() as i32);
+                file.write_all(&bytes)?;
+            }
+        }
+        file.sync_data()?;
+        self.len = file.seek(SeekFrom::End(0))?;
+        Ok(metadata)
+    }
+
+    /// Adds the row groups of the `metadata` of a tile to the metadata of the file.
+    fn append(&mut self, metadata: FileMetaData) {
+        match &mut self.metadata {
+            Some(file) => {
+                file.num_rows += metadata.num_rows;
+                file.row_groups.extend(metadata.row_groups);
+            }
+            None => self.metadata = Some(metadata),
+        }
+    }
+
+    /// Writes the footer, moves the file to its path and removes the progress.
+    pub fn finish(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match &self.metadata {
+            Some(metadata) => {
+                let mut file = OpenOptions::new().write(true).open(&self.tmp_path)?;
+                file.set_len(self.len)?;
+                file.seek(SeekFrom::Start(self.len))?;
+                amend::write_footer(&mut file, metadata).map_err(|e| e.to_string())?;
+                file.sync_data()?;
+            }
+            // All points were clipped, or no tiles were selected.
+            None => {
+                ArrowWriter::try_new(
+                    File::create(&self.tmp_path)?,
+                    self.schema.clone(),
+                    Some(self.properties.clone()),
+                )?
+                .close()?;
+            }
+        }
+        fs::rename(&self.tmp_path, &self.path)?;
+        fs::remove_file(&self.progress_path)?;
+        Ok(())
+    }
+}
+
+/// Writer of the Parquet file of the row groups of a tile to the `.tmp` file, which drops the
+/// first `skip` bytes.
+struct Segment<'a> {
+    file: &'a mut File,
+    skip: usize,
+}
+
+impl Write for Segment<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let skipped = self.skip.min(buf.len());
+        self.skip -= skipped;
+        self.file.write_all(&buf[skipped..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Returns the fields and metadata of `schema`, to check that a build is resumed with the same
+/// schema.
+fn fingerprint(schema: &Schema) -> String {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            format!(
+                "{}: {:?}{}",
+                field.name(),
+                field.data_type(),
+                if field.is_nullable() { "?" } else { "" }
+            )
+        })
+        .collect::<Vec<_>>();
+    let metadata = schema.metadata().iter().collect::<BTreeMap<_, _>>();
+    serde_json::json!({ "fields": fields, "metadata": metadata }).to_string()
+}
+
+/// Encodes `metadata` with the thrift compact protocol as base64.
+fn encode(metadata: &FileMetaData) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(base64::encode(amend::encode(metadata)?))
+}
+
+/// Decodes the metadata encoded by [`encode`].
+fn decode(metadata: &str) -> Result<FileMetaData, Box<dyn Error>> {
+    Ok(amend::decode_footer(&base64::decode(metadata)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Band, Coords};
+    use arrow::array::{Array, Float64Array, Int32Array};
+    use parquet::arrow::arrow_reader::{ArrowReaderOptions, ParquetRecordBatchReaderBuilder};
+
+    fn batch(schema: &SchemaRef, lat: f64, rows: usize) -> RecordBatch {
+        RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Float64Array::from(vec![lat; rows])),
+                Arc::new(Float64Array::from(
+                    (0..rows).map(|i| i as f64).collect::<Vec<_>>(),
+                )),
+                Arc::new(Int32Array::from((0..rows as i32).collect::<Vec<_>>())),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resume_interrupted_merge() {
+        let dir = std::env::temp_dir().join(format!("aw3d30-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("merged.parquet");
+        let band = Band {
+            index: 1,
+            name: "elevation".to_string(),
+            unit: "m".to_string(),
+        };
+        // Small pages, so that the offset indexes of the tiles have pages to shift.
+        let conversion = Conversion::new(
+            vec![band],
+            None,
+            IfExists::Verify,
+            None,
+            Coords::Flat,
+            false,
+        )
+        .unwrap()
+        .with_page_size(1024);
+
+        let mut merged = MergedParquet::create(&path, &conversion, IfExists::Verify).unwrap();
+        let schema = merged.schema.clone();
+        merged.write("a", &[batch(&schema, 52., 2000)]).unwrap();
+        // Interrupted while writing `b`: its row groups are written, its progress line is cut off.
+        merged.write_row_groups(&[batch(&schema, 0., 500)]).unwrap();
+        drop(merged);
+        let mut progress = OpenOptions::new()
+            .append(true)
+            .open(path.with_extension("parquet.progress"))
+            .unwrap();
+        progress.write_all(b"{\"tile\":\"b\",\"len").unwrap();
+        drop(progress);
+
+        let mut merged = MergedParquet::create(&path, &conversion, IfExists::Verify).unwrap();
+        assert!(merged.completed("a"));
+        assert!(!merged.completed("b"));
+        merged.write("b", &[batch(&schema, 53., 2000)]).unwrap();
+        drop(merged);
+        // The progress of `b` follows the completed tiles, not the line that was cut off.
+        let merged = MergedParquet::create(&path, &conversion, IfExists::Verify).unwrap();
+        assert!(merged.completed("a") && merged.completed("b"));
+        merged.finish().unwrap();
+        assert!(!path.with_extension("parquet.tmp").exists());
+        assert!(!path.with_extension("parquet.progress").exists());
+
+        // The pages are read with the shifted offset indexes.
+        let reader = ParquetRecordBatchReaderBuilder::try_new_with_options(
+            File::open(&path).unwrap(),
+            ArrowReaderOptions::new().with_page_index(true),
+        )
+        .unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert!(reader.metadata().offset_index().is_some());
+        let mut lat = Vec::new();
+        let mut elevation = Vec::new();
+        for batch in reader.build().unwrap() {
+            let batch = batch.unwrap();
+            let column = |index: usize| batch.column(index).as_any();
+            let lats = column(0).downcast_ref::<Float64Array>().unwrap();
+            lat.extend(lats.values().iter().copied());
+            let elevations = column(2).downcast_ref::<Int32Array>().unwrap();
+            elevation.extend(elevations.values().iter().copied());
+        }
+        assert_eq!(lat.len(), 4000);
+        assert!(lat[..2000].iter().all(|&lat| lat == 52.));
+        assert!(lat[2000..].iter().all(|&lat| lat == 53.));
+        assert!(elevation.iter().copied().eq((0..2000).chain(0..2000)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
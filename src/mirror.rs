@@ -0,0 +1,123 @@
+//! Download servers other than the OpenTopography S3 bucket, that serve the tiles at URLs of a
+//! fixed layout.
+
+use crate::{dataset::corner_name, tile_coordinate, TILE_PATTERN};
+use regex::Regex;
+use reqwest::Url;
+use std::{error::Error, fmt, str::FromStr, sync::Arc};
+
+/// Adapter of a download server that serves the tiles at URLs of a fixed layout.
+///
+/// [`Source`](crate::Source) looks up the selected tiles in the directory index pages of such
+/// servers, and probes the URLs of the tiles in dirs without an index page. Other mirrors only implement this trait.
+pub trait Mirror: fmt::Display + Send + Sync {
+    /// Returns the URL of the download of the tile with GeoTIFF file `name`, e.g.
+    /// `ALPSMLC30_N052E004_DSM.tif`.
+    fn url(&self, name: &str) -> Result<Url, Box<dyn Error + Send + Sync>>;
+
+    /// Returns true when the downloads are zip archives with the GeoTIFF file of the tile.
+    fn archived(&self) -> bool {
+        false
+    }
+}
+
+/// HTTPS mirror with the GeoTIFF files of all tiles in one dir.
+pub struct HttpsMirror {
+    base: Url,
+}
+
+impl fmt::Display for HttpsMirror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base)
+    }
+}
+
+impl Mirror for HttpsMirror {
+    fn url(&self, name: &str) -> Result<Url, Box<dyn Error + Send + Sync>> {
+        Ok(self.base.join(name)?)
+    }
+}
+
+/// Server with the layout of the JAXA distribution: a zip archive per tile, e.g.
+/// `N052E004.zip`, in a dir per block of 5 by 5 degrees named after its south-west corner,
+/// e.g. `N050E000/`.
+pub struct Jaxa {
+    base: Url,
+    re: Regex,
+}
+
+impl fmt::Display for Jaxa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base)
+    }
+}
+
+impl Mirror for Jaxa {
+    fn url(&self, name: &str) -> Result<Url, Box<dyn Error + Send + Sync>> {
+        let (lat, lon) = tile_coordinate(&self.re, name)
+            .ok_or_else(|| format!("`{}` is not a tile", name))?
+            .degrees();
+        let block = corner_name(lat.div_euclid(5) * 5, lon.div_euclid(5) * 5);
+        let tile = corner_name(lat, lon);
+        Ok(self.base.join(&format!("{}/{}.zip", block, tile))?)
+    }
+
+    fn archived(&self) -> bool {
+        true
+    }
+}
+
+/// Server to download the GeoTIFF files from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Server {
+    /// The OpenTopography S3 bucket, or S3 mirrors of it
+    OpenTopography,
+    /// HTTPS mirror with the GeoTIFF files in one dir, `https://host/path/`
+    Https(Url),
+    /// Server with the layout of the JAXA distribution, `jaxa:https://host/path/`
+    Jaxa(Url),
+}
+
+impl FromStr for Server {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str =
+            "expected `opentopography`, `https://host/path/` or `jaxa:https://host/path/`";
+        // Relative URLs are joined to the dir of the base URL.
+        let base = |s: &str| match s.ends_with('/') {
+            true => Url::parse(s),
+            false => Url::parse(&format!("{}/", s)),
+        };
+        match s.split_once(':') {
+            _ if s == "opentopography" => Ok(Server::OpenTopography),
+            Some(("jaxa", url)) => base(url).map(Server::Jaxa).map_err(|_| ERR),
+            Some(("http", _)) | Some(("https", _)) => base(s).map(Server::Https).map_err(|_| ERR),
+            _ => Err(ERR),
+        }
+    }
+}
+
+impl Server {
+    /// Returns the adapter of the server, `None` for the OpenTopography S3 bucket.
+    pub fn mirror(&self) -> Result<Option<Arc<dyn Mirror>>, Box<dyn Error>> {
+        let mirror: Arc<dyn Mirror> = match self {
+            Server::OpenTopography => return Ok(None),
+            Server::Https(base) => Arc::new(HttpsMirror { base: base.clone() }),
+            Server::Jaxa(base) => Arc::new(Jaxa {
+                base: base.clone(),
+                re: Regex::new(TILE_PATTERN)?,
+            }),
+        };
+        Ok(Some(mirror))
+    }
+
+    /// Returns the name of the server for the metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Server::OpenTopography => "s3",
+            Server::Https(_) => "https",
+            Server::Jaxa(_) => "jaxa",
+        }
+    }
+}
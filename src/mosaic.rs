@@ -0,0 +1,219 @@
+use crate::{manifest::Upstream, write_parquet, Conversion, IfExists, RemoteFile, Source, Usage};
+use futures::{stream, StreamExt};
+use gdal::Dataset;
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::task;
+use tracing::{event, Level};
+
+/// A tile of the mosaic.
+struct Tile {
+    path: PathBuf,
+    gt: [f64; 6],
+    size: (usize, usize),
+}
+
+impl Tile {
+    /// Returns the extent (`min_lon`, `min_lat`, `max_lon`, `max_lat`) of the north-up tile.
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (x_size, y_size) = self.size;
+        (
+            self.gt[0],
+            self.gt[3] + y_size as f64 * self.gt[5],
+            self.gt[0] + x_size as f64 * self.gt[1],
+            self.gt[3],
+        )
+    }
+}
+
+/// Escapes the XML special characters of `s`.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes a GDAL VRT at `path` that mosaics the GeoTIFF files of the `tiles`, cropped to the
+/// `extent` (`min_lon`, `min_lat`, `max_lon`, `max_lat`) when it is given.
+///
+/// The mosaic has the finest resolution of the tiles, coarser tiles (at high latitudes) are
+/// resampled with nearest neighbour. Pixels that are not covered by a tile are nodata.
+pub fn write_vrt(
+    tiles: &[PathBuf],
+    extent: Option<(f64, f64, f64, f64)>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let first = Dataset::open(tiles.first().ok_or("a mosaic requires tiles")?)?;
+    let projection = first.projection();
+    let nodata = (1..=first.raster_count())
+        .map(|band| Ok(first.rasterband(band)?.no_data_value()))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let tiles = tiles
+        .iter()
+        .map(|path| -> Result<_, Box<dyn Error>> {
+            let dataset = Dataset::open(path)?;
+            let gt = dataset.geo_transform()?;
+            if gt[2] != 0. || gt[4] != 0. {
+                return Err(format!("`{}` is not north-up", path.display()).into());
+            }
+            if dataset.raster_count() != nodata.len() as isize {
+                return Err(format!("`{}` has a different number of bands", path.display()).into());
+            }
+            Ok(Tile {
+                path: fs::canonicalize(path)?,
+                gt,
+                size: dataset.raster_size(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The pixels of the mosaic line up with the pixels of the tiles at the finest resolution.
+    let res_x = tiles
+        .iter()
+        .map(|tile| tile.gt[1])
+        .fold(f64::INFINITY, f64::min);
+    let res_y = tiles
+        .iter()
+        .map(|tile| -tile.gt[5])
+        .fold(f64::INFINITY, f64::min);
+    let bounds = tiles.iter().map(Tile::bounds);
+    let min_lon = bounds.clone().map(|b| b.0).fold(f64::INFINITY, f64::min);
+    let min_lat = bounds.clone().map(|b| b.1).fold(f64::INFINITY, f64::min);
+    let max_lon = bounds
+        .clone()
+        .map(|b| b.2)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let max_lat = bounds.map(|b| b.3).fold(f64::NEG_INFINITY, f64::max);
+    let (crop_min_lon, crop_min_lat, crop_max_lon, crop_max_lat) = extent
+        .map(|(a, b, c, d)| {
+            (
+                a.max(min_lon),
+                b.max(min_lat),
+                c.min(max_lon),
+                d.min(max_lat),
+            )
+        })
+        .unwrap_or((min_lon, min_lat, max_lon, max_lat));
+    if crop_min_lon >= crop_max_lon || crop_min_lat >= crop_max_lat {
+        return Err("the extent of the mosaic does not overlap the tiles".into());
+    }
+    // Pixels that the extent covers partly are included, up to rounding errors.
+    let floor = |pixels: f64| (pixels + 1e-6).floor();
+    let ceil = |pixels: f64| (pixels - 1e-6).ceil();
+    let x0 = floor((crop_min_lon - min_lon) / res_x);
+    let y0 = floor((max_lat - crop_max_lat) / res_y);
+    let x_size = (ceil((crop_max_lon - min_lon) / res_x) - x0) as usize;
+    let y_size = (ceil((max_lat - crop_min_lat) / res_y) - y0) as usize;
+    let (origin_lon, origin_lat) = (min_lon + x0 * res_x, max_lat - y0 * res_y);
+    event!(
+        Level::INFO,
+        "Mosaic of {} tiles: {} by {} pixels",
+        tiles.len(),
+        x_size,
+        y_size
+    );
+
+    let mut vrt = String::new();
+    writeln!(
+        vrt,
+        r#"<VRTDataset rasterXSize="{}" rasterYSize="{}">"#,
+        x_size, y_size
+    )?;
+    writeln!(vrt, "  <SRS>{}</SRS>", escape(&projection))?;
+    writeln!(
+        vrt,
+        "  <GeoTransform>{}, {}, 0, {}, 0, {}</GeoTransform>",
+        origin_lon, res_x, origin_lat, -res_y
+    )?;
+    // The bands are read as 32-bit integers by the conversion.
+    for (index, nodata) in nodata.iter().enumerate() {
+        writeln!(
+            vrt,
+            r#"  <VRTRasterBand dataType="Int32" band="{}">"#,
+            index + 1
+        )?;
+        if let Some(nodata) = nodata {
+            writeln!(vrt, "    <NoDataValue>{}</NoDataValue>", nodata)?;
+        }
+        for tile in &tiles {
+            let (x_size, y_size) = tile.size;
+            writeln!(vrt, "    <SimpleSource>")?;
+            writeln!(
+                vrt,
+                r#"      <SourceFilename relativeToVRT="0">{}</SourceFilename>"#,
+                escape(&tile.path.to_string_lossy())
+            )?;
+            writeln!(vrt, "      <SourceBand>{}</SourceBand>", index + 1)?;
+            writeln!(
+                vrt,
+                r#"      <SrcRect xOff="0" yOff="0" xSize="{}" ySize="{}" />"#,
+                x_size, y_size
+            )?;
+            writeln!(
+                vrt,
+                r#"      <DstRect xOff="{}" yOff="{}" xSize="{}" ySize="{}" />"#,
+                (tile.gt[0] - origin_lon) / res_x,
+                (origin_lat - tile.gt[3]) / res_y,
+                x_size as f64 * tile.gt[1] / res_x,
+                y_size as f64 * -tile.gt[5] / res_y
+            )?;
+            writeln!(vrt, "    </SimpleSource>")?;
+        }
+        writeln!(vrt, "  </VRTRasterBand>")?;
+    }
+    writeln!(vrt, "</VRTDataset>")?;
+    fs::write(path, vrt)?;
+    Ok(())
+}
+
+/// Downloads the `objects` to `tif_dir` and converts a mosaic of the tiles, cropped to the
+/// `extent` (`min_lon`, `min_lat`, `max_lon`, `max_lat`) when it is given, to a single Parquet
+/// file at `output`. The VRT of the mosaic is written next to it.
+///
+/// The points of the mosaic are converted in one pass, so the mosaic is read into memory.
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_mosaic(
+    source: &Source,
+    objects: Vec<RemoteFile>,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    extent: Option<(f64, f64, f64, f64)>,
+    output: &Path,
+    conversion: Arc<Conversion>,
+    usage: &Arc<Usage>,
+) -> Result<(), Box<dyn Error>> {
+    event!(Level::INFO, "Downloading {} files", objects.len());
+    let mut tiles = Vec::with_capacity(objects.len());
+    let mut paths = stream::iter(objects)
+        .map(|object| source.download(object, tif_dir.to_path_buf(), if_exists, usage.clone()))
+        .buffer_unordered(1);
+    while let Some(path) = paths.next().await {
+        let (path, _) = path?.map_err(|e| e as Box<dyn Error>)?;
+        tiles.push(path);
+    }
+    // The order of the sources does not matter, the tiles do not overlap.
+    tiles.sort();
+
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let vrt = output.with_extension("vrt");
+    write_vrt(&tiles, extent, &vrt)?;
+    // The tiles of a mosaic have no single upstream version.
+    let (output_path, usage) = (output.to_path_buf(), usage.clone());
+    let written = task::spawn_blocking(move || {
+        write_parquet(vrt, output_path, &Upstream::default(), conversion, usage)
+            .map_err(|e| e.to_string())
+    })
+    .await??;
+    if written {
+        event!(Level::INFO, "Wrote mosaic `{}`", output.display());
+    }
+    Ok(())
+}
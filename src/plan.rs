@@ -0,0 +1,243 @@
+//! Plan of a run: the files it downloads and writes, their size and its projected duration.
+
+use crate::{
+    convert::{format_compression, valid_parquet},
+    disk,
+    job::parquet_name,
+    manifest::Manifest,
+    tile_coordinate,
+    usage::{format_bytes, Calibration},
+    Conversion, IfExists, RemoteFile, TILE_PATTERN,
+};
+use regex::Regex;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{event, Level};
+
+/// Number of pixels along the sides of a tile.
+const TILE_PIXELS: u64 = 3600;
+
+/// Downloads and conversions of a run, for dry runs and the disk space check.
+pub struct Plan {
+    /// GeoTIFF files to download
+    downloads: usize,
+    download_size: u64,
+    /// Size of the largest GeoTIFF file to download
+    largest_download: u64,
+    /// Parquet files to write
+    conversions: usize,
+    /// Size of the GeoTIFF files to convert, as far as known before downloading them
+    conversion_size: u64,
+    /// Estimated size of the Parquet files to write
+    parquet_size: u64,
+    /// What the size of the Parquet files is estimated from
+    estimate: Estimate,
+    /// Projected seconds of the downloads and conversions, calibrated by past runs
+    download_seconds: Option<f64>,
+    conversion_seconds: Option<f64>,
+    /// Number of past runs the projections are calibrated by
+    calibrated_by: usize,
+    /// Existing files that are kept
+    skipped: Vec<PathBuf>,
+}
+
+/// Source of the estimated size of the Parquet files of a [`Plan`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Estimate {
+    /// The size of the values before compression, an upper bound
+    UpperBound,
+    /// The mean size of the existing Parquet files
+    Files(usize),
+    /// The compression ratio of past runs with the same codec
+    History(usize),
+}
+
+impl Plan {
+    /// Returns the plan of a run with the `objects`, which writes Parquet files to `parquet_dir`
+    /// when converting. The durations and the size of the Parquet files are projected with the
+    /// `calibration` of past runs.
+    pub fn new(
+        objects: &[RemoteFile],
+        tif_dir: &Path,
+        parquet_dir: Option<&Path>,
+        if_exists: IfExists,
+        conversion: &Conversion,
+        bbox_names: bool,
+        calibration: &Calibration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut plan = Plan {
+            downloads: 0,
+            download_size: 0,
+            largest_download: 0,
+            conversions: 0,
+            conversion_size: 0,
+            parquet_size: 0,
+            estimate: Estimate::UpperBound,
+            download_seconds: None,
+            conversion_seconds: None,
+            calibrated_by: calibration.runs,
+            skipped: Vec::new(),
+        };
+        // Local files are converted where they are.
+        for object in objects
+            .iter()
+            .filter(|object| !matches!(object, RemoteFile::Local(_)))
+        {
+            let path = tif_dir.join(object.name());
+            let size = object.size();
+            // Downloads verify the checksum of existing files as well, compare the size only.
+            let complete = || {
+                size.map_or(true, |size| {
+                    path.metadata()
+                        .map_or(false, |metadata| metadata.len() == size)
+                })
+            };
+            if if_exists.keep(&path, complete)? {
+                plan.skipped.push(path);
+            } else {
+                plan.downloads += 1;
+                plan.download_size += size.unwrap_or_default();
+                plan.largest_download = plan.largest_download.max(size.unwrap_or_default());
+            }
+        }
+
+        if let Some(parquet_dir) = parquet_dir {
+            let re = Regex::new(TILE_PATTERN)?;
+            for object in objects {
+                let coordinate = bbox_names
+                    .then(|| tile_coordinate(&re, object.name()))
+                    .flatten();
+                let path = parquet_dir.join(parquet_name(object.name(), coordinate));
+                if if_exists.keep(&path, || valid_parquet(&path))? {
+                    plan.skipped.push(path);
+                } else {
+                    plan.conversions += 1;
+                    plan.conversion_size += object.size().unwrap_or_default();
+                }
+            }
+            // Estimate the size of a file from the files in the dir, or else by the compression
+            // of past runs, or else by the size of the values before compression.
+            let manifest = Manifest::load(parquet_dir)?;
+            let sizes = manifest
+                .files
+                .iter()
+                .filter(|file| !file.path.starts_with('_'))
+                .map(|file| file.size)
+                .collect::<Vec<_>>();
+            let codec = format_compression(conversion.compression());
+            // The sizes of the GeoTIFF files are unknown for an HTTP directory index.
+            let ratio = calibration
+                .compression_ratio(&codec)
+                .filter(|_| plan.conversion_size > 0);
+            plan.parquet_size = match (sizes.len(), ratio) {
+                (0, Some(ratio)) => {
+                    plan.estimate = Estimate::History(calibration.runs);
+                    (plan.conversion_size as f64 * ratio) as u64
+                }
+                (0, None) => {
+                    plan.conversions as u64 * conversion.uncompressed_tile_size(TILE_PIXELS)
+                }
+                (count, _) => {
+                    plan.estimate = Estimate::Files(count);
+                    plan.conversions as u64 * (sizes.iter().sum::<u64>() / count as u64)
+                }
+            };
+            plan.conversion_seconds = calibration.conversion_seconds(plan.conversion_size);
+        }
+        plan.download_seconds = calibration.download_seconds(plan.download_size);
+        Ok(plan)
+    }
+
+    /// Returns an error when the file systems of `tif_dir` and `parquet_dir` (when converting)
+    /// don't have room for the downloads and Parquet files, or only warns when `force`.
+    /// Downloaded GeoTIFF files are removed after their conversion when `remove_tif`.
+    pub fn check_disk_space(
+        &self,
+        tif_dir: &Path,
+        parquet_dir: Option<&Path>,
+        remove_tif: bool,
+        force: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        // Without the files that are removed, the GeoTIFF files of the tile that is converted
+        // and the next download are on disk.
+        let tif_size = if remove_tif {
+            2 * self.largest_download
+        } else {
+            self.download_size
+        };
+        // The space per dir, and the part of it for Parquet files.
+        let mut required = vec![(tif_dir, tif_size, 0)];
+        if let Some(parquet_dir) = parquet_dir {
+            if tif_size > 0 && disk::same_file_system(tif_dir, parquet_dir)? {
+                required[0].1 += self.parquet_size;
+                required[0].2 = self.parquet_size;
+            } else {
+                required.push((parquet_dir, self.parquet_size, self.parquet_size));
+            }
+        }
+        for (dir, size, parquet_size) in required.into_iter().filter(|&(_, size, _)| size > 0) {
+            let available = disk::available_space(dir)?;
+            if size <= available {
+                continue;
+            }
+            let message = format!(
+                "`{}` needs {} but has {} available",
+                dir.display(),
+                format_bytes(size),
+                format_bytes(available)
+            );
+            // An upper bound of the size of the Parquet files does not have to fit.
+            if force || (self.estimate == Estimate::UpperBound && size - parquet_size <= available)
+            {
+                event!(Level::WARN, "{}", message);
+            } else {
+                return Err(format!("{}, pass --force to start anyway", message).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports the plan to stdout, with the Parquet files when `convert`.
+    pub fn report(&self, convert: bool) {
+        println!(
+            "{} GeoTIFF files to download, {} total",
+            self.downloads,
+            format_bytes(self.download_size)
+        );
+        if convert {
+            let estimate = match self.estimate {
+                Estimate::UpperBound => "at most, before compression".to_string(),
+                Estimate::Files(count) => format!("estimated from {} existing files", count),
+                Estimate::History(runs) => format!("estimated from {} past runs", runs),
+            };
+            println!(
+                "{} Parquet files to write, {} total ({})",
+                self.conversions,
+                format_bytes(self.parquet_size),
+                estimate
+            );
+        }
+        // The stages overlap, the slowest stage bounds the duration of the run.
+        let seconds = |seconds: Option<f64>| {
+            seconds.map_or_else(
+                || "unknown".to_string(),
+                |seconds| format!("{:.0?}", Duration::from_secs_f64(seconds)),
+            )
+        };
+        if self.calibrated_by > 0 {
+            print!("Projected duration from {} past runs: ", self.calibrated_by);
+            print!("download {}", seconds(self.download_seconds));
+            if convert {
+                print!(", conversion {}", seconds(self.conversion_seconds));
+            }
+            println!();
+        }
+        println!("{} existing files are skipped", self.skipped.len());
+        for path in &self.skipped {
+            println!("\t{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use gdal::{
+    vector::{Feature, FieldValue, Geometry},
+    Dataset,
+};
+use std::{error::Error, path::Path};
+
+/// A polygon (or multi polygon) feature, with its rings in longitude and latitude.
+pub struct Polygon {
+    /// Feature id
+    pub id: i64,
+    /// Rings of all polygons, points inside an odd number of rings are in the polygon
+    rings: Vec<Vec<(f64, f64)>>,
+    /// Bounding box (`min_lon`, `min_lat`, `max_lon`, `max_lat`)
+    pub bbox: (f64, f64, f64, f64),
+}
+
+impl Polygon {
+    /// Returns true when the point is in the polygon.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        let (min_lon, min_lat, max_lon, max_lat) = self.bbox;
+        if lon < min_lon || lon > max_lon || lat < min_lat || lat > max_lat {
+            return false;
+        }
+        // Even-odd ray casting over all rings, which handles holes and multi polygons.
+        let mut inside = false;
+        for ring in &self.rings {
+            for (&(x0, y0), &(x1, y1)) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+                if (y0 > lat) != (y1 > lat) && lon < x0 + (lat - y0) / (y1 - y0) * (x1 - x0) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// Returns true when the polygon intersects the rectangle (`min_lon`, `min_lat`, `max_lon`,
+    /// `max_lat`).
+    pub fn intersects(&self, rect: (f64, f64, f64, f64)) -> bool {
+        let (min_lon, min_lat, max_lon, max_lat) = rect;
+        let bbox = self.bbox;
+        if bbox.0 > max_lon || bbox.2 < min_lon || bbox.1 > max_lat || bbox.3 < min_lat {
+            return false;
+        }
+        // The rectangle is in the polygon, or an edge of the polygon crosses the rectangle.
+        self.contains(min_lon, min_lat)
+            || self.rings.iter().any(|ring| {
+                ring.iter()
+                    .zip(ring.iter().cycle().skip(1))
+                    .any(|(&start, &end)| clips(start, end, rect))
+            })
+    }
+}
+
+/// Returns true when the segment from `start` to `end` intersects the rectangle (Liang-Barsky).
+fn clips((x0, y0): (f64, f64), (x1, y1): (f64, f64), rect: (f64, f64, f64, f64)) -> bool {
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let (mut t0, mut t1) = (0_f64, 1_f64);
+    for (p, q) in [
+        (-dx, x0 - rect.0),
+        (dx, rect.2 - x0),
+        (-dy, y0 - rect.1),
+        (dy, rect.3 - y0),
+    ] {
+        if p == 0. {
+            if q < 0. {
+                return false;
+            }
+        } else if p < 0. {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    t0 <= t1
+}
+
+/// Collects the rings of a (multi) polygon geometry.
+fn collect_rings(geometry: &Geometry, rings: &mut Vec<Vec<(f64, f64)>>) {
+    match geometry.geometry_count() {
+        0 => rings.push(
+            geometry
+                .get_point_vec()
+                .into_iter()
+                .map(|(x, y, _)| (x, y))
+                .collect(),
+        ),
+        count => (0..count).for_each(|idx| collect_rings(&geometry.get_geometry(idx), rings)),
+    }
+}
+
+/// Returns the polygon of the `idx`th `feature` of a layer.
+fn polygon(feature: &Feature, idx: usize) -> Polygon {
+    let mut rings = Vec::new();
+    collect_rings(feature.geometry(), &mut rings);
+    let bbox = rings.iter().flatten().fold(
+        (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |(min_lon, min_lat, max_lon, max_lat), &(lon, lat)| {
+            (
+                min_lon.min(lon),
+                min_lat.min(lat),
+                max_lon.max(lon),
+                max_lat.max(lat),
+            )
+        },
+    );
+    Polygon {
+        id: feature.fid().map_or(idx as i64, |fid| fid as i64),
+        rings,
+        bbox,
+    }
+}
+
+/// Reads the polygons of the first layer of the vector file at `path` (e.g. GeoJSON or
+/// Shapefile), with coordinates in longitude and latitude.
+pub fn read_polygons(path: &Path) -> Result<Vec<Polygon>, Box<dyn Error>> {
+    let dataset = Dataset::open(path)?;
+    let mut layer = dataset.layer(0)?;
+    let polygons = layer
+        .features()
+        .enumerate()
+        .map(|(idx, feature)| polygon(&feature, idx))
+        .collect();
+    Ok(polygons)
+}
+
+/// Reads the polygons of the first layer of the vector file at `path` like [`read_polygons`],
+/// with the value of their `field` attribute. Features without a value are skipped.
+pub fn read_labeled_polygons(
+    path: &Path,
+    field: &str,
+) -> Result<Vec<(String, Polygon)>, Box<dyn Error>> {
+    let dataset = Dataset::open(path)?;
+    let mut layer = dataset.layer(0)?;
+    let mut polygons = Vec::new();
+    for (idx, feature) in layer.features().enumerate() {
+        if let Some(label) = feature.field(field)?.and_then(FieldValue::into_string) {
+            polygons.push((label, polygon(&feature, idx)));
+        }
+    }
+    Ok(polygons)
+}
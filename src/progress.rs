@@ -0,0 +1,116 @@
+use crate::{job::Progress, Usage};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    error::Error,
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Interval between updates of the download bar.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Progress bars on stderr: the tiles that are done, the bytes of the current download and a
+/// counter of the converted tiles.
+#[derive(Clone)]
+pub struct BarProgress {
+    bars: MultiProgress,
+    tiles: ProgressBar,
+    download: ProgressBar,
+    converted: ProgressBar,
+    usage: Arc<Usage>,
+    /// Downloaded bytes at the start of the current download
+    offset: Arc<AtomicU64>,
+}
+
+impl BarProgress {
+    /// Returns the progress bars, which read the downloaded bytes from `usage`.
+    pub fn new(usage: Arc<Usage>) -> Self {
+        let style = |template| ProgressStyle::with_template(template).unwrap();
+        BarProgress {
+            bars: MultiProgress::new(),
+            tiles: ProgressBar::new(0).with_style(style(
+                "[{elapsed_precise}] {wide_bar} {pos}/{len} tiles ({eta})",
+            )),
+            download: ProgressBar::new(0)
+                .with_style(style("{spinner} {msg} {bytes} ({bytes_per_sec})")),
+            converted: ProgressBar::new(0).with_style(style("{pos} tiles converted")),
+            usage,
+            offset: Arc::default(),
+        }
+    }
+
+    /// Shows the bars for a job of `tiles` tiles.
+    pub fn start(&self, tiles: u64) {
+        self.tiles.set_length(tiles);
+        for bar in [&self.tiles, &self.download, &self.converted] {
+            self.bars.add(bar.clone());
+        }
+        // Downloads run one at a time, so the bytes since its start are of the current file.
+        let progress = self.clone();
+        thread::spawn(move || {
+            while progress.tiles.position() < progress.tiles.length().unwrap_or_default() {
+                let downloaded = progress.usage.downloaded.load(Ordering::Relaxed);
+                let offset = progress.offset.load(Ordering::Relaxed);
+                progress
+                    .download
+                    .set_position(downloaded.saturating_sub(offset));
+                progress.download.tick();
+                thread::sleep(TICK);
+            }
+            for bar in [&progress.tiles, &progress.download, &progress.converted] {
+                bar.finish();
+            }
+        });
+    }
+
+    /// Returns a writer for log lines, which prints them above the bars.
+    pub fn writer(&self) -> BarWriter {
+        BarWriter(self.bars.clone())
+    }
+}
+
+impl Progress for BarProgress {
+    fn started(&self, tile: &str) {
+        self.offset.store(
+            self.usage.downloaded.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.download.set_message(tile.to_string());
+    }
+
+    fn finished(&self, _tile: &str, _path: &Path, written: bool) {
+        if written {
+            self.converted.inc(1);
+        }
+        self.tiles.inc(1);
+    }
+
+    fn failed(&self, tile: &str, error: &(dyn Error + Send + Sync)) {
+        let _ = self
+            .bars
+            .println(format!("Converting {} failed: {}", tile, error));
+        self.tiles.inc(1);
+    }
+}
+
+/// Writer that prints log lines above the progress bars.
+#[derive(Clone)]
+pub struct BarWriter(MultiProgress);
+
+impl io::Write for BarWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The log formatter writes one event at a time.
+        self.0.println(String::from_utf8_lossy(buf).trim_end())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Reading the points of a Parquet output dir, for consumers of the dataset.
+
+use crate::{
+    index,
+    manifest::{Manifest, ManifestFile},
+    tile_coordinate, Bbox, TILE_PATTERN,
+};
+use parquet::{
+    file::reader::{FileReader, RowGroupReader, SerializedFileReader},
+    record::{Field, Row},
+    schema::types::Type,
+};
+use regex::Regex;
+use std::{
+    error::Error,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A point of the dataset with the value of a band, `None` for nodata values.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Elevation {
+    pub lat: f64,
+    pub lon: f64,
+    pub value: Option<f64>,
+}
+
+/// Reader of the points of a Parquet output dir, e.g.
+/// `Aw3d30Reader::open(dir)?.bbox(bbox).elevations()?`.
+///
+/// Only the files in the manifest are read. Files of tiles outside the bounding box and row
+/// groups of which the statistics of the coordinates are outside it are skipped without reading
+/// their data.
+pub struct Aw3d30Reader {
+    dir: PathBuf,
+    files: Vec<ManifestFile>,
+    bbox: Option<Bbox>,
+    band: String,
+}
+
+impl Aw3d30Reader {
+    /// Opens the Parquet output dir at `dir`, reading the `elevation` band of all points.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.as_ref().to_path_buf();
+        let manifest = Manifest::load(&dir)?;
+        let files = manifest
+            .files
+            .into_iter()
+            .filter(|file| !file.path.starts_with('_') && file.path.ends_with(".parquet"))
+            .collect();
+        Ok(Aw3d30Reader {
+            dir,
+            files,
+            bbox: None,
+            band: "elevation".to_string(),
+        })
+    }
+
+    /// Only reads the points in `bbox`, including its edges.
+    pub fn bbox(mut self, bbox: Bbox) -> Self {
+        self.bbox = Some(bbox);
+        self
+    }
+
+    /// Reads the band column `name` instead of `elevation`.
+    pub fn band(mut self, name: &str) -> Self {
+        self.band = name.to_string();
+        self
+    }
+
+    /// Returns the paths of the files that may have points in the bounding box, by the names of
+    /// their tiles.
+    pub fn files(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let re = Regex::new(TILE_PATTERN)?;
+        Ok(self
+            .files
+            .iter()
+            .filter(
+                |file| match (&self.bbox, tile_coordinate(&re, &file.path)) {
+                    (Some(bbox), Some(coordinate)) => bbox.intersects(coordinate.degrees()),
+                    _ => true,
+                },
+            )
+            .map(|file| self.dir.join(&file.path))
+            .collect())
+    }
+
+    /// Returns the points in the bounding box with the values of the band.
+    pub fn elevations(&self) -> Result<Vec<Elevation>, Box<dyn Error>> {
+        let mut elevations = Vec::new();
+        for path in self.files()? {
+            let reader = SerializedFileReader::new(File::open(&path)?)?;
+            let metadata = reader.metadata();
+            // The nodata value of the band, as it is written.
+            let key = format!("{}.nodata", self.band);
+            let nodata = metadata
+                .file_metadata()
+                .key_value_metadata()
+                .and_then(|key_values| key_values.iter().find(|kv| kv.key == key))
+                .and_then(|kv| kv.value.as_ref())
+                .and_then(|value| value.parse::<f64>().ok());
+            let projection = self.projection(metadata.file_metadata().schema(), &path)?;
+            for index in 0..reader.num_row_groups() {
+                let row_group = metadata.row_group(index);
+                if let Some(bbox) = &self.bbox {
+                    let overlaps = |bounds: Option<(f64, f64)>, min: f64, max: f64| {
+                        bounds.map_or(true, |(start, end)| start <= max && end >= min)
+                    };
+                    if !overlaps(index::min_max(row_group, "lat"), bbox.min_lat, bbox.max_lat)
+                        || !overlaps(index::min_max(row_group, "lon"), bbox.min_lon, bbox.max_lon)
+                    {
+                        continue;
+                    }
+                }
+                // The rows borrow the reader of the row group.
+                let group = reader.get_row_group(index)?;
+                for row in group.get_row_iter(Some(projection.clone()))? {
+                    let elevation = self.elevation(&row?, nodata);
+                    let inside = self.bbox.map_or(true, |bbox| {
+                        (bbox.min_lat..=bbox.max_lat).contains(&elevation.lat)
+                            && (bbox.min_lon..=bbox.max_lon).contains(&elevation.lon)
+                    });
+                    if inside {
+                        elevations.push(elevation);
+                    }
+                }
+            }
+        }
+        Ok(elevations)
+    }
+
+    /// Returns the projection of the `schema` of the file at `path` onto the coordinates and the
+    /// band.
+    fn projection(&self, schema: &Type, path: &Path) -> Result<Type, Box<dyn Error>> {
+        let names = ["lat", "lon", "location", self.band.as_str()];
+        let fields = schema
+            .get_fields()
+            .iter()
+            .filter(|field| names.contains(&field.name()))
+            .cloned()
+            .collect::<Vec<Arc<Type>>>();
+        let has = |name: &str| fields.iter().any(|field| field.name() == name);
+        if !has(&self.band) || !(has("location") || (has("lat") && has("lon"))) {
+            return Err(format!(
+                "`{}` has no `lat` and `lon` coordinates or no `{}` column",
+                path.display(),
+                self.band
+            )
+            .into());
+        }
+        Ok(Type::group_type_builder(schema.name())
+            .with_fields(fields)
+            .build()?)
+    }
+
+    /// Returns the point of a `row` of the projection, with `nodata` values as `None`.
+    fn elevation(&self, row: &Row, nodata: Option<f64>) -> Elevation {
+        let mut elevation = Elevation {
+            lat: f64::NAN,
+            lon: f64::NAN,
+            value: None,
+        };
+        let number = |field: &Field| match *field {
+            Field::UByte(value) => Some(f64::from(value)),
+            Field::Short(value) => Some(f64::from(value)),
+            Field::UShort(value) => Some(f64::from(value)),
+            Field::Int(value) => Some(f64::from(value)),
+            Field::Float(value) => Some(f64::from(value)),
+            Field::Double(value) => Some(value),
+            _ => None,
+        };
+        for (name, field) in row.get_column_iter() {
+            match (name.as_str(), field) {
+                ("location", Field::Group(location)) => {
+                    for (name, field) in location.get_column_iter() {
+                        match name.as_str() {
+                            "lat" => elevation.lat = number(field).unwrap_or(f64::NAN),
+                            "lon" => elevation.lon = number(field).unwrap_or(f64::NAN),
+                            _ => {}
+                        }
+                    }
+                }
+                ("lat", field) => elevation.lat = number(field).unwrap_or(f64::NAN),
+                ("lon", field) => elevation.lon = number(field).unwrap_or(f64::NAN),
+                (_, field) => {
+                    elevation.value = number(field).filter(|&value| Some(value) != nodata)
+                }
+            }
+        }
+        elevation
+    }
+}
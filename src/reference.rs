@@ -0,0 +1,132 @@
+use crate::{source::list_local, RemoteFile};
+use gdal::Dataset;
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// A raster of a reference dataset.
+struct Raster {
+    path: PathBuf,
+    gt: [f64; 6],
+    size: (usize, usize),
+}
+
+impl Raster {
+    /// Returns the extent (`min_lon`, `min_lat`, `max_lon`, `max_lat`) of the north-up raster.
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (x_size, y_size) = self.size;
+        (
+            self.gt[0],
+            self.gt[3] + y_size as f64 * self.gt[5],
+            self.gt[0] + x_size as f64 * self.gt[1],
+            self.gt[3],
+        )
+    }
+}
+
+/// A reference raster dataset in longitude and latitude (e.g. another DEM or a land cover map):
+/// a raster dataset (e.g. a VRT) or a dir of GeoTIFF files, of which the first band is used.
+pub struct Reference {
+    rasters: Vec<Raster>,
+}
+
+impl Reference {
+    /// Opens the reference dataset at `path`.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let paths = if path.is_dir() {
+            list_local(path, true)?
+                .into_iter()
+                .filter_map(|object| match object {
+                    RemoteFile::Local(path) => Some(path),
+                    _ => None,
+                })
+                .collect()
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let rasters = paths
+            .into_iter()
+            .map(|path| -> Result<_, Box<dyn Error>> {
+                let dataset = Dataset::open(&path)?;
+                let gt = dataset.geo_transform()?;
+                if gt[2] != 0. || gt[4] != 0. {
+                    return Err(format!("`{}` is not north-up", path.display()).into());
+                }
+                Ok(Raster {
+                    gt,
+                    size: dataset.raster_size(),
+                    path,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if rasters.is_empty() {
+            return Err(format!("no reference rasters in `{}`", path.display()).into());
+        }
+        Ok(Reference { rasters })
+    }
+
+    /// Returns the tiles (named after their south-west corner) that overlap the reference.
+    pub fn tiles(&self) -> HashSet<(i16, i16)> {
+        let mut tiles = HashSet::new();
+        for raster in &self.rasters {
+            let (min_lon, min_lat, max_lon, max_lat) = raster.bounds();
+            // Tiles that only touch the raster at its max edge are excluded.
+            for lat in min_lat.floor() as i16..max_lat.ceil() as i16 {
+                for lon in min_lon.floor() as i16..max_lon.ceil() as i16 {
+                    tiles.insert((lat, lon));
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Returns the value of the reference at the `points` (`lon`, `lat`) in `bounds`, or
+    /// `None` outside the reference and for its nodata pixels. Only the windows of the rasters
+    /// that overlap the bounds are read.
+    pub fn sample(
+        &self,
+        bounds: (f64, f64, f64, f64),
+        points: &[(f64, f64)],
+    ) -> Result<Vec<Option<f64>>, Box<dyn Error>> {
+        let (min_lon, min_lat, max_lon, max_lat) = bounds;
+        let mut values = vec![None; points.len()];
+        for raster in &self.rasters {
+            let (gt, (x_size, y_size)) = (raster.gt, raster.size);
+            let pixel = |lon: f64, lat: f64| ((lon - gt[0]) / gt[1], (lat - gt[3]) / gt[5]);
+            let (x0, y0) = pixel(min_lon, max_lat);
+            let (x1, y1) = pixel(max_lon, min_lat);
+            let (x0, y0) = (x0.floor().max(0.) as usize, y0.floor().max(0.) as usize);
+            let (x1, y1) = (
+                (x1.ceil().max(0.) as usize).min(x_size),
+                (y1.ceil().max(0.) as usize).min(y_size),
+            );
+            if x0 >= x1 || y0 >= y1 {
+                continue;
+            }
+            let (width, height) = (x1 - x0, y1 - y0);
+            let dataset = Dataset::open(&raster.path)?;
+            let rasterband = dataset.rasterband(1)?;
+            let nodata = rasterband.no_data_value();
+            let data = rasterband
+                .read_as::<f64>((x0 as isize, y0 as isize), (width, height), (width, height))?
+                .data;
+            for (value, &(lon, lat)) in values.iter_mut().zip(points) {
+                if value.is_some() {
+                    continue;
+                }
+                let (x, y) = pixel(lon, lat);
+                let (x, y) = (x.floor(), y.floor());
+                if x < x0 as f64 || y < y0 as f64 || x >= x1 as f64 || y >= y1 as f64 {
+                    continue;
+                }
+                let sample = data[(y as usize - y0) * width + (x as usize - x0)];
+                if nodata.map_or(true, |nodata| sample != nodata) {
+                    *value = Some(sample);
+                }
+            }
+        }
+        Ok(values)
+    }
+}
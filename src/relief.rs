@@ -0,0 +1,218 @@
+use crate::{
+    column::read_band,
+    terrain::{self, Light},
+};
+use gdal::{raster::Buffer, Dataset, Driver};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Colors of the relief at positions in the equalized elevation range, from low to high.
+const RAMP: [(f64, [f64; 3]); 6] = [
+    (0.0, [0., 97., 71.]),
+    (0.25, [16., 122., 47.]),
+    (0.5, [232., 215., 125.]),
+    (0.75, [161., 67., 0.]),
+    (0.9, [130., 30., 30.]),
+    (1.0, [255., 255., 255.]),
+];
+
+/// Image format of a color relief.
+#[derive(Copy, Clone, Debug)]
+pub enum ReliefFormat {
+    Png,
+    GeoTiff,
+}
+
+impl FromStr for ReliefFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(Self::Png),
+            "tif" => Ok(Self::GeoTiff),
+            _ => Err("expected one of: png, tif"),
+        }
+    }
+}
+
+/// Hillshaded color relief previews of converted tiles, for visual QA.
+#[derive(Clone, Debug)]
+pub struct ColorRelief {
+    /// Output dir of the previews
+    pub dir: PathBuf,
+    pub format: ReliefFormat,
+    /// Index of the elevation band in the raster (starting at 1)
+    pub band: isize,
+    /// Override of the nodata value of the band, nodata pixels are transparent
+    pub nodata: Option<f64>,
+    /// Float elevations are rounded to whole meters
+    pub rounded: bool,
+}
+
+impl ColorRelief {
+    /// Returns the path of the preview of the GeoTIFF file at `input_path`.
+    pub fn path(&self, input_path: &Path) -> PathBuf {
+        let extension = match self.format {
+            ReliefFormat::Png => "png",
+            ReliefFormat::GeoTiff => "tif",
+        };
+        self.dir
+            .join(input_path.file_stem().unwrap())
+            .with_extension(extension)
+    }
+
+    /// Writes the preview of the GeoTIFF file at `input_path`, returning its path.
+    ///
+    /// The colors are assigned by the rank of the elevation in the tile (histogram
+    /// equalization), so that the preview shows the relief of flat and mountainous tiles alike.
+    pub fn write(&self, input_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let dataset = Dataset::open(input_path)?;
+        let gt = dataset.geo_transform()?;
+        let (x_size, y_size) = dataset.raster_size();
+        let rasterband = dataset.rasterband(self.band)?;
+        let nodata = self.nodata.or_else(|| rasterband.no_data_value());
+        let (_, data) = read_band(&rasterband, self.rounded).map_err(|e| e as Box<dyn Error>)?;
+        let valid = |value: i32| nodata.map_or(true, |nodata| value as f64 != nodata);
+
+        // Cumulative histogram of the elevation, as the fraction of pixels at or below a value.
+        let (min, max) = data
+            .iter()
+            .filter(|&&value| valid(value))
+            .fold((i32::MAX, i32::MIN), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            });
+        let mut cdf = vec![
+            0.;
+            if min <= max {
+                (max - min) as usize + 1
+            } else {
+                0
+            }
+        ];
+        data.iter()
+            .filter(|&&value| valid(value))
+            .for_each(|&value| cdf[(value - min) as usize] += 1.);
+        let total = cdf.iter().sum::<f64>();
+        let mut sum = 0.;
+        for count in &mut cdf {
+            sum += *count;
+            *count = sum / total;
+        }
+
+        let shades = terrain::hillshade(&data, &gt, (x_size, y_size), nodata, &Light::default());
+        let mut bands = vec![vec![0u8; x_size * y_size]; 4];
+        for (offset, (&value, shade)) in data.iter().zip(shades).enumerate() {
+            let shade = match shade {
+                Some(shade) => f64::from(shade) / 255.,
+                None => continue,
+            };
+            let color = ramp(cdf[(value - min) as usize]);
+            for (band, channel) in bands.iter_mut().zip(color) {
+                band[offset] = (channel * (0.4 + 0.6 * shade)).round() as u8;
+            }
+            bands[3][offset] = 255;
+        }
+
+        // The PNG driver only supports copies, so write those to memory first.
+        let driver = Driver::get(match self.format {
+            ReliefFormat::Png => "MEM",
+            ReliefFormat::GeoTiff => "GTiff",
+        })?;
+        let path = self.path(input_path);
+        let filename = path.to_string_lossy();
+        fs::create_dir_all(&self.dir)?;
+        let mut output = driver.create_with_band_type::<u8, _>(
+            &*filename,
+            x_size as isize,
+            y_size as isize,
+            bands.len() as isize,
+        )?;
+        output.set_geo_transform(&gt)?;
+        output.set_projection(&dataset.projection())?;
+        for (index, band) in bands.into_iter().enumerate() {
+            output.rasterband(index as isize + 1)?.write(
+                (0, 0),
+                (x_size, y_size),
+                &Buffer::new((x_size, y_size), band),
+            )?;
+        }
+        if let ReliefFormat::Png = self.format {
+            output.create_copy(&Driver::get("PNG")?, &*filename)?;
+        }
+        Ok(path)
+    }
+}
+
+/// Hillshade GeoTIFFs of converted tiles, for visualization pipelines.
+#[derive(Clone, Debug)]
+pub struct HillshadeRaster {
+    /// Output dir of the GeoTIFFs
+    pub dir: PathBuf,
+    pub light: Light,
+    /// Index of the elevation band in the raster (starting at 1)
+    pub band: isize,
+    /// Override of the nodata value of the band, nodata pixels are 0 in the hillshade
+    pub nodata: Option<f64>,
+    /// Float elevations are rounded to whole meters
+    pub rounded: bool,
+}
+
+impl HillshadeRaster {
+    /// Returns the path of the hillshade of the GeoTIFF file at `input_path`.
+    pub fn path(&self, input_path: &Path) -> PathBuf {
+        self.dir
+            .join(input_path.file_stem().unwrap())
+            .with_extension("tif")
+    }
+
+    /// Writes the hillshade of the GeoTIFF file at `input_path` with the size and geotransform
+    /// of the tile, as an 8-bit GeoTIFF like `gdaldem hillshade`, returning its path.
+    pub fn write(&self, input_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let dataset = Dataset::open(input_path)?;
+        let gt = dataset.geo_transform()?;
+        let (x_size, y_size) = dataset.raster_size();
+        let rasterband = dataset.rasterband(self.band)?;
+        let nodata = self.nodata.or_else(|| rasterband.no_data_value());
+        let (_, data) = read_band(&rasterband, self.rounded).map_err(|e| e as Box<dyn Error>)?;
+        let shades = terrain::hillshade(&data, &gt, (x_size, y_size), nodata, &self.light)
+            .into_iter()
+            // Like `gdaldem hillshade`, 0 is nodata and shadows are 1.
+            .map(|shade| shade.map_or(0, |shade| shade.max(1)))
+            .collect();
+
+        let path = self.path(input_path);
+        fs::create_dir_all(&self.dir)?;
+        let mut output = Driver::get("GTiff")?.create_with_band_type::<u8, _>(
+            &*path.to_string_lossy(),
+            x_size as isize,
+            y_size as isize,
+            1,
+        )?;
+        output.set_geo_transform(&gt)?;
+        output.set_projection(&dataset.projection())?;
+        let mut band = output.rasterband(1)?;
+        band.set_no_data_value(0.)?;
+        band.write(
+            (0, 0),
+            (x_size, y_size),
+            &Buffer::new((x_size, y_size), shades),
+        )?;
+        Ok(path)
+    }
+}
+
+/// Returns the color of the ramp at `position` between 0 and 1.
+fn ramp(position: f64) -> [f64; 3] {
+    let upper = RAMP
+        .iter()
+        .position(|&(stop, _)| stop >= position)
+        .unwrap_or(RAMP.len() - 1)
+        .max(1);
+    let ((start, from), (end, to)) = (RAMP[upper - 1], RAMP[upper]);
+    let fraction = ((position - start) / (end - start)).max(0.).min(1.);
+    [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * fraction)
+}
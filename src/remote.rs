@@ -0,0 +1,121 @@
+//! Comparison of the manifest of an output dir with the current listing of the source files, to
+//! audit a mirror before syncing it.
+
+use crate::{
+    manifest::{Manifest, ManifestFile},
+    tile_coordinate, Coordinate, RemoteFile, TILE_PATTERN,
+};
+use regex::Regex;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
+
+/// Differences between the manifest and the remote listing of the source files.
+#[derive(Debug, Default, Serialize)]
+pub struct RemoteDiff {
+    /// Keys of the source files of tiles without a file in the manifest
+    pub added: Vec<String>,
+    /// Paths of the files in the manifest of tiles that are no longer listed
+    pub removed: Vec<String>,
+    /// Files in the manifest of which the source file changed
+    pub changed: Vec<Change>,
+}
+
+/// A file in the manifest of which the source file changed.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    /// Path of the file in the manifest
+    pub path: String,
+    /// Key of the listed source file
+    pub key: String,
+    /// Properties of the source file that changed: `key`, `size` and `etag`
+    pub fields: Vec<&'static str>,
+}
+
+/// Compares the files in the `manifest` with the listed source files in `objects`, by the
+/// coordinate of their tile. Only the files of tiles for which `selected` returns true are
+/// compared, so that the tiles outside the listed set are not reported as removed.
+///
+/// The sizes and entity tags are only compared when both the manifest and the listing have
+/// them, e.g. not for the files of HTTPS mirrors, which are listed without them.
+pub fn remote_diff(
+    manifest: &Manifest,
+    objects: &[RemoteFile],
+    selected: impl Fn(Coordinate) -> bool,
+) -> Result<RemoteDiff, regex::Error> {
+    let re = Regex::new(TILE_PATTERN)?;
+    let remote = objects
+        .iter()
+        .filter_map(|object| {
+            tile_coordinate(&re, object.name()).map(|coordinate| (coordinate.degrees(), object))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let local = manifest
+        .files
+        .iter()
+        .filter_map(|file| {
+            tile_coordinate(&re, &file.path)
+                .filter(|&coordinate| selected(coordinate))
+                .map(|coordinate| (coordinate.degrees(), file))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut diff = RemoteDiff::default();
+    for (tile, object) in &remote {
+        match local.get(tile) {
+            Some(file) => {
+                let fields = changes(file, object);
+                if !fields.is_empty() {
+                    diff.changed.push(Change {
+                        path: file.path.clone(),
+                        key: object.location(),
+                        fields,
+                    });
+                }
+            }
+            None => diff.added.push(object.location()),
+        }
+    }
+    diff.removed = local
+        .iter()
+        .filter(|(tile, _)| !remote.contains_key(tile))
+        .map(|(_, file)| file.path.clone())
+        .collect();
+    Ok(diff)
+}
+
+/// Returns the properties of the source file `object` that differ from the version that `file`
+/// was converted from.
+fn changes(file: &ManifestFile, object: &RemoteFile) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    // The Parquet files are named after their GeoTIFF file, when their source key is unknown.
+    let key_changed = match &file.upstream.key {
+        Some(key) => key != &object.location(),
+        None => {
+            let stem = Path::new(object.name()).file_stem().unwrap_or_default();
+            let name = Path::new(&file.path).file_name().unwrap_or_default();
+            !name.to_string_lossy().starts_with(&*stem.to_string_lossy())
+        }
+    };
+    if key_changed {
+        fields.push("key");
+    }
+    let upstream = object.upstream();
+    if differs(
+        file.upstream.size,
+        upstream.and_then(|upstream| upstream.size),
+    ) {
+        fields.push("size");
+    }
+    if differs(
+        file.upstream.etag.as_deref(),
+        upstream.and_then(|upstream| upstream.etag.as_deref()),
+    ) {
+        fields.push("etag");
+    }
+    fields
+}
+
+/// Returns true when both values are known and they differ.
+fn differs<T: PartialEq>(local: Option<T>, remote: Option<T>) -> bool {
+    matches!((local, remote), (Some(local), Some(remote)) if local != remote)
+}
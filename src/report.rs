@@ -0,0 +1,214 @@
+//! Reports of the Parquet output dir and the source files, printed or written by the
+//! subcommands that do not convert tiles.
+
+use crate::{
+    coverage::{self, Status},
+    hypsometry::{self, Region},
+    inspect::{self, FileReport},
+    manifest::Manifest,
+    polygon, remote, tile_coordinate,
+    usage::format_bytes,
+    Aw3d30Reader, Band, Bbox, RemoteFile, Set, Source, TILE_PATTERN,
+};
+use regex::Regex;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+use tracing::{event, Level};
+
+/// Verifies the files in `parquet_dir` against the stored manifest, or the `expect`ed manifest.
+pub fn verify_manifest(parquet_dir: &Path, expect: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let manifest = match &expect {
+        Some(path) => {
+            event!(
+                Level::INFO,
+                "Verifying `{}` against `{}`",
+                parquet_dir.display(),
+                path.display()
+            );
+            Manifest::read(path)?
+        }
+        None => {
+            event!(
+                Level::INFO,
+                "Verifying `{}` against manifest",
+                parquet_dir.display()
+            );
+            Manifest::load(parquet_dir)?
+        }
+    };
+    let verification = manifest.verify(parquet_dir)?;
+    for path in &verification.missing {
+        event!(Level::ERROR, "Missing file: {}", path);
+    }
+    for path in &verification.extra {
+        event!(Level::ERROR, "Extra file: {}", path);
+    }
+    for path in &verification.corrupted {
+        event!(Level::ERROR, "Corrupted file: {}", path);
+    }
+    if verification.is_ok() {
+        event!(Level::INFO, "All files match the manifest");
+        Ok(())
+    } else {
+        Err("manifest verification failed".into())
+    }
+}
+
+/// Prints the `objects` with their size and the total size to stdout, as JSON when `json`.
+pub fn list_files(objects: &[RemoteFile], json: bool) -> Result<(), Box<dyn Error>> {
+    // Files of an HTTP directory index have an unknown size.
+    let total = objects.iter().filter_map(RemoteFile::size).sum::<u64>();
+    if json {
+        let files = objects
+            .iter()
+            .map(|object| serde_json::json!({ "key": object.location(), "size": object.size() }))
+            .collect::<Vec<_>>();
+        let list = serde_json::json!({ "files": files, "total_size": total });
+        println!("{}", serde_json::to_string_pretty(&list)?);
+    } else {
+        for object in objects {
+            let size = object.size().map_or_else(|| "-".to_string(), format_bytes);
+            println!("{}\t{}", object.location(), size);
+        }
+        println!("{} files, {} total", objects.len(), format_bytes(total));
+    }
+    Ok(())
+}
+
+/// Writes the hypsometric curve of the `band` in the `region` of the points in `parquet_dir` to
+/// `output`, and a plot of it to `plot`.
+pub fn hypsometry_report(
+    parquet_dir: &Path,
+    region: &str,
+    interval: f64,
+    output: &Path,
+    plot: Option<PathBuf>,
+    band: &Band,
+) -> Result<(), Box<dyn Error>> {
+    if !interval.is_finite() || interval <= 0. {
+        return Err("the elevation interval must be positive".into());
+    }
+    let region = match region.parse::<Bbox>() {
+        Ok(bbox) => Region::Bbox(bbox),
+        Err(_) => Region::Polygons(polygon::read_polygons(Path::new(region))?),
+    };
+    let elevations = Aw3d30Reader::open(parquet_dir)?
+        .bbox(region.bbox())
+        .band(&band.name)
+        .elevations()?
+        .into_iter()
+        .filter(|elevation| region.contains(elevation.lat, elevation.lon))
+        .collect::<Vec<_>>();
+    let bins = hypsometry::hypsometry(&elevations, interval);
+    if bins.is_empty() {
+        return Err("the region has no points with a value".into());
+    }
+    hypsometry::write_bins(output, &bins)?;
+    event!(
+        Level::INFO,
+        "Wrote {} elevation intervals of {} points to `{}`",
+        bins.len(),
+        elevations.len(),
+        output.display()
+    );
+    if let Some(path) = plot {
+        hypsometry::write_svg(&path, &bins, &band.unit)?;
+    }
+    Ok(())
+}
+
+/// Prints the footer reports of the Parquet `files`, or of the Parquet files in `parquet_dir`.
+pub fn inspect_files(
+    parquet_dir: &Path,
+    files: Vec<PathBuf>,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let files = if files.is_empty() {
+        inspect::parquet_files(parquet_dir)?
+    } else {
+        files
+    };
+    let reports = files
+        .iter()
+        .map(|path| FileReport::read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        reports.iter().for_each(FileReport::print);
+    }
+    Ok(())
+}
+
+/// Writes the coverage of the tiles of `set` in `parquet_dir` to `output`.
+pub async fn tile_coverage(
+    source: &Source,
+    set: Set,
+    parquet_dir: &Path,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let re = Regex::new(TILE_PATTERN)?;
+    let objects = source
+        .list(|key| tile_coordinate(&re, key).map_or(false, |coordinate| set.filter(coordinate)))
+        .await?;
+    let manifest = Manifest::load(parquet_dir)?;
+    let tiles = objects
+        .iter()
+        .map(|object| {
+            let name = object.name().to_string();
+            let coordinate = tile_coordinate(&re, &name).unwrap();
+            let status = Status::of(&manifest, &name, coordinate);
+            (name, coordinate, status)
+        })
+        .collect::<Vec<_>>();
+    for status in [Status::Selected, Status::Completed, Status::Failed] {
+        let count = tiles.iter().filter(|(.., s)| *s == status).count();
+        event!(Level::INFO, "{:?}: {} tiles", status, count);
+    }
+    event!(Level::INFO, "Writing coverage to `{}`", output.display());
+    coverage::write_coverage(output, &tiles)?;
+    Ok(())
+}
+
+/// Prints the differences between the manifest in `parquet_dir` and the current listing of the
+/// source files of the tiles of `set`.
+pub async fn remote_diff_report(
+    source: &Source,
+    set: Set,
+    parquet_dir: &Path,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let re = Regex::new(TILE_PATTERN)?;
+    let objects = source
+        .list(|key| tile_coordinate(&re, key).map_or(false, |coordinate| set.filter(coordinate)))
+        .await?;
+    let manifest = Manifest::load(parquet_dir)?;
+    let diff = remote::remote_diff(&manifest, &objects, |coordinate| set.filter(coordinate))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        for key in &diff.added {
+            println!("added\t{}", key);
+        }
+        for path in &diff.removed {
+            println!("removed\t{}", path);
+        }
+        for change in &diff.changed {
+            println!(
+                "changed\t{}\t{}\t{}",
+                change.path,
+                change.key,
+                change.fields.join(",")
+            );
+        }
+        println!(
+            "{} added, {} removed, {} changed",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+    }
+    Ok(())
+}
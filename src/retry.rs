@@ -0,0 +1,420 @@
+use crate::manifest::RetryState;
+use rusoto_core::RusotoError;
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{event, Level};
+
+/// Upper bound of the delay between attempts.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Upper bound of the delay between attempts of a failed tile.
+const MAX_TILE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Upper bound of the pause of a tripped circuit breaker.
+const MAX_COOLDOWN: Duration = Duration::from_secs(600);
+
+/// Policy to retry failed operations with exponential backoff and jitter.
+#[derive(Copy, Clone, Debug)]
+pub struct Retry {
+    /// Maximum number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every next retry
+    pub base_delay: Duration,
+    /// Return the error when the endpoint is down instead of waiting for it, so that the
+    /// operation can fail over to another endpoint
+    pub fail_over: bool,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            fail_over: false,
+        }
+    }
+}
+
+impl Retry {
+    /// Returns the delay after failed `attempt` (starting at 1), a random duration up to the
+    /// exponential backoff ("full jitter"), so that concurrent retries spread out.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .checked_mul(1 << (attempt - 1).min(16))
+            .map_or(MAX_DELAY, |backoff| backoff.min(MAX_DELAY));
+        // The hasher keys of a new `RandomState` are random.
+        let random = RandomState::new().build_hasher().finish();
+        backoff.mul_f64(random as f64 / u64::MAX as f64)
+    }
+
+    /// Runs `operation` until it succeeds, it fails with an error for which `retryable` returns
+    /// false, or the attempts are exhausted.
+    ///
+    /// The outcomes are recorded by the `breaker` of the endpoint. While it is open the
+    /// operation waits, and failures that find the endpoint down count as attempts too, so an
+    /// endpoint that stays down fails the operation after `max_attempts` pauses (unless the
+    /// operation fails over, then the first such failure is returned).
+    pub async fn run<T, E: Display, F: Future<Output = Result<T, E>>>(
+        &self,
+        breaker: &CircuitBreaker,
+        mut operation: impl FnMut() -> F,
+        retryable: impl Fn(&E) -> bool,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            breaker.wait().await;
+            match operation().await {
+                Ok(value) => {
+                    breaker.succeeded();
+                    return Ok(value);
+                }
+                // Retried once the breaker resumes the operations.
+                Err(e) if retryable(&e) && breaker.failed() => {
+                    if self.fail_over || attempt >= self.max_attempts {
+                        return Err(e);
+                    }
+                    event!(
+                        Level::WARN,
+                        "Attempt {} of {} failed, endpoint is down: {}",
+                        attempt,
+                        self.max_attempts,
+                        e
+                    );
+                    attempt += 1;
+                }
+                Err(e) if attempt < self.max_attempts && retryable(&e) => {
+                    let delay = self.delay(attempt);
+                    event!(
+                        Level::WARN,
+                        "Attempt {} of {} failed, retrying in {:.1?}: {}",
+                        attempt,
+                        self.max_attempts,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Policy to retry tiles that failed in earlier runs, as recorded in the manifest, with
+/// exponential backoff across restarts.
+#[derive(Copy, Clone, Debug)]
+pub struct TileRetry {
+    /// Maximum number of runs that attempt a tile, after which it is no longer retried
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt, doubled for every next failed attempt
+    pub base_delay: Duration,
+}
+
+impl Default for TileRetry {
+    fn default() -> Self {
+        TileRetry {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl TileRetry {
+    /// Returns the delay after failed attempt `attempts` (starting at 1), before the tile is
+    /// attempted again.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        self.base_delay
+            .checked_mul(1 << attempts.saturating_sub(1).min(16))
+            .map_or(MAX_TILE_DELAY, |delay| delay.min(MAX_TILE_DELAY))
+    }
+
+    /// Returns the time until the tile with `state` may be attempted again, zero when it is
+    /// due, or `None` when its attempts are exhausted.
+    pub fn wait(&self, state: &RetryState, now: SystemTime) -> Option<Duration> {
+        if state.attempts >= self.max_attempts {
+            return None;
+        }
+        let due = UNIX_EPOCH + Duration::from_secs(state.failed_at) + self.delay(state.attempts);
+        Some(due.duration_since(now).unwrap_or_default())
+    }
+}
+
+/// Policy of the circuit breakers that pause all operations on an endpoint that is down.
+#[derive(Copy, Clone, Debug)]
+pub struct BreakerPolicy {
+    /// Number of consecutive transient failures that trip the breaker, 0 disables it
+    pub threshold: u32,
+    /// Pause after tripping, doubled every time the endpoint is still down after a pause
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy {
+            threshold: 10,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Circuit breaker of an endpoint, shared by all operations on it.
+///
+/// The breaker trips when consecutive operations fail with transient errors. Operations then
+/// wait until the cooldown has passed, after which they are tried again: the breaker closes
+/// on the first success and trips again, with a longer cooldown, on the next failure.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    endpoint: String,
+    policy: BreakerPolicy,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    /// Consecutive transient failures
+    failures: u32,
+    /// Times the breaker tripped since it was last closed
+    trips: u32,
+    /// End of the pause, set while the breaker is not closed
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(endpoint: impl Into<String>, policy: BreakerPolicy) -> Self {
+        CircuitBreaker {
+            endpoint: endpoint.into(),
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Waits until the breaker is no longer paused.
+    pub async fn wait(&self) {
+        let open_until = self.state.lock().unwrap().open_until;
+        if let Some(open_until) = open_until {
+            tokio::time::sleep_until(open_until.into()).await;
+        }
+    }
+
+    /// Records a successful operation, closing the breaker.
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.take().is_some() {
+            event!(
+                Level::WARN,
+                "`{}` is reachable again, resuming downloads",
+                self.endpoint
+            );
+        }
+        state.failures = 0;
+        state.trips = 0;
+    }
+
+    /// Returns true while the breaker pauses the operations on the endpoint.
+    pub fn is_open(&self) -> bool {
+        let open_until = self.state.lock().unwrap().open_until;
+        open_until.map_or(false, |open_until| open_until > Instant::now())
+    }
+
+    /// Records an operation that failed with a transient error. Returns true when the endpoint
+    /// is considered down, i.e. the breaker tripped or was not closed yet.
+    pub fn failed(&self) -> bool {
+        if self.policy.threshold == 0 {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        let now = Instant::now();
+        match state.open_until {
+            // Failures of operations that started before the breaker tripped.
+            Some(open_until) if open_until > now => true,
+            // The first failure after a pause, or too many consecutive failures.
+            paused if paused.is_some() || state.failures >= self.policy.threshold => {
+                let cooldown = self
+                    .policy
+                    .cooldown
+                    .checked_mul(1 << state.trips.min(16))
+                    .map_or(MAX_COOLDOWN, |cooldown| cooldown.min(MAX_COOLDOWN));
+                state.trips += 1;
+                state.open_until = Some(now + cooldown);
+                event!(
+                    Level::ERROR,
+                    "`{}` is down after {} consecutive failures, pausing all downloads for {:.0?}",
+                    self.endpoint,
+                    state.failures,
+                    cooldown
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns true for errors of S3 operations that may succeed when retried: connection and I/O
+/// errors, throttling and server errors.
+pub fn transient<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => {
+            response.status.is_server_error() || response.status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Policy without delays, so that the tests don't sleep.
+    fn retry(max_attempts: u32, fail_over: bool) -> Retry {
+        Retry {
+            max_attempts,
+            base_delay: Duration::ZERO,
+            fail_over,
+        }
+    }
+
+    fn breaker(threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test",
+            BreakerPolicy {
+                threshold,
+                cooldown: Duration::ZERO,
+            },
+        )
+    }
+
+    /// Runs an operation that fails `failures` times, returning its result and number of calls.
+    async fn run(
+        retry: Retry,
+        breaker: &CircuitBreaker,
+        failures: u32,
+        retryable: bool,
+    ) -> (Result<u32, &'static str>, u32) {
+        let calls = Cell::new(0);
+        let result = retry
+            .run(
+                breaker,
+                || {
+                    calls.set(calls.get() + 1);
+                    let call = calls.get();
+                    async move {
+                        match call > failures {
+                            true => Ok(call),
+                            false => Err("failed"),
+                        }
+                    }
+                },
+                |_| retryable,
+            )
+            .await;
+        (result, calls.get())
+    }
+
+    #[test]
+    fn delay_is_bounded_by_the_backoff() {
+        let retry = Retry {
+            base_delay: Duration::from_millis(500),
+            ..Retry::default()
+        };
+        for attempt in 1..=40 {
+            let backoff = Duration::from_millis(500)
+                .checked_mul(1 << (attempt - 1).min(16))
+                .unwrap()
+                .min(MAX_DELAY);
+            assert!(retry.delay(attempt) <= backoff, "attempt {}", attempt);
+        }
+    }
+
+    #[tokio::test]
+    async fn run_counts_attempts() {
+        let breaker = breaker(0);
+        assert_eq!(run(retry(3, false), &breaker, 0, true).await, (Ok(1), 1));
+        assert_eq!(run(retry(3, false), &breaker, 2, true).await, (Ok(3), 3));
+        assert_eq!(
+            run(retry(3, false), &breaker, 3, true).await,
+            (Err("failed"), 3)
+        );
+        assert_eq!(
+            run(retry(3, false), &breaker, 1, false).await,
+            (Err("failed"), 1)
+        );
+        assert_eq!(
+            run(retry(1, false), &breaker, 1, true).await,
+            (Err("failed"), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn run_counts_attempts_while_the_endpoint_is_down() {
+        // Every failure trips the breaker, the attempts are still limited.
+        let down = breaker(1);
+        assert_eq!(
+            run(retry(4, false), &down, u32::MAX, true).await,
+            (Err("failed"), 4)
+        );
+        // The first failure that finds the endpoint down is returned to fail over.
+        let down = breaker(1);
+        assert_eq!(
+            run(retry(4, true), &down, u32::MAX, true).await,
+            (Err("failed"), 1)
+        );
+        // A success closes the breaker again.
+        let down = breaker(1);
+        assert_eq!(run(retry(4, false), &down, 2, true).await, (Ok(3), 3));
+        assert!(!down.is_open());
+    }
+
+    #[test]
+    fn breaker_trips_after_threshold() {
+        let breaker = CircuitBreaker::new(
+            "test",
+            BreakerPolicy {
+                threshold: 3,
+                cooldown: Duration::from_secs(30),
+            },
+        );
+        assert!(!breaker.failed());
+        assert!(!breaker.failed());
+        assert!(breaker.failed());
+        assert!(breaker.is_open());
+        // Failures of operations that started before the breaker tripped.
+        assert!(breaker.failed());
+        breaker.succeeded();
+        assert!(!breaker.is_open());
+        assert!(!breaker.failed());
+    }
+
+    #[test]
+    fn tile_retry_backs_off() {
+        let retry = TileRetry {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(60),
+        };
+        assert_eq!(retry.delay(1), Duration::from_secs(60));
+        assert_eq!(retry.delay(2), Duration::from_secs(120));
+        assert_eq!(retry.delay(100), MAX_TILE_DELAY);
+        let state = |attempts| RetryState {
+            attempts,
+            error: "failed".to_string(),
+            failed_at: 1_000,
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_030);
+        assert_eq!(retry.wait(&state(1), now), Some(Duration::from_secs(30)));
+        assert_eq!(retry.wait(&state(2), now), Some(Duration::from_secs(90)));
+        let later = UNIX_EPOCH + Duration::from_secs(2_000);
+        assert_eq!(retry.wait(&state(1), later), Some(Duration::ZERO));
+        assert_eq!(retry.wait(&state(3), later), None);
+    }
+}
@@ -0,0 +1,895 @@
+//! Runs a command of the command line: the subcommands that report or sample, or the download
+//! and conversion of the tiles of a set.
+
+use crate::{
+    amend::{self, Amendment},
+    catalog,
+    cli::{self, Catalog, Command, Format, Opt},
+    convert::{format_compression, BandType, Sentinel},
+    country::CountryIndex,
+    crs::TargetCrs,
+    dataset::Dataset,
+    delta::DeltaTable,
+    diff,
+    gpkg::GEOPACKAGE,
+    grid::{Downsample, TargetGrid},
+    job::ConvertJob,
+    join::RasterJoin,
+    mask,
+    mirror::Server,
+    mosaic,
+    plan::Plan,
+    polygon,
+    progress::BarProgress,
+    relief::{ColorRelief, HillshadeRaster},
+    report,
+    retry::{BreakerPolicy, Retry, TileRetry},
+    sample,
+    sink::{self, Sink},
+    source::{list_local, shard},
+    terrain::Light,
+    tile_coordinate,
+    upload::Uploader,
+    usage::{format_bytes, Calibration},
+    zonal, Band, Conversion, Coordinate, Set, Source, Usage, TILE_PATTERN,
+};
+use clap::CommandFactory;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{fs, task};
+use tracing::{event, Level};
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// What to do with the selected tiles.
+#[derive(Copy, Clone)]
+enum Mode {
+    Convert,
+    Download,
+    List { json: bool },
+}
+
+/// Runs the command of `opt`, with the dataset `options` that were given, which are recorded in
+/// the manifest of the Parquet dir.
+pub async fn run(opt: Opt, options: BTreeMap<String, Vec<String>>) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let Opt {
+        command,
+        tif_dir,
+        parquet_dir,
+        staging_dir,
+        output_url,
+        delta_table,
+        event_log,
+        color_relief_dir,
+        color_relief_format,
+        hillshade_dir,
+        bands,
+        band_names,
+        band_units,
+        band_type,
+        band_scale,
+        band_offset,
+        nodata_value,
+        nodata,
+        valid_range,
+        out_of_range,
+        nodata_sentinel,
+        allow_clamped_nodata,
+        target_grid,
+        grid_origin,
+        resampling,
+        downsample,
+        downsample_method,
+        coords,
+        target_crs,
+        compression,
+        compression_per_column,
+        row_group_size,
+        page_size,
+        max_memory,
+        encoding,
+        parquet_version,
+        emit_indices,
+        allow_mixed_schema,
+        append,
+        name_include_bbox,
+        remove_tif,
+        output,
+        format,
+        merge,
+        mosaic,
+        boundary,
+        clip,
+        thin,
+        sort,
+        split_degrees,
+        mask,
+        derive,
+        hillshade,
+        hillshade_azimuth,
+        hillshade_altitude,
+        cell_area,
+        country_boundaries,
+        country_field,
+        join_raster,
+        h3_resolution,
+        s2_level,
+        map_tile_zoom,
+        map_tile_format,
+        geoparquet,
+        if_exists,
+        dataset,
+        release,
+        index_url,
+        server,
+        endpoint,
+        endpoint_strategy,
+        schedule_bandwidth,
+        max_attempts,
+        retry_delay,
+        breaker_threshold,
+        breaker_cooldown,
+        tile_max_attempts,
+        tile_retry_delay,
+        reset_retries,
+        list_page_size,
+        max_keys,
+        #[cfg(feature = "chaos")]
+        chaos_download_rate,
+        #[cfg(feature = "chaos")]
+        chaos_convert_rate,
+        shard_index,
+        shard_count,
+        progress,
+        quiet,
+        dry_run,
+        force,
+        metrics_file,
+        config: _,
+    } = opt;
+    // The settings of the run in its metrics.
+    let command_name = command.name();
+    let mut settings = BTreeMap::new();
+    let mut setting = |key: &str, value: String| settings.insert(key.to_string(), value);
+    setting("bands", format!("{:?}", bands));
+    setting("coords", format!("{:?}", coords).to_lowercase());
+    if let Some(crs) = &target_crs {
+        setting("target_crs", crs.clone());
+    }
+    setting("compression", format_compression(compression));
+    for (column, compression) in &compression_per_column {
+        setting(
+            &format!("compression.{}", column),
+            format_compression(*compression),
+        );
+    }
+    if let Some(row_group_size) = row_group_size {
+        setting("row_group_size", row_group_size.to_string());
+    }
+    if let Some(page_size) = page_size {
+        setting("page_size", page_size.to_string());
+    }
+    if let Some(max_memory) = max_memory {
+        setting("max_memory", format_bytes(max_memory));
+    }
+    for (column, encoding) in &encoding {
+        setting(
+            &format!("encoding.{}", column),
+            encoding.to_string().to_lowercase(),
+        );
+    }
+    if band_type != BandType::Int32 {
+        setting("band_type", format!("{:?}", band_type).to_lowercase());
+        setting("band_scale", band_scale.to_string());
+        setting("band_offset", band_offset.to_string());
+    }
+    setting("nodata", format!("{:?}", nodata).to_lowercase());
+    if let Some(range) = valid_range {
+        setting("valid_range", format!("{}..{}", range.min, range.max));
+        setting("out_of_range", format!("{:?}", out_of_range).to_lowercase());
+    }
+    if let Some(sentinel) = nodata_sentinel {
+        setting(
+            "nodata_sentinel",
+            match sentinel {
+                Sentinel::Value(value) => value.to_string(),
+                Sentinel::Clamp => "clamp".to_string(),
+            },
+        );
+    }
+    if let Some(resolution) = target_grid {
+        setting("target_grid", resolution.to_string());
+        setting("resampling", format!("{:?}", resampling).to_lowercase());
+    }
+    if let Some(factor) = downsample {
+        setting("downsample", factor.to_string());
+        setting(
+            "downsample_method",
+            format!("{:?}", downsample_method).to_lowercase(),
+        );
+    }
+    setting("parquet_version", parquet_version.as_num().to_string());
+    setting("append", append.to_string());
+    if let Some(thin) = thin {
+        setting("thin", thin.to_string());
+    }
+    if let Some(order) = sort {
+        setting("sort", order.to_string());
+    }
+    if let Some(degrees) = split_degrees {
+        setting("split_degrees", degrees.to_string());
+    }
+    setting("mask", mask.to_string());
+    if !derive.is_empty() {
+        let names = derive
+            .iter()
+            .map(|derived| derived.name())
+            .collect::<Vec<_>>();
+        setting("derive", names.join(","));
+    }
+    setting("hillshade", hillshade.to_string());
+    setting("cell_area", cell_area.to_string());
+    setting("country_codes", country_boundaries.is_some().to_string());
+    for (name, path) in &join_raster {
+        setting(&format!("join.{}", name), path.display().to_string());
+    }
+    if let Some(resolution) = h3_resolution {
+        setting("h3_resolution", resolution.to_string());
+    }
+    if let Some(level) = s2_level {
+        setting("s2_level", level.to_string());
+    }
+    if let Some(zoom) = map_tile_zoom {
+        setting("map_tile_zoom", zoom.to_string());
+        setting(
+            "map_tile_format",
+            format!("{:?}", map_tile_format).to_lowercase(),
+        );
+    }
+    setting("geoparquet", geoparquet.to_string());
+    setting("emit_indices", emit_indices.to_string());
+    let format_name = match format {
+        Format::Parquet => "parquet",
+        Format::GeoPackage => "gpkg",
+    };
+    setting("format", format_name.to_string());
+    setting("if_exists", format!("{:?}", if_exists).to_lowercase());
+    let source_name = if index_url.is_some() {
+        "http"
+    } else {
+        server.name()
+    };
+    setting("source", source_name.to_string());
+    setting("dataset", dataset.name().to_string());
+    if let Some(release) = &release {
+        setting("version", release.clone());
+    }
+    setting(
+        "bandwidth_schedule",
+        schedule_bandwidth.is_some().to_string(),
+    );
+    setting("max_attempts", max_attempts.to_string());
+    setting("tile_max_attempts", tile_max_attempts.to_string());
+    setting("shard_count", shard_count.to_string());
+    if let Some(url) = &output_url {
+        setting("output_url", url.to_string());
+    }
+    if let Some(dir) = &delta_table {
+        setting("delta_table", dir.display().to_string());
+    }
+    // Reports the resource usage, and appends the metrics of the run to the metrics file.
+    let report_usage = |usage: &Usage| -> Result<(), Box<dyn Error>> {
+        usage.report(start.elapsed());
+        if let Some(path) = &metrics_file {
+            usage
+                .metrics(start.elapsed(), command_name, settings.clone())
+                .append(path)?;
+        }
+        Ok(())
+    };
+
+    let usage = Arc::new(Usage::default());
+    let progress = progress.then(|| BarProgress::new(usage.clone()));
+    let subscriber = tracing_subscriber::fmt()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_max_level(if quiet || progress.is_some() {
+            Level::WARN
+        } else {
+            Level::INFO
+        });
+    match &progress {
+        // Print the log lines above the progress bars.
+        Some(progress) => {
+            let progress = progress.clone();
+            subscriber.with_writer(move || progress.writer()).init()
+        }
+        // Log to stderr, so that listings on stdout can be piped.
+        None => subscriber.with_writer(std::io::stderr).init(),
+    }
+    // The points of a GeoPackage are in longitude and latitude.
+    if let (Format::GeoPackage, Some(_)) = (format, &target_crs) {
+        return Err("a GeoPackage can not be written with a target CRS".into());
+    }
+    if let (Format::GeoPackage, Some(_)) = (format, &output_url) {
+        return Err("a GeoPackage can not be uploaded to an output URL".into());
+    }
+    if let (Format::GeoPackage, true) = (format, event_log) {
+        return Err("the event log is only written for Parquet files".into());
+    }
+    let output = match (format, output, merge) {
+        (Format::Parquet, output, None) => output,
+        (Format::Parquet, _, Some(path)) => Some(Sink::Merge(path)),
+        (Format::GeoPackage, None, None) if mosaic.is_none() => {
+            Some(Sink::GeoPackage(parquet_dir.join(GEOPACKAGE)))
+        }
+        (Format::GeoPackage, None, None) => {
+            return Err("a GeoPackage can not be written for a mosaic".into())
+        }
+        (Format::GeoPackage, Some(_), _) => {
+            return Err("a GeoPackage is written to the Parquet dir, not to `--output`".into())
+        }
+        (Format::GeoPackage, None, Some(_)) => {
+            return Err("a GeoPackage can not be merged into a Parquet file".into())
+        }
+    };
+    if dataset != Dataset::Aw3d30 && (mask || matches!(server, Server::Jaxa(_))) {
+        return Err("the mask files and the JAXA layout are only available for AW3D30".into());
+    }
+    // The override takes precedence over the nodata value of the dataset.
+    let nodata_value = nodata_value.or_else(|| dataset.nodata_value());
+    if bands.len() != band_names.len() || bands.len() != band_units.len() {
+        return Err("number of band names and units must match number of bands".into());
+    }
+    let bands = bands
+        .into_iter()
+        .zip(band_names)
+        .zip(band_units)
+        .map(|((index, name), unit)| Band { index, name, unit })
+        .collect::<Vec<_>>();
+
+    if max_attempts == 0 || !retry_delay.is_finite() || retry_delay < 0. {
+        return Err("retries need at least 1 attempt and a non-negative delay".into());
+    }
+    if !breaker_cooldown.is_finite() || breaker_cooldown < 0. {
+        return Err("breaker cooldown must be non-negative".into());
+    }
+    if tile_max_attempts == 0 || !tile_retry_delay.is_finite() || tile_retry_delay < 0. {
+        return Err("tile retries need at least 1 attempt and a non-negative delay".into());
+    }
+    let retry = Retry {
+        max_attempts,
+        base_delay: Duration::from_secs_f64(retry_delay),
+        fail_over: false,
+    };
+    let breaker_policy = BreakerPolicy {
+        threshold: breaker_threshold,
+        cooldown: Duration::from_secs_f64(breaker_cooldown),
+    };
+    let mut source = Source::new(index_url)?
+        .with_dataset(dataset)
+        .with_retry(retry)
+        .with_circuit_breaker(breaker_policy);
+    if !endpoint.is_empty() {
+        source = source.with_endpoints(&endpoint, endpoint_strategy)?;
+    }
+    if let Some(mirror) = server.mirror()? {
+        source = source.with_mirror(mirror);
+    }
+    let release = match release.as_deref() {
+        Some("latest") => {
+            let latest = source.latest_release().await?;
+            event!(
+                Level::INFO,
+                "Latest release of {} is `{}`",
+                dataset.name(),
+                latest
+            );
+            latest
+        }
+        Some(release) => release.to_string(),
+        None => dataset.release().to_string(),
+    };
+    source = source.with_release(release.clone());
+    if let Some(schedule) = schedule_bandwidth {
+        source = source.with_bandwidth_schedule(schedule);
+    }
+    #[cfg(feature = "chaos")]
+    crate::chaos::configure(chaos_download_rate, chaos_convert_rate)?;
+    if list_page_size == Some(0) || max_keys == Some(0) {
+        return Err("listing requires a positive page size and maximum number of keys".into());
+    }
+    if let Some(list_page_size) = list_page_size {
+        source = source.with_list_page_size(list_page_size);
+    }
+    if let Some(max_keys) = max_keys {
+        source = source.with_max_keys(max_keys);
+    }
+    // The set of tiles to download, or the local GeoTIFF files to convert.
+    let (set, local, mode) = match command {
+        Command::Set(set) => (Some(set), None, Mode::Convert),
+        Command::List { json, set } => (Some(set), None, Mode::List { json }),
+        Command::Download { set } => (Some(set), None, Mode::Download),
+        Command::Convert { input, recursive } => {
+            (None, Some(list_local(&input, recursive)?), Mode::Convert)
+        }
+        Command::VerifyManifest { expect } => return report::verify_manifest(&parquet_dir, expect),
+        Command::Inspect { files, json } => {
+            return report::inspect_files(&parquet_dir, files, json)
+        }
+        Command::Hypsometry {
+            region,
+            interval,
+            output,
+            plot,
+        } => {
+            return report::hypsometry_report(
+                &parquet_dir,
+                &region,
+                interval,
+                &output,
+                plot,
+                &bands[0],
+            )
+        }
+        Command::Completions { shell } => {
+            let name = env!("CARGO_PKG_NAME");
+            clap_complete::generate(shell, &mut Opt::command(), name, &mut io::stdout());
+            return Ok(());
+        }
+        Command::Manpage => return cli::print_manpage(),
+        Command::AmendMetadata { set, remove } => {
+            let amended = amend::amend_metadata(&parquet_dir, &Amendment { set, remove })?;
+            event!(Level::INFO, "Amended {} files", amended);
+            return Ok(());
+        }
+        Command::SamplePoints { points, output } => {
+            fs::create_dir_all(&tif_dir).await?;
+            sample::sample_points(
+                &source, &points, &output, &tif_dir, if_exists, &bands, &usage,
+            )
+            .await?;
+            report_usage(&usage)?;
+            return Ok(());
+        }
+        Command::Lookup { tile_cache } => {
+            fs::create_dir_all(&tif_dir).await?;
+            sample::lookup(
+                &source,
+                tokio::io::BufReader::new(tokio::io::stdin()),
+                io::stdout(),
+                &tif_dir,
+                if_exists,
+                &bands,
+                &usage,
+                tile_cache,
+            )
+            .await?;
+            report_usage(&usage)?;
+            return Ok(());
+        }
+        Command::Register(Catalog::Glue {
+            database,
+            table,
+            location,
+        }) => {
+            let columns = catalog::hive_columns(&parquet_dir)?;
+            event!(
+                Level::INFO,
+                "Registering table `{}.{}` at `{}`",
+                database,
+                table,
+                location
+            );
+            return catalog::register_glue(database, table, location, columns).await;
+        }
+        Command::Coverage { output, set } => {
+            return report::tile_coverage(&source, set, &parquet_dir, &output).await;
+        }
+        Command::RemoteDiff { json, set } => {
+            return report::remote_diff_report(&source, set, &parquet_dir, json).await;
+        }
+        Command::ZonalStats {
+            zones,
+            percentiles,
+            output,
+        } => {
+            fs::create_dir_all(&tif_dir).await?;
+            zonal::zonal_stats(
+                &source,
+                &zones,
+                &percentiles,
+                &output,
+                &tif_dir,
+                if_exists,
+                &bands[0],
+                nodata_value,
+                &usage,
+            )
+            .await?;
+            report_usage(&usage)?;
+            return Ok(());
+        }
+        Command::Diff { reference, output } => {
+            fs::create_dir_all(&tif_dir).await?;
+            diff::diff(
+                &source,
+                &reference,
+                &output,
+                &tif_dir,
+                if_exists,
+                &bands[0],
+                nodata_value,
+                &usage,
+            )
+            .await?;
+            report_usage(&usage)?;
+            return Ok(());
+        }
+    };
+    if let Some(set) = set {
+        event!(Level::INFO, "Preparing data for {:?}", set);
+    }
+    // Local files are converted in place, listing does not download files.
+    if let (Some(_), Mode::Convert | Mode::Download, false) = (set, mode, dry_run) {
+        event!(
+            Level::INFO,
+            "GeoTIFF data will be written to `{}`",
+            &tif_dir.display()
+        );
+        fs::create_dir_all(&tif_dir).await?;
+    }
+
+    // Tiles that are streamed to a sink are not written to Parquet files.
+    if let (Mode::Convert, None, false) = (mode, &output, dry_run) {
+        event!(
+            Level::INFO,
+            "Parquet data data will be written to `{}`",
+            &parquet_dir.display()
+        );
+        fs::create_dir_all(&parquet_dir).await?;
+    }
+
+    // Setup parquet write info.
+    if grid_origin.len() != 2 {
+        return Err("grid origin must be `lon,lat`".into());
+    }
+    let target_grid = target_grid.map(|resolution| TargetGrid {
+        resolution,
+        origin: (grid_origin[0], grid_origin[1]),
+        resampling,
+    });
+    // The previews show the first band, which is the elevation by default.
+    let relief_band = bands[0].index;
+    let light = Light {
+        azimuth: hillshade_azimuth,
+        altitude: hillshade_altitude,
+    };
+    let mut conversion = Conversion::new(
+        bands,
+        nodata_value,
+        if_exists,
+        target_grid,
+        coords,
+        emit_indices,
+    )?;
+    conversion = conversion
+        .with_dataset(dataset, &release)
+        .with_band_type(band_type, band_scale, band_offset)?
+        .with_nodata(nodata)?;
+    if let Some(range) = valid_range {
+        conversion = conversion.with_valid_range(range, out_of_range);
+    }
+    if let Some(sentinel) = nodata_sentinel {
+        if sentinel == Sentinel::Clamp && !allow_clamped_nodata {
+            return Err(
+                "`--nodata-sentinel clamp` writes realistic values for the nodata pixels, \
+                pass `--allow-clamped-nodata` to accept that"
+                    .into(),
+            );
+        }
+        conversion = conversion.with_sentinel(sentinel)?;
+    }
+    if let Some(crs) = &target_crs {
+        conversion = conversion.with_target_crs(TargetCrs::new(crs)?)?;
+    }
+    if let Some(factor) = downsample {
+        conversion = conversion.with_downsample(Downsample {
+            factor,
+            aggregation: downsample_method,
+        })?;
+    }
+    let boundary = boundary
+        .map(|path| polygon::read_polygons(&path))
+        .transpose()?
+        .map(Arc::new);
+    if let (Some(polygons), true) = (&boundary, clip) {
+        conversion = conversion.with_clip(polygons.clone());
+    }
+    if let Some(thin) = thin {
+        if thin == 0 {
+            return Err("thinning requires a positive n".into());
+        }
+        conversion = conversion.with_thin(thin);
+    }
+    if let Some(order) = sort {
+        conversion = conversion.with_sort(order);
+    }
+    if let Some(degrees) = split_degrees {
+        conversion = conversion.with_split(degrees)?;
+    }
+    if !derive.is_empty() {
+        conversion = conversion.with_derived(derive)?;
+    }
+    if hillshade {
+        conversion = conversion.with_hillshade(light)?;
+    }
+    if cell_area {
+        conversion = conversion.with_cell_area()?;
+    }
+    if mask {
+        conversion = conversion.with_mask()?;
+    }
+    if let Some(path) = country_boundaries {
+        let countries = CountryIndex::open(&path, &country_field)?;
+        conversion = conversion.with_country_codes(Arc::new(countries))?;
+    }
+    for (name, path) in &join_raster {
+        conversion = conversion.with_join(Arc::new(RasterJoin::open(name, path)?))?;
+    }
+    if let Some(resolution) = h3_resolution {
+        conversion = conversion.with_h3(resolution)?;
+    }
+    if let Some(level) = s2_level {
+        conversion = conversion.with_s2(level)?;
+    }
+    if let Some(zoom) = map_tile_zoom {
+        conversion = conversion.with_map_tiles(zoom, map_tile_format)?;
+    }
+    if geoparquet {
+        conversion = conversion.with_geoparquet()?;
+    }
+    conversion = conversion
+        .with_compression(compression)
+        .with_writer_version(parquet_version);
+    if let Some(row_group_size) = row_group_size {
+        if row_group_size == 0 {
+            return Err("row group size must be positive".into());
+        }
+        conversion = conversion.with_row_group_size(row_group_size);
+    }
+    if let Some(page_size) = page_size {
+        conversion = conversion.with_page_size(page_size);
+    }
+    if let Some(max_memory) = max_memory {
+        conversion = conversion.with_max_memory(max_memory);
+    }
+    let mut conversion = conversion
+        .with_column_compression(compression_per_column)?
+        .with_encodings(encoding)?;
+    conversion.check_options()?;
+
+    let re = Regex::new(TILE_PATTERN)?;
+    // The tiles of the set that intersect the boundary, if any.
+    let selected = |coordinate: Coordinate| {
+        let (lat, lon) = coordinate.degrees();
+        let (lat, lon) = (f64::from(lat), f64::from(lon));
+        set.map_or(true, |set| set.filter(coordinate))
+            && boundary.as_ref().map_or(true, |polygons| {
+                polygons
+                    .iter()
+                    .any(|polygon| polygon.intersects((lon, lat, lon + 1., lat + 1.)))
+            })
+    };
+    // The mask files are selected by the coordinate of their tile.
+    let coordinate = |name: &str| match mask {
+        true => tile_coordinate(&re, &mask::tile_name(name)),
+        false => tile_coordinate(&re, name),
+    };
+    let converts_local = local.is_some();
+    let objects = match local {
+        // Local files that are not named after a tile are not filtered by the boundary.
+        Some(mut objects) => {
+            objects.retain(|object| coordinate(object.name()).map_or(true, selected));
+            objects
+        }
+        // todo(mb): create list of objects based on set instead of filtering fetched object list
+        None => {
+            source
+                .list(|key| coordinate(key).map_or(false, selected))
+                .await?
+        }
+    };
+    let (objects, masks) = match mask {
+        true => mask::split(objects),
+        false => (objects, Vec::new()),
+    };
+    if converts_local {
+        event!(Level::INFO, "Converting {} local files", objects.len());
+    }
+    let objects = shard(objects, shard_index, shard_count)?;
+    // Local mask files are read where they are.
+    let masks = match converts_local {
+        true => Vec::new(),
+        false => mask::of_tiles(&objects, masks),
+    };
+
+    if let Mode::Convert | Mode::Download = mode {
+        let convert = matches!(mode, Mode::Convert);
+        // Tiles that are streamed to a sink are not written to Parquet files.
+        let parquet_dir =
+            (convert && output.is_none() && mosaic.is_none()).then(|| parquet_dir.as_path());
+        // The metrics of past runs calibrate the projections.
+        let calibration = match &metrics_file {
+            Some(path) => Calibration::load(path)?,
+            None => Calibration::default(),
+        };
+        let plan = Plan::new(
+            &objects,
+            &tif_dir,
+            parquet_dir,
+            if_exists,
+            &conversion,
+            name_include_bbox,
+            &calibration,
+        )?;
+        if dry_run {
+            plan.report(parquet_dir.is_some());
+            return Ok(());
+        }
+        plan.check_disk_space(&tif_dir, parquet_dir, convert && remove_tif, force)?;
+    }
+
+    match mode {
+        Mode::Convert => {}
+        Mode::List { json } => {
+            let objects = objects.into_iter().chain(masks).collect::<Vec<_>>();
+            return report::list_files(&objects, json);
+        }
+        Mode::Download => {
+            let objects = objects.into_iter().chain(masks).collect();
+            source
+                .download_all(objects, &tif_dir, if_exists, &usage)
+                .await?;
+            event!(Level::INFO, "Done");
+            report_usage(&usage)?;
+            return Ok(());
+        }
+    }
+    // The band columns of the auto band type are picked from the first tile, which is converted
+    // later from the same download.
+    if band_type == BandType::Auto {
+        if let Some(object) = objects.first() {
+            let (path, _) = source
+                .download(object.clone(), tif_dir.clone(), if_exists, usage.clone())
+                .await?
+                .map_err(|e| e as Box<dyn Error>)?;
+            conversion = conversion.with_band_columns_of(&path)?;
+            event!(Level::INFO, "Band columns: {:?}", conversion.band_columns());
+        }
+    }
+    let conversion = Arc::new(conversion);
+    // The mask files are small, they are downloaded before the tiles are converted.
+    if !masks.is_empty() {
+        event!(Level::INFO, "Downloading {} mask files", masks.len());
+        source
+            .download_all(masks, &tif_dir, if_exists, &usage)
+            .await?;
+    }
+
+    if let Some(path) = mosaic {
+        // The mosaic is cropped to the bounding box and the extent of the boundary.
+        let mut extent = match set {
+            Some(Set::Custom { bbox }) => {
+                Some((bbox.min_lon, bbox.min_lat, bbox.max_lon, bbox.max_lat))
+            }
+            _ => None,
+        };
+        if let Some(polygons) = &boundary {
+            let bounds = polygons.iter().map(|polygon| polygon.bbox).fold(
+                (
+                    f64::INFINITY,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                    f64::NEG_INFINITY,
+                ),
+                |a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)),
+            );
+            extent = Some(extent.map_or(bounds, |a| {
+                (
+                    a.0.max(bounds.0),
+                    a.1.max(bounds.1),
+                    a.2.min(bounds.2),
+                    a.3.min(bounds.3),
+                )
+            }));
+        }
+        mosaic::convert_mosaic(
+            &source, objects, &tif_dir, if_exists, extent, &path, conversion, &usage,
+        )
+        .await?;
+        event!(Level::INFO, "Done");
+        report_usage(&usage)?;
+        return Ok(());
+    }
+
+    if let Some(sink) = output {
+        let sink = sink.connect(&conversion, if_exists).await?;
+        sink::put_tiles(
+            &source, objects, &tif_dir, if_exists, conversion, &usage, sink, remove_tif,
+        )
+        .await?;
+        event!(Level::INFO, "Done");
+        report_usage(&usage)?;
+        return Ok(());
+    }
+
+    let tiles = objects.len() as u64;
+    let delta = delta_table.map(|dir| {
+        let schema = conversion.arrow_schema();
+        (DeltaTable::new(&dir), parquet_dir.clone(), schema)
+    });
+    let mut job = ConvertJob::new(objects, tif_dir, parquet_dir, conversion, usage.clone())
+        .with_mixed_schema(allow_mixed_schema)
+        .with_options(options)
+        .with_bbox_names(name_include_bbox)
+        .with_remove_tif(remove_tif)
+        .with_tile_retry(TileRetry {
+            max_attempts: tile_max_attempts,
+            base_delay: Duration::from_secs_f64(tile_retry_delay),
+        })
+        .with_reset_retries(reset_retries)
+        .with_event_log(event_log);
+    if let Some(progress) = progress {
+        progress.start(tiles);
+        job = job.with_progress(progress);
+    }
+    if let Some(staging_dir) = staging_dir {
+        job = job.with_staging_dir(staging_dir);
+    }
+    if let Some(url) = output_url {
+        job = job.with_upload(Uploader::new(url, retry, breaker_policy)?);
+    }
+    if let Some(dir) = color_relief_dir {
+        job = job.with_color_relief(ColorRelief {
+            dir,
+            format: color_relief_format,
+            band: relief_band,
+            nodata: nodata_value,
+            rounded: dataset.rounded(),
+        });
+    }
+    if let Some(dir) = hillshade_dir {
+        job = job.with_hillshade_raster(HillshadeRaster {
+            dir,
+            light,
+            band: relief_band,
+            nodata: nodata_value,
+            rounded: dataset.rounded(),
+        });
+    }
+    // Finish the tiles in progress on Ctrl-C, so that the manifest is saved.
+    let cancellation = job.cancellation_token();
+    task::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            event!(Level::WARN, "Cancelling after the tiles in progress");
+            cancellation.cancel();
+        }
+    });
+    let result = job.run(&source).await;
+    // The files that were converted are committed when other tiles failed.
+    if let Some((table, parquet_dir, schema)) = delta {
+        table.commit(&parquet_dir, &schema)?;
+    }
+    result?;
+
+    event!(Level::INFO, "Done");
+    report_usage(&usage)?;
+
+    Ok(())
+}
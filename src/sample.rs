@@ -0,0 +1,485 @@
+use crate::{
+    column::read_band, dataset::Dataset as Dem, tile_coordinate, Band, IfExists, Source, Usage,
+    TILE_PATTERN,
+};
+use futures::{stream, StreamExt};
+use gdal::Dataset;
+use parquet::{
+    basic::{self, Repetition},
+    column::writer::ColumnWriter,
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    record::RowAccessor,
+    schema::types::Type,
+};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    error::Error,
+    fs::File,
+    future::Future,
+    io::Write,
+    path::Path,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    task,
+};
+use tracing::{event, Level};
+
+/// Reads the `lat` and `lon` columns of the Parquet file with points at `path`.
+pub fn read_points(path: &Path) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let fields = reader.metadata().file_metadata().schema().get_fields();
+    let field = |name: &str| {
+        fields
+            .iter()
+            .position(|field| field.name() == name)
+            .ok_or_else(|| format!("points file without `{}` column", name))
+    };
+    let (lat, lon) = (field("lat")?, field("lon")?);
+    let mut points = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        points.push((row.get_double(lat)?, row.get_double(lon)?));
+    }
+    Ok(points)
+}
+
+/// Band values of a tile, read from its GeoTIFF file.
+pub struct TileRaster {
+    /// Geo transform of the raster
+    gt: [f64; 6],
+    x_size: usize,
+    y_size: usize,
+    /// Values of the bands, row by row
+    bands: Vec<Vec<i32>>,
+}
+
+impl TileRaster {
+    /// Reads the `bands` of the raster at `path`. Float bands are `rounded` or refused.
+    pub fn read(path: &Path, bands: &[Band], rounded: bool) -> Result<Self, Box<dyn Error>> {
+        let dataset = Dataset::open(path)?;
+        let (x_size, y_size) = dataset.raster_size();
+        let bands = bands
+            .iter()
+            .map(|band| -> Result<_, Box<dyn Error>> {
+                let (_, data) = read_band(&dataset.rasterband(band.index)?, rounded)
+                    .map_err(|e| e as Box<dyn Error>)?;
+                Ok(data)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TileRaster {
+            gt: dataset.geo_transform()?,
+            x_size,
+            y_size,
+            bands,
+        })
+    }
+
+    /// Returns the values of the bands at the point, or `None` for points outside the raster.
+    pub fn sample(&self, lat: f64, lon: f64) -> Vec<Option<i32>> {
+        // The tiles are north-up, so the rotation terms are zero.
+        let x = ((lon - self.gt[0]) / self.gt[1]).floor();
+        let y = ((lat - self.gt[3]) / self.gt[5]).floor();
+        let offset =
+            (x >= 0. && y >= 0. && (x as usize) < self.x_size && (y as usize) < self.y_size)
+                .then(|| y as usize * self.x_size + x as usize);
+        self.bands
+            .iter()
+            .map(|data| offset.map(|offset| data[offset]))
+            .collect()
+    }
+
+    /// Returns the size of the band values in bytes.
+    pub fn bytes(&self) -> usize {
+        self.bands.len() * self.x_size * self.y_size * std::mem::size_of::<i32>()
+    }
+}
+
+/// Returns the values of the bands at the points, for every band, or `None` for points outside
+/// the raster at `path`. Float bands are `rounded` or refused.
+pub fn sample_tile(
+    path: &Path,
+    bands: &[Band],
+    points: &[(f64, f64)],
+    rounded: bool,
+) -> Result<Vec<Vec<Option<i32>>>, Box<dyn Error>> {
+    let raster = TileRaster::read(path, bands, rounded)?;
+    let samples = points
+        .iter()
+        .map(|&(lat, lon)| raster.sample(lat, lon))
+        .collect::<Vec<_>>();
+    Ok((0..bands.len())
+        .map(|band| samples.iter().map(|values| values[band]).collect())
+        .collect())
+}
+
+/// Decoded tiles by their south-west corner, of which the least recently used are evicted when
+/// they take more than the capacity.
+pub struct TileCache {
+    /// Maximum size of the cached band values in bytes
+    capacity: usize,
+    /// Size of the cached band values in bytes
+    used: usize,
+    /// Counter of the lookups, the last lookup of every tile orders them by their use
+    clock: u64,
+    tiles: HashMap<(i16, i16), (u64, Arc<TileRaster>)>,
+}
+
+impl TileCache {
+    /// Returns an empty cache of tiles of at most `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        TileCache {
+            capacity,
+            used: 0,
+            clock: 0,
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Returns the cached tile with south-west corner `key`.
+    pub fn get(&mut self, key: (i16, i16)) -> Option<Arc<TileRaster>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.tiles.get_mut(&key).map(|(used, tile)| {
+            *used = clock;
+            tile.clone()
+        })
+    }
+
+    /// Returns the cached tile with south-west corner `key`, or the tile of `read`, which is
+    /// cached. Tiles that `read` does not find (`None`) are not cached.
+    pub async fn get_or_read<F, E>(
+        &mut self,
+        key: (i16, i16),
+        read: impl FnOnce() -> F,
+    ) -> Result<Option<Arc<TileRaster>>, E>
+    where
+        F: Future<Output = Result<Option<TileRaster>, E>>,
+    {
+        if let Some(tile) = self.get(key) {
+            return Ok(Some(tile));
+        }
+        Ok(read().await?.map(|tile| {
+            let tile = Arc::new(tile);
+            self.insert(key, tile.clone());
+            tile
+        }))
+    }
+
+    /// Caches `tile` with south-west corner `key`, evicting the least recently used tiles to make
+    /// room for it. Tiles that are larger than the capacity are not cached.
+    pub fn insert(&mut self, key: (i16, i16), tile: Arc<TileRaster>) {
+        let bytes = tile.bytes();
+        if bytes > self.capacity {
+            return;
+        }
+        while self.used + bytes > self.capacity {
+            let lru = self
+                .tiles
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(&key, _)| key)
+                .unwrap();
+            let (_, evicted) = self.tiles.remove(&lru).unwrap();
+            self.used -= evicted.bytes();
+        }
+        self.clock += 1;
+        self.used += bytes;
+        if let Some((_, replaced)) = self.tiles.insert(key, (self.clock, tile)) {
+            self.used -= replaced.bytes();
+        }
+    }
+}
+
+/// Writes the points with their sampled band values (or nulls) to a Parquet file at `path`.
+pub fn write_samples(
+    path: &Path,
+    bands: &[Band],
+    points: &[(f64, f64)],
+    values: &[Vec<Option<i32>>],
+) -> Result<(), Box<dyn Error>> {
+    let field = |name: &str, physical_type, repetition| {
+        Arc::new(
+            Type::primitive_type_builder(name, physical_type)
+                .with_repetition(repetition)
+                .build()
+                .unwrap(),
+        )
+    };
+    let mut fields = vec![
+        field("lat", basic::Type::DOUBLE, Repetition::REQUIRED),
+        field("lon", basic::Type::DOUBLE, Repetition::REQUIRED),
+    ];
+    fields.extend(
+        bands
+            .iter()
+            .map(|band| field(&band.name, basic::Type::INT32, Repetition::OPTIONAL)),
+    );
+    let schema = Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?,
+    );
+
+    let lat = points.iter().map(|(lat, _)| *lat).collect::<Vec<_>>();
+    let lon = points.iter().map(|(_, lon)| *lon).collect::<Vec<_>>();
+    let mut writer = SerializedFileWriter::new(
+        File::create(path)?,
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_writer = writer.next_row_group()?;
+    for coordinates in [&lat, &lon] {
+        if let Some(mut col_writer) = row_writer.next_column()? {
+            match col_writer.untyped() {
+                ColumnWriter::DoubleColumnWriter(c) => c.write_batch(coordinates, None, None)?,
+                _ => unreachable!(),
+            };
+            col_writer.close()?;
+        }
+    }
+    for values in values {
+        if let Some(mut col_writer) = row_writer.next_column()? {
+            let def_levels = values
+                .iter()
+                .map(|value| value.is_some() as i16)
+                .collect::<Vec<_>>();
+            let values = values.iter().flatten().copied().collect::<Vec<_>>();
+            match col_writer.untyped() {
+                ColumnWriter::Int32ColumnWriter(c) => {
+                    c.write_batch(&values, Some(&def_levels), None)?
+                }
+                _ => unreachable!(),
+            };
+            col_writer.close()?;
+        }
+    }
+    row_writer.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Samples the bands at the points of the `points` Parquet file and writes them to `output`,
+/// downloading only the tiles that contain the points.
+pub async fn sample_points(
+    source: &Source,
+    points: &Path,
+    output: &Path,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    bands: &[Band],
+    usage: &Arc<Usage>,
+) -> Result<(), Box<dyn Error>> {
+    let points = read_points(points)?;
+    event!(Level::INFO, "Sampling {} points", points.len());
+    // Group the points by their tile, which is named after its south-west corner.
+    let mut tiles = HashMap::<_, Vec<_>>::new();
+    for (idx, (lat, lon)) in points.iter().enumerate() {
+        tiles
+            .entry((lat.floor() as i16, lon.floor() as i16))
+            .or_default()
+            .push(idx);
+    }
+
+    let re = Regex::new(TILE_PATTERN)?;
+    let objects = source
+        .list(|key| {
+            tile_coordinate(&re, key).map_or(false, |coordinate| {
+                tiles.contains_key(&coordinate.degrees())
+            })
+        })
+        .await?;
+    event!(Level::INFO, "Downloading {} files", objects.len());
+
+    // Points in tiles that do not exist (e.g. ocean) have no values.
+    let mut values = vec![vec![None; points.len()]; bands.len()];
+    let rounded = source.dataset().rounded();
+    let mut paths = stream::iter(objects)
+        .map(|object| source.download(object, tif_dir.to_path_buf(), if_exists, usage.clone()))
+        .buffer_unordered(1);
+    while let Some(path) = paths.next().await {
+        let (path, _) = path?.map_err(|e| e as Box<dyn Error>)?;
+        let coordinate = tile_coordinate(&re, &path.to_string_lossy()).unwrap();
+        let idxs = &tiles[&coordinate.degrees()];
+        let tile_points = idxs.iter().map(|&idx| points[idx]).collect::<Vec<_>>();
+        let bands = bands.to_vec();
+        let tile_values = task::spawn_blocking(move || {
+            sample_tile(&path, &bands, &tile_points, rounded).map_err(|e| e.to_string())
+        })
+        .await??;
+        for (band, tile_values) in tile_values.into_iter().enumerate() {
+            for (&idx, value) in idxs.iter().zip(tile_values) {
+                values[band][idx] = value;
+            }
+        }
+    }
+
+    event!(Level::INFO, "Writing samples to `{}`", output.display());
+    write_samples(output, bands, &points, &values)
+}
+
+/// Returns the file name of the tile of `dataset` that contains the point at `lat` and `lon`,
+/// with the south-west corner of the tile as matched by `re`
+/// ([`TILE_PATTERN`](crate::TILE_PATTERN)). Points outside the coverage of the dataset have no
+/// tile.
+fn tile_of(re: &Regex, dataset: Dem, lat: f64, lon: f64) -> Option<(String, (i16, i16))> {
+    let (lat, lon) = (lat.floor(), lon.floor());
+    if !(-180. ..180.).contains(&lon) || !dataset.latitudes().contains(&(lat as i16)) {
+        return None;
+    }
+    let name = dataset.tile_name(lat as i16, lon as i16);
+    let coordinate = tile_coordinate(re, &name)?;
+    Some((name, coordinate.degrees()))
+}
+
+/// Looks up the band values at the points of the lines of `input`, `lat,lon` per line, and
+/// writes a line `lat,lon,value,...` per point to `output`, with empty values for points
+/// without data. The tile of a point is downloaded on its first lookup, without listing the
+/// source, and is kept in a cache of at most `cache_bytes` of band values, so that lookups of
+/// nearby points don't read the GeoTIFF file of their tile again.
+#[allow(clippy::too_many_arguments)]
+pub async fn lookup(
+    source: &Source,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl Write,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    bands: &[Band],
+    usage: &Arc<Usage>,
+    cache_bytes: u64,
+) -> Result<(), Box<dyn Error>> {
+    let re = Regex::new(TILE_PATTERN)?;
+    let dataset = source.dataset();
+    let rounded = dataset.rounded();
+    let mut cache = TileCache::new(usize::try_from(cache_bytes).unwrap_or(usize::MAX));
+    // Tiles that do not exist (e.g. of the ocean) are looked up once.
+    let mut missing = HashSet::new();
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        let point = line
+            .split_once(',')
+            .and_then(|(lat, lon)| Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?)));
+        let (lat, lon): (f64, f64) = match point {
+            Some(point) => point,
+            None => {
+                event!(Level::WARN, "Skipping `{}`, expected `lat,lon`", line);
+                continue;
+            }
+        };
+        let tile = match tile_of(&re, dataset, lat, lon) {
+            Some((name, key)) if !missing.contains(&key) => {
+                let tile = cache
+                    .get_or_read(key, || async {
+                        let object = match source.find(&name).await? {
+                            Some(object) => object,
+                            None => return Ok::<_, Box<dyn Error>>(None),
+                        };
+                        let (path, _) = source
+                            .download(object, tif_dir.to_path_buf(), if_exists, usage.clone())
+                            .await?
+                            .map_err(|e| e as Box<dyn Error>)?;
+                        let bands = bands.to_vec();
+                        let tile = task::spawn_blocking(move || {
+                            TileRaster::read(&path, &bands, rounded).map_err(|e| e.to_string())
+                        })
+                        .await??;
+                        Ok(Some(tile))
+                    })
+                    .await?;
+                if tile.is_none() {
+                    event!(Level::INFO, "No tile `{}`", name);
+                    missing.insert(key);
+                }
+                tile
+            }
+            _ => None,
+        };
+        // Points in tiles that do not exist have no values.
+        let values = match tile {
+            Some(tile) => tile.sample(lat, lon),
+            None => vec![None; bands.len()],
+        };
+        let values = values
+            .iter()
+            .map(|value| value.map_or_else(String::new, |value| value.to_string()))
+            .collect::<Vec<_>>();
+        writeln!(output, "{},{},{}", lat, lon, values.join(","))?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn tile_of_points() {
+        let re = Regex::new(TILE_PATTERN).unwrap();
+        assert_eq!(
+            tile_of(&re, Dem::Aw3d30, 52.37, 4.89),
+            Some(("ALPSMLC30_N052E004_DSM.tif".to_string(), (52, 4)))
+        );
+        assert_eq!(
+            tile_of(&re, Dem::Cop30, -33.86, -70.65),
+            Some((
+                "Copernicus_DSM_10_S34_00_W071_00_DEM.tif".to_string(),
+                (-34, -71)
+            ))
+        );
+        assert_eq!(
+            tile_of(&re, Dem::SrtmGl1, 0.5, -0.5),
+            Some(("N00W001.hgt".to_string(), (0, -1)))
+        );
+        // Outside the coverage of the dataset.
+        assert_eq!(tile_of(&re, Dem::SrtmGl1, 61., 4.), None);
+        assert_eq!(tile_of(&re, Dem::Aw3d30, 90., 4.), None);
+        assert_eq!(tile_of(&re, Dem::Aw3d30, 52., 180.), None);
+    }
+
+    /// A tile of 2 by 2 pixels with one band, of 16 bytes.
+    fn tile() -> TileRaster {
+        TileRaster {
+            gt: [4., 0.5, 0., 53., 0., -0.5],
+            x_size: 2,
+            y_size: 2,
+            bands: vec![vec![1, 2, 3, 4]],
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_reads_tiles_once() {
+        let reads = Cell::new(0);
+        let read = || async {
+            reads.set(reads.get() + 1);
+            Ok::<_, ()>(Some(tile()))
+        };
+        // Room for two tiles.
+        let mut cache = TileCache::new(32);
+        for key in [(52, 4), (52, 4), (53, 4), (52, 4), (53, 4)] {
+            let tile = cache.get_or_read(key, read).await.unwrap().unwrap();
+            assert_eq!(tile.sample(52.8, 4.7), [Some(2)]);
+        }
+        assert_eq!(reads.get(), 2);
+        // The third tile evicts the least recently used, which is read again.
+        cache.get_or_read((54, 4), read).await.unwrap();
+        cache.get_or_read((52, 4), read).await.unwrap();
+        assert_eq!(reads.get(), 4);
+        cache.get_or_read((54, 4), read).await.unwrap();
+        assert_eq!(reads.get(), 4);
+        // Tiles that are not found are not cached.
+        let missing = cache
+            .get_or_read((0, 0), || async { Ok::<_, ()>(None) })
+            .await;
+        assert!(missing.unwrap().is_none());
+        assert!(cache.get((0, 0)).is_none());
+    }
+}
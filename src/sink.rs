@@ -0,0 +1,277 @@
+use crate::{
+    convert::record_batches,
+    flight::{self, FlightClient, FlightTarget},
+    gpkg::{self, GeoPackage},
+    kafka::{self, KafkaProducer, KafkaTarget},
+    mask,
+    merge::MergedParquet,
+    Conversion, IfExists, RemoteFile, Source, Usage,
+};
+use arrow::record_batch::RecordBatch;
+use futures::{
+    future::{self, LocalBoxFuture},
+    stream, FutureExt, StreamExt,
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::task;
+use tracing::{event, Level};
+
+/// Output of the converted tiles, instead of Parquet files.
+#[derive(Clone, Debug)]
+pub enum Sink {
+    /// Arrow Flight endpoint, `flight://host:port`
+    Flight(FlightTarget),
+    /// Kafka topic, `kafka://host:port/topic`
+    Kafka(KafkaTarget),
+    /// GeoPackage file with a point layer
+    GeoPackage(PathBuf),
+    /// Single Parquet file with a row group per tile
+    Merge(PathBuf),
+}
+
+impl FromStr for Sink {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("://") {
+            Some(("flight", _)) => s.parse().map(Self::Flight),
+            Some(("kafka", _)) => s.parse().map(Self::Kafka),
+            _ => Err("expected `flight://host:port` or `kafka://host:port/topic`"),
+        }
+    }
+}
+
+impl Sink {
+    /// Connects to the sink, or creates its file.
+    pub async fn connect(
+        &self,
+        conversion: &Conversion,
+        if_exists: IfExists,
+    ) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+        Ok(match self {
+            Sink::Flight(target) => Box::new(target.connect().await?),
+            Sink::Kafka(target) => Box::new(target.connect()?),
+            Sink::GeoPackage(path) => Box::new(Blocking(Some(GeoPackage::create(
+                path, conversion, if_exists,
+            )?))),
+            Sink::Merge(path) => Box::new(Blocking(Some(MergedParquet::create(
+                path, conversion, if_exists,
+            )?))),
+        })
+    }
+}
+
+/// Output that receives the points of the converted tiles as Arrow record batches.
+///
+/// [`put_tiles`] converts the tiles and sends them to an output sink, so other outputs (e.g. Arrow
+/// IPC files or Delta tables) only implement this trait. The Parquet files of the tiles are
+/// written by [`ConvertJob`](crate::job::ConvertJob) instead.
+pub trait OutputSink {
+    /// Returns the maximum number of rows of the record batches that are sent.
+    fn batch_rows(&self) -> usize;
+
+    /// Sends the `batches` of `tile`. The batches are empty when all points of the tile were
+    /// clipped.
+    fn send<'a>(
+        &'a mut self,
+        tile: &'a str,
+        batches: Vec<RecordBatch>,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>>;
+
+    /// Returns true when `tile` was sent before, by an interrupted run that is resumed, so that
+    /// it is skipped.
+    fn sent(&self, _tile: &str) -> bool {
+        false
+    }
+
+    /// Completes the output after the last tile.
+    fn finish(
+        self: Box<Self>,
+    ) -> LocalBoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>> {
+        future::ready(Ok(())).boxed_local()
+    }
+}
+
+/// File output that blocks while it writes, so it is written on the blocking threads of the
+/// runtime (which also works on a current-thread runtime).
+struct Blocking<T>(Option<T>);
+
+impl<T: Send + 'static> Blocking<T> {
+    /// Runs `f` with the output on a blocking thread.
+    async fn run<R: Send + 'static>(
+        &mut self,
+        f: impl FnOnce(&mut T) -> R + Send + 'static,
+    ) -> Result<R, Box<dyn Error + Send + Sync>> {
+        // The output is only lost when a write panicked.
+        let mut output = self
+            .0
+            .take()
+            .ok_or("the output is unusable after a failed write")?;
+        let (output, result) = task::spawn_blocking(move || {
+            let result = f(&mut output);
+            (output, result)
+        })
+        .await?;
+        self.0 = Some(output);
+        Ok(result)
+    }
+}
+
+impl OutputSink for FlightClient {
+    fn batch_rows(&self) -> usize {
+        flight::BATCH_ROWS
+    }
+
+    fn send<'a>(
+        &'a mut self,
+        tile: &'a str,
+        batches: Vec<RecordBatch>,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        self.put(tile, batches).boxed_local()
+    }
+}
+
+impl OutputSink for KafkaProducer {
+    fn batch_rows(&self) -> usize {
+        kafka::BATCH_ROWS
+    }
+
+    fn send<'a>(
+        &'a mut self,
+        tile: &'a str,
+        batches: Vec<RecordBatch>,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        KafkaProducer::send(self, tile, batches).boxed_local()
+    }
+}
+
+impl OutputSink for Blocking<GeoPackage> {
+    fn batch_rows(&self) -> usize {
+        gpkg::BATCH_ROWS
+    }
+
+    fn send<'a>(
+        &'a mut self,
+        _tile: &'a str,
+        batches: Vec<RecordBatch>,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        async move { self.run(move |gpkg| gpkg.write(&batches)).await? }.boxed_local()
+    }
+}
+
+impl OutputSink for Blocking<MergedParquet> {
+    fn batch_rows(&self) -> usize {
+        self.0
+            .as_ref()
+            .map_or(usize::MAX, |merged| merged.batch_rows)
+    }
+
+    fn send<'a>(
+        &'a mut self,
+        tile: &'a str,
+        batches: Vec<RecordBatch>,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        let tile = tile.to_string();
+        async move {
+            self.run(move |merged| merged.write(&tile, &batches))
+                .await?
+        }
+        .boxed_local()
+    }
+
+    fn sent(&self, tile: &str) -> bool {
+        self.0
+            .as_ref()
+            .map_or(false, |merged| merged.completed(tile))
+    }
+
+    fn finish(
+        self: Box<Self>,
+    ) -> LocalBoxFuture<'static, Result<(), Box<dyn Error + Send + Sync>>> {
+        async move {
+            let merged = self
+                .0
+                .ok_or("the output is unusable after a failed write")?;
+            task::spawn_blocking(move || merged.finish()).await?
+        }
+        .boxed_local()
+    }
+}
+
+/// Downloads the `objects` and streams the points of every tile to the `sink`, skipping the
+/// tiles that it has from an interrupted run. No Parquet files are written.
+///
+/// The sink is finished after the last tile.
+///
+/// The GeoTIFF files that were downloaded are removed after their tile is sent when
+/// `remove_tif`. Returns an error when tiles failed, after sending the other tiles.
+#[allow(clippy::too_many_arguments)]
+pub async fn put_tiles(
+    source: &Source,
+    objects: Vec<RemoteFile>,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    conversion: Arc<Conversion>,
+    usage: &Arc<Usage>,
+    mut sink: Box<dyn OutputSink>,
+    remove_tif: bool,
+) -> Result<(), Box<dyn Error>> {
+    let batch_rows = sink.batch_rows();
+    let (sent, objects): (Vec<_>, Vec<_>) = objects
+        .into_iter()
+        .partition(|object| sink.sent(object.name()));
+    if !sent.is_empty() {
+        event!(Level::INFO, "Skipping {} tiles that were sent", sent.len());
+    }
+    event!(Level::INFO, "Downloading {} files", objects.len());
+    let mut failed = 0;
+    let mut downloads = stream::iter(objects)
+        .map(|object| {
+            let tile = object.name().to_string();
+            let local = matches!(object, RemoteFile::Local(_));
+            source
+                .download(object, tif_dir.to_path_buf(), if_exists, usage.clone())
+                .map(move |result| (tile, local, result))
+        })
+        .buffer_unordered(1);
+    while let Some((tile, local, download)) = downloads.next().await {
+        let result = async {
+            let (input_path, upstream) = download??;
+            let mask = conversion.mask();
+            let conversion = conversion.clone();
+            let path = input_path.clone();
+            let batches = task::spawn_blocking(move || {
+                record_batches(&path, &upstream, &conversion, batch_rows).map_err(|e| e.to_string())
+            })
+            .await??;
+            let rows = batches.iter().map(|batch| batch.num_rows()).sum::<usize>();
+            sink.send(&tile, batches).await?;
+            event!(Level::INFO, "Sent {} rows of {}", rows, tile);
+
+            usage
+                .converted_read
+                .fetch_add(input_path.metadata()?.len(), Ordering::Relaxed);
+            if remove_tif && !local {
+                mask::remove_tile(&input_path, mask)?;
+            }
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        }
+        .await;
+        if let Err(e) = result {
+            event!(Level::ERROR, "Sending {} failed: {}", tile, e);
+            failed += 1;
+        }
+    }
+
+    // The tiles that were sent are kept when other tiles failed.
+    sink.finish().await.map_err(|e| e as Box<dyn Error>)?;
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} tiles failed", failed).into()),
+    }
+}
@@ -0,0 +1,1189 @@
+use crate::{
+    bandwidth::{Schedule, Throttle},
+    dataset::Dataset,
+    http,
+    manifest::Upstream,
+    mirror::Mirror,
+    retry::{self, BreakerPolicy, CircuitBreaker, Retry},
+    IfExists, Usage, BUCKET, ENDPOINT,
+};
+use futures::{
+    future::{self, BoxFuture, Shared},
+    stream, FutureExt, StreamExt, TryStreamExt,
+};
+use md5::{Digest, Md5};
+use reqwest::{StatusCode, Url};
+use rusoto_core::{
+    credential::{AwsCredentials, StaticProvider},
+    HttpClient, Region, RusotoError,
+};
+use rusoto_s3::{
+    GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectOutput, HeadObjectRequest,
+    ListObjectsV2Output, ListObjectsV2Request, Object, S3Client, S3,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    task,
+};
+use tracing::{event, instrument, Level};
+
+/// Number of concurrent requests to probe the tiles on a mirror.
+const PROBES: usize = 16;
+
+/// A GeoTIFF file to download.
+#[derive(Clone)]
+pub enum RemoteFile {
+    /// Object key, size and version in the OpenTopography S3 bucket
+    S3(String, u64, Upstream),
+    /// File linked from an HTTP directory index, or on an HTTPS mirror
+    Http(Url),
+    /// Zip archive on a mirror, with the GeoTIFF file of the tile with the name
+    Archive(Url, String),
+    /// File that is already on disk, which is converted in place
+    Local(PathBuf),
+}
+
+impl RemoteFile {
+    /// Returns the file name of the tile.
+    pub fn name(&self) -> &str {
+        let path = match self {
+            RemoteFile::S3(key, ..) => key.as_str(),
+            RemoteFile::Http(url) => url.path(),
+            RemoteFile::Archive(_, name) => name.as_str(),
+            RemoteFile::Local(path) => path.to_str().unwrap_or_default(),
+        };
+        path.rsplit('/').next().unwrap_or(path)
+    }
+
+    /// Returns the size of the file, when it is known before downloading it.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            RemoteFile::S3(_, size, _) => Some(*size),
+            RemoteFile::Http(_) | RemoteFile::Archive(..) => None,
+            RemoteFile::Local(path) => path.metadata().ok().map(|m| m.len()),
+        }
+    }
+
+    /// Returns the version of the file, when it is listed with it.
+    pub fn upstream(&self) -> Option<&Upstream> {
+        match self {
+            RemoteFile::S3(.., upstream) => Some(upstream),
+            RemoteFile::Http(_) | RemoteFile::Archive(..) | RemoteFile::Local(_) => None,
+        }
+    }
+
+    /// Returns the object key, URL or path of the file.
+    pub fn location(&self) -> String {
+        match self {
+            RemoteFile::S3(key, ..) => key.clone(),
+            RemoteFile::Http(url) | RemoteFile::Archive(url, _) => url.to_string(),
+            RemoteFile::Local(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Order in which the S3 endpoints are tried for an operation.
+#[derive(Copy, Clone, Debug)]
+pub enum EndpointStrategy {
+    /// Always start at the first endpoint, the others are tried when it fails
+    Failover,
+    /// Start at the next endpoint in turn, to spread the load over the endpoints
+    RoundRobin,
+}
+
+impl FromStr for EndpointStrategy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failover" => Ok(Self::Failover),
+            "round-robin" => Ok(Self::RoundRobin),
+            _ => Err("expected one of: failover, round-robin"),
+        }
+    }
+}
+
+/// Source of the GeoTIFF files, the OpenTopography S3 bucket, an HTTP directory index or a
+/// [`Mirror`].
+pub struct Source {
+    /// S3 endpoints of the bucket with their client, mirrors of each other
+    endpoints: Vec<(String, S3Client)>,
+    strategy: EndpointStrategy,
+    /// Number of operations that started at an endpoint, to take turns
+    turns: Arc<AtomicUsize>,
+    http_client: reqwest::Client,
+    index_url: Option<Url>,
+    mirror: Option<Arc<dyn Mirror>>,
+    /// DEM of the GeoTIFF files
+    dataset: Dataset,
+    /// Release of the dataset in the bucket, the default release of the dataset by default
+    release: Option<String>,
+    throttle: Arc<Throttle>,
+    retry: Retry,
+    breaker_policy: BreakerPolicy,
+    /// Maximum number of keys per page of the S3 listing, 1000 by default
+    list_page_size: Option<usize>,
+    /// Maximum number of keys to list, all keys by default
+    max_keys: Option<usize>,
+    /// Circuit breakers by endpoint
+    breakers: Arc<Mutex<HashMap<String, Arc<CircuitBreaker>>>>,
+    /// Downloads in progress by their path
+    in_flight: Arc<Mutex<HashMap<PathBuf, InFlight>>>,
+}
+
+/// Shared result of a download in progress.
+type InFlight = Shared<BoxFuture<'static, Result<(PathBuf, Upstream), String>>>;
+
+impl Source {
+    /// Returns a source for the OpenTopography S3 bucket, or the HTTP directory index at
+    /// `index_url`.
+    pub fn new(index_url: Option<Url>) -> Result<Self, Box<dyn Error>> {
+        Ok(Source {
+            endpoints: vec![(ENDPOINT.to_string(), s3_client(ENDPOINT)?)],
+            strategy: EndpointStrategy::Failover,
+            turns: Arc::default(),
+            http_client: reqwest::Client::new(),
+            index_url,
+            mirror: None,
+            dataset: Dataset::Aw3d30,
+            release: None,
+            throttle: Arc::default(),
+            retry: Retry::default(),
+            breaker_policy: BreakerPolicy::default(),
+            list_page_size: None,
+            max_keys: None,
+            breakers: Arc::default(),
+            in_flight: Arc::default(),
+        })
+    }
+
+    /// Downloads the GeoTIFF files of `dataset` instead of AW3D30.
+    pub fn with_dataset(mut self, dataset: Dataset) -> Self {
+        self.dataset = dataset;
+        self
+    }
+
+    /// Returns the DEM of the GeoTIFF files.
+    pub fn dataset(&self) -> Dataset {
+        self.dataset
+    }
+
+    /// Downloads the GeoTIFF files of `release` of the dataset, the name of a dir next to the
+    /// default release in the bucket (e.g. `AW3D30_global` in `AW3D30/`).
+    pub fn with_release(mut self, release: String) -> Self {
+        self.release = Some(release);
+        self
+    }
+
+    /// Returns the prefix of the GeoTIFF files of the release in the bucket.
+    fn prefix(&self) -> String {
+        match &self.release {
+            Some(release) => format!("{}{}/", self.dataset.releases(), release),
+            None => self.dataset.prefix().to_string(),
+        }
+    }
+
+    /// Returns the name of the latest release of the dataset in the bucket: the release with
+    /// the most recently modified first file, as the names of the releases do not follow a
+    /// scheme that orders them.
+    pub async fn latest_release(&self) -> Result<String, Box<dyn Error>> {
+        let (endpoint, client, breaker) = self.endpoint_order().remove(0);
+        event!(Level::INFO, "Listing the releases on `{}`", endpoint);
+        let list = |prefix: String, delimiter: Option<String>, max_keys: Option<i64>| {
+            let client = &client;
+            let breaker = &breaker;
+            async move {
+                let req = ListObjectsV2Request {
+                    bucket: BUCKET.to_string(),
+                    prefix: Some(prefix),
+                    delimiter,
+                    max_keys,
+                    ..Default::default()
+                };
+                self.retry
+                    .run(
+                        breaker,
+                        || client.list_objects_v2(req.clone()),
+                        retry::transient,
+                    )
+                    .await
+            }
+        };
+        let releases = list(
+            self.dataset.releases().to_string(),
+            Some("/".to_string()),
+            None,
+        )
+        .await?
+        .common_prefixes
+        .unwrap_or_default();
+        let mut latest = None;
+        for prefix in releases.into_iter().filter_map(|prefix| prefix.prefix) {
+            let modified = list(prefix.clone(), None, Some(1))
+                .await?
+                .contents
+                .and_then(|contents| contents.into_iter().next())
+                .and_then(|object| object.last_modified);
+            // The times are ISO 8601 in UTC, which sort like strings.
+            if let Some(modified) = modified {
+                if latest
+                    .as_ref()
+                    .map_or(true, |(latest, _)| &modified > latest)
+                {
+                    latest = Some((modified, prefix));
+                }
+            }
+        }
+        let (_, prefix) = latest.ok_or("no releases of the dataset in the bucket")?;
+        Ok(prefix[self.dataset.releases().len()..]
+            .trim_end_matches('/')
+            .to_string())
+    }
+
+    /// Uses the S3 `endpoints`, mirrors of the bucket of the OpenTopography storage server,
+    /// instead of that server. The endpoints are tried in the order of `strategy`: an operation
+    /// that fails on an endpoint, or finds it down, fails over to the next one. Endpoints that
+    /// are down are tried last.
+    pub fn with_endpoints(
+        mut self,
+        endpoints: &[String],
+        strategy: EndpointStrategy,
+    ) -> Result<Self, Box<dyn Error>> {
+        if endpoints.is_empty() {
+            return Err("expected at least one endpoint".into());
+        }
+        self.endpoints = endpoints
+            .iter()
+            .map(|endpoint| Ok((endpoint.clone(), s3_client(endpoint)?)))
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        self.strategy = strategy;
+        Ok(self)
+    }
+
+    /// Downloads the files from `mirror` instead of the OpenTopography S3 bucket.
+    pub fn with_mirror(mut self, mirror: Arc<dyn Mirror>) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// Retries transient failures of S3 operations according to `retry`.
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Pauses all operations on an endpoint that is down according to `policy`.
+    pub fn with_circuit_breaker(mut self, policy: BreakerPolicy) -> Self {
+        self.breaker_policy = policy;
+        self
+    }
+
+    /// Requests pages of at most `list_page_size` keys when listing the S3 bucket. The server
+    /// may return fewer keys per page (at most 1000).
+    pub fn with_list_page_size(mut self, list_page_size: usize) -> Self {
+        self.list_page_size = Some(list_page_size);
+        self
+    }
+
+    /// Stops listing the S3 bucket after `max_keys` keys, e.g. to try a run on the first tiles.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// Returns the circuit breaker of `endpoint`.
+    fn breaker(&self, endpoint: &str) -> Arc<CircuitBreaker> {
+        self.breakers
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(endpoint, self.breaker_policy)))
+            .clone()
+    }
+
+    /// Returns the endpoints to try for an operation in order, with their client and circuit
+    /// breaker.
+    fn endpoint_order(&self) -> Vec<(String, S3Client, Arc<CircuitBreaker>)> {
+        let start = match self.strategy {
+            EndpointStrategy::Failover => 0,
+            EndpointStrategy::RoundRobin => self.turns.fetch_add(1, Ordering::Relaxed),
+        };
+        let mut endpoints = self
+            .endpoints
+            .iter()
+            .cycle()
+            .skip(start % self.endpoints.len())
+            .take(self.endpoints.len())
+            .map(|(endpoint, client)| (endpoint.clone(), client.clone(), self.breaker(endpoint)))
+            .collect::<Vec<_>>();
+        // The sort is stable, so the endpoints that are up keep their order.
+        endpoints.sort_by_key(|(.., breaker)| breaker.is_open());
+        endpoints
+    }
+
+    /// Limits the bandwidth of the downloads according to `schedule`.
+    pub fn with_bandwidth_schedule(mut self, schedule: Schedule) -> Self {
+        self.throttle = Arc::new(Throttle::new(schedule));
+        self
+    }
+
+    /// Lists the files with a key for which `selected` returns true.
+    pub async fn list(
+        &self,
+        selected: impl Fn(&str) -> bool,
+    ) -> Result<Vec<RemoteFile>, Box<dyn Error>> {
+        let mut objects = Vec::default();
+        if let Some(index_url) = &self.index_url {
+            event!(Level::INFO, "Listing `{}`", index_url);
+            objects.extend(
+                http::list_index(&self.http_client, index_url)
+                    .await?
+                    .into_iter()
+                    .filter(|url| selected(url.path()))
+                    .map(RemoteFile::Http),
+            );
+        } else if let Some(mirror) = &self.mirror {
+            objects = self.list_mirror(mirror.as_ref(), selected).await?;
+        } else {
+            event!(Level::INFO, "Connecting to OpenTopology server");
+            // The listing starts over on the next endpoint, continuation tokens are not shared.
+            let endpoints = self.endpoint_order();
+            let last = endpoints.len() - 1;
+            for (index, (endpoint, client, breaker)) in endpoints.into_iter().enumerate() {
+                let retry = Retry {
+                    fail_over: index < last,
+                    ..self.retry
+                };
+                match self.list_s3(&client, &breaker, retry, &selected).await {
+                    Ok(listed) => {
+                        objects = listed;
+                        break;
+                    }
+                    Err(e) if index < last => {
+                        event!(
+                            Level::WARN,
+                            "Listing `{}` failed, failing over: {}",
+                            endpoint,
+                            e
+                        )
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Lists the files with a key for which `selected` returns true from the bucket at an S3
+    /// endpoint with `client`, retried according to `retry`.
+    async fn list_s3(
+        &self,
+        client: &S3Client,
+        breaker: &CircuitBreaker,
+        retry: Retry,
+        selected: impl Fn(&str) -> bool,
+    ) -> Result<Vec<RemoteFile>, Box<dyn Error>> {
+        let mut objects = Vec::default();
+        // List all objects of the dataset.
+        let mut req = ListObjectsV2Request {
+            bucket: BUCKET.to_string(),
+            prefix: Some(self.prefix()),
+            ..Default::default()
+        };
+        let (mut pages, mut keys) = (0, 0);
+        loop {
+            // The last page only has the remaining keys.
+            let page_size = match (self.list_page_size, self.max_keys) {
+                (page_size, Some(max_keys)) => Some(page_size.unwrap_or(1000).min(max_keys - keys)),
+                (page_size, None) => page_size,
+            };
+            req.max_keys = page_size.map(|page_size| page_size as i64);
+            let ListObjectsV2Output {
+                contents,
+                next_continuation_token,
+                is_truncated,
+                ..
+            } = retry
+                .run(
+                    breaker,
+                    || client.list_objects_v2(req.clone()),
+                    retry::transient,
+                )
+                .await?;
+
+            // Collect all objects keys.
+            pages += 1;
+            if let Some(contents) = contents {
+                keys += contents.len();
+                objects.extend(
+                    contents
+                        .into_iter()
+                        .filter(|Object { key, .. }| selected(key.as_deref().unwrap()))
+                        .map(
+                            |Object {
+                                 key,
+                                 size,
+                                 e_tag,
+                                 last_modified,
+                                 ..
+                             }| {
+                                let (key, size) = (key.unwrap(), size.unwrap() as u64);
+                                let upstream = Upstream {
+                                    etag: e_tag,
+                                    last_modified,
+                                    size: Some(size),
+                                    key: Some(key.clone()),
+                                };
+                                RemoteFile::S3(key, size, upstream)
+                            },
+                        ),
+                )
+            }
+
+            event!(
+                Level::INFO,
+                "Listed page {}: {} keys, {} selected",
+                pages,
+                keys,
+                objects.len()
+            );
+
+            // Fetch next object when needed.
+            req.continuation_token = next_continuation_token;
+            if let Some(false) = is_truncated {
+                event!(Level::INFO, "Listed all objects");
+                break;
+            }
+            if self.max_keys.map_or(false, |max_keys| keys >= max_keys) {
+                event!(Level::INFO, "Listed the maximum of {} keys", keys);
+                break;
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Lists the files of the tiles for which `selected` returns true on `mirror`. The files are
+    /// looked up in the directory index pages of the dirs with the selected tiles, and only the
+    /// tiles of dirs without an index page are probed. Tiles that are missing (e.g. of the ocean)
+    /// are skipped.
+    async fn list_mirror(
+        &self,
+        mirror: &dyn Mirror,
+        selected: impl Fn(&str) -> bool,
+    ) -> Result<Vec<RemoteFile>, Box<dyn Error>> {
+        let mut dirs = BTreeMap::<Url, Vec<(String, Url)>>::new();
+        for name in self.dataset.tile_names().filter(|name| selected(name)) {
+            let url = mirror.url(&name).map_err(|e| e as Box<dyn Error>)?;
+            dirs.entry(url.join("./")?).or_default().push((name, url));
+        }
+        let listings = stream::iter(dirs)
+            .map(|(dir, tiles)| async move {
+                let links = http::list_index(&self.http_client, &dir)
+                    .await
+                    .map(|links| links.into_iter().collect::<HashSet<_>>());
+                (tiles, links.ok())
+            })
+            .buffered(PROBES)
+            .collect::<Vec<_>>()
+            .await;
+        let (mut listed, mut names) = (Vec::new(), Vec::new());
+        for (tiles, links) in listings {
+            match links {
+                // An index page without any of the tiles is not taken for a listing of the dir.
+                Some(links) if tiles.iter().any(|(_, url)| links.contains(url)) => listed.extend(
+                    tiles
+                        .into_iter()
+                        .filter(|(_, url)| links.contains(url))
+                        .map(|(name, url)| match mirror.archived() {
+                            true => RemoteFile::Archive(url, name),
+                            false => RemoteFile::Http(url),
+                        }),
+                ),
+                _ => names.extend(tiles.into_iter().map(|(name, _)| name)),
+            }
+        }
+        event!(
+            Level::INFO,
+            "Listed {} tiles in the index pages of `{}`",
+            listed.len(),
+            mirror
+        );
+        event!(Level::INFO, "Probing {} tiles on `{}`", names.len(), mirror);
+        let objects = stream::iter(names)
+            .map(|name| self.probe_mirror(mirror, name))
+            .buffered(PROBES)
+            .try_filter_map(|object| future::ready(Ok(object)))
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| e as Box<dyn Error>)?;
+        let mut objects = listed.into_iter().chain(objects).collect::<Vec<_>>();
+        objects.sort_by(|a, b| a.name().cmp(b.name()));
+        event!(Level::INFO, "Found {} tiles", objects.len());
+        Ok(objects)
+    }
+
+    /// Returns the file of the tile `name` on `mirror`, `None` when it is missing.
+    async fn probe_mirror(
+        &self,
+        mirror: &dyn Mirror,
+        name: String,
+    ) -> Result<Option<RemoteFile>, Box<dyn Error + Send + Sync>> {
+        let url = mirror.url(&name)?;
+        if !self.probe(&url).await? {
+            return Ok(None);
+        }
+        Ok(Some(match mirror.archived() {
+            true => RemoteFile::Archive(url, name),
+            false => RemoteFile::Http(url),
+        }))
+    }
+
+    /// Returns true when the file at `url` exists, from the response to a HEAD request.
+    async fn probe(&self, url: &Url) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let breaker = self.breaker(url.host_str().unwrap_or_default());
+        let response = self
+            .retry
+            .run(
+                &breaker,
+                || self.http_client.head(url.clone()).send(),
+                |e| http::transient(e),
+            )
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
+    }
+
+    /// Looks up the file of the tile `name` (see [`Dataset::tile_name`]) without listing the
+    /// source, `None` when the tile does not exist (e.g. of the ocean).
+    pub async fn find(&self, name: &str) -> Result<Option<RemoteFile>, Box<dyn Error>> {
+        // The files of an HTTP directory index are taken to be next to the index page.
+        if let Some(index_url) = &self.index_url {
+            let url = index_url.join(name)?;
+            return match self.probe(&url).await.map_err(|e| e as Box<dyn Error>)? {
+                true => Ok(Some(RemoteFile::Http(url))),
+                false => Ok(None),
+            };
+        }
+        if let Some(mirror) = &self.mirror {
+            return self
+                .probe_mirror(mirror.as_ref(), name.to_string())
+                .await
+                .map_err(|e| e as Box<dyn Error>);
+        }
+        let key = format!("{}{}", self.prefix(), name);
+        let request = HeadObjectRequest {
+            bucket: BUCKET.to_string(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let endpoints = self.endpoint_order();
+        let last = endpoints.len() - 1;
+        for (index, (endpoint, client, breaker)) in endpoints.into_iter().enumerate() {
+            let retry = Retry {
+                fail_over: index < last,
+                ..self.retry
+            };
+            match retry
+                .run(
+                    &breaker,
+                    || client.head_object(request.clone()),
+                    retry::transient,
+                )
+                .await
+            {
+                Ok(HeadObjectOutput {
+                    content_length,
+                    e_tag,
+                    last_modified,
+                    ..
+                }) => {
+                    let size = content_length.unwrap_or_default() as u64;
+                    let upstream = Upstream {
+                        etag: e_tag,
+                        last_modified,
+                        size: Some(size),
+                        key: Some(key.clone()),
+                    };
+                    return Ok(Some(RemoteFile::S3(key, size, upstream)));
+                }
+                Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => return Ok(None),
+                // HEAD responses have no body, so a missing object is an unknown 404.
+                Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => {
+                    return Ok(None)
+                }
+                Err(e) if index < last => event!(
+                    Level::WARN,
+                    "Looking up `{}` at `{}` failed, failing over: {}",
+                    key,
+                    endpoint,
+                    e
+                ),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        unreachable!("there is at least one endpoint")
+    }
+
+    /// Spawns a task that downloads a file to `tif_dir`, returning its path and upstream version.
+    ///
+    /// Concurrent downloads of a file to the same path are deduplicated, the file is downloaded
+    /// once and all tasks return its result.
+    pub fn download(
+        &self,
+        object: RemoteFile,
+        tif_dir: PathBuf,
+        if_exists: IfExists,
+        usage: Arc<Usage>,
+    ) -> task::JoinHandle<Result<(PathBuf, Upstream), Box<dyn Error + Send + Sync>>> {
+        let path = tif_dir.join(object.name());
+        let download = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(path.clone())
+            .or_insert_with(|| {
+                let download = self.spawn_download(object, tif_dir, if_exists, usage);
+                let in_flight = self.in_flight.clone();
+                async move {
+                    let result = match download.await {
+                        Ok(result) => result.map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    in_flight.lock().unwrap().remove(&path);
+                    result
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+        task::spawn(async move { download.await.map_err(Into::into) })
+    }
+
+    /// Spawns the task that downloads a file, see [`Source::download`].
+    fn spawn_download(
+        &self,
+        object: RemoteFile,
+        tif_dir: PathBuf,
+        if_exists: IfExists,
+        usage: Arc<Usage>,
+    ) -> task::JoinHandle<Result<(PathBuf, Upstream), Box<dyn Error + Send + Sync>>> {
+        let http_client = self.http_client.clone();
+        let throttle = self.throttle.clone();
+        let retry = self.retry;
+        let (endpoints, breaker) = match &object {
+            RemoteFile::S3(..) => (self.endpoint_order(), None),
+            RemoteFile::Http(url) | RemoteFile::Archive(url, _) => (
+                Vec::new(),
+                Some(self.breaker(url.host_str().unwrap_or_default())),
+            ),
+            RemoteFile::Local(_) => (Vec::new(), None),
+        };
+        task::spawn(async move {
+            match object {
+                // Failed downloads fail over to the next endpoint, until the last one.
+                RemoteFile::S3(key, size, upstream) => {
+                    let last = endpoints.len() - 1;
+                    for (index, (endpoint, client, breaker)) in endpoints.into_iter().enumerate() {
+                        let retry = Retry {
+                            fail_over: index < last,
+                            ..retry
+                        };
+                        match download_object(
+                            client,
+                            key.clone(),
+                            size,
+                            upstream.etag.clone(),
+                            tif_dir.clone(),
+                            if_exists,
+                            usage.clone(),
+                            throttle.clone(),
+                            retry,
+                            breaker,
+                        )
+                        .await
+                        {
+                            Ok(path) => return Ok((path, upstream)),
+                            Err(e) if index < last => event!(
+                                Level::WARN,
+                                "Downloading `{}` from `{}` failed, failing over: {}",
+                                key,
+                                endpoint,
+                                e
+                            ),
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    unreachable!("there is at least one endpoint")
+                }
+                // Downloads from an index are not retried, except when the endpoint is down.
+                RemoteFile::Http(url) => {
+                    let once = Retry {
+                        max_attempts: 1,
+                        ..retry
+                    };
+                    let download = || {
+                        http::download(
+                            http_client.clone(),
+                            url.clone(),
+                            tif_dir.clone(),
+                            if_exists,
+                            usage.clone(),
+                            throttle.clone(),
+                        )
+                    };
+                    once.run(&breaker.unwrap(), download, |e| http::transient(e.as_ref()))
+                        .await
+                }
+                // Archives are extracted once they are downloaded, and removed.
+                RemoteFile::Archive(url, name) => {
+                    let path = tif_dir.join(&name);
+                    // Verify existing files by the checksum of the archive member they were
+                    // extracted from, so that corrupted files are extracted again.
+                    let valid = match if_exists {
+                        IfExists::Verify if path.exists() => {
+                            let path = path.clone();
+                            task::spawn_blocking(move || extracted(&path))
+                                .await
+                                .unwrap_or(false)
+                        }
+                        _ => false,
+                    };
+                    if if_exists.keep(&path, || valid)? {
+                        event!(Level::WARN, "Skipping download. File already exists.");
+                        return Ok((path, Upstream::default()));
+                    }
+                    let once = Retry {
+                        max_attempts: 1,
+                        ..retry
+                    };
+                    let download = || {
+                        http::download(
+                            http_client.clone(),
+                            url.clone(),
+                            tif_dir.clone(),
+                            IfExists::Overwrite,
+                            usage.clone(),
+                            throttle.clone(),
+                        )
+                    };
+                    let (archive, upstream) = once
+                        .run(&breaker.unwrap(), download, |e| http::transient(e.as_ref()))
+                        .await?;
+                    let path =
+                        task::spawn_blocking(move || extract(&archive, &name, &tif_dir)).await??;
+                    Ok((path, upstream))
+                }
+                RemoteFile::Local(path) => Ok((path, Upstream::default())),
+            }
+        })
+    }
+
+    /// Downloads the files to `tif_dir` without converting them.
+    ///
+    /// Returns an error when files failed, after downloading the other files.
+    pub async fn download_all(
+        &self,
+        objects: Vec<RemoteFile>,
+        tif_dir: &Path,
+        if_exists: IfExists,
+        usage: &Arc<Usage>,
+    ) -> Result<(), Box<dyn Error>> {
+        event!(Level::INFO, "Downloading {} files", objects.len());
+        let failed = stream::iter(objects)
+            .map(|object| {
+                let tile = object.name().to_string();
+                self.download(object, tif_dir.to_path_buf(), if_exists, usage.clone())
+                    .map(|result| (tile, result))
+            })
+            .buffer_unordered(1)
+            .filter(|(tile, download)| {
+                let error = match download {
+                    Ok(Ok(_)) => return future::ready(false),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                event!(Level::ERROR, "Downloading {} failed: {}", tile, error);
+                future::ready(true)
+            })
+            .count()
+            .await;
+        match failed {
+            0 => Ok(()),
+            failed => Err(format!("{} tiles failed", failed).into()),
+        }
+    }
+}
+
+/// Extracts the GeoTIFF file `name` from the zip archive at `archive` to `tif_dir`, and removes
+/// the archive. The CRC-32 and size of the archive member are recorded in a `.crc32` file next
+/// to the GeoTIFF file, to verify it with [`extracted`].
+fn extract(
+    archive: &Path,
+    name: &str,
+    tif_dir: &Path,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(archive)?)?;
+    let index = (0..zip.len())
+        .find(|&index| {
+            zip.by_index(index)
+                .map_or(false, |file| file.name().rsplit('/').next() == Some(name))
+        })
+        .ok_or_else(|| format!("`{}` is not in `{}`", name, archive.display()))?;
+    // Extract to a partial file, so that an interrupted extraction is not taken for the tile.
+    let part_path = tif_dir.join(format!("{}.part", name));
+    let mut file = zip.by_index(index)?;
+    let (crc32, size) = (file.crc32(), file.size());
+    io::copy(&mut file, &mut fs::File::create(&part_path)?)?;
+    let path = tif_dir.join(name);
+    fs::rename(part_path, &path)?;
+    fs::write(checksum_path(&path), format!("{:08x} {}\n", crc32, size))?;
+    fs::remove_file(archive)?;
+    Ok(path)
+}
+
+/// Returns the path of the file with the checksum of the GeoTIFF file at `path` that was
+/// extracted from an archive.
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".crc32");
+    path.with_file_name(name)
+}
+
+/// Returns true when the GeoTIFF file at `path` matches the CRC-32 and size of the archive
+/// member it was extracted from, as recorded by [`extract`].
+fn extracted(path: &Path) -> bool {
+    let valid = || -> io::Result<bool> {
+        let checksum = fs::read_to_string(checksum_path(path))?;
+        let (crc32, size) = match checksum.trim().split_once(' ') {
+            Some((crc32, size)) => (crc32.to_string(), size.parse::<u64>().ok()),
+            None => return Ok(false),
+        };
+        if Some(path.metadata()?.len()) != size {
+            return Ok(false);
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        let mut file = fs::File::open(path)?;
+        let mut buf = vec![0; 1 << 16];
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                len => hasher.update(&buf[..len]),
+            }
+        }
+        Ok(format!("{:08x}", hasher.finalize()) == crc32)
+    };
+    valid().unwrap_or(false)
+}
+
+/// Returns a client for the S3 server at `endpoint`, with anonymous access.
+fn s3_client(endpoint: &str) -> Result<S3Client, Box<dyn Error>> {
+    Ok(S3Client::new_with(
+        HttpClient::new()?,
+        StaticProvider::from(AwsCredentials::default()),
+        Region::Custom {
+            name: String::new(),
+            endpoint: endpoint.to_string(),
+        },
+    ))
+}
+
+/// Returns the MD5 digest in the ETag of an S3 object, if it is one. The ETag of an object
+/// that was uploaded in multiple parts is not a digest of its content.
+pub fn etag_md5(etag: &str) -> Option<String> {
+    let etag = etag.trim_matches('"');
+    (etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| etag.to_ascii_lowercase())
+}
+
+/// Returns the hex encoded MD5 digest of the file at `path`.
+fn md5_digest(path: &Path) -> io::Result<String> {
+    let mut hasher = Md5::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Downloads the object with `key`, `size` and `etag` from the OpenTopography S3 bucket (or a
+/// mirror) with `client` to `tif_dir`, limited by `throttle`.
+///
+/// The object is downloaded to a partial file named after its ETag, which is renamed once it
+/// is complete, so that an interrupted download is resumed with a ranged request from the
+/// partial file. Transient failures and downloads that do not match the MD5 digest in the ETag
+/// are retried according to `retry`, and pause while the `breaker` of the endpoint is open.
+#[allow(clippy::too_many_arguments)]
+#[instrument(
+    err,
+    skip(
+        client, size, etag, tif_dir, if_exists, usage, throttle, retry, breaker
+    )
+)]
+pub async fn download_object(
+    client: S3Client,
+    key: String,
+    size: u64,
+    etag: Option<String>,
+    tif_dir: PathBuf,
+    if_exists: IfExists,
+    usage: Arc<Usage>,
+    throttle: Arc<Throttle>,
+    retry: Retry,
+    breaker: Arc<CircuitBreaker>,
+) -> Result<PathBuf, RusotoError<GetObjectError>> {
+    let file_name = Path::new(&key).file_name().unwrap().to_string_lossy();
+    let path = tif_dir.join(file_name.as_ref());
+    let md5 = etag.as_deref().and_then(etag_md5);
+    // Verify existing files by their size and digest, so that corrupted files are replaced.
+    let sized = || path.metadata().map_or(false, |m| m.len() == size);
+    let digest = if md5.is_some() && matches!(if_exists, IfExists::Verify) && sized() {
+        let path = path.clone();
+        task::spawn_blocking(move || md5_digest(&path).ok())
+            .await
+            .ok()
+            .flatten()
+    } else {
+        None
+    };
+    let valid = || {
+        sized()
+            && md5
+                .as_ref()
+                .map_or(true, |md5| digest.as_ref() == Some(md5))
+    };
+    if if_exists.keep(&path, valid)? {
+        event!(Level::WARN, "Skipping download. File already exists.");
+        return Ok(path);
+    }
+
+    // Partial files of other versions of the object are never resumed.
+    let part_path = etag.as_ref().map_or_else(
+        || tif_dir.join(format!("{}.part", file_name)),
+        |etag| tif_dir.join(format!("{}.{}.part", file_name, etag.trim_matches('"'))),
+    );
+    let request = |range: Option<String>| GetObjectRequest {
+        bucket: BUCKET.to_string(),
+        key: key.clone(),
+        // Guard the remainder of a resumed download against changes of the object.
+        if_match: range.as_ref().and(etag.clone()),
+        range,
+        ..Default::default()
+    };
+    let download = || async {
+        let mut hasher = Md5::new();
+        let mut offset = match part_path.metadata() {
+            Ok(metadata) if etag.is_some() && metadata.len() <= size => metadata.len(),
+            _ => 0,
+        };
+        let mut output = None;
+        if offset > 0 {
+            event!(Level::INFO, "Resuming download at {} bytes", offset);
+            match client
+                .get_object(request(Some(format!("bytes={}-", offset))))
+                .await
+            {
+                Ok(response) => output = Some(response),
+                // The partial file is complete.
+                Err(RusotoError::Unknown(response))
+                    if response.status.as_u16() == 416 && offset == size => {}
+                // The object changed since the partial download, start over.
+                Err(RusotoError::Unknown(response)) if response.status.as_u16() == 412 => {
+                    offset = 0
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let mut file = if offset > 0 {
+            if md5.is_some() {
+                let part_path = part_path.clone();
+                hasher = task::spawn_blocking(move || -> io::Result<_> {
+                    io::copy(&mut fs::File::open(&part_path)?.take(offset), &mut hasher)?;
+                    Ok(hasher)
+                })
+                .await
+                .map_err(io::Error::from)??;
+            }
+            OpenOptions::new().append(true).open(&part_path).await?
+        } else {
+            output = Some(client.get_object(request(None)).await?);
+            File::create(&part_path).await?
+        };
+        if let Some(mut bytes) = output.and_then(|output| output.body) {
+            while let Some(chunk) = bytes.next().await {
+                #[cfg(feature = "chaos")]
+                if crate::chaos::fail(crate::chaos::Stage::Download) {
+                    return Err(RusotoError::HttpDispatch(
+                        rusoto_core::request::HttpDispatchError::new(
+                            "injected failure".to_string(),
+                        ),
+                    ));
+                }
+                let chunk = chunk?;
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+                usage
+                    .downloaded
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                throttle.consume(chunk.len() as u64).await;
+            }
+        }
+        file.flush().await?;
+        // A mismatch is likely a corrupted transfer, which is retried from scratch.
+        let digest = format!("{:x}", hasher.finalize());
+        if let Some(md5) = md5.as_ref().filter(|&md5| md5 != &digest) {
+            fs::remove_file(&part_path)?;
+            return Ok(Err(format!(
+                "MD5 digest {} does not match ETag {}",
+                digest, md5
+            )));
+        }
+        fs::rename(&part_path, &path)?;
+        Ok::<_, RusotoError<GetObjectError>>(Ok(()))
+    };
+    // The endpoint served the object, so a digest mismatch is retried without counting as a
+    // failure of the endpoint. The tile fails (and is retried by a later run) when the digest
+    // keeps mismatching.
+    let mut attempt = 1;
+    loop {
+        match retry.run(&breaker, &download, retry::transient).await? {
+            Ok(()) => return Ok(path),
+            Err(mismatch) if attempt < retry.max_attempts => {
+                let delay = retry.delay(attempt);
+                event!(
+                    Level::WARN,
+                    "Attempt {} of {} failed, retrying in {:.1?}: {}",
+                    attempt,
+                    retry.max_attempts,
+                    delay,
+                    mismatch
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(mismatch) => return Err(RusotoError::Validation(mismatch)),
+        }
+    }
+}
+
+/// Returns the raster files (GeoTIFF `*.tif` and SRTM `*.hgt`) in `dir`, including its
+/// subdirectories when `recursive`.
+pub fn list_local(dir: &Path, recursive: bool) -> io::Result<Vec<RemoteFile>> {
+    let mut objects = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                objects.extend(list_local(&path, recursive)?);
+            }
+        } else if path
+            .extension()
+            .map_or(false, |ext| ext == "tif" || ext == "hgt")
+        {
+            objects.push(RemoteFile::Local(path));
+        }
+    }
+    Ok(objects)
+}
+
+/// Returns the tiles of shard `index` of `count` shards.
+///
+/// The tiles are sorted by name and assigned round-robin, so that workers with the same tile
+/// list process disjoint subsets without coordination.
+pub fn shard(
+    mut objects: Vec<RemoteFile>,
+    index: usize,
+    count: usize,
+) -> Result<Vec<RemoteFile>, Box<dyn Error>> {
+    if index >= count {
+        return Err("shard index must be less than the shard count".into());
+    }
+    objects.sort_by(|a, b| a.name().cmp(b.name()));
+    let objects = objects
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| idx % count == index)
+        .map(|(_, object)| object)
+        .collect::<Vec<_>>();
+    if count > 1 {
+        event!(
+            Level::INFO,
+            "Processing {} files of shard {}/{}",
+            objects.len(),
+            index,
+            count
+        );
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(objects: &[RemoteFile]) -> Vec<&str> {
+        objects.iter().map(RemoteFile::name).collect()
+    }
+
+    #[test]
+    fn shards_are_disjoint() {
+        let objects = ["c.tif", "a.tif", "e.tif", "b.tif", "d.tif"]
+            .iter()
+            .map(|name| RemoteFile::Local(PathBuf::from(name)))
+            .collect::<Vec<_>>();
+        let shards = (0..2)
+            .map(|index| shard(objects.clone(), index, 2).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names(&shards[0]), ["a.tif", "c.tif", "e.tif"]);
+        assert_eq!(names(&shards[1]), ["b.tif", "d.tif"]);
+        // The order of the listing does not matter.
+        let mut reversed = objects.clone();
+        reversed.reverse();
+        assert_eq!(names(&shard(reversed, 1, 2).unwrap()), ["b.tif", "d.tif"]);
+        assert_eq!(shard(objects.clone(), 0, 1).unwrap().len(), 5);
+        assert!(shard(objects.clone(), 7, 8).unwrap().is_empty());
+        assert!(shard(objects.clone(), 2, 2).is_err());
+        assert!(shard(objects, 0, 0).is_err());
+    }
+
+    #[test]
+    fn etag_digests() {
+        assert_eq!(
+            etag_md5("\"D41D8CD98F00B204E9800998ECF8427E\"").as_deref(),
+            Some("d41d8cd98f00b204e9800998ecf8427e")
+        );
+        // The ETags of multipart uploads have the number of parts.
+        assert_eq!(etag_md5("\"d41d8cd98f00b204e9800998ecf8427e-3\""), None);
+        assert_eq!(etag_md5("\"not a digest\""), None);
+    }
+
+    #[test]
+    fn extracted_checksum() {
+        let dir = std::env::temp_dir().join(format!("aw3d30-extracted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ALPSMLC30_N052E004_DSM.tif");
+        fs::write(&path, b"123456789").unwrap();
+        // No recorded checksum.
+        assert!(!extracted(&path));
+        // The CRC-32 of `123456789` is the check value of the algorithm.
+        fs::write(checksum_path(&path), "cbf43926 9\n").unwrap();
+        assert!(extracted(&path));
+        fs::write(checksum_path(&path), "cbf43926 10\n").unwrap();
+        assert!(!extracted(&path));
+        fs::write(checksum_path(&path), "00000000 9\n").unwrap();
+        assert!(!extracted(&path));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
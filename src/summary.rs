@@ -0,0 +1,71 @@
+use crate::{
+    amend::{read_footer, write_footer, MAGIC},
+    manifest::Manifest,
+};
+use parquet::format::FileMetaData;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+/// File name of the summary metadata in the Parquet output dir.
+///
+/// Query engines like Spark and Dask read the footers of the files of a dataset from this
+/// file, instead of opening every file to plan a query.
+pub const SUMMARY_METADATA: &str = "_metadata";
+
+/// Writes the summary metadata of the Parquet files in the manifest of `dir`: a Parquet file
+/// without data, with the row groups of all files in its footer (referring to their file by
+/// path). Only the key-value metadata that all files have in common is kept.
+///
+/// The summary replaces the previous one atomically. Returns the number of row groups, or
+/// `None` when the files have different schemas, which can not be summarized. A previous
+/// summary is removed in that case.
+pub fn write_summary_metadata(dir: &Path) -> Result<Option<usize>, Box<dyn Error>> {
+    let manifest = Manifest::load(dir)?;
+    let mut summary: Option<FileMetaData> = None;
+    for file in &manifest.files {
+        let path = dir.join(&file.path);
+        let (mut metadata, _) = read_footer(&mut File::open(&path)?, &path)?;
+        for row_group in &mut metadata.row_groups {
+            for column in &mut row_group.columns {
+                column.file_path = Some(file.path.clone());
+            }
+        }
+        match &mut summary {
+            None => summary = Some(metadata),
+            Some(summary) if summary.schema == metadata.schema => {
+                summary.num_rows += metadata.num_rows;
+                summary.row_groups.append(&mut metadata.row_groups);
+                if let Some(key_value_metadata) = &mut summary.key_value_metadata {
+                    let file_metadata = metadata.key_value_metadata.unwrap_or_default();
+                    key_value_metadata.retain(|kv| file_metadata.contains(kv));
+                }
+            }
+            Some(_) => {
+                match fs::remove_file(dir.join(SUMMARY_METADATA)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+                return Ok(None);
+            }
+        }
+    }
+    let summary = match summary {
+        Some(summary) => summary,
+        None => return Ok(Some(0)),
+    };
+
+    // Write to a temporary file first, so that readers never observe a partial summary.
+    let tmp_path = dir.join(SUMMARY_METADATA).with_extension("tmp");
+    let mut output = io::BufWriter::new(File::create(&tmp_path)?);
+    output.write_all(MAGIC)?;
+    write_footer(&mut output, &summary)?;
+    output.flush()?;
+    drop(output);
+    fs::rename(tmp_path, dir.join(SUMMARY_METADATA))?;
+
+    Ok(Some(summary.row_groups.len()))
+}
@@ -0,0 +1,160 @@
+//! Terrain attributes derived from the elevation of the pixels.
+
+use std::str::FromStr;
+
+/// Approximate length of a degree of latitude in meters.
+pub(crate) const METERS_PER_DEGREE: f64 = 111_320.;
+
+/// A terrain attribute that is written as a column.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Derived {
+    /// Steepness in degrees, from 0 (flat) to 90
+    Slope,
+    /// Compass direction the slope faces in degrees, clockwise from north
+    Aspect,
+}
+
+impl FromStr for Derived {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slope" => Ok(Self::Slope),
+            "aspect" => Ok(Self::Aspect),
+            _ => Err("expected one of: slope, aspect"),
+        }
+    }
+}
+
+impl Derived {
+    /// Returns the name of the column.
+    pub fn name(self) -> &'static str {
+        match self {
+            Derived::Slope => "slope",
+            Derived::Aspect => "aspect",
+        }
+    }
+}
+
+/// Light source of a hillshade.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    /// Compass direction of the light in degrees, clockwise from north
+    pub azimuth: f64,
+    /// Angle of the light above the horizon in degrees
+    pub altitude: f64,
+}
+
+impl Default for Light {
+    /// Light from the northwest at 45 degrees, the default of `gdaldem hillshade`.
+    fn default() -> Self {
+        Light {
+            azimuth: 315.,
+            altitude: 45.,
+        }
+    }
+}
+
+impl Light {
+    /// Returns the illumination between 0 (in shadow) and 1 of a pixel with gradient `dz_dx`
+    /// towards the east and `dz_dy` towards the south.
+    fn shade(&self, dz_dx: f64, dz_dy: f64) -> f64 {
+        let slope = dz_dx.hypot(dz_dy).atan();
+        // Compass direction the slope faces, downhill.
+        let aspect = (-dz_dx).atan2(dz_dy);
+        let (azimuth, altitude) = (self.azimuth.to_radians(), self.altitude.to_radians());
+        (altitude.sin() * slope.cos() + altitude.cos() * slope.sin() * (azimuth - aspect).cos())
+            .max(0.)
+    }
+}
+
+/// Calls `f` with the gradient towards the east and the south of the pixels of the elevation
+/// `data` of a raster with geotransform `gt` and `size`, row by row, from the 3x3
+/// neighbourhood of the pixels (Horn's method).
+///
+/// Pixels with the `nodata` value have no gradient. Neighbours outside the raster or with the
+/// `nodata` value are taken as the center pixel.
+fn gradients(
+    data: &[i32],
+    gt: &[f64; 6],
+    size: (usize, usize),
+    nodata: Option<f64>,
+    mut f: impl FnMut(Option<(f64, f64)>),
+) {
+    let (x_size, y_size) = size;
+    let valid = |value: i32| nodata.map_or(true, |nodata| value as f64 != nodata);
+    let elevation = |x: isize, y: isize, center: i32| {
+        let x = x.max(0).min(x_size as isize - 1) as usize;
+        let y = y.max(0).min(y_size as isize - 1) as usize;
+        let value = data[y * x_size + x];
+        f64::from(if valid(value) { value } else { center })
+    };
+    for y in 0..y_size {
+        // Pixel size in meters at the center of the row.
+        let lat = (gt[3] + (y as f64 + 0.5) * gt[5]).to_radians();
+        let dx = gt[1].abs() * METERS_PER_DEGREE * lat.cos();
+        let dy = gt[5].abs() * METERS_PER_DEGREE;
+        for x in 0..x_size {
+            let value = data[y * x_size + x];
+            if !valid(value) {
+                f(None);
+                continue;
+            }
+            let (x, y) = (x as isize, y as isize);
+            let z = |i: isize, j: isize| elevation(x + i, y + j, value);
+            let dz_dx = ((z(1, -1) + 2. * z(1, 0) + z(1, 1))
+                - (z(-1, -1) + 2. * z(-1, 0) + z(-1, 1)))
+                / (8. * dx);
+            let dz_dy = ((z(-1, 1) + 2. * z(0, 1) + z(1, 1))
+                - (z(-1, -1) + 2. * z(0, -1) + z(1, -1)))
+                / (8. * dy);
+            f(Some((dz_dx, dz_dy)));
+        }
+    }
+}
+
+/// Returns the slope and aspect in degrees of the pixels of the elevation `data` of a raster
+/// with geotransform `gt` and `size`, from the gradient of the 3x3 neighbourhood (Horn's
+/// method).
+///
+/// Pixels with the `nodata` value have no slope or aspect, and flat pixels have no aspect.
+/// Neighbours outside the raster or with the `nodata` value are taken as the center pixel.
+pub fn slope_aspect(
+    data: &[i32],
+    gt: &[f64; 6],
+    size: (usize, usize),
+    nodata: Option<f64>,
+) -> (Vec<Option<f32>>, Vec<Option<f32>>) {
+    let mut slope = Vec::with_capacity(data.len());
+    let mut aspect = Vec::with_capacity(data.len());
+    gradients(data, gt, size, nodata, |gradient| {
+        slope.push(gradient.map(|(dz_dx, dz_dy)| dz_dx.hypot(dz_dy).atan().to_degrees() as f32));
+        // The slope faces downhill, against the gradient.
+        aspect.push(gradient.and_then(|(dz_dx, dz_dy)| {
+            (dz_dx != 0. || dz_dy != 0.)
+                .then(|| (-dz_dx).atan2(dz_dy).to_degrees().rem_euclid(360.) as f32)
+        }));
+    });
+    (slope, aspect)
+}
+
+/// Returns the hillshade of the pixels of the elevation `data` of a raster with geotransform
+/// `gt` and `size` lit by `light`, from 0 (in shadow) to 255 (facing the light), like
+/// `gdaldem hillshade`.
+///
+/// Pixels with the `nodata` value have no hillshade. Neighbours outside the raster or with the
+/// `nodata` value are taken as the center pixel.
+pub fn hillshade(
+    data: &[i32],
+    gt: &[f64; 6],
+    size: (usize, usize),
+    nodata: Option<f64>,
+    light: &Light,
+) -> Vec<Option<u8>> {
+    let mut shades = Vec::with_capacity(data.len());
+    gradients(data, gt, size, nodata, |gradient| {
+        shades
+            .push(gradient.map(|(dz_dx, dz_dy)| (255. * light.shade(dz_dx, dz_dy)).round() as u8));
+    });
+    shades
+}
@@ -0,0 +1,371 @@
+use clap::Subcommand;
+use regex::{Captures, Regex};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// A selection of tiles.
+#[derive(Copy, Clone, Debug, Subcommand)]
+pub enum Set {
+    /// Prepare data for the Netherlands (Requires ~300 MiB disk space)
+    #[command(alias = "nl")]
+    Netherlands,
+    /// Prepare data for France (Requires ~5 GiB disk space)
+    #[command(alias = "fr")]
+    France,
+    /// Prepare data for Europe (Requires ~43 GiB disk space)
+    #[command(alias = "eu")]
+    Europe,
+    /// Prepare data for the World (Requires ~400 GiB disk space)
+    #[command(alias = "all")]
+    World,
+    /// Prepare data for the bounding boxes of a country, by its ISO 3166-1 alpha-2 or alpha-3
+    /// code (e.g. `DE`). This is not a mask of the country: the boxes of its parts (e.g. Alaska
+    /// and Hawaii) include tiles of neighbouring countries and of the sea, combine it with
+    /// `--boundary` (and `--clip`) for the tiles (and points) of the country itself
+    CountryBbox {
+        /// ISO 3166-1 code of the country
+        country: Country,
+    },
+    /// Prepare data for the tiles that intersect a bounding box
+    Custom {
+        /// Bounding box `min_lon,min_lat,max_lon,max_lat` in degrees
+        #[arg(long, allow_hyphen_values = true)]
+        bbox: Bbox,
+    },
+}
+
+/// A bounding box in degrees.
+#[derive(Copy, Clone, Debug)]
+pub struct Bbox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl FromStr for Bbox {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str = "expected `min_lon,min_lat,max_lon,max_lat`";
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| ERR))
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(Bbox {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => Err(ERR),
+        }
+    }
+}
+
+/// Bounding boxes of countries, derived from the Natural Earth admin 0 boundaries and rounded
+/// outwards to 0.1 degree. Countries with distant parts (e.g. overseas regions without a code of
+/// their own) or that cross the antimeridian have a row per part.
+pub const COUNTRIES: &str = include_str!("../data/countries.csv");
+
+/// A country from the embedded [`COUNTRIES`] table.
+#[derive(Copy, Clone)]
+pub struct Country {
+    /// ISO 3166-1 alpha-2 code
+    pub alpha2: &'static str,
+    pub name: &'static str,
+}
+
+impl fmt::Debug for Country {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.alpha2)
+    }
+}
+
+impl FromStr for Country {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        COUNTRIES
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .find(|fields| fields[0].eq_ignore_ascii_case(s) || fields[1].eq_ignore_ascii_case(s))
+            .map(|fields| Country {
+                alpha2: fields[0],
+                name: fields[2],
+            })
+            .ok_or_else(|| format!("unknown ISO 3166-1 country code `{}`", s))
+    }
+}
+
+impl Country {
+    /// Returns the bounding boxes of the parts of the country.
+    pub fn bboxes(&self) -> impl Iterator<Item = Bbox> + '_ {
+        COUNTRIES
+            .lines()
+            .skip(1)
+            .filter(move |line| line.split(',').next() == Some(self.alpha2))
+            .map(|line| {
+                let fields = line.split(',').collect::<Vec<_>>();
+                fields[3..].join(",").parse().unwrap()
+            })
+    }
+}
+
+impl Bbox {
+    /// Returns true when the bounding box intersects the tile with its south-west corner at
+    /// `lat` and `lon`.
+    pub fn intersects(&self, (lat, lon): (i16, i16)) -> bool {
+        // Tiles that only touch the box at its max edge are excluded, but a box with its min
+        // edge on a tile boundary (e.g. a point) selects that tile.
+        let overlaps = |start: i16, min: f64, max: f64| {
+            let start = f64::from(start);
+            (start < max || start == min) && start + 1. > min
+        };
+        overlaps(lat, self.min_lat, self.max_lat) && overlaps(lon, self.min_lon, self.max_lon)
+    }
+}
+
+impl Set {
+    /// Returns true when the tile at `coordinate` is in the set.
+    pub fn filter(&self, coordinate: Coordinate) -> bool {
+        match self {
+            Self::Netherlands => {
+                matches!(coordinate.lat, Lat::North(ref y) if (50..=53).contains(y))
+                    && matches!(coordinate.lon, Lon::East(ref x) if (3..=7).contains(x))
+            }
+            Self::France => {
+                matches!(coordinate.lat, Lat::North(ref y) if (42..=51).contains(y))
+                    && (matches!(coordinate.lon, Lon::West(x) if x <= 6)
+                        || matches!(coordinate.lon, Lon::East(x) if x <= 9))
+            }
+            Self::Europe => {
+                matches!(coordinate.lat, Lat::North(ref y) if (23..=80).contains(y))
+                    && (matches!(coordinate.lon, Lon::West(x) if x <= 25)
+                        || matches!(coordinate.lon, Lon::East(x) if x <= 49))
+            }
+            Self::World => true,
+            Self::CountryBbox { country } => country
+                .bboxes()
+                .any(|bbox| bbox.intersects(coordinate.degrees())),
+            Self::Custom { bbox } => bbox.intersects(coordinate.degrees()),
+        }
+    }
+}
+
+/// Coordinate of a tile, named after its south-west corner.
+#[derive(Copy, Clone)]
+pub struct Coordinate {
+    lat: Lat,
+    lon: Lon,
+}
+
+impl Coordinate {
+    /// Returns the latitude and longitude of the south-west corner of the tile in degrees.
+    pub fn degrees(self) -> (i16, i16) {
+        let lat = match self.lat {
+            Lat::North(y) => y as i16,
+            Lat::South(y) => -(y as i16),
+        };
+        let lon = match self.lon {
+            Lon::East(x) => x as i16,
+            Lon::West(x) => -(x as i16),
+        };
+        (lat, lon)
+    }
+
+    /// Returns the bounding box of the tile as `min_lat_max_lat_min_lon_max_lon` in degrees,
+    /// e.g. `52.0_53.0_4.0_5.0`, for output file names.
+    pub fn bbox_name(self) -> String {
+        let (lat, lon) = self.degrees();
+        let (lat, lon) = (f64::from(lat), f64::from(lon));
+        format!("{:.1}_{:.1}_{:.1}_{:.1}", lat, lat + 1., lon, lon + 1.)
+    }
+}
+
+/// Returns the coordinate of the tile in a key or path, matched by `re`
+/// ([`TILE_PATTERN`](crate::TILE_PATTERN)).
+pub fn tile_coordinate(re: &Regex, key: &str) -> Option<Coordinate> {
+    re.captures(key)
+        .and_then(|cap| Coordinate::try_from(cap).ok())
+}
+
+#[derive(Copy, Clone)]
+enum Lat {
+    South(u8),
+    North(u8),
+}
+
+#[derive(Copy, Clone)]
+enum Lon {
+    East(u8),
+    West(u8),
+}
+
+impl<'a> TryFrom<Captures<'a>> for Coordinate {
+    type Error = &'static str;
+
+    fn try_from(cap: Captures) -> Result<Self, Self::Error> {
+        cap.name("lat")
+            .and_then(|y| y.as_str().parse().ok())
+            .and_then(|y| match cap.name("y").map(|y| y.as_str()) {
+                Some("N") => Some(Lat::North(y)),
+                Some("S") => Some(Lat::South(y)),
+                _ => None,
+            })
+            .and_then(|lat| {
+                cap.name("lon")
+                    .and_then(|x| x.as_str().parse().ok())
+                    .and_then(|x| {
+                        match cap.name("x").map(|y| y.as_str()) {
+                            Some("E") => Some(Lon::East(x)),
+                            Some("W") => Some(Lon::West(x)),
+                            _ => None,
+                        }
+                        .map(|lon| Coordinate { lat, lon })
+                    })
+            })
+            .ok_or("bad input")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TILE_PATTERN;
+
+    fn coordinate(name: &str) -> Coordinate {
+        tile_coordinate(&Regex::new(TILE_PATTERN).unwrap(), name).unwrap()
+    }
+
+    #[test]
+    fn bbox_from_str() {
+        let bbox = " 3.3,50.7, 7.3,53.6".parse::<Bbox>().unwrap();
+        assert_eq!(
+            (bbox.min_lon, bbox.min_lat, bbox.max_lon, bbox.max_lat),
+            (3.3, 50.7, 7.3, 53.6)
+        );
+        assert!("-180,-90,180,90".parse::<Bbox>().is_ok());
+        // A point is a valid box.
+        assert!("4,52,4,52".parse::<Bbox>().is_ok());
+        assert!("3.3,50.7,7.3".parse::<Bbox>().is_err());
+        assert!("3.3,50.7,7.3,53.6,1".parse::<Bbox>().is_err());
+        assert!("3.3,50.7,7.3,north".parse::<Bbox>().is_err());
+        assert!("".parse::<Bbox>().is_err());
+        // Boxes across the antimeridian are split in two instead.
+        assert!("170,-20,-170,-10".parse::<Bbox>().is_err());
+        assert!("3.3,53.6,7.3,50.7".parse::<Bbox>().is_err());
+    }
+
+    #[test]
+    fn bbox_intersects() {
+        let bbox = "3.3,50.7,7.3,53.6".parse::<Bbox>().unwrap();
+        assert!(bbox.intersects((50, 3)));
+        assert!(bbox.intersects((53, 7)));
+        assert!(!bbox.intersects((49, 3)));
+        assert!(!bbox.intersects((53, 8)));
+        assert!(!bbox.intersects((54, 7)));
+
+        // Tiles that only touch the max edge are excluded, those at the min edge are not.
+        let bbox = "3,50,4,51".parse::<Bbox>().unwrap();
+        assert!(bbox.intersects((50, 3)));
+        assert!(!bbox.intersects((51, 3)));
+        assert!(!bbox.intersects((50, 4)));
+        assert!(!bbox.intersects((50, 2)));
+        assert!(!bbox.intersects((49, 3)));
+
+        // A point on a tile boundary selects the tile to its north-east.
+        let bbox = "4,52,4,52".parse::<Bbox>().unwrap();
+        assert!(bbox.intersects((52, 4)));
+        assert!(!bbox.intersects((51, 3)));
+        assert!(!bbox.intersects((51, 4)));
+
+        // Both sides of the antimeridian.
+        assert!("179.5,-17,180,-16"
+            .parse::<Bbox>()
+            .unwrap()
+            .intersects((-17, 179)));
+        assert!("-180,-17,-179.5,-16"
+            .parse::<Bbox>()
+            .unwrap()
+            .intersects((-17, -180)));
+    }
+
+    #[test]
+    fn country_from_str() {
+        for code in ["NL", "nl", "NLD", "nld"] {
+            let country = code.parse::<Country>().unwrap();
+            assert_eq!(country.alpha2, "NL");
+            assert_eq!(country.name, "Netherlands");
+        }
+        assert!("XX".parse::<Country>().is_err());
+        assert!("Netherlands".parse::<Country>().is_err());
+        assert!("".parse::<Country>().is_err());
+    }
+
+    #[test]
+    fn country_bboxes() {
+        let us = "US".parse::<Country>().unwrap();
+        assert_eq!(us.bboxes().count(), 4);
+        let set = Set::CountryBbox { country: us };
+        // The contiguous states, Hawaii and the Aleutians on both sides of the antimeridian.
+        assert!(set.filter(coordinate("ALPSMLC30_N040W100_DSM.tif")));
+        assert!(set.filter(coordinate("ALPSMLC30_N019W156_DSM.tif")));
+        assert!(set.filter(coordinate("ALPSMLC30_N052E179_DSM.tif")));
+        assert!(set.filter(coordinate("ALPSMLC30_N052W180_DSM.tif")));
+        assert!(!set.filter(coordinate("ALPSMLC30_N052E004_DSM.tif")));
+        assert!(!set.filter(coordinate("ALPSMLC30_N000E000_DSM.tif")));
+
+        let nl = Set::CountryBbox {
+            country: "NL".parse().unwrap(),
+        };
+        assert!(nl.filter(coordinate("ALPSMLC30_N052E004_DSM.tif")));
+        assert!(nl.filter(coordinate("ALPSMLC30_N012W069_DSM.tif")));
+        assert!(!nl.filter(coordinate("ALPSMLC30_N048E002_DSM.tif")));
+    }
+
+    #[test]
+    fn country_bboxes_are_not_a_mask() {
+        let nl = Set::CountryBbox {
+            country: "NL".parse().unwrap(),
+        };
+        // Tiles of Belgium, Germany and the North Sea in the box of the European part, and of
+        // Curaçao next to Bonaire.
+        assert!(nl.filter(coordinate("ALPSMLC30_N050E004_DSM.tif")));
+        assert!(nl.filter(coordinate("ALPSMLC30_N051E007_DSM.tif")));
+        assert!(nl.filter(coordinate("ALPSMLC30_N053E003_DSM.tif")));
+        assert!(nl.filter(coordinate("ALPSMLC30_N012W069_DSM.tif")));
+        // All tiles of the box are selected, the same as the `netherlands` set.
+        let tiles = (50..=53)
+            .flat_map(|lat| (3..=7).map(move |lon| (lat, lon)))
+            .map(|(lat, lon)| coordinate(&format!("ALPSMLC30_N{:03}E{:03}_DSM.tif", lat, lon)))
+            .collect::<Vec<_>>();
+        assert!(tiles.iter().all(|&tile| nl.filter(tile)));
+        assert!(tiles.iter().all(|&tile| Set::Netherlands.filter(tile)));
+    }
+
+    #[test]
+    fn coordinate_degrees() {
+        assert_eq!(coordinate("ALPSMLC30_N052E004_DSM.tif").degrees(), (52, 4));
+        assert_eq!(
+            coordinate("ALPSMLC30_S017W180_DSM.tif").degrees(),
+            (-17, -180)
+        );
+        assert!(tile_coordinate(&Regex::new(TILE_PATTERN).unwrap(), "README.md").is_none());
+    }
+
+    #[test]
+    fn coordinate_bbox_name() {
+        assert_eq!(
+            coordinate("ALPSMLC30_N052E004_DSM.tif").bbox_name(),
+            "52.0_53.0_4.0_5.0"
+        );
+        assert_eq!(
+            coordinate("ALPSMLC30_S017W180_DSM.tif").bbox_name(),
+            "-17.0_-16.0_-180.0_-179.0"
+        );
+    }
+}
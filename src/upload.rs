@@ -0,0 +1,751 @@
+//! Upload of the Parquet files of a dataset to object storage: S3 (or S3 compatible object
+//! storage), Google Cloud Storage or Azure Blob Storage.
+
+use crate::{
+    http,
+    retry::{self, BreakerPolicy, CircuitBreaker, Retry},
+};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_RANGE, LOCATION},
+    Client, Method, RequestBuilder, StatusCode, Url,
+};
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, HeadObjectError, HeadObjectRequest,
+    PutObjectRequest, S3Client, UploadPartRequest, S3,
+};
+use serde::Deserialize;
+use std::{env, error::Error, fmt, path::Path, str::FromStr};
+use tokio::{fs::File, io::AsyncReadExt};
+use tracing::{event, Level};
+
+/// Size of the parts of multipart uploads, files of at least this size are uploaded in parts.
+/// A multiple of 256 KiB, as required for the chunks of resumable uploads to Cloud Storage.
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Token endpoint of the metadata server of Google Compute Engine.
+const GCE_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Token endpoint of the managed identity of Azure virtual machines, for Blob Storage.
+const AZURE_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Fstorage.azure.com%2F";
+
+/// Version of the Blob Storage REST API, which is required with bearer tokens.
+const AZURE_VERSION: &str = "2020-04-08";
+
+/// Object storage service of an [`OutputUrl`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Scheme {
+    /// S3 or S3 compatible object storage, `s3://`
+    S3,
+    /// Google Cloud Storage, `gs://`
+    Gcs,
+    /// Azure Blob Storage, `az://`
+    Azure,
+}
+
+impl Scheme {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scheme::S3 => "s3",
+            Scheme::Gcs => "gs",
+            Scheme::Azure => "az",
+        }
+    }
+}
+
+/// Location of the output files in object storage, `s3://bucket/prefix/`,
+/// `gs://bucket/prefix/` or `az://container/prefix/`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputUrl {
+    pub scheme: Scheme,
+    /// Bucket, or container of Blob Storage
+    pub bucket: String,
+    /// Prefix of the keys, empty or ending with a `/`
+    pub prefix: String,
+}
+
+impl FromStr for OutputUrl {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str =
+            "expected `s3://bucket/prefix/`, `gs://bucket/prefix/` or `az://container/prefix/`";
+        let (scheme, path) = match s.split_once("://") {
+            Some(("s3", path)) => (Scheme::S3, path),
+            Some(("gs", path)) => (Scheme::Gcs, path),
+            Some(("az", path)) => (Scheme::Azure, path),
+            _ => return Err(EXPECTED),
+        };
+        match path.split_once('/').unwrap_or((path, "")) {
+            (bucket, prefix) if !bucket.is_empty() => {
+                let prefix = prefix.trim_matches('/');
+                Ok(OutputUrl {
+                    scheme,
+                    bucket: bucket.to_string(),
+                    prefix: match prefix.is_empty() {
+                        true => String::new(),
+                        false => format!("{}/", prefix),
+                    },
+                })
+            }
+            _ => Err(EXPECTED),
+        }
+    }
+}
+
+impl fmt::Display for OutputUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://{}/{}",
+            self.scheme.as_str(),
+            self.bucket,
+            self.prefix
+        )
+    }
+}
+
+/// Operations of an object storage service that an [`Uploader`] needs.
+///
+/// Every service has its own API for large uploads and its own credentials, so other services
+/// only implement this trait.
+trait ObjectStore: Send + Sync {
+    /// Returns the size of the object with `key`, or `None` when it does not exist.
+    fn size<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>>;
+
+    /// Uploads the file at `path` of `size` bytes as the object with `key`, in parts of
+    /// [`PART_SIZE`] when it is large.
+    fn upload<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        size: u64,
+    ) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>>;
+}
+
+/// Uploads files to an [`OutputUrl`], with the credentials of the environment of its service.
+///
+/// Failed requests are retried according to `retry` and pause while the service is down, like
+/// the downloads.
+pub struct Uploader {
+    url: OutputUrl,
+    store: Box<dyn ObjectStore>,
+}
+
+impl Uploader {
+    /// Returns an uploader to `url`. Returns an error when the credentials of its service are
+    /// incomplete.
+    pub fn new(
+        url: OutputUrl,
+        retry: Retry,
+        policy: BreakerPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        let breaker = CircuitBreaker::new(url.to_string(), policy);
+        let bucket = url.bucket.clone();
+        let store: Box<dyn ObjectStore> = match url.scheme {
+            Scheme::S3 => Box::new(S3Store {
+                client: S3Client::new(Region::default()),
+                bucket,
+                retry,
+                breaker,
+            }),
+            Scheme::Gcs => Box::new(GcsStore {
+                client: Client::new(),
+                bucket,
+                token: env::var("GOOGLE_OAUTH_ACCESS_TOKEN").ok(),
+                retry,
+                breaker,
+            }),
+            Scheme::Azure => Box::new(AzureStore {
+                client: Client::new(),
+                account: env::var("AZURE_STORAGE_ACCOUNT")
+                    .map_err(|_| "`az://` output URLs need `AZURE_STORAGE_ACCOUNT`")?,
+                container: bucket,
+                sas: env::var("AZURE_STORAGE_SAS_TOKEN")
+                    .ok()
+                    .map(|sas| sas.trim_start_matches('?').to_string()),
+                retry,
+                breaker,
+            }),
+        };
+        Ok(Uploader { url, store })
+    }
+
+    /// Returns the location of the output files.
+    pub fn url(&self) -> &OutputUrl {
+        &self.url
+    }
+
+    /// Returns true when the object of the file `name` exists with `size` bytes.
+    pub async fn exists(
+        &self,
+        name: &str,
+        size: u64,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self.store.size(&self.key(name)).await? == Some(size))
+    }
+
+    /// Uploads the file at `path` as the object of the file `name`, in parts when it is large.
+    pub async fn upload(
+        &self,
+        path: &Path,
+        name: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let size = tokio::fs::metadata(path).await?.len();
+        self.store.upload(&self.key(name), path, size).await?;
+        event!(Level::INFO, "Uploaded `{}` ({} bytes)", name, size);
+        Ok(())
+    }
+
+    /// Returns the key of the object of the file `name`.
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.url.prefix, name)
+    }
+}
+
+/// Reads the next part of at most [`PART_SIZE`] bytes of `file`, which is empty at the end.
+async fn read_part(file: &mut File) -> std::io::Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE as usize);
+    file.take(PART_SIZE).read_to_end(&mut part).await?;
+    Ok(part)
+}
+
+/// Bearer token of a token endpoint.
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+/// S3 with the credentials and region of the environment, e.g. `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_DEFAULT_REGION`.
+struct S3Store {
+    client: S3Client,
+    bucket: String,
+    retry: Retry,
+    breaker: CircuitBreaker,
+}
+
+impl ObjectStore for S3Store {
+    fn size<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>> {
+        async move {
+            let request = HeadObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            };
+            match self
+                .retry
+                .run(
+                    &self.breaker,
+                    || self.client.head_object(request.clone()),
+                    retry::transient,
+                )
+                .await
+            {
+                Ok(output) => Ok(output.content_length.map(|size| size as u64)),
+                Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+                // HEAD responses have no body, so a missing object is an unknown 404.
+                Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+        .boxed()
+    }
+
+    /// A failed multipart upload is aborted, so that its parts are not kept (and billed).
+    fn upload<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        size: u64,
+    ) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        async move {
+            if size < PART_SIZE {
+                // Requests with a body can't be cloned, so they are created for every attempt.
+                let body = tokio::fs::read(path).await?;
+                self.retry
+                    .run(
+                        &self.breaker,
+                        || {
+                            self.client.put_object(PutObjectRequest {
+                                bucket: self.bucket.clone(),
+                                key: key.to_string(),
+                                body: Some(body.clone().into()),
+                                content_length: Some(size as i64),
+                                ..Default::default()
+                            })
+                        },
+                        retry::transient,
+                    )
+                    .await?;
+                return Ok(());
+            }
+            let upload_id = self
+                .retry
+                .run(
+                    &self.breaker,
+                    || {
+                        self.client
+                            .create_multipart_upload(CreateMultipartUploadRequest {
+                                bucket: self.bucket.clone(),
+                                key: key.to_string(),
+                                ..Default::default()
+                            })
+                    },
+                    retry::transient,
+                )
+                .await?
+                .upload_id
+                .ok_or("missing upload id")?;
+            if let Err(e) = self.upload_parts(path, key, &upload_id).await {
+                let abort = self
+                    .client
+                    .abort_multipart_upload(AbortMultipartUploadRequest {
+                        bucket: self.bucket.clone(),
+                        key: key.to_string(),
+                        upload_id,
+                        ..Default::default()
+                    })
+                    .await;
+                if let Err(abort) = abort {
+                    event!(
+                        Level::WARN,
+                        "Failed to abort upload of `{}`: {}",
+                        key,
+                        abort
+                    );
+                }
+                return Err(e);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl S3Store {
+    /// Uploads the parts of the file at `path` and completes the multipart upload.
+    async fn upload_parts(
+        &self,
+        path: &Path,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = File::open(path).await?;
+        let mut parts = Vec::new();
+        loop {
+            let body = read_part(&mut file).await?;
+            if body.is_empty() {
+                break;
+            }
+            // Part numbers start at 1.
+            let part_number = parts.len() as i64 + 1;
+            let output = self
+                .retry
+                .run(
+                    &self.breaker,
+                    || {
+                        self.client.upload_part(UploadPartRequest {
+                            bucket: self.bucket.clone(),
+                            key: key.to_string(),
+                            upload_id: upload_id.to_string(),
+                            part_number,
+                            body: Some(body.clone().into()),
+                            content_length: Some(body.len() as i64),
+                            ..Default::default()
+                        })
+                    },
+                    retry::transient,
+                )
+                .await?;
+            parts.push(CompletedPart {
+                e_tag: output.e_tag,
+                part_number: Some(part_number),
+            });
+        }
+        let request = CompleteMultipartUploadRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+            multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+            ..Default::default()
+        };
+        self.retry
+            .run(
+                &self.breaker,
+                || self.client.complete_multipart_upload(request.clone()),
+                retry::transient,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Google Cloud Storage with the access token in `GOOGLE_OAUTH_ACCESS_TOKEN`, or else the
+/// token of the service account of the Compute Engine instance (or GKE workload).
+struct GcsStore {
+    client: Client,
+    bucket: String,
+    token: Option<String>,
+    retry: Retry,
+    breaker: CircuitBreaker,
+}
+
+impl GcsStore {
+    /// Returns an authorized request. Tokens of the metadata server are requested for every
+    /// request, as the server caches and refreshes them.
+    async fn request(
+        &self,
+        method: Method,
+        url: Url,
+    ) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        let token = match &self.token {
+            Some(token) => token.clone(),
+            None => {
+                let response = self
+                    .client
+                    .get(GCE_TOKEN_URL)
+                    .header("Metadata-Flavor", "Google")
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                serde_json::from_str::<Token>(&response.text().await?)?.access_token
+            }
+        };
+        Ok(self.client.request(method, url).bearer_auth(token))
+    }
+
+    /// Returns the URL of `path` of the JSON API with the bucket.
+    fn url(&self, path: &str, object: Option<&str>) -> Url {
+        let mut url = Url::parse("https://storage.googleapis.com").unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .extend(path.split('/'))
+            .extend(&["b", self.bucket.as_str(), "o"])
+            // Object names are a single segment, with their slashes escaped.
+            .extend(object);
+        url
+    }
+}
+
+impl ObjectStore for GcsStore {
+    fn size<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>> {
+        #[derive(Deserialize)]
+        struct Object {
+            size: String,
+        }
+        async move {
+            let url = &self.url("storage/v1", Some(key));
+            self.retry
+                .run(
+                    &self.breaker,
+                    move || async move {
+                        let response = self.request(Method::GET, url.clone()).await?.send().await?;
+                        if response.status() == StatusCode::NOT_FOUND {
+                            return Ok(None);
+                        }
+                        let text = response.error_for_status()?.text().await?;
+                        let size = serde_json::from_str::<Object>(&text)?.size.parse()?;
+                        Ok::<_, Box<dyn Error + Send + Sync>>(Some(size))
+                    },
+                    |e| http::transient(e.as_ref()),
+                )
+                .await
+        }
+        .boxed()
+    }
+
+    /// Large files are uploaded in chunks to a resumable upload session, which is cancelled
+    /// when a chunk fails.
+    fn upload<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        size: u64,
+    ) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        async move {
+            let mut url = self.url("upload/storage/v1", None);
+            let upload_type = if size < PART_SIZE {
+                "media"
+            } else {
+                "resumable"
+            };
+            url.query_pairs_mut()
+                .append_pair("uploadType", upload_type)
+                .append_pair("name", key);
+            let url = &url;
+            if size < PART_SIZE {
+                let body = &tokio::fs::read(path).await?;
+                self.retry
+                    .run(
+                        &self.breaker,
+                        move || async move {
+                            self.request(Method::POST, url.clone())
+                                .await?
+                                .body(body.clone())
+                                .send()
+                                .await?
+                                .error_for_status()?;
+                            Ok::<_, Box<dyn Error + Send + Sync>>(())
+                        },
+                        |e| http::transient(e.as_ref()),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            let session = self
+                .retry
+                .run(
+                    &self.breaker,
+                    move || async move {
+                        let response = self
+                            .request(Method::POST, url.clone())
+                            .await?
+                            .header("X-Upload-Content-Length", size)
+                            .header(CONTENT_LENGTH, 0)
+                            .send()
+                            .await?
+                            .error_for_status()?;
+                        let location = response
+                            .headers()
+                            .get(LOCATION)
+                            .ok_or("missing upload session")?;
+                        let session = Url::parse(location.to_str()?)?;
+                        Ok::<_, Box<dyn Error + Send + Sync>>(session)
+                    },
+                    |e| http::transient(e.as_ref()),
+                )
+                .await?;
+            if let Err(e) = self.upload_chunks(&session, path, size).await {
+                let cancel = self.client.delete(session).send().await;
+                if let Err(cancel) = cancel {
+                    event!(
+                        Level::WARN,
+                        "Failed to cancel upload of `{}`: {}",
+                        key,
+                        cancel
+                    );
+                }
+                return Err(e);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl GcsStore {
+    /// Uploads the chunks of the file at `path` to the resumable upload `session`.
+    async fn upload_chunks(
+        &self,
+        session: &Url,
+        path: &Path,
+        size: u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = File::open(path).await?;
+        let mut offset = 0;
+        loop {
+            let body = &read_part(&mut file).await?;
+            if body.is_empty() {
+                break;
+            }
+            let range = &format!(
+                "bytes {}-{}/{}",
+                offset,
+                offset + body.len() as u64 - 1,
+                size
+            );
+            self.retry
+                .run(
+                    &self.breaker,
+                    move || async move {
+                        // The session URL authorizes the request.
+                        let response = self
+                            .client
+                            .put(session.clone())
+                            .header(CONTENT_RANGE, range)
+                            .body(body.clone())
+                            .send()
+                            .await?;
+                        // Chunks before the last one are acknowledged with 308.
+                        if response.status() != StatusCode::PERMANENT_REDIRECT {
+                            response.error_for_status()?;
+                        }
+                        Ok::<_, Box<dyn Error + Send + Sync>>(())
+                    },
+                    |e| http::transient(e.as_ref()),
+                )
+                .await?;
+            offset += body.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Azure Blob Storage of the account in `AZURE_STORAGE_ACCOUNT`, with the SAS token in
+/// `AZURE_STORAGE_SAS_TOKEN`, or else the managed identity of the virtual machine.
+struct AzureStore {
+    client: Client,
+    account: String,
+    container: String,
+    sas: Option<String>,
+    retry: Retry,
+    breaker: CircuitBreaker,
+}
+
+impl AzureStore {
+    /// Returns the URL of the blob with `key`, with the SAS token and the `query`.
+    fn url(&self, key: &str, query: &[(&str, &str)]) -> Result<Url, Box<dyn Error + Send + Sync>> {
+        let mut url = Url::parse(&format!("https://{}.blob.core.windows.net", self.account))?;
+        url.path_segments_mut()
+            .unwrap()
+            .push(&self.container)
+            .extend(key.split('/'));
+        url.set_query(self.sas.as_deref());
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        Ok(url)
+    }
+
+    /// Returns an authorized request. Without a SAS token, the token of the managed identity is
+    /// requested for every request, as the endpoint caches and refreshes it.
+    async fn request(
+        &self,
+        method: Method,
+        url: Url,
+    ) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        let request = self
+            .client
+            .request(method, url)
+            .header("x-ms-version", AZURE_VERSION);
+        if self.sas.is_some() {
+            return Ok(request);
+        }
+        let response = self
+            .client
+            .get(AZURE_TOKEN_URL)
+            .header("Metadata", "true")
+            .send()
+            .await?
+            .error_for_status()?;
+        let token = serde_json::from_str::<Token>(&response.text().await?)?.access_token;
+        Ok(request.bearer_auth(token))
+    }
+
+    /// Puts `body` at `url`, with the `headers`.
+    async fn put(
+        &self,
+        url: &Url,
+        headers: &[(&'static str, &str)],
+        body: &[u8],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.retry
+            .run(
+                &self.breaker,
+                move || async move {
+                    let mut request = self.request(Method::PUT, url.clone()).await?;
+                    for &(name, value) in headers {
+                        request = request.header(name, value);
+                    }
+                    request
+                        .body(body.to_vec())
+                        .send()
+                        .await?
+                        .error_for_status()?;
+                    Ok::<_, Box<dyn Error + Send + Sync>>(())
+                },
+                |e| http::transient(e.as_ref()),
+            )
+            .await
+    }
+}
+
+impl ObjectStore for AzureStore {
+    fn size<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, Box<dyn Error + Send + Sync>>> {
+        async move {
+            let url = &self.url(key, &[])?;
+            self.retry
+                .run(
+                    &self.breaker,
+                    move || async move {
+                        let response = self
+                            .request(Method::HEAD, url.clone())
+                            .await?
+                            .send()
+                            .await?;
+                        if response.status() == StatusCode::NOT_FOUND {
+                            return Ok(None);
+                        }
+                        let response = response.error_for_status()?;
+                        let size = response
+                            .headers()
+                            .get(CONTENT_LENGTH)
+                            .ok_or("missing content length")?;
+                        let size = size.to_str()?.parse()?;
+                        Ok::<_, Box<dyn Error + Send + Sync>>(Some(size))
+                    },
+                    |e| http::transient(e.as_ref()),
+                )
+                .await
+        }
+        .boxed()
+    }
+
+    /// Large files are uploaded as blocks that are committed with a block list. Blocks of a
+    /// failed upload are never committed, and removed by the service after a week.
+    fn upload<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        size: u64,
+    ) -> BoxFuture<'a, Result<(), Box<dyn Error + Send + Sync>>> {
+        async move {
+            if size < PART_SIZE {
+                let body = tokio::fs::read(path).await?;
+                let url = self.url(key, &[])?;
+                return self
+                    .put(&url, &[("x-ms-blob-type", "BlockBlob")], &body)
+                    .await;
+            }
+            let mut file = File::open(path).await?;
+            let mut blocks = Vec::new();
+            loop {
+                let body = read_part(&mut file).await?;
+                if body.is_empty() {
+                    break;
+                }
+                // The IDs of the blocks of a blob must have the same length.
+                let id = base64::encode(format!("{:06}", blocks.len()));
+                let url = self.url(key, &[("comp", "block"), ("blockid", id.as_str())])?;
+                self.put(&url, &[], &body).await?;
+                blocks.push(id);
+            }
+            let block_list = format!(
+                r#"<?xml version="1.0" encoding="utf-8"?><BlockList>{}</BlockList>"#,
+                blocks
+                    .iter()
+                    .map(|id| format!("<Latest>{}</Latest>", id))
+                    .collect::<String>()
+            );
+            let url = self.url(key, &[("comp", "blocklist")])?;
+            self.put(&url, &[], block_list.as_bytes()).await
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{event, Level};
+
+/// Version of the schema of [`RunMetrics`], incremented when fields change or are removed.
+const METRICS_VERSION: u32 = 1;
+
+/// Bytes read and written by the download and conversion stages of a run.
+#[derive(Default)]
+pub struct Usage {
+    /// Bytes downloaded
+    pub downloaded: AtomicU64,
+    /// Bytes of GeoTIFF files read by conversions
+    pub converted_read: AtomicU64,
+    /// Bytes of Parquet files written by conversions
+    pub converted_written: AtomicU64,
+    /// Values of the bands outside the valid range
+    pub out_of_range: AtomicU64,
+}
+
+impl Usage {
+    /// Reports the resource usage of this process, which ran for `elapsed`.
+    pub fn report(&self, elapsed: Duration) {
+        let (user, system, peak_rss) = resource_usage();
+        event!(Level::INFO, "Wall time: {:.1?}", elapsed);
+        event!(
+            Level::INFO,
+            "CPU time: {:.1?} user, {:.1?} system",
+            user,
+            system
+        );
+        event!(Level::INFO, "Peak RSS: {}", format_bytes(peak_rss));
+        event!(
+            Level::INFO,
+            "Download: {} written",
+            format_bytes(self.downloaded.load(Ordering::Relaxed))
+        );
+        event!(
+            Level::INFO,
+            "Convert: {} read, {} written",
+            format_bytes(self.converted_read.load(Ordering::Relaxed)),
+            format_bytes(self.converted_written.load(Ordering::Relaxed))
+        );
+        let out_of_range = self.out_of_range.load(Ordering::Relaxed);
+        if out_of_range > 0 {
+            event!(
+                Level::WARN,
+                "{} values out of the valid range",
+                out_of_range
+            );
+        }
+    }
+
+    /// Returns the metrics of a run of `command` with `settings`, which ran for `elapsed`.
+    pub fn metrics(
+        &self,
+        elapsed: Duration,
+        command: &str,
+        settings: BTreeMap<String, String>,
+    ) -> RunMetrics {
+        let (user, system, peak_rss) = resource_usage();
+        let wall_seconds = elapsed.as_secs_f64();
+        let downloaded_bytes = self.downloaded.load(Ordering::Relaxed);
+        let converted_read_bytes = self.converted_read.load(Ordering::Relaxed);
+        RunMetrics {
+            version: METRICS_VERSION,
+            started_at: SystemTime::now()
+                .checked_sub(elapsed)
+                .and_then(|start| start.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |start| start.as_secs()),
+            command: command.to_string(),
+            wall_seconds,
+            user_seconds: user.as_secs_f64(),
+            system_seconds: system.as_secs_f64(),
+            peak_rss_bytes: peak_rss,
+            downloaded_bytes,
+            converted_read_bytes,
+            converted_written_bytes: self.converted_written.load(Ordering::Relaxed),
+            download_throughput: downloaded_bytes as f64 / wall_seconds,
+            conversion_throughput: converted_read_bytes as f64 / wall_seconds,
+            out_of_range_values: self.out_of_range.load(Ordering::Relaxed),
+            settings,
+        }
+    }
+}
+
+/// Returns the user and system CPU time and the peak resident set size in bytes of this process.
+fn resource_usage() -> (Duration, Duration, u64) {
+    // Safety: getrusage only writes to the provided struct.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut rusage) };
+    let cpu_time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    // Linux reports the maximum resident set size in KiB.
+    (
+        cpu_time(rusage.ru_utime),
+        cpu_time(rusage.ru_stime),
+        rusage.ru_maxrss as u64 * 1024,
+    )
+}
+
+/// Metrics of a run, without paths, names of hosts or other identifying information.
+///
+/// The fields are only added to within a [`RunMetrics::version`], so that the metrics of runs
+/// can be aggregated over time.
+#[derive(Debug, Serialize)]
+pub struct RunMetrics {
+    /// Version of the schema
+    pub version: u32,
+    /// Start of the run in seconds since the Unix epoch
+    pub started_at: u64,
+    /// Subcommand of the run
+    pub command: String,
+    pub wall_seconds: f64,
+    pub user_seconds: f64,
+    pub system_seconds: f64,
+    pub peak_rss_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub converted_read_bytes: u64,
+    pub converted_written_bytes: u64,
+    /// Bytes downloaded per second of wall time
+    pub download_throughput: f64,
+    /// Bytes of GeoTIFF files converted per second of wall time
+    pub conversion_throughput: f64,
+    /// Values of the bands outside the valid range
+    pub out_of_range_values: u64,
+    /// Conversion and download settings of the run
+    pub settings: BTreeMap<String, String>,
+}
+
+impl RunMetrics {
+    /// Appends the metrics as a JSON line to the file at `path`, which is created if needed.
+    pub fn append(&self, path: &Path) -> io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        // A single write, so that concurrent runs do not interleave their lines.
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)
+    }
+}
+
+/// Fields of [`RunMetrics`] that calibrate the projections of a run.
+#[derive(Deserialize)]
+struct PastRun {
+    version: u32,
+    wall_seconds: f64,
+    downloaded_bytes: u64,
+    converted_read_bytes: u64,
+    converted_written_bytes: u64,
+    #[serde(default)]
+    settings: BTreeMap<String, String>,
+}
+
+/// Throughput and compression measured by past runs, to project the duration and the size of
+/// the output of a run.
+///
+/// The throughput is the total of the bytes over the total wall time of the runs, so that
+/// longer runs weigh more.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    /// Number of past runs that downloaded or converted files
+    pub runs: usize,
+    /// Bytes downloaded and the wall seconds of the runs that downloaded
+    download: (u64, f64),
+    /// Bytes of GeoTIFF files converted and the wall seconds of the runs that converted
+    conversion: (u64, f64),
+    /// Bytes of GeoTIFF files read and of Parquet files written per compression codec
+    compression: BTreeMap<String, (u64, u64)>,
+}
+
+impl Calibration {
+    /// Reads the metrics of past runs from the metrics file at `path`, which has no runs when
+    /// it does not exist. Lines of other versions of the metrics are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut calibration = Calibration::default();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(calibration),
+            Err(e) => return Err(e),
+        };
+        let runs = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<PastRun>(line).ok())
+            .filter(|run| run.version == METRICS_VERSION && run.wall_seconds > 0.);
+        for run in runs {
+            if run.downloaded_bytes == 0 && run.converted_read_bytes == 0 {
+                continue;
+            }
+            calibration.runs += 1;
+            if run.downloaded_bytes > 0 {
+                calibration.download.0 += run.downloaded_bytes;
+                calibration.download.1 += run.wall_seconds;
+            }
+            // Outputs other than Parquet files are not written by the conversions.
+            if run.converted_read_bytes > 0 && run.converted_written_bytes > 0 {
+                calibration.conversion.0 += run.converted_read_bytes;
+                calibration.conversion.1 += run.wall_seconds;
+                if let Some(codec) = run.settings.get("compression") {
+                    let sizes = calibration.compression.entry(codec.clone()).or_default();
+                    sizes.0 += run.converted_read_bytes;
+                    sizes.1 += run.converted_written_bytes;
+                }
+            }
+        }
+        Ok(calibration)
+    }
+
+    /// Returns the projected seconds to download `bytes`, if past runs downloaded files.
+    pub fn download_seconds(&self, bytes: u64) -> Option<f64> {
+        let (downloaded, seconds) = self.download;
+        (downloaded > 0).then(|| bytes as f64 * seconds / downloaded as f64)
+    }
+
+    /// Returns the projected seconds to convert `bytes` of GeoTIFF files, if past runs
+    /// converted files.
+    pub fn conversion_seconds(&self, bytes: u64) -> Option<f64> {
+        let (converted, seconds) = self.conversion;
+        (converted > 0).then(|| bytes as f64 * seconds / converted as f64)
+    }
+
+    /// Returns the size of the Parquet files relative to the GeoTIFF files they were converted
+    /// from, if past runs compressed the Parquet files with `codec`.
+    pub fn compression_ratio(&self, codec: &str) -> Option<f64> {
+        self.compression
+            .get(codec)
+            .map(|&(read, written)| written as f64 / read as f64)
+    }
+}
+
+/// Parses a positive number of bytes with a decimal or binary unit, e.g. `512MB` or `1.5GiB`.
+pub fn parse_bytes(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (value, unit) = s.split_at(split);
+    let multiplier = match unit {
+        "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+    match value.parse::<f64>() {
+        Ok(value) if value > 0. => Some((value * multiplier as f64) as u64),
+        _ => None,
+    }
+}
+
+/// Formats a number of bytes using binary prefixes.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
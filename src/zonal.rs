@@ -0,0 +1,222 @@
+use crate::{
+    column::read_band,
+    polygon::{read_polygons, Polygon},
+    tile_coordinate, Band, IfExists, Source, Usage, TILE_PATTERN,
+};
+use futures::{stream, StreamExt};
+use gdal::Dataset;
+use parquet::{
+    basic::{self, Repetition},
+    column::writer::ColumnWriter,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use regex::Regex;
+use std::{collections::HashSet, error::Error, fs::File, path::Path, sync::Arc};
+use tokio::task;
+use tracing::{event, Level};
+
+/// Adds the values of `band` of the raster at `path` with their pixel center in a zone to the
+/// values of that zone, skipping `nodata` pixels (the band's nodata value when `None`). Float
+/// bands are `rounded` or refused.
+pub fn collect_tile(
+    path: &Path,
+    band: isize,
+    nodata: Option<f64>,
+    rounded: bool,
+    zones: &[Polygon],
+    values: &mut [Vec<i32>],
+) -> Result<(), Box<dyn Error>> {
+    let dataset = Dataset::open(path)?;
+    let gt = dataset.geo_transform()?;
+    let (x_size, y_size) = dataset.raster_size();
+    let rasterband = dataset.rasterband(band)?;
+    let nodata = nodata.or_else(|| rasterband.no_data_value());
+    let (_, data) = read_band(&rasterband, rounded).map_err(|e| e as Box<dyn Error>)?;
+    // Pixel window of a bounding box, the tiles are north-up.
+    let window = |min: f64, max: f64, origin: f64, step: f64, size: usize| {
+        let (a, b) = ((min - origin) / step, (max - origin) / step);
+        let clamp = |value: f64| value.max(0.).min(size as f64) as usize;
+        clamp(a.min(b).floor())..clamp(a.max(b).ceil())
+    };
+    for (zone, values) in zones.iter().zip(values) {
+        let (min_lon, min_lat, max_lon, max_lat) = zone.bbox;
+        for y in window(min_lat, max_lat, gt[3], gt[5], y_size) {
+            let lat = gt[3] + (y as f64 + 0.5) * gt[5];
+            for x in window(min_lon, max_lon, gt[0], gt[1], x_size) {
+                let lon = gt[0] + (x as f64 + 0.5) * gt[1];
+                let value = data[y * x_size + x];
+                if nodata.map_or(true, |nodata| value as f64 != nodata) && zone.contains(lon, lat) {
+                    values.push(value);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the count, min, max, mean and `percentiles` of the values of every zone to a Parquet
+/// file at `path`. The statistics of zones without values are null.
+pub fn write_stats(
+    path: &Path,
+    zones: &[Polygon],
+    mut values: Vec<Vec<i32>>,
+    percentiles: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let field = |name: &str, physical_type, repetition| {
+        Arc::new(
+            Type::primitive_type_builder(name, physical_type)
+                .with_repetition(repetition)
+                .build()
+                .unwrap(),
+        )
+    };
+    let mut fields = vec![
+        field("zone", basic::Type::INT64, Repetition::REQUIRED),
+        field("count", basic::Type::INT64, Repetition::REQUIRED),
+        field("min", basic::Type::INT32, Repetition::OPTIONAL),
+        field("max", basic::Type::INT32, Repetition::OPTIONAL),
+        field("mean", basic::Type::DOUBLE, Repetition::OPTIONAL),
+    ];
+    fields.extend(percentiles.iter().map(|percentile| {
+        field(
+            &format!("p{}", percentile),
+            basic::Type::INT32,
+            Repetition::OPTIONAL,
+        )
+    }));
+    let schema = Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?,
+    );
+
+    // Nearest-rank percentiles of the sorted values.
+    values.iter_mut().for_each(|values| values.sort_unstable());
+    let rank = |values: &[i32], percentile: u8| {
+        let rank = (percentile as f64 / 100. * values.len() as f64).ceil() as usize;
+        values[rank.max(1) - 1]
+    };
+    let mut int32_columns = vec![
+        values
+            .iter()
+            .map(|v| v.first().copied())
+            .collect::<Vec<_>>(),
+        values.iter().map(|v| v.last().copied()).collect(),
+    ];
+    int32_columns.extend(percentiles.iter().map(|&percentile| {
+        values
+            .iter()
+            .map(|v| (!v.is_empty()).then(|| rank(v, percentile)))
+            .collect()
+    }));
+    let mean = values
+        .iter()
+        .map(|v| {
+            let sum = v.iter().map(|&v| v as f64).sum::<f64>();
+            (!v.is_empty()).then(|| sum / v.len() as f64)
+        })
+        .collect::<Vec<_>>();
+    let def_levels = values
+        .iter()
+        .map(|v| !v.is_empty() as i16)
+        .collect::<Vec<_>>();
+
+    let mut writer = SerializedFileWriter::new(
+        File::create(path)?,
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_writer = writer.next_row_group()?;
+    let ids = zones.iter().map(|zone| zone.id).collect::<Vec<_>>();
+    let counts = values.iter().map(|v| v.len() as i64).collect::<Vec<_>>();
+    for column in [&ids, &counts] {
+        if let Some(mut col_writer) = row_writer.next_column()? {
+            match col_writer.untyped() {
+                ColumnWriter::Int64ColumnWriter(c) => c.write_batch(column, None, None)?,
+                _ => unreachable!(),
+            };
+            col_writer.close()?;
+        }
+    }
+    // The mean follows min and max, in schema order.
+    let mut int32_columns = int32_columns.into_iter();
+    while let Some(mut col_writer) = row_writer.next_column()? {
+        match col_writer.untyped() {
+            ColumnWriter::Int32ColumnWriter(c) => {
+                let column = int32_columns.next().unwrap();
+                let column = column.into_iter().flatten().collect::<Vec<_>>();
+                c.write_batch(&column, Some(&def_levels), None)?
+            }
+            ColumnWriter::DoubleColumnWriter(c) => {
+                let column = mean.iter().flatten().copied().collect::<Vec<_>>();
+                c.write_batch(&column, Some(&def_levels), None)?
+            }
+            _ => unreachable!(),
+        };
+        col_writer.close()?;
+    }
+    row_writer.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Computes the statistics of the first band per zone of the `zones` vector file and writes
+/// them to `output`, downloading only the tiles that overlap the zones.
+#[allow(clippy::too_many_arguments)]
+pub async fn zonal_stats(
+    source: &Source,
+    zones: &Path,
+    percentiles: &[u8],
+    output: &Path,
+    tif_dir: &Path,
+    if_exists: IfExists,
+    band: &Band,
+    nodata_value: Option<f64>,
+    usage: &Arc<Usage>,
+) -> Result<(), Box<dyn Error>> {
+    if percentiles.iter().any(|&percentile| percentile > 100) {
+        return Err("percentiles must be between 0 and 100".into());
+    }
+    let zones = Arc::new(read_polygons(zones)?);
+    event!(Level::INFO, "Computing statistics of {} zones", zones.len());
+    // The tiles that overlap the bounding box of a zone, named after their south-west corner.
+    let mut tiles = HashSet::new();
+    for zone in zones.iter() {
+        let (min_lon, min_lat, max_lon, max_lat) = zone.bbox;
+        for lat in min_lat.floor() as i16..=max_lat.floor() as i16 {
+            for lon in min_lon.floor() as i16..=max_lon.floor() as i16 {
+                tiles.insert((lat, lon));
+            }
+        }
+    }
+
+    let re = Regex::new(TILE_PATTERN)?;
+    let objects = source
+        .list(|key| {
+            tile_coordinate(&re, key)
+                .map_or(false, |coordinate| tiles.contains(&coordinate.degrees()))
+        })
+        .await?;
+    event!(Level::INFO, "Downloading {} files", objects.len());
+
+    let mut values = vec![Vec::new(); zones.len()];
+    let rounded = source.dataset().rounded();
+    let mut paths = stream::iter(objects)
+        .map(|object| source.download(object, tif_dir.to_path_buf(), if_exists, usage.clone()))
+        .buffer_unordered(1);
+    while let Some(path) = paths.next().await {
+        let (path, _) = path?.map_err(|e| e as Box<dyn Error>)?;
+        let (index, zones) = (band.index, zones.clone());
+        // The values are moved to the blocking task and back.
+        values = task::spawn_blocking(move || {
+            collect_tile(&path, index, nodata_value, rounded, &zones, &mut values)
+                .map(|_| values)
+                .map_err(|e| e.to_string())
+        })
+        .await??;
+    }
+
+    event!(Level::INFO, "Writing statistics to `{}`", output.display());
+    write_stats(output, &zones, values, percentiles)
+}